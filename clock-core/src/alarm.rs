@@ -0,0 +1,522 @@
+//! Alarm scheduling.
+//!
+//! Alarms are set in local time and fire against a UTC clock, such as the RTC, with the time zone
+//! worked out by `PosixTz` so alarms stay at the same wall clock time across daylight saving
+//! changes. Working out when an alarm fires doesn't touch any hardware; what an alarm does while it
+//! rings is described by `Actions`, which the application turns into LED colours and beeps.
+
+use chrono::{Datelike, NaiveTime, Weekday};
+use core::ops::BitOr;
+
+use crate::tz::PosixTz;
+
+/// A set of days of the week.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Weekdays(u8);
+
+impl Weekdays {
+    pub const NONE: Self = Self(0);
+    pub const MONDAY: Self = Self(1 << 0);
+    pub const TUESDAY: Self = Self(1 << 1);
+    pub const WEDNESDAY: Self = Self(1 << 2);
+    pub const THURSDAY: Self = Self(1 << 3);
+    pub const FRIDAY: Self = Self(1 << 4);
+    pub const SATURDAY: Self = Self(1 << 5);
+    pub const SUNDAY: Self = Self(1 << 6);
+    pub const WORKDAYS: Self = Self(0b0011111);
+    pub const WEEKEND: Self = Self(0b1100000);
+    pub const EVERY_DAY: Self = Self(0b1111111);
+
+    pub const fn from_bits(bits: u8) -> Self {
+        Self(bits & Self::EVERY_DAY.0)
+    }
+
+    pub const fn bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn contains(self, day: Weekday) -> bool {
+        self.0 & (1 << day.num_days_from_monday()) != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for Weekdays {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+impl From<Weekday> for Weekdays {
+    fn from(day: Weekday) -> Self {
+        Self(1 << day.num_days_from_monday())
+    }
+}
+
+/// Whether an alarm goes off once or every week.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Repeat {
+    /// Fires the next time the clock reaches the alarm time, then disables itself.
+    Once,
+    /// Fires at the alarm time on each of the given days.
+    Weekly(Weekdays),
+}
+
+/// An animation for a WS2812 strip.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StripPattern {
+    /// The whole strip flashes on and off once a second.
+    Flash([u8; 3]),
+    /// A single lit LED runs along the strip.
+    Chase([u8; 3]),
+    /// A rainbow moves along the strip.
+    Rainbow { brightness: u8 },
+}
+
+impl StripPattern {
+    /// Returns the colour of LED `index` on a strip of `len` LEDs, `elapsed_ms` into the alarm.
+    pub fn color(&self, index: usize, len: usize, elapsed_ms: u32) -> [u8; 3] {
+        match *self {
+            StripPattern::Flash(color) => {
+                if (elapsed_ms / 500).is_multiple_of(2) {
+                    color
+                } else {
+                    [0, 0, 0]
+                }
+            }
+            StripPattern::Chase(color) => {
+                if len > 0 && (elapsed_ms / 100) as usize % len == index {
+                    color
+                } else {
+                    [0, 0, 0]
+                }
+            }
+            StripPattern::Rainbow { brightness } => {
+                let step = if len > 0 { 256 / len as u32 } else { 0 };
+                let hue = ((elapsed_ms / 10) + index as u32 * step) % 256;
+                wheel(hue as u8, brightness)
+            }
+        }
+    }
+}
+
+/// Maps 0-255 to a colour around the colour wheel.
+fn wheel(position: u8, brightness: u8) -> [u8; 3] {
+    let scale = |v: u32| (v * brightness as u32 / 255) as u8;
+    let p = position as u32;
+    match p {
+        0..=84 => [scale(255 - p * 3), scale(p * 3), 0],
+        85..=169 => [0, scale(255 - (p - 85) * 3), scale((p - 85) * 3)],
+        _ => [scale((p - 170) * 3), 0, scale(255 - (p - 170) * 3)],
+    }
+}
+
+/// A beep pattern for a speaker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Beep {
+    pub frequency_hz: u32,
+    pub on_ms: u32,
+    pub off_ms: u32,
+}
+
+impl Beep {
+    pub const DEFAULT: Self = Self {
+        frequency_hz: 2000,
+        on_ms: 200,
+        off_ms: 300,
+    };
+
+    /// Returns how long the current beep has left to run, or None during a gap.
+    pub fn remaining_ms(&self, elapsed_ms: u32) -> Option<u32> {
+        let period = self.on_ms + self.off_ms;
+        if period == 0 {
+            return None;
+        }
+        let phase = elapsed_ms % period;
+        (phase < self.on_ms).then(|| self.on_ms - phase)
+    }
+}
+
+/// What an alarm does while it is ringing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Actions {
+    pub strip: Option<StripPattern>,
+    /// The onboard RGB LED, as red, green and blue on/off, blinked twice a second.
+    pub rgb_led: Option<[bool; 3]>,
+    pub speaker: Option<Beep>,
+}
+
+impl Actions {
+    pub const fn new() -> Self {
+        Self {
+            strip: None,
+            rgb_led: None,
+            speaker: None,
+        }
+    }
+
+    pub const fn with_strip(mut self, pattern: StripPattern) -> Self {
+        self.strip = Some(pattern);
+        self
+    }
+
+    pub const fn with_rgb_led(mut self, red: bool, green: bool, blue: bool) -> Self {
+        self.rgb_led = Some([red, green, blue]);
+        self
+    }
+
+    pub const fn with_speaker(mut self, beep: Beep) -> Self {
+        self.speaker = Some(beep);
+        self
+    }
+
+    /// Returns the state of the onboard RGB LED `elapsed_ms` into the alarm.
+    pub fn rgb_led_at(&self, elapsed_ms: u32) -> Option<[bool; 3]> {
+        let color = self.rgb_led?;
+        Some(if (elapsed_ms / 250).is_multiple_of(2) { color } else { [false; 3] })
+    }
+}
+
+/// A single alarm.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Alarm {
+    pub time: NaiveTime,
+    pub repeat: Repeat,
+    pub actions: Actions,
+    enabled: bool,
+    /// When the alarm next fires in UTC seconds, None if it hasn't been worked out yet.
+    next: Option<i64>,
+}
+
+impl Alarm {
+    /// Returns None if the time is invalid.
+    pub fn new(hour: u32, minute: u32, repeat: Repeat, actions: Actions) -> Option<Self> {
+        Some(Self {
+            time: NaiveTime::from_hms_opt(hour, minute, 0)?,
+            repeat,
+            actions,
+            enabled: true,
+            next: None,
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Returns when the alarm is next due in UTC seconds, once it has been scheduled.
+    pub fn next(&self) -> Option<i64> {
+        self.next.filter(|_| self.enabled)
+    }
+
+    /// Works out the first time after `after` (UTC seconds) that the alarm fires.
+    ///
+    /// Returns None if the alarm repeats on no days.
+    pub fn next_fire(&self, tz: &PosixTz, after: i64) -> Option<i64> {
+        let today = tz.to_local(after).date();
+
+        // Eight days covers the same weekday next week when today's time has already passed.
+        (0..8)
+            .filter_map(|days| today.checked_add_days(chrono::Days::new(days)))
+            .filter(|date| match self.repeat {
+                Repeat::Once => true,
+                Repeat::Weekly(days) => days.contains(date.weekday()),
+            })
+            .map(|date| tz.to_utc(date.and_time(self.time)))
+            .find(|&utc| utc > after)
+    }
+}
+
+/// The alarm which is going off.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ringing {
+    pub index: usize,
+    /// UTC seconds when it started ringing.
+    pub since: i64,
+}
+
+impl Ringing {
+    pub fn elapsed_ms(&self, now_ms: i64) -> u32 {
+        (now_ms - self.since * 1000).clamp(0, u32::MAX as i64) as u32
+    }
+}
+
+/// What happened during a call to `AlarmClock::poll`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlarmEvent {
+    Started(usize),
+    /// The alarm rang for longer than the ring limit and was stopped.
+    TimedOut(usize),
+}
+
+/// A set of up to `N` alarms.
+pub struct AlarmClock<const N: usize> {
+    alarms: heapless::Vec<Alarm, N>,
+    tz: PosixTz,
+    pub snooze_minutes: u32,
+    /// How long an alarm rings if it isn't snoozed or dismissed, 0 for ever.
+    pub ring_limit_secs: u32,
+    ringing: Option<Ringing>,
+}
+
+impl<const N: usize> AlarmClock<N> {
+    pub fn new(tz: PosixTz) -> Self {
+        Self {
+            alarms: heapless::Vec::new(),
+            tz,
+            snooze_minutes: 9,
+            ring_limit_secs: 10 * 60,
+            ringing: None,
+        }
+    }
+
+    pub fn tz(&self) -> &PosixTz {
+        &self.tz
+    }
+
+    /// Changes the time zone, the alarms are rescheduled at the next poll.
+    pub fn set_tz(&mut self, tz: PosixTz) {
+        self.tz = tz;
+        self.clock_changed();
+    }
+
+    /// Call this when the clock has been set, for example from NTP. The alarms are rescheduled
+    /// from the new time at the next poll, rather than every alarm in a jump forward going off.
+    pub fn clock_changed(&mut self) {
+        for alarm in self.alarms.iter_mut() {
+            alarm.next = None;
+        }
+    }
+
+    pub fn alarms(&self) -> &[Alarm] {
+        &self.alarms
+    }
+
+    /// Adds an alarm, returning its index, or gives it back if there is no room.
+    pub fn add(&mut self, alarm: Alarm) -> Result<usize, Alarm> {
+        self.alarms.push(alarm)?;
+        Ok(self.alarms.len() - 1)
+    }
+
+    /// Removes an alarm, the indexes of later alarms drop by one.
+    pub fn remove(&mut self, index: usize) -> Option<Alarm> {
+        if index >= self.alarms.len() {
+            return None;
+        }
+        match self.ringing {
+            Some(ringing) if ringing.index == index => self.ringing = None,
+            Some(ref mut ringing) if ringing.index > index => ringing.index -= 1,
+            _ => {}
+        }
+        Some(self.alarms.remove(index))
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) {
+        if let Some(alarm) = self.alarms.get_mut(index) {
+            alarm.enabled = enabled;
+            alarm.next = None;
+        }
+    }
+
+    /// Returns the alarm that fires next and when, as UTC seconds.
+    pub fn next(&self) -> Option<(usize, i64)> {
+        self.alarms
+            .iter()
+            .enumerate()
+            .filter_map(|(index, alarm)| alarm.next().map(|next| (index, next)))
+            .min_by_key(|&(_, next)| next)
+    }
+
+    pub fn ringing(&self) -> Option<Ringing> {
+        self.ringing
+    }
+
+    /// Returns the actions of the ringing alarm.
+    pub fn ringing_actions(&self) -> Option<&Actions> {
+        self.ringing.and_then(|ringing| self.alarms.get(ringing.index)).map(|alarm| &alarm.actions)
+    }
+
+    /// Checks the alarms against the current UTC time, call this at least once a second.
+    pub fn poll(&mut self, now: i64) -> Option<AlarmEvent> {
+        if let Some(ringing) = self.ringing
+            && self.ring_limit_secs > 0
+            && now - ringing.since >= self.ring_limit_secs as i64
+        {
+            self.ringing = None;
+            return Some(AlarmEvent::TimedOut(ringing.index));
+        }
+
+        let tz = self.tz;
+        let mut fired = None;
+        for (index, alarm) in self.alarms.iter_mut().enumerate() {
+            if !alarm.enabled {
+                continue;
+            }
+            match alarm.next {
+                None => alarm.next = alarm.next_fire(&tz, now),
+                Some(next) if next <= now => {
+                    match alarm.repeat {
+                        Repeat::Once => {
+                            alarm.enabled = false;
+                            alarm.next = None;
+                        }
+                        Repeat::Weekly(_) => alarm.next = alarm.next_fire(&tz, now),
+                    }
+                    // If several are due at once the first one wins, the others are skipped.
+                    if fired.is_none() {
+                        fired = Some(index);
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+
+        let index = fired?;
+        self.ringing = Some(Ringing { index, since: now });
+        Some(AlarmEvent::Started(index))
+    }
+
+    /// Silences the ringing alarm and has it go off again after the snooze time.
+    pub fn snooze(&mut self, now: i64) {
+        if let Some(ringing) = self.ringing.take()
+            && let Some(alarm) = self.alarms.get_mut(ringing.index)
+        {
+            let again = now + self.snooze_minutes as i64 * 60;
+            alarm.enabled = true;
+            alarm.next = Some(alarm.next.map_or(again, |next| next.min(again)));
+        }
+    }
+
+    /// Silences the ringing alarm until its next scheduled time.
+    pub fn dismiss(&mut self) {
+        self.ringing = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn uk() -> PosixTz {
+        PosixTz::parse("GMT0BST,M3.5.0/1,M10.5.0/2").unwrap()
+    }
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> i64 {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap().and_hms_opt(hour, minute, 0).unwrap().and_utc().timestamp()
+    }
+
+    fn daily(hour: u32, minute: u32) -> Alarm {
+        Alarm::new(hour, minute, Repeat::Weekly(Weekdays::EVERY_DAY), Actions::new()).unwrap()
+    }
+
+    #[test]
+    fn rejects_invalid_times() {
+        assert!(Alarm::new(24, 0, Repeat::Once, Actions::new()).is_none());
+        assert!(Alarm::new(7, 60, Repeat::Once, Actions::new()).is_none());
+    }
+
+    #[test]
+    fn keeps_the_wall_clock_time_across_daylight_saving() {
+        let alarm = daily(7, 0);
+        // The clocks go forward on 31 March 2024, so 07:00 is an hour earlier in UTC from then.
+        assert_eq!(alarm.next_fire(&uk(), utc(2024, 3, 30, 12, 0)), Some(utc(2024, 3, 31, 6, 0)));
+        assert_eq!(alarm.next_fire(&uk(), utc(2024, 3, 31, 6, 0)), Some(utc(2024, 4, 1, 6, 0)));
+        // And back on 27 October.
+        assert_eq!(alarm.next_fire(&uk(), utc(2024, 10, 26, 12, 0)), Some(utc(2024, 10, 27, 7, 0)));
+    }
+
+    #[test]
+    fn fires_after_the_gap_when_the_clocks_go_forward() {
+        // 01:30 doesn't happen on 31 March 2024, the clocks go from 01:00 to 02:00.
+        let next = daily(1, 30).next_fire(&uk(), utc(2024, 3, 30, 12, 0)).unwrap();
+        assert_eq!(next, utc(2024, 3, 31, 1, 30));
+        assert_eq!(uk().to_local(next).time(), NaiveTime::from_hms_opt(2, 30, 0).unwrap());
+    }
+
+    #[test]
+    fn fires_once_when_the_clocks_go_back() {
+        // 01:30 happens twice on 27 October 2024, first in BST then in GMT.
+        let alarm = daily(1, 30);
+        let first = alarm.next_fire(&uk(), utc(2024, 10, 26, 12, 0)).unwrap();
+        assert_eq!(first, utc(2024, 10, 27, 0, 30));
+        assert_eq!(alarm.next_fire(&uk(), first), Some(utc(2024, 10, 28, 1, 30)));
+    }
+
+    #[test]
+    fn southern_hemisphere_daylight_saving() {
+        let sydney = PosixTz::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        let alarm = daily(7, 0);
+        // Daylight saving ends on 7 April 2024.
+        assert_eq!(alarm.next_fire(&sydney, utc(2024, 4, 5, 12, 0)), Some(utc(2024, 4, 5, 20, 0)));
+        assert_eq!(alarm.next_fire(&sydney, utc(2024, 4, 6, 12, 0)), Some(utc(2024, 4, 6, 21, 0)));
+    }
+
+    #[test]
+    fn only_fires_on_the_chosen_days() {
+        let alarm = Alarm::new(7, 0, Repeat::Weekly(Weekdays::WORKDAYS), Actions::new()).unwrap();
+        // 7 June 2024 is a Friday.
+        assert_eq!(alarm.next_fire(&uk(), utc(2024, 6, 7, 5, 0)), Some(utc(2024, 6, 7, 6, 0)));
+        assert_eq!(alarm.next_fire(&uk(), utc(2024, 6, 7, 6, 0)), Some(utc(2024, 6, 10, 6, 0)));
+
+        let sunday = Alarm::new(7, 0, Repeat::Weekly(Weekdays::SUNDAY), Actions::new()).unwrap();
+        // A week on when the time has passed on the day itself, 9 June is a Sunday.
+        assert_eq!(sunday.next_fire(&uk(), utc(2024, 6, 9, 8, 0)), Some(utc(2024, 6, 16, 6, 0)));
+
+        let never = Alarm::new(7, 0, Repeat::Weekly(Weekdays::NONE), Actions::new()).unwrap();
+        assert_eq!(never.next_fire(&uk(), utc(2024, 6, 7, 5, 0)), None);
+    }
+
+    #[test]
+    fn once_fires_a_single_time() {
+        let mut clock = AlarmClock::<2>::new(PosixTz::UTC);
+        clock.add(Alarm::new(7, 0, Repeat::Once, Actions::new()).unwrap()).unwrap();
+        let start = utc(2024, 6, 7, 6, 0);
+
+        // The first poll schedules it.
+        assert_eq!(clock.poll(start), None);
+        assert_eq!(clock.next(), Some((0, utc(2024, 6, 7, 7, 0))));
+        assert_eq!(clock.poll(utc(2024, 6, 7, 7, 0)), Some(AlarmEvent::Started(0)));
+        clock.dismiss();
+        assert!(!clock.alarms()[0].is_enabled());
+        assert_eq!(clock.poll(utc(2024, 6, 8, 7, 0)), None);
+        assert_eq!(clock.next(), None);
+    }
+
+    #[test]
+    fn rings_until_the_limit_and_snoozes() {
+        let mut clock = AlarmClock::<2>::new(PosixTz::UTC);
+        clock.add(daily(7, 0)).unwrap();
+        let due = utc(2024, 6, 7, 7, 0);
+        clock.poll(due - 60);
+
+        assert_eq!(clock.poll(due), Some(AlarmEvent::Started(0)));
+        assert_eq!(clock.ringing(), Some(Ringing { index: 0, since: due }));
+        assert_eq!(clock.poll(due + 599), None);
+        assert_eq!(clock.poll(due + 600), Some(AlarmEvent::TimedOut(0)));
+        assert_eq!(clock.ringing(), None);
+
+        clock.poll(due + 3600 * 24);
+        clock.snooze(due + 3600 * 24);
+        assert_eq!(clock.ringing(), None);
+        assert_eq!(clock.next(), Some((0, due + 3600 * 24 + 9 * 60)));
+        assert_eq!(clock.poll(due + 3600 * 24 + 9 * 60), Some(AlarmEvent::Started(0)));
+    }
+
+    #[test]
+    fn setting_the_clock_doesnt_fire_skipped_alarms() {
+        let mut clock = AlarmClock::<2>::new(PosixTz::UTC);
+        clock.add(daily(7, 0)).unwrap();
+        clock.poll(utc(2024, 6, 7, 6, 0));
+
+        // NTP moves the clock on past the alarm.
+        clock.clock_changed();
+        assert_eq!(clock.poll(utc(2024, 6, 7, 12, 0)), None);
+        assert_eq!(clock.next(), Some((0, utc(2024, 6, 8, 7, 0))));
+    }
+}
@@ -0,0 +1,313 @@
+//! Sunrise, sunset, twilight and moon phase.
+//!
+//! These use the usual low precision formulas (the sunrise equation, and Meeus' simplified lunar
+//! terms). Angles are worked out in `f32` with micromath, whose trigonometry is only approximate,
+//! so sun times are good to a few minutes and moon phases to a few hours, which is plenty for a
+//! clock. Only the day count is kept in `f64` as `f32` can't hold it accurately.
+
+use chrono::{NaiveDate, Timelike};
+use micromath::F32Ext;
+
+use crate::tz::PosixTz;
+
+/// Unix time of J2000.0, 2000-01-01 12:00 UTC.
+const J2000: i64 = 946_728_000;
+const DAY: i64 = 86_400;
+const SYNODIC_MONTH: f32 = 29.530_588;
+/// The tilt of the Earth's axis in degrees.
+const OBLIQUITY: f32 = 23.4397;
+
+/// Sun altitude for sunrise and sunset, allowing for refraction and the size of the sun.
+pub const SUNRISE_ALTITUDE: f32 = -0.833;
+pub const CIVIL_TWILIGHT_ALTITUDE: f32 = -6.0;
+pub const NAUTICAL_TWILIGHT_ALTITUDE: f32 = -12.0;
+pub const ASTRONOMICAL_TWILIGHT_ALTITUDE: f32 = -18.0;
+
+/// A position on the Earth in degrees, north and east positive.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub latitude: f32,
+    pub longitude: f32,
+}
+
+impl Location {
+    pub const fn new(latitude: f32, longitude: f32) -> Self {
+        Self { latitude, longitude }
+    }
+}
+
+/// When the sun crosses a given altitude on one day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sun {
+    /// Rises and sets at these times, in UTC seconds since the epoch.
+    RisesAndSets { rise: i64, set: i64 },
+    /// Polar day, the sun stays above the altitude all day.
+    AlwaysUp,
+    /// Polar night, the sun stays below the altitude all day.
+    AlwaysDown,
+}
+
+impl Sun {
+    /// Returns the rise and set times as local hours of the day (0.0 to 24.0).
+    pub fn local_hours(&self, tz: &PosixTz) -> Option<(f32, f32)> {
+        let Sun::RisesAndSets { rise, set } = *self else {
+            return None;
+        };
+        let hours = |utc: i64| {
+            let local = tz.to_local(utc);
+            local.num_seconds_from_midnight() as f32 / 3600.0
+        };
+        Some((hours(rise), hours(set)))
+    }
+}
+
+fn wrap_degrees(degrees: f64) -> f32 {
+    let degrees = (degrees % 360.0) as f32;
+    if degrees < 0.0 { degrees + 360.0 } else { degrees }
+}
+
+fn sin_deg(degrees: f32) -> f32 {
+    F32Ext::sin(degrees.to_radians())
+}
+
+fn cos_deg(degrees: f32) -> f32 {
+    F32Ext::cos(degrees.to_radians())
+}
+
+/// Works out when the sun passes `altitude` degrees on `date`, for example `SUNRISE_ALTITUDE`.
+pub fn sun_event(date: NaiveDate, location: &Location, altitude: f32) -> Sun {
+    let epoch = NaiveDate::from_ymd_opt(2000, 1, 1).unwrap_or_default();
+    let days = (date - epoch).num_days();
+
+    // Mean solar noon at this longitude, in days from J2000.
+    let noon = days as f64 - location.longitude as f64 / 360.0;
+
+    let anomaly = wrap_degrees(357.5291 + 0.985_600_28 * noon);
+    let center = 1.9148 * sin_deg(anomaly) + 0.02 * sin_deg(2.0 * anomaly) + 0.0003 * sin_deg(3.0 * anomaly);
+    let ecliptic_longitude = F32Ext::rem_euclid(anomaly + center + 180.0 + 102.9372, 360.0);
+
+    // Solar noon relative to the start of J2000 day `days`, in days.
+    let transit = -location.longitude / 360.0 + 0.0053 * sin_deg(anomaly) - 0.0069 * sin_deg(2.0 * ecliptic_longitude);
+
+    let sin_declination = sin_deg(ecliptic_longitude) * sin_deg(OBLIQUITY);
+    let cos_declination = F32Ext::sqrt(1.0 - sin_declination * sin_declination);
+    let cos_hour_angle = (sin_deg(altitude) - sin_deg(location.latitude) * sin_declination)
+        / (cos_deg(location.latitude) * cos_declination);
+
+    if cos_hour_angle > 1.0 {
+        return Sun::AlwaysDown;
+    }
+    if cos_hour_angle < -1.0 {
+        return Sun::AlwaysUp;
+    }
+
+    let hour_angle = F32Ext::acos(cos_hour_angle).to_degrees() / 360.0;
+    let at = |offset: f32| J2000 + days * DAY + ((transit + offset) * DAY as f32) as i64;
+
+    Sun::RisesAndSets {
+        rise: at(-hour_angle),
+        set: at(hour_angle),
+    }
+}
+
+/// Sunrise and sunset on `date`.
+pub fn sunrise_sunset(date: NaiveDate, location: &Location) -> Sun {
+    sun_event(date, location, SUNRISE_ALTITUDE)
+}
+
+/// The start of morning and end of evening civil twilight on `date`.
+pub fn civil_twilight(date: NaiveDate, location: &Location) -> Sun {
+    sun_event(date, location, CIVIL_TWILIGHT_ALTITUDE)
+}
+
+/// The eight traditional phases of the moon.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhaseName {
+    NewMoon,
+    WaxingCrescent,
+    FirstQuarter,
+    WaxingGibbous,
+    FullMoon,
+    WaningGibbous,
+    LastQuarter,
+    WaningCrescent,
+}
+
+impl PhaseName {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PhaseName::NewMoon => "New moon",
+            PhaseName::WaxingCrescent => "Waxing crescent",
+            PhaseName::FirstQuarter => "First quarter",
+            PhaseName::WaxingGibbous => "Waxing gibbous",
+            PhaseName::FullMoon => "Full moon",
+            PhaseName::WaningGibbous => "Waning gibbous",
+            PhaseName::LastQuarter => "Last quarter",
+            PhaseName::WaningCrescent => "Waning crescent",
+        }
+    }
+}
+
+/// The phase of the moon at one moment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MoonPhase {
+    /// How far through the lunar month, 0.0 at new moon and 0.5 at full moon.
+    pub phase: f32,
+    /// The fraction of the disc which is lit, 0.0 to 1.0.
+    pub illumination: f32,
+}
+
+impl MoonPhase {
+    /// Approximate days since the last new moon.
+    pub fn age_days(&self) -> f32 {
+        self.phase * SYNODIC_MONTH
+    }
+
+    pub fn is_waxing(&self) -> bool {
+        self.phase < 0.5
+    }
+
+    pub fn name(&self) -> PhaseName {
+        // Each named phase is centred on its eighth of the month.
+        match ((self.phase * 8.0 + 0.5) as u32) % 8 {
+            0 => PhaseName::NewMoon,
+            1 => PhaseName::WaxingCrescent,
+            2 => PhaseName::FirstQuarter,
+            3 => PhaseName::WaxingGibbous,
+            4 => PhaseName::FullMoon,
+            5 => PhaseName::WaningGibbous,
+            6 => PhaseName::LastQuarter,
+            _ => PhaseName::WaningCrescent,
+        }
+    }
+}
+
+/// Works out the phase of the moon at `utc` seconds since the epoch.
+pub fn moon_phase(utc: i64) -> MoonPhase {
+    let days = (utc - J2000) as f64 / DAY as f64;
+
+    let elongation = wrap_degrees(297.850_192 + 12.190_749_1 * days);
+    let sun_anomaly = wrap_degrees(357.529_109 + 0.985_600_28 * days);
+    let moon_anomaly = wrap_degrees(134.963_396 + 13.064_992_9 * days);
+
+    // The phase angle, 180 degrees at new moon and 0 at full moon.
+    let phase_angle = 180.0 - elongation - 6.289 * sin_deg(moon_anomaly) + 2.1 * sin_deg(sun_anomaly)
+        - 1.274 * sin_deg(2.0 * elongation - moon_anomaly)
+        - 0.658 * sin_deg(2.0 * elongation)
+        - 0.214 * sin_deg(2.0 * moon_anomaly)
+        - 0.11 * sin_deg(elongation);
+
+    MoonPhase {
+        phase: F32Ext::rem_euclid((180.0 - phase_angle) / 360.0, 1.0),
+        illumination: (1.0 + cos_deg(phase_angle)) / 2.0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LONDON: Location = Location::new(51.5074, -0.1278);
+    const SYDNEY: Location = Location::new(-33.8688, 151.2093);
+    const TROMSO: Location = Location::new(69.6492, 18.9553);
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> i64 {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap().and_hms_opt(hour, minute, 0).unwrap().and_utc().timestamp()
+    }
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    /// Checks the rise and set against published times in UTC, to within `minutes`.
+    fn assert_sun(sun: Sun, rise: i64, set: i64, minutes: i64) {
+        let Sun::RisesAndSets { rise: got_rise, set: got_set } = sun else {
+            panic!("expected the sun to rise and set, got {:?}", sun);
+        };
+        assert!((got_rise - rise).abs() <= minutes * 60, "rise {} is {}s from {}", got_rise, got_rise - rise, rise);
+        assert!((got_set - set).abs() <= minutes * 60, "set {} is {}s from {}", got_set, got_set - set, set);
+    }
+
+    /// Checks the phase at a published new, quarter or full moon, to within a few hours.
+    fn assert_phase(utc: i64, phase: f32, name: PhaseName) {
+        let moon = moon_phase(utc);
+        let hours = 4.0 / 24.0 / SYNODIC_MONTH;
+        let off = (moon.phase - phase + 0.5).rem_euclid(1.0) - 0.5;
+        assert!(off.abs() <= hours, "phase {} at {} is off by {}", moon.phase, utc, off);
+        assert_eq!(moon.name(), name);
+    }
+
+    // The reference sun times are from the US Naval Observatory tables, rounded to the minute.
+
+    #[test]
+    fn london_solstices() {
+        assert_sun(sunrise_sunset(date(2024, 6, 20), &LONDON), utc(2024, 6, 20, 3, 43), utc(2024, 6, 20, 20, 21), 3);
+        assert_sun(sunrise_sunset(date(2024, 12, 21), &LONDON), utc(2024, 12, 21, 8, 4), utc(2024, 12, 21, 15, 53), 3);
+    }
+
+    #[test]
+    fn sydney_winter() {
+        // Sydney is east of Greenwich, so sunrise falls on the previous UTC day.
+        assert_sun(sunrise_sunset(date(2024, 6, 21), &SYDNEY), utc(2024, 6, 20, 21, 0), utc(2024, 6, 21, 6, 54), 3);
+    }
+
+    #[test]
+    fn local_hours() {
+        let bst = PosixTz::parse("GMT0BST,M3.5.0/1,M10.5.0/2").unwrap();
+        let (rise, set) = sunrise_sunset(date(2024, 6, 20), &LONDON).local_hours(&bst).unwrap();
+        assert!((rise - (4.0 + 43.0 / 60.0)).abs() < 0.05, "rise {}", rise);
+        assert!((set - (21.0 + 21.0 / 60.0)).abs() < 0.05, "set {}", set);
+        assert_eq!(Sun::AlwaysUp.local_hours(&bst), None);
+    }
+
+    #[test]
+    fn polar_day_and_night() {
+        assert_eq!(sunrise_sunset(date(2024, 6, 21), &TROMSO), Sun::AlwaysUp);
+        assert_eq!(sunrise_sunset(date(2024, 12, 21), &TROMSO), Sun::AlwaysDown);
+        // The sun gets close enough to the horizon at midday for civil twilight.
+        assert!(matches!(civil_twilight(date(2024, 12, 21), &TROMSO), Sun::RisesAndSets { .. }));
+        // Antarctica has them the other way round.
+        let mcmurdo = Location::new(-77.846, 166.676);
+        assert_eq!(sunrise_sunset(date(2024, 6, 21), &mcmurdo), Sun::AlwaysDown);
+        assert_eq!(sunrise_sunset(date(2024, 12, 21), &mcmurdo), Sun::AlwaysUp);
+    }
+
+    #[test]
+    fn twilight_is_outside_sunrise_and_sunset() {
+        let (Sun::RisesAndSets { rise, set }, Sun::RisesAndSets { rise: dawn, set: dusk }) =
+            (sunrise_sunset(date(2024, 3, 20), &LONDON), civil_twilight(date(2024, 3, 20), &LONDON))
+        else {
+            panic!("the sun rises and sets in London in March");
+        };
+        assert!(dawn < rise && set < dusk);
+        assert!((rise - dawn - 33 * 60).abs() <= 3 * 60, "{}s of twilight", rise - dawn);
+    }
+
+    // The reference moon phases are from the US Naval Observatory, in UTC.
+
+    #[test]
+    fn moon_phases_in_2024() {
+        assert_phase(utc(2024, 1, 11, 11, 57), 0.0, PhaseName::NewMoon);
+        assert_phase(utc(2024, 1, 18, 3, 53), 0.25, PhaseName::FirstQuarter);
+        assert_phase(utc(2024, 1, 25, 17, 54), 0.5, PhaseName::FullMoon);
+        assert_phase(utc(2024, 2, 2, 23, 18), 0.75, PhaseName::LastQuarter);
+        assert_phase(utc(2024, 4, 8, 18, 21), 0.0, PhaseName::NewMoon);
+        assert_phase(utc(2024, 9, 18, 2, 34), 0.5, PhaseName::FullMoon);
+    }
+
+    #[test]
+    fn moon_illumination() {
+        let full = moon_phase(utc(2024, 1, 25, 17, 54));
+        assert!(full.illumination > 0.99);
+        assert!((full.age_days() - 14.77).abs() < 0.5);
+        let new = moon_phase(utc(2024, 4, 8, 18, 21));
+        assert!(new.illumination < 0.01);
+        let first_quarter = moon_phase(utc(2024, 1, 18, 3, 53));
+        assert!((first_quarter.illumination - 0.5).abs() < 0.03);
+        assert!(first_quarter.is_waxing());
+        assert!(!moon_phase(utc(2024, 2, 2, 23, 18)).is_waxing());
+        // A few days either side of full is gibbous.
+        assert_eq!(moon_phase(utc(2024, 1, 22, 0, 0)).name(), PhaseName::WaxingGibbous);
+        assert_eq!(moon_phase(utc(2024, 1, 29, 12, 0)).name(), PhaseName::WaningGibbous);
+    }
+}
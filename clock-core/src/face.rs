@@ -0,0 +1,770 @@
+//! Configurable clock faces.
+//!
+//! `ClockFace` replaces the fixed `create_face`/`draw_face` pair with a face that can have minute
+//! ticks, Arabic or Roman numerals, a date window, a 24 hour dial and a colour theme. It is generic
+//! over any `DrawTarget` and also acts as the `Underlay` for `Hands`, so the face is repaired when a
+//! hand moves off it.
+
+use core::f32::consts::PI;
+use embedded_graphics::{
+    mono_font::{
+        MonoFont, MonoTextStyle,
+        ascii::{FONT_6X10, FONT_9X15},
+    },
+    pixelcolor::{BinaryColor, Rgb565},
+    prelude::*,
+    primitives::{Arc, Circle, Line, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+};
+use micromath::F32Ext;
+
+use crate::{
+    arc_angle, day_hours_to_angle,
+    hand::{Blend, HandStyle, Hands, Underlay},
+    polar,
+};
+
+const ARABIC: [&str; 24] = [
+    "1", "2", "3", "4", "5", "6", "7", "8", "9", "10", "11", "12", "13", "14", "15", "16", "17", "18", "19",
+    "20", "21", "22", "23", "24",
+];
+
+const ROMAN: [&str; 24] = [
+    "I", "II", "III", "IV", "V", "VI", "VII", "VIII", "IX", "X", "XI", "XII", "XIII", "XIV", "XV", "XVI",
+    "XVII", "XVIII", "XIX", "XX", "XXI", "XXII", "XXIII", "XXIV",
+];
+
+/// How the hours are labelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Numerals {
+    None,
+    Arabic,
+    Roman,
+}
+
+/// How many hours one turn of the hour hand takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dial {
+    TwelveHour,
+    TwentyFourHour,
+}
+
+impl Dial {
+    pub const fn hours(self) -> u32 {
+        match self {
+            Dial::TwelveHour => 12,
+            Dial::TwentyFourHour => 24,
+        }
+    }
+}
+
+/// The colours used to draw a clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme<C> {
+    pub background: C,
+    pub rim: C,
+    pub hour_ticks: C,
+    pub minute_ticks: C,
+    pub numerals: C,
+    pub hands: C,
+    pub second_hand: C,
+    pub date_text: C,
+    pub date_background: C,
+    /// The band between sunset and sunrise on a 24 hour dial.
+    pub night: C,
+}
+
+impl<C: Copy> Theme<C> {
+    /// A theme which draws everything in one colour.
+    pub const fn plain(foreground: C, background: C) -> Self {
+        Self {
+            background,
+            rim: foreground,
+            hour_ticks: foreground,
+            minute_ticks: foreground,
+            numerals: foreground,
+            hands: foreground,
+            second_hand: foreground,
+            date_text: background,
+            date_background: foreground,
+            night: foreground,
+        }
+    }
+}
+
+impl Theme<Rgb565> {
+    /// Green on black, the original look of the clock.
+    pub const CLASSIC: Self = Self {
+        night: Rgb565::CSS_DARK_GREEN,
+        ..Self::plain(Rgb565::GREEN, Rgb565::BLACK)
+    };
+
+    /// Black on white with a red second hand.
+    pub const PAPER: Self = Self {
+        background: Rgb565::WHITE,
+        rim: Rgb565::BLACK,
+        hour_ticks: Rgb565::BLACK,
+        minute_ticks: Rgb565::CSS_GRAY,
+        numerals: Rgb565::BLACK,
+        hands: Rgb565::BLACK,
+        second_hand: Rgb565::RED,
+        date_text: Rgb565::BLACK,
+        date_background: Rgb565::CSS_LIGHT_GRAY,
+        night: Rgb565::CSS_LIGHT_STEEL_BLUE,
+    };
+
+    /// Dim red on black, for a clock in a bedroom.
+    pub const NIGHT: Self = Self {
+        background: Rgb565::BLACK,
+        rim: Rgb565::CSS_DARK_RED,
+        hour_ticks: Rgb565::CSS_DARK_RED,
+        minute_ticks: Rgb565::CSS_MAROON,
+        numerals: Rgb565::CSS_DARK_RED,
+        hands: Rgb565::CSS_DARK_RED,
+        second_hand: Rgb565::CSS_MAROON,
+        date_text: Rgb565::BLACK,
+        date_background: Rgb565::CSS_MAROON,
+        night: Rgb565::CSS_MAROON,
+    };
+
+    /// Gold on navy.
+    pub const NAVY: Self = Self {
+        background: Rgb565::CSS_NAVY,
+        rim: Rgb565::CSS_GOLD,
+        hour_ticks: Rgb565::CSS_GOLD,
+        minute_ticks: Rgb565::CSS_LIGHT_STEEL_BLUE,
+        numerals: Rgb565::WHITE,
+        hands: Rgb565::CSS_GOLD,
+        second_hand: Rgb565::CSS_ORANGE_RED,
+        date_text: Rgb565::CSS_NAVY,
+        date_background: Rgb565::CSS_GOLD,
+        night: Rgb565::CSS_MIDNIGHT_BLUE,
+    };
+}
+
+impl Theme<BinaryColor> {
+    pub const MONO: Self = Self::plain(BinaryColor::On, BinaryColor::Off);
+}
+
+/// The angle of mark `index` of `count` evenly spaced round the dial.
+fn tick_angle(index: u32, count: u32) -> f32 {
+    index as f32 / count as f32 * 2.0 * PI
+}
+
+/// A clock face and how it should be drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockFace<C> {
+    pub circle: Circle,
+    pub theme: Theme<C>,
+    pub dial: Dial,
+    pub numerals: Numerals,
+    pub minute_ticks: bool,
+    pub date_window: bool,
+    /// Width of the rim in pixels, 0 for none.
+    pub rim_width: u32,
+    /// Length of the hour ticks in pixels.
+    pub tick_length: u32,
+    date: Option<u8>,
+    /// Sunrise and sunset in local hours, for the night band.
+    daylight: Option<(f32, f32)>,
+}
+
+impl<C: PixelColor> ClockFace<C> {
+    /// A face in the given circle which looks the same as `draw_face`.
+    pub fn new(circle: Circle, theme: Theme<C>) -> Self {
+        Self {
+            circle,
+            theme,
+            dial: Dial::TwelveHour,
+            numerals: Numerals::None,
+            minute_ticks: false,
+            date_window: false,
+            rim_width: 2,
+            tick_length: 10,
+            date: None,
+            daylight: None,
+        }
+    }
+
+    /// A face centered on the target, `margin` pixels from the nearest edge.
+    pub fn fit(target: &impl DrawTarget, margin: u32, theme: Theme<C>) -> Self {
+        let bounding_box = target.bounding_box();
+        let diameter = bounding_box.size.width.min(bounding_box.size.height).saturating_sub(2 * margin);
+
+        Self::new(Circle::with_center(bounding_box.center(), diameter), theme)
+    }
+
+    pub fn with_dial(mut self, dial: Dial) -> Self {
+        self.dial = dial;
+        self
+    }
+
+    pub fn with_numerals(mut self, numerals: Numerals) -> Self {
+        self.numerals = numerals;
+        self
+    }
+
+    pub fn with_minute_ticks(mut self, minute_ticks: bool) -> Self {
+        self.minute_ticks = minute_ticks;
+        self
+    }
+
+    pub fn with_date_window(mut self, date_window: bool) -> Self {
+        self.date_window = date_window;
+        self
+    }
+
+    pub fn with_rim_width(mut self, rim_width: u32) -> Self {
+        self.rim_width = rim_width;
+        self
+    }
+
+    pub fn with_tick_length(mut self, tick_length: u32) -> Self {
+        self.tick_length = tick_length;
+        self
+    }
+
+    fn radius(&self) -> i32 {
+        self.circle.diameter as i32 / 2
+    }
+
+    fn numeral_font(&self) -> &'static MonoFont<'static> {
+        // Roman numerals and the 24 hour dial need more room than 12 Arabic numerals.
+        if self.radius() >= 90 && self.dial == Dial::TwelveHour && self.numerals == Numerals::Arabic {
+            &FONT_9X15
+        } else {
+            &FONT_6X10
+        }
+    }
+
+    /// Converts a time into the angle of the hour hand, which moves smoothly through the hour.
+    pub fn hour_angle(&self, hour: u32, minute: u32) -> f32 {
+        let hours = self.dial.hours();
+        let hour = (hour % hours) as f32 + minute as f32 / 60.0;
+
+        hour / hours as f32 * 2.0 * PI
+    }
+
+    /// Returns the area covered by the date window.
+    pub fn date_window_area(&self) -> Rectangle {
+        let font = &FONT_6X10;
+        let size = Size::new(font.character_size.width * 2 + 6, font.character_size.height + 4);
+        // Halfway between the center and the numerals on the 3 o'clock side.
+        let center = self.circle.center() + Point::new(self.radius() / 2, 0);
+
+        Rectangle::with_center(center, size)
+    }
+
+    /// Sets the day of the month shown in the date window.
+    ///
+    /// Returns true if it changed, in which case the window needs to be drawn again with
+    /// `draw_date`.
+    pub fn set_date(&mut self, day: u8) -> bool {
+        let changed = self.date != Some(day);
+        self.date = Some(day);
+        changed
+    }
+
+    /// Sets the local hours of sunrise and sunset, as given by `Sun::local_hours`, so a 24 hour
+    /// dial shades the night between them. None removes the band, for example during polar day.
+    ///
+    /// Returns true if it changed, in which case the face needs to be drawn again.
+    pub fn set_daylight(&mut self, daylight: Option<(f32, f32)>) -> bool {
+        let changed = self.daylight != daylight;
+        self.daylight = daylight;
+        changed
+    }
+
+    /// Draws the whole face, including the date window if it is enabled.
+    pub fn draw<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        self.circle
+            .into_styled(PrimitiveStyle::with_fill(self.theme.background))
+            .draw(target)?;
+
+        self.draw_night(target)?;
+
+        if self.rim_width > 0 {
+            self.circle
+                .into_styled(PrimitiveStyle::with_stroke(self.theme.rim, self.rim_width))
+                .draw(target)?;
+        }
+
+        let hours = self.dial.hours();
+
+        if self.minute_ticks {
+            let style = PrimitiveStyle::with_stroke(self.theme.minute_ticks, 1);
+            // Leave out the minutes which have an hour tick. On a 24 hour dial the hours are two and a half
+            // minutes apart, so only every fifth minute has one.
+            for minute in (0..60).filter(|m| !(m * hours).is_multiple_of(60)) {
+                let angle = tick_angle(minute, 60);
+                Line::new(polar(&self.circle, angle, -1), polar(&self.circle, angle, -4))
+                    .into_styled(style)
+                    .draw(target)?;
+            }
+        }
+
+        let tick_style = PrimitiveStyle::with_stroke(self.theme.hour_ticks, 2);
+        for hour in 0..hours {
+            let angle = tick_angle(hour, hours);
+            // On a 24 hour dial the odd hours get shorter ticks to keep it readable.
+            let length = if hours == 24 && hour % 2 == 1 {
+                self.tick_length / 2
+            } else {
+                self.tick_length
+            };
+            Line::new(polar(&self.circle, angle, 0), polar(&self.circle, angle, -(length as i32)))
+                .into_styled(tick_style)
+                .draw(target)?;
+        }
+
+        self.draw_numerals(target)?;
+
+        if self.date_window {
+            self.draw_date(target)?;
+        }
+
+        Ok(())
+    }
+
+    /// Shades the ticks from sunset round to sunrise on a 24 hour dial.
+    fn draw_night<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        let Some((rise, set)) = self.daylight else {
+            return Ok(());
+        };
+        if self.dial != Dial::TwentyFourHour {
+            return Ok(());
+        }
+
+        let night = F32Ext::rem_euclid(rise - set, 24.0);
+        Arc::with_center(
+            self.circle.center(),
+            self.circle.diameter.saturating_sub(self.tick_length),
+            arc_angle(day_hours_to_angle(set)),
+            Angle::from_radians(day_hours_to_angle(night)),
+        )
+        .into_styled(PrimitiveStyle::with_stroke(self.theme.night, self.tick_length))
+        .draw(target)
+    }
+
+    fn draw_numerals<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        let labels = match self.numerals {
+            Numerals::None => return Ok(()),
+            Numerals::Arabic => &ARABIC,
+            Numerals::Roman => &ROMAN,
+        };
+
+        let font = self.numeral_font();
+        let character_style = MonoTextStyle::new(font, self.theme.numerals);
+        let text_style = TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Middle)
+            .build();
+        let hours = self.dial.hours();
+        let inset = self.tick_length as i32 + 3 + font.character_size.height as i32 / 2;
+
+        for hour in 1..=hours {
+            let angle = tick_angle(hour, hours);
+            let label = labels[hour as usize - 1];
+
+            // Wide labels near 3 and 9 o'clock have to be moved in further to clear the ticks.
+            let width = label.len() as f32 * font.character_size.width as f32;
+            let inset = inset + (F32Ext::sin(angle).abs() * (width / 2.0 - font.character_size.height as f32 / 2.0)).max(0.0) as i32;
+
+            Text::with_text_style(label, polar(&self.circle, angle, -inset), character_style, text_style)
+                .draw(target)?;
+        }
+
+        Ok(())
+    }
+
+    /// Draws the date window with the current date, if one has been set.
+    pub fn draw_date<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        let area = self.date_window_area();
+        let style = PrimitiveStyleBuilder::new()
+            .fill_color(self.theme.date_background)
+            .stroke_color(self.theme.rim)
+            .stroke_width(1)
+            .build();
+        area.into_styled(style).draw(target)?;
+
+        if let Some(day) = self.date {
+            let digits = [b'0' + day / 10 % 10, b'0' + day % 10];
+            let text = core::str::from_utf8(&digits).unwrap_or("");
+            let text_style = TextStyleBuilder::new()
+                .alignment(Alignment::Center)
+                .baseline(Baseline::Middle)
+                .build();
+
+            Text::with_text_style(
+                text,
+                area.center() + Point::new(1, 0),
+                MonoTextStyle::new(&FONT_6X10, self.theme.date_text),
+                text_style,
+            )
+            .draw(target)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<C: Blend> ClockFace<C> {
+    /// Creates hands sized to the face in the theme colours.
+    pub fn hands(&self) -> Hands<C> {
+        let radius = self.radius();
+        let tick = self.tick_length as i32;
+
+        Hands::new(
+            HandStyle::line(self.theme.hands, -(radius * 2 / 5).max(tick + 2))
+                .with_width(5.0)
+                .with_taper(3.0)
+                .with_anti_alias(true),
+            HandStyle::line(self.theme.hands, -(radius / 5).max(tick / 2))
+                .with_width(3.0)
+                .with_taper(2.0)
+                .with_anti_alias(true),
+            HandStyle::line(self.theme.second_hand, 0)
+                .with_counterweight((radius / 8) as u32, 7)
+                .with_decoration(11, -(radius / 6))
+                .with_anti_alias(true),
+        )
+    }
+}
+
+impl<C: PixelColor> Underlay<C> for ClockFace<C> {
+    fn draw_underlay<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        self.draw(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use embedded_graphics::mock_display::MockDisplay;
+    use std::{vec, vec::Vec};
+
+    const HOUR_TICK: Rgb565 = Rgb565::RED;
+    const MINUTE_TICK: Rgb565 = Rgb565::BLUE;
+
+    /// A display big enough for a full size face, which unlike `MockDisplay` allows overdrawing.
+    struct Canvas {
+        size: Size,
+        pixels: Vec<Option<Rgb565>>,
+    }
+
+    impl Canvas {
+        fn new(size: u32) -> Self {
+            Self {
+                size: Size::new(size, size),
+                pixels: vec![None; (size * size) as usize],
+            }
+        }
+
+        fn get(&self, point: Point) -> Option<Rgb565> {
+            if !self.bounding_box().contains(point) {
+                return None;
+            }
+            self.pixels[(point.y as u32 * self.size.width + point.x as u32) as usize]
+        }
+
+        /// Whether any pixel within one of `point` has the colour.
+        fn near(&self, point: Point, color: Rgb565) -> bool {
+            (-1..=1).any(|dy| (-1..=1).any(|dx| self.get(point + Point::new(dx, dy)) == Some(color)))
+        }
+    }
+
+    impl OriginDimensions for Canvas {
+        fn size(&self) -> Size {
+            self.size
+        }
+    }
+
+    impl DrawTarget for Canvas {
+        type Color = Rgb565;
+        type Error = core::convert::Infallible;
+
+        fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+        where
+            I: IntoIterator<Item = Pixel<Self::Color>>,
+        {
+            for Pixel(point, color) in pixels {
+                if self.bounding_box().contains(point) {
+                    self.pixels[(point.y as u32 * self.size.width + point.x as u32) as usize] = Some(color);
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn draw(dial: Dial) -> (Canvas, ClockFace<Rgb565>) {
+        let theme = Theme {
+            hour_ticks: HOUR_TICK,
+            minute_ticks: MINUTE_TICK,
+            ..Theme::plain(Rgb565::WHITE, Rgb565::BLACK)
+        };
+        let mut canvas = Canvas::new(240);
+        let face = ClockFace::fit(&canvas, 10, theme).with_dial(dial).with_minute_ticks(true);
+        face.draw(&mut canvas).unwrap();
+        (canvas, face)
+    }
+
+    #[test]
+    fn ticks_line_up_on_a_12_hour_dial() {
+        let (canvas, face) = draw(Dial::TwelveHour);
+        for minute in 0..60 {
+            let at = polar(&face.circle, tick_angle(minute, 60), -2);
+            if minute % 5 == 0 {
+                assert!(canvas.near(at, HOUR_TICK), "minute {}", minute);
+                assert!(!canvas.near(at, MINUTE_TICK), "minute {}", minute);
+            }
+            else {
+                assert!(canvas.near(at, MINUTE_TICK), "minute {}", minute);
+            }
+        }
+    }
+
+    #[test]
+    fn ticks_line_up_on_a_24_hour_dial() {
+        let (canvas, face) = draw(Dial::TwentyFourHour);
+        for hour in 0..24 {
+            // The odd hours, half way between two minutes, have short ticks but no minute tick under them.
+            let at = polar(&face.circle, tick_angle(hour, 24), -3);
+            assert!(canvas.near(at, HOUR_TICK), "hour {}", hour);
+            assert!(!canvas.near(at, MINUTE_TICK), "hour {}", hour);
+        }
+        for minute in (0..60).filter(|minute| minute % 5 != 0) {
+            let at = polar(&face.circle, tick_angle(minute, 60), -2);
+            assert!(canvas.near(at, MINUTE_TICK), "minute {}", minute);
+            assert!(!canvas.near(at, HOUR_TICK), "minute {}", minute);
+        }
+    }
+
+    #[test]
+    fn hour_hand_turns_once_a_day_on_a_24_hour_dial() {
+        let face = ClockFace::new(Circle::new(Point::zero(), 100), Theme::MONO).with_dial(Dial::TwentyFourHour);
+        assert_eq!(face.hour_angle(0, 0), 0.0);
+        assert!((face.hour_angle(6, 0) - PI / 2.0).abs() < 1e-6);
+        assert!((face.hour_angle(18, 30) - tick_angle(37, 48)).abs() < 1e-6);
+        let face = face.with_dial(Dial::TwelveHour);
+        assert!((face.hour_angle(15, 0) - PI / 2.0).abs() < 1e-6);
+    }
+
+    /// A snapshot of the smallest useful face, so changes to how it is drawn show up.
+    #[test]
+    fn draws_the_plain_face() {
+        let mut display = MockDisplay::new();
+        display.set_allow_overdraw(true);
+        ClockFace::new(Circle::new(Point::zero(), 21), Theme::MONO)
+            .with_rim_width(1)
+            .with_tick_length(4)
+            .draw(&mut display)
+            .unwrap();
+        display.assert_pattern(&[
+            "       #######       ",
+            "     ##...##..###    ",
+            "    ###...##...##    ",
+            "   #..##..##..##.#   ",
+            " ##...##..##..##..#  ",
+            " ####..##....##...## ",
+            " #.###..........#### ",
+            "#....#.........###..#",
+            "#..............#....#",
+            "#####...............#",
+            "#####...........#####",
+            "#...............#####",
+            "#....#..............#",
+            "#..###.........#....#",
+            " ####..........###.# ",
+            " ##...##....##..#### ",
+            "  #..##..##..##...## ",
+            "   #.##..##..##..#   ",
+            "    ##...##...###    ",
+            "    ###..##...##     ",
+            "       #######       ",
+        ]);
+    }
+
+    /// Draws `face` on a `MockDisplay`, which is 64 pixels square.
+    fn mock(face: &ClockFace<Rgb565>) -> MockDisplay<Rgb565> {
+        let mut display = MockDisplay::new();
+        display.set_allow_overdraw(true);
+        face.draw(&mut display).unwrap();
+        display
+    }
+
+    fn pixels(display: &MockDisplay<Rgb565>, color: Rgb565) -> Vec<Point> {
+        display.bounding_box().points().filter(|p| display.get_pixel(*p) == Some(color)).collect()
+    }
+
+    fn bounds(points: &[Point]) -> Rectangle {
+        let top_left = points.iter().fold(points[0], |a, b| a.component_min(*b));
+        let bottom_right = points.iter().fold(points[0], |a, b| a.component_max(*b));
+        Rectangle::with_corners(top_left, bottom_right)
+    }
+
+    /// A small face with every part in a different colour.
+    fn small_face() -> ClockFace<Rgb565> {
+        let theme = Theme {
+            hour_ticks: HOUR_TICK,
+            minute_ticks: MINUTE_TICK,
+            numerals: Rgb565::YELLOW,
+            date_text: Rgb565::CYAN,
+            date_background: Rgb565::MAGENTA,
+            night: Rgb565::CSS_GRAY,
+            ..Theme::plain(Rgb565::WHITE, Rgb565::BLACK)
+        };
+        ClockFace::new(Circle::new(Point::new(2, 2), 60), theme).with_tick_length(6)
+    }
+
+    #[test]
+    fn numerals_clear_the_ticks() {
+        let face = small_face();
+        let ticks = pixels(&mock(&face), HOUR_TICK);
+        assert!(pixels(&mock(&face), Rgb565::YELLOW).is_empty());
+
+        for numerals in [Numerals::Arabic, Numerals::Roman] {
+            let display = mock(&face.with_numerals(numerals));
+            assert_eq!(pixels(&display, HOUR_TICK), ticks, "{:?}", numerals);
+            let drawn = pixels(&display, Rgb565::YELLOW);
+            assert!(drawn.iter().all(|p| face.circle.contains(*p)), "{:?}", numerals);
+        }
+    }
+
+    #[test]
+    fn draws_roman_and_arabic_numerals() {
+        let face = small_face();
+        // Labels at 12 and 6 o'clock sit straight in from the tick, half the font height clear of it.
+        let inset = face.tick_length as i32 + 3 + FONT_6X10.character_size.height as i32 / 2;
+        let text_style = TextStyleBuilder::new()
+            .alignment(Alignment::Center)
+            .baseline(Baseline::Middle)
+            .build();
+
+        for (numerals, twelve, six) in [(Numerals::Arabic, "12", "6"), (Numerals::Roman, "XII", "VI")] {
+            let display = mock(&face.with_numerals(numerals));
+            for (label, angle) in [(twelve, 0.0), (six, PI)] {
+                let mut expected = MockDisplay::new();
+                Text::with_text_style(label, polar(&face.circle, angle, -inset), MonoTextStyle::new(&FONT_6X10, Rgb565::YELLOW), text_style)
+                    .draw(&mut expected)
+                    .unwrap();
+                let expected = pixels(&expected, Rgb565::YELLOW);
+                assert!(!expected.is_empty());
+                assert!(expected.iter().all(|p| display.get_pixel(*p) == Some(Rgb565::YELLOW)), "{}", label);
+            }
+        }
+    }
+
+    #[test]
+    fn draws_the_date_window() {
+        let mut face = small_face().with_date_window(true);
+        let area = face.date_window_area();
+        let inside = Rectangle::new(area.top_left + Point::new(1, 1), area.size - Size::new(2, 2));
+
+        // Without a date the window is empty.
+        let display = mock(&face);
+        assert!(inside.points().all(|p| display.get_pixel(p) == Some(Rgb565::MAGENTA)));
+        assert!(pixels(&display, Rgb565::CYAN).is_empty());
+
+        assert!(face.set_date(7));
+        assert!(!face.set_date(7));
+        let seventh = pixels(&mock(&face), Rgb565::CYAN);
+        assert!(!seventh.is_empty());
+        assert!(seventh.iter().all(|p| inside.contains(*p)));
+        assert!((bounds(&seventh).center().y - area.center().y).abs() <= 1);
+
+        assert!(face.set_date(18));
+        let eighteenth = pixels(&mock(&face), Rgb565::CYAN);
+        assert_ne!(seventh, eighteenth);
+
+        // Only the window is drawn when the date changes.
+        let mut display = MockDisplay::new();
+        display.set_allow_overdraw(true);
+        face.draw_date(&mut display).unwrap();
+        assert_eq!(display.affected_area(), area);
+    }
+
+    #[test]
+    fn draws_in_the_theme_colours() {
+        let mut face = ClockFace::new(Circle::new(Point::new(2, 2), 60), Theme::NAVY)
+            .with_tick_length(6)
+            .with_numerals(Numerals::Arabic)
+            .with_minute_ticks(true)
+            .with_date_window(true);
+        face.set_date(25);
+        let display = mock(&face);
+
+        let theme = Theme::NAVY;
+        let used = [theme.background, theme.rim, theme.minute_ticks, theme.numerals, theme.date_background];
+        for color in used {
+            assert!(!pixels(&display, color).is_empty(), "{:?}", color);
+        }
+        let drawn: Vec<_> = display.bounding_box().points().filter_map(|p| display.get_pixel(p)).collect();
+        assert!(drawn.iter().all(|color| used.contains(color)));
+        assert_eq!(display.get_pixel(face.circle.center()), Some(theme.background));
+        assert_eq!(display.get_pixel(Point::new(31, 2)), Some(theme.rim));
+    }
+
+    #[test]
+    fn shades_the_night_on_a_24_hour_dial() {
+        let mut face = small_face().with_dial(Dial::TwentyFourHour);
+        // Between the hour ticks, half way along them.
+        let circle = face.circle;
+        let at = |hours: f32| polar(&circle, day_hours_to_angle(hours), -3);
+
+        assert!(face.set_daylight(Some((6.0, 18.0))));
+        assert!(!face.set_daylight(Some((6.0, 18.0))));
+        let display = mock(&face);
+        for hours in [18.5, 23.5, 0.5, 5.5] {
+            assert_eq!(display.get_pixel(at(hours)), Some(Rgb565::CSS_GRAY), "{}", hours);
+        }
+        for hours in [6.5, 12.5, 17.5] {
+            assert_eq!(display.get_pixel(at(hours)), Some(Rgb565::BLACK), "{}", hours);
+        }
+
+        // The odd hours have ticks too, which a 12 hour dial doesn't.
+        let one = polar(&face.circle, tick_angle(1, 24), -2);
+        assert!(pixels(&display, HOUR_TICK).contains(&one));
+        assert!(!pixels(&mock(&face.with_dial(Dial::TwelveHour)), HOUR_TICK).contains(&one));
+
+        // And there is no night band on a 12 hour dial.
+        assert!(pixels(&mock(&face.with_dial(Dial::TwelveHour)), Rgb565::CSS_GRAY).is_empty());
+        face.set_daylight(None);
+        assert!(pixels(&mock(&face), Rgb565::CSS_GRAY).is_empty());
+    }
+
+    #[test]
+    fn numbers_the_24_hour_dial_from_midnight() {
+        let face = small_face().with_dial(Dial::TwentyFourHour).with_numerals(Numerals::Arabic);
+        let display = mock(&face);
+        let ticks = pixels(&mock(&small_face().with_dial(Dial::TwentyFourHour)), HOUR_TICK);
+        assert_eq!(pixels(&display, HOUR_TICK), ticks);
+
+        // "24" at the top and "12" at the bottom, each two characters wide.
+        let numerals = pixels(&display, Rgb565::YELLOW);
+        let center = face.circle.center();
+        for (label, area) in [
+            ("24", bounds(&numerals.iter().copied().filter(|p| p.y < center.y - 10 && (p.x - center.x).abs() < 6).collect::<Vec<_>>())),
+            ("12", bounds(&numerals.iter().copied().filter(|p| p.y > center.y + 10 && (p.x - center.x).abs() < 6).collect::<Vec<_>>())),
+        ] {
+            assert!(area.size.width > 6 && area.size.width <= 12, "{} {:?}", label, area);
+            assert!((area.center().x - center.x).abs() <= 1, "{} {:?}", label, area);
+        }
+    }
+}
@@ -0,0 +1,388 @@
+//! Styled clock hands.
+//!
+//! Hands are rendered by working out how much of each pixel is covered by the hand shape, which gives
+//! anti-aliasing against a known background colour, variable width and tapering for free. Erasing a
+//! hand only clears the pixels it covered and then repaints the face underneath, clipped to the hand,
+//! so face markers are no longer wiped out when a hand moves over them.
+
+use embedded_graphics::{
+    draw_target::DrawTargetExt,
+    pixelcolor::{BinaryColor, Rgb565, Rgb888},
+    prelude::*,
+    primitives::{Circle, Rectangle},
+};
+use micromath::F32Ext;
+
+use crate::{draw_face, polar};
+
+/// Colours which can be mixed, used to anti-alias against a known background colour.
+pub trait Blend: PixelColor {
+    /// Mixes `self` over `background` where `alpha` runs from 0 (all background) to 255 (all `self`).
+    fn blend(self, background: Self, alpha: u8) -> Self;
+}
+
+fn mix(fg: u8, bg: u8, alpha: u8) -> u8 {
+    ((fg as u32 * alpha as u32 + bg as u32 * (255 - alpha as u32) + 127) / 255) as u8
+}
+
+impl Blend for Rgb565 {
+    fn blend(self, background: Self, alpha: u8) -> Self {
+        Rgb565::new(
+            mix(self.r(), background.r(), alpha),
+            mix(self.g(), background.g(), alpha),
+            mix(self.b(), background.b(), alpha),
+        )
+    }
+}
+
+impl Blend for Rgb888 {
+    fn blend(self, background: Self, alpha: u8) -> Self {
+        Rgb888::new(
+            mix(self.r(), background.r(), alpha),
+            mix(self.g(), background.g(), alpha),
+            mix(self.b(), background.b(), alpha),
+        )
+    }
+}
+
+impl Blend for BinaryColor {
+    fn blend(self, background: Self, alpha: u8) -> Self {
+        if alpha >= 128 { self } else { background }
+    }
+}
+
+/// Anything drawn underneath the hands, which has to be repainted when a hand is erased.
+pub trait Underlay<C: PixelColor> {
+    fn draw_underlay<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>;
+}
+
+/// The simple face drawn by `draw_face`.
+pub struct SimpleFace<C> {
+    pub clock_face: Circle,
+    pub color: C,
+}
+
+impl<C: PixelColor> Underlay<C> for SimpleFace<C> {
+    fn draw_underlay<D>(&self, target: &mut D) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        draw_face(target, &self.clock_face, self.color)
+    }
+}
+
+/// Describes how a hand is drawn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HandStyle<C> {
+    pub color: C,
+    /// The position of the tip relative to the edge of the clock face.
+    pub length_delta: i32,
+    /// Width in pixels at the center of the face.
+    pub width: f32,
+    /// Width in pixels at the tip, the hand is tapered if this is less than `width`.
+    pub tip_width: f32,
+    /// Length in pixels of the tail which extends behind the center.
+    pub tail: u32,
+    /// Diameter of the disc at the end of the tail, 0 for none.
+    pub counterweight: u32,
+    /// Diameter of the decorative ring on the hand, 0 for none.
+    pub decoration: u32,
+    /// The position of the decorative ring relative to the edge of the clock face.
+    pub decoration_delta: i32,
+    pub anti_alias: bool,
+}
+
+impl<C: Blend> HandStyle<C> {
+    /// A plain 1px hand, the same as `draw_hand`.
+    pub const fn line(color: C, length_delta: i32) -> Self {
+        Self {
+            color,
+            length_delta,
+            width: 1.0,
+            tip_width: 1.0,
+            tail: 0,
+            counterweight: 0,
+            decoration: 0,
+            decoration_delta: 0,
+            anti_alias: false,
+        }
+    }
+
+    /// Sets the width of the whole hand.
+    pub const fn with_width(mut self, width: f32) -> Self {
+        self.width = width;
+        self.tip_width = width;
+        self
+    }
+
+    /// Tapers the hand from its current width at the center to `tip_width` at the tip.
+    pub const fn with_taper(mut self, tip_width: f32) -> Self {
+        self.tip_width = tip_width;
+        self
+    }
+
+    /// Adds a tail behind the center, with a disc of the given diameter at its end.
+    pub const fn with_counterweight(mut self, tail: u32, diameter: u32) -> Self {
+        self.tail = tail;
+        self.counterweight = diameter;
+        self
+    }
+
+    /// Adds a decorative ring, like the one `draw_second_decoration` draws.
+    pub const fn with_decoration(mut self, diameter: u32, delta: i32) -> Self {
+        self.decoration = diameter;
+        self.decoration_delta = delta;
+        self
+    }
+
+    pub const fn with_anti_alias(mut self, anti_alias: bool) -> Self {
+        self.anti_alias = anti_alias;
+        self
+    }
+
+    /// Returns the area which may be touched when drawing the hand at the given angle.
+    pub fn bounding_box(&self, clock_face: &Circle, angle: f32) -> Rectangle {
+        let shape = Shape::new(self, clock_face, angle);
+        let margin = (self.width.max(self.tip_width) / 2.0) as i32 + 2;
+        let mut top_left = clock_face.center().component_min(shape.tip).component_min(shape.tail);
+        let mut bottom_right = clock_face.center().component_max(shape.tip).component_max(shape.tail);
+
+        let mut include = |center: Point, diameter: u32| {
+            let r = diameter as i32 / 2 + 1;
+            top_left = top_left.component_min(center - Point::new(r, r));
+            bottom_right = bottom_right.component_max(center + Point::new(r, r));
+        };
+        if self.counterweight > 0 {
+            include(shape.tail, self.counterweight);
+        }
+        if self.decoration > 0 {
+            include(shape.decoration, self.decoration);
+        }
+
+        Rectangle::with_corners(top_left - Point::new(margin, margin), bottom_right + Point::new(margin, margin))
+    }
+
+    /// Draws the hand, blending the edges into `bg_color` when anti-aliasing is enabled.
+    pub fn draw<D>(&self, target: &mut D, clock_face: &Circle, angle: f32, bg_color: C) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        let shape = Shape::new(self, clock_face, angle);
+        let area = self.bounding_box(clock_face, angle);
+        target.draw_iter(
+            area.points()
+                .filter_map(|p| shape.color_at(self, p, bg_color).map(|c| Pixel(p, c))),
+        )
+    }
+
+    /// Erases a hand previously drawn at `angle` and repaints the part of `underlay` it covered.
+    ///
+    /// Other hands in the same area need to be redrawn afterwards.
+    pub fn erase<D, U>(&self, target: &mut D, clock_face: &Circle, angle: f32, bg_color: C, underlay: &U) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+        U: Underlay<C>,
+    {
+        let shape = Shape::new(self, clock_face, angle);
+        let area = self.bounding_box(clock_face, angle);
+        target.draw_iter(
+            area.points()
+                .filter(|p| shape.color_at(self, *p, bg_color).is_some())
+                .map(|p| Pixel(p, bg_color)),
+        )?;
+        underlay.draw_underlay(&mut target.clipped(&area))
+    }
+}
+
+/// The hand geometry for one angle, in pixel coordinates.
+struct Shape {
+    center: (f32, f32),
+    direction: (f32, f32),
+    length: f32,
+    tip: Point,
+    tail: Point,
+    decoration: Point,
+}
+
+impl Shape {
+    fn new<C: Blend>(style: &HandStyle<C>, clock_face: &Circle, angle: f32) -> Self {
+        let center = clock_face.center();
+        let radius = clock_face.diameter as f32 / 2.0;
+        Self {
+            center: (center.x as f32, center.y as f32),
+            direction: (F32Ext::sin(angle), -F32Ext::cos(angle)),
+            length: radius + style.length_delta as f32,
+            tip: polar(clock_face, angle, style.length_delta),
+            tail: polar(clock_face, angle, -(radius as i32) - style.tail as i32),
+            decoration: polar(clock_face, angle, style.decoration_delta),
+        }
+    }
+
+    /// Returns the colour of the pixel at `p`, or None if the hand doesn't touch it.
+    fn color_at<C: Blend>(&self, style: &HandStyle<C>, p: Point, bg_color: C) -> Option<C> {
+        let coverage = |distance_inside: f32| -> u8 {
+            if style.anti_alias {
+                ((distance_inside + 0.5).clamp(0.0, 1.0) * 255.0) as u8
+            } else if distance_inside >= 0.0 {
+                255
+            } else {
+                0
+            }
+        };
+        let distance = |a: (f32, f32), b: Point| {
+            let dx = b.x as f32 - a.0;
+            let dy = b.y as f32 - a.1;
+            F32Ext::sqrt(dx * dx + dy * dy)
+        };
+
+        // The shaft is a line from the end of the tail to the tip, with the width varying along it.
+        let dx = p.x as f32 - self.center.0;
+        let dy = p.y as f32 - self.center.1;
+        let t = (dx * self.direction.0 + dy * self.direction.1).clamp(-(style.tail as f32), self.length);
+        let nearest = (self.center.0 + self.direction.0 * t, self.center.1 + self.direction.1 * t);
+        let half_width = if t <= 0.0 || self.length <= 0.0 {
+            style.width / 2.0
+        } else {
+            (style.width + (style.tip_width - style.width) * t / self.length) / 2.0
+        };
+        let mut alpha = coverage(half_width - distance(nearest, p));
+
+        if style.counterweight > 0 {
+            let tail = (self.tail.x as f32, self.tail.y as f32);
+            alpha = alpha.max(coverage(style.counterweight as f32 / 2.0 - distance(tail, p)));
+        }
+
+        let mut drawn = alpha > 0;
+        let mut color = style.color.blend(bg_color, alpha);
+
+        if style.decoration > 0 {
+            // A ring in the hand colour, filled with the background so the shaft doesn't show through.
+            let radius = style.decoration as f32 / 2.0;
+            let d = distance((self.decoration.x as f32, self.decoration.y as f32), p);
+            let fill = coverage(radius - 1.0 - d);
+            let ring = coverage(0.5 - (d - (radius - 0.5)).abs());
+            drawn |= fill > 0 || ring > 0;
+            color = bg_color.blend(color, fill);
+            color = style.color.blend(color, ring);
+        }
+
+        drawn.then_some(color)
+    }
+}
+
+/// The hour, minute and second hands of a clock, remembering where they were last drawn.
+pub struct Hands<C> {
+    pub hour: HandStyle<C>,
+    pub minute: HandStyle<C>,
+    pub second: HandStyle<C>,
+    angles: Option<[f32; 3]>,
+}
+
+impl<C: Blend> Hands<C> {
+    pub fn new(hour: HandStyle<C>, minute: HandStyle<C>, second: HandStyle<C>) -> Self {
+        Self {
+            hour,
+            minute,
+            second,
+            angles: None,
+        }
+    }
+
+    /// Moves the hands to the given angles, erasing the old hands and restoring the face underneath them.
+    ///
+    /// Nothing is drawn if none of the hands have moved, otherwise returns true.
+    pub fn update<D, U>(
+        &mut self,
+        target: &mut D,
+        clock_face: &Circle,
+        bg_color: C,
+        angles: [f32; 3],
+        underlay: &U,
+    ) -> Result<bool, D::Error>
+    where
+        D: DrawTarget<Color = C>,
+        U: Underlay<C>,
+    {
+        let styles = [self.hour, self.minute, self.second];
+
+        if let Some(previous) = self.angles {
+            if previous == angles {
+                return Ok(false);
+            }
+            for ((style, old), new) in styles.iter().zip(previous).zip(angles) {
+                if old != new {
+                    style.erase(target, clock_face, old, bg_color, underlay)?;
+                }
+            }
+        }
+
+        // Every hand is redrawn as erasing one may have damaged another.
+        for (style, angle) in styles.iter().zip(angles) {
+            style.draw(target, clock_face, angle, bg_color)?;
+        }
+        self.angles = Some(angles);
+        Ok(true)
+    }
+
+    /// Forgets where the hands were, for example after the screen has been cleared.
+    pub fn reset(&mut self) {
+        self.angles = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::f32::consts::PI;
+    use embedded_graphics::mock_display::MockDisplay;
+
+    const BACKGROUND: Rgb565 = Rgb565::BLACK;
+
+    fn face() -> SimpleFace<Rgb565> {
+        SimpleFace {
+            clock_face: Circle::new(Point::new(2, 2), 60),
+            color: Rgb565::WHITE,
+        }
+    }
+
+    /// The face on its own, with the hands drawn over it at `angles` if given.
+    fn expected(hands: &Hands<Rgb565>, angles: Option<[f32; 3]>) -> MockDisplay<Rgb565> {
+        let face = face();
+        let mut display = MockDisplay::new();
+        display.set_allow_overdraw(true);
+        display.clear(BACKGROUND).unwrap();
+        face.draw_underlay(&mut display).unwrap();
+        if let Some(angles) = angles {
+            for (style, angle) in [hands.hour, hands.minute, hands.second].iter().zip(angles) {
+                style.draw(&mut display, &face.clock_face, angle, BACKGROUND).unwrap();
+            }
+        }
+        display
+    }
+
+    #[test]
+    fn moving_a_hand_restores_the_face_under_it() {
+        let face = face();
+        let mut hands = Hands::new(
+            HandStyle::line(Rgb565::RED, -6).with_width(3.0),
+            HandStyle::line(Rgb565::GREEN, -4).with_width(2.0).with_anti_alias(true),
+            HandStyle::line(Rgb565::BLUE, -2).with_counterweight(6, 4),
+        );
+        let mut display = expected(&hands, None);
+
+        // The hour hand starts over the three o'clock tick.
+        let tick = Point::new(54, 31);
+        assert_eq!(display.get_pixel(tick), Some(Rgb565::WHITE));
+        let before = [PI / 2.0, 0.0, PI];
+        assert!(hands.update(&mut display, &face.clock_face, BACKGROUND, before, &face).unwrap());
+        assert_eq!(display.get_pixel(tick), Some(Rgb565::RED));
+        assert!(!hands.update(&mut display, &face.clock_face, BACKGROUND, before, &face).unwrap());
+
+        let after = [PI, PI / 6.0, 3.0 * PI / 2.0];
+        assert!(hands.update(&mut display, &face.clock_face, BACKGROUND, after, &face).unwrap());
+        assert_eq!(display.get_pixel(tick), Some(Rgb565::WHITE));
+        assert_eq!(display, expected(&hands, Some(after)));
+    }
+}
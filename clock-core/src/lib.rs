@@ -1,23 +1,31 @@
 #![no_std]
 
-//! # Example: Analog Clock
+//! Clock drawing and time keeping shared by the clock firmwares: the face and hands, alarms, timers, time zones,
+//! world clocks and sunrise and moon phase calculations. Nothing here touches the hardware, so it builds and is
+//! tested on the host.
 //!
-//! ![Screenshot of clock example]( data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAAfwAAAH9AQMAAADFwFz1AAAABlBMVEUAAAD///+l2Z/dAAAFM0lEQVR42u3dYWrjMBAF4Afz1zBX8QEEc/WBvdAcQKBNussK3EbVpDNSt+SRYMWWPrBsybQJCK+88sp/EWm32BagN+/EcoBuLRUE8K2wA6B7c9yBN2I9QK3+2eItrS0HWv0L9c9LgV6f+p6lADdcAWq6AuiVO9DRhYBYty571wBc8RGApquApkM4H+A6lvOBpkM6H+A6tvOBpkM8H6A61BcAosMetnSA6olBzpYOsI0B0Wyg4cAgSjUZIPsEgCQDoqCRD7DlAvX2Hlyo414lFSD7HJAsoI/lc3An9xGdBFRMpCYCZDOAJAKsMwBrHiCYCVka0I+MI0GAvw97vSxAMBeyLKBiMjUJIJsFJAjwX8VeMwcQzIYsB6iYTk0ByOYBCQL8V7HXzQAE8yHLAAocSQHMA5QEgNQDcALAPkDjAYEnZPFAhSs1HjAfUMIBUh/A4QB7AY0GBL6QRQMFzoQD5gVKMEBuQKIB9QIcDDC8IY0FxA9YLFDgTjBQ/UCNBcwPlFCA1A9wKMBPAE1DgeI/AwoFxN+LJ1kkUHD655NQoLrntAOokYAB6h9NgQAZnohEAvoMwFHA+9HcHuUCaCCAZwAKBOQ5wOKAcgGU7q/WLoULgB8FXMZSlVqkisq1cAHKDwJIrzdSlXZvdysU6YUrwD8ZqHR/3VKpKeRvgVoawHgOIE0DfrX7S6X35q/bpkoaIM8CFgWUy4G3VnegSr21fSt8BOAnA2+75O9cet9WQG7lNMCeBUoW0B7kBwOkzwIcB/iSD+h2oKwGnv8XMWkSgHM7wNsB2Fqg4OlzsDQAuh0o2wHS3QDO7QCvBGxw7JOUTODYDpDuBlC2A6S7AZzbAd4OwBYB/XL5xxMnA6S7AZTtAG0HYNsB3g7AWDcDR6ubAfoGQFsAtGbPAdLal4Fv0gcjAK1+EfgGN9IXgcK6GVBsBhi7gXM7oDdjK8DYDZxrANioD8dASQYYO4F/bY+NAOlu4MBu4NwNkG4FekvKBUZ/WtkEYJkAYTdwbAesz6x7AMJu4FgJ8INDnwOkeQBhN1B68dwD6FKAdNCHQ4CRBpTtgO4G+DKws4H3T9dzEihpgO4GGKuBMujDIWBZgF6G9nKAsRyQQR+OAAoD+DJXTwOaAxxYCPTCaBLVwYyWAZDuBg5sAGDosXmgpACELUAv4XB8fWwpgO0B5NKHcwAFAtz70ANoIPCvaA6AA4E+O3q+QWfEAf2CeAAJBPAUUCKBCqgPOIAaCRQwfAApLBIQnHD+iOCkUIBJvQCThgIVXgAtFCD1A4xIYDyaPtZLLFD9QB0CbqD4AYsFxA1QMMB+QGOB/nH26coIBswLSDAAN1CigeIFLBoQJ0DhAKvv6coaDZAXQDQA8wElHqg+oMYD4gLI4gF2/daTNR4gH4B4AOYBSgZQPIBlAOIAKALw3En6vm4GQDYPCDIA1Hmg5gAyDZDlAKyzAGsOQDb7fBfkAKizQM0CZBIgywJY5wDWLIBsDhBkAZCp5ztZHsD6ALZxLT/g7UU9LicaALiGE4EulfIAUQD6wVXUfj3ZMgGy2xuPAep9mASgfg7UXEAUxwCAgi0XIMM5Ak4IcgE0nKNb+aSaDbDpaDgfotkAVR1NaUdDBOBfWkj7GeYDVEdAQz6Apo+frlxTgUGls+OpwONaZ7dXAFzfA51eAfR6HehyCOBfdu/o8BIAYleg710DUNP3ADesAnrlDnR0EYBWL0Dfkw90oQPU24cA/uVYqTV8Ffj/1rT9Buv6foO1jb/D+s6vvPKKI78B89G0YRkxkl8AAAAASUVORK5CYII=)
-//!
-//! This example shows some more advanced usage of Embedded Graphics. It draws a round clock face
-//! with hour, minute and second hands. A digital clock is drawn in the middle of the clock. The
-//! whole thing is updated with your computer's local time every 50ms.
+//! The helpers below started out as the embedded-graphics analog clock example. They draw the basic face, hands
+//! and digital readout.
 
 // use chrono::{Local, Timelike};
 use core::f32::consts::PI;
 use embedded_graphics::{
     mono_font::{MonoTextStyle, ascii::FONT_9X15},
-    pixelcolor::{BinaryColor, Rgb565},
     prelude::*,
     primitives::{Circle, Line, PrimitiveStyle, PrimitiveStyleBuilder, Rectangle},
     text::Text,
 };
+// Float functions are called through `F32Ext` rather than as methods, as std's methods take precedence when
+// the tests link std and the tests would no longer check the micromath results the firmware gets.
 use micromath::F32Ext;
+
+pub mod alarm;
+pub mod astro;
+pub mod face;
+pub mod hand;
+pub mod timer;
+pub mod tz;
+pub mod world;
 // // use embedded_graphics_simulator::{
 // //     OutputSettingsBuilder, SimulatorDisplay, SimulatorEvent, Window,
 // // };
@@ -36,8 +44,8 @@ pub fn polar(circle: &Circle, angle: f32, radius_delta: i32) -> Point {
 
     circle.center()
         + Point::new(
-            (angle.sin() * radius) as i32,
-            -(angle.cos() * radius) as i32,
+            (F32Ext::sin(angle) * radius) as i32,
+            -(F32Ext::cos(angle) * radius) as i32,
         )
 }
 
@@ -54,6 +62,17 @@ pub fn sexagesimal_to_angle(value: u32) -> f32 {
     (value as f32 / 60.0) * 2.0 * PI
 }
 
+/// Converts a time of day in hours (0.0 to 24.0) into an angle in radians on a 24 hour dial.
+pub fn day_hours_to_angle(hours: f32) -> f32 {
+    (hours / 24.0) * 2.0 * PI
+}
+
+/// Converts an angle relative to the 12 o'clock position, as used by `polar`, into an
+/// embedded-graphics `Angle`, which is measured from 3 o'clock.
+pub fn arc_angle(angle: f32) -> Angle {
+    Angle::from_radians(angle - PI / 2.0)
+}
+
 /// Creates a centered circle for the clock face.
 pub fn create_face(target: &impl DrawTarget) -> Circle {
     // The draw target bounding box can be used to determine the size of the display.
@@ -152,7 +171,7 @@ where
 {
     // Create a styled text object for the time text.
     let mut text = Text::new(
-        &time_str,
+        time_str,
         Point::zero(),
         MonoTextStyle::new(&FONT_9X15, bg_color),
     );
@@ -0,0 +1,520 @@
+//! Countdown timer and stopwatch.
+//!
+//! All times are milliseconds from a monotonic clock, such as `Rtc::current_time_us() / 1000`, and
+//! nothing here touches the hardware, so the apps only have to feed in the time and button presses
+//! and draw the result.
+
+use core::f32::consts::PI;
+use core::fmt::Write;
+use embedded_graphics::{
+    prelude::*,
+    primitives::{Arc, Circle, PrimitiveStyle},
+};
+
+/// Formats a duration as `MM:SS.t`, or `H:MM:SS` from an hour up.
+pub fn format_duration<const N: usize>(ms: u64, out: &mut heapless::String<N>) -> core::fmt::Result {
+    out.clear();
+    let tenths = ms / 100 % 10;
+    let seconds = ms / 1000 % 60;
+    let minutes = ms / 60_000 % 60;
+    let hours = ms / 3_600_000;
+
+    if hours > 0 {
+        write!(out, "{}:{:02}:{:02}", hours, minutes, seconds)
+    } else {
+        write!(out, "{:02}:{:02}.{}", minutes, seconds, tenths)
+    }
+}
+
+/// A stopwatch which remembers up to `LAPS` lap times.
+#[derive(Debug, Clone, Default)]
+pub struct Stopwatch<const LAPS: usize> {
+    /// Time run up to the last stop.
+    accumulated: u64,
+    started: Option<u64>,
+    /// The elapsed time at each lap.
+    laps: heapless::Vec<u64, LAPS>,
+}
+
+impl<const LAPS: usize> Stopwatch<LAPS> {
+    pub fn new() -> Self {
+        Self {
+            accumulated: 0,
+            started: None,
+            laps: heapless::Vec::new(),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.started.is_some()
+    }
+
+    /// True once the stopwatch has been started, until it is reset.
+    pub fn is_touched(&self) -> bool {
+        self.is_running() || self.accumulated > 0
+    }
+
+    pub fn elapsed(&self, now: u64) -> u64 {
+        self.accumulated + self.started.map_or(0, |started| now.saturating_sub(started))
+    }
+
+    pub fn start(&mut self, now: u64) {
+        if self.started.is_none() {
+            self.started = Some(now);
+        }
+    }
+
+    pub fn stop(&mut self, now: u64) {
+        self.accumulated = self.elapsed(now);
+        self.started = None;
+    }
+
+    pub fn toggle(&mut self, now: u64) {
+        if self.is_running() {
+            self.stop(now);
+        } else {
+            self.start(now);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.accumulated = 0;
+        self.started = None;
+        self.laps.clear();
+    }
+
+    /// Records a lap and returns its length. When the lap list is full the oldest lap is dropped.
+    pub fn lap(&mut self, now: u64) -> Option<u64> {
+        if !self.is_running() || LAPS == 0 {
+            return None;
+        }
+        let elapsed = self.elapsed(now);
+        let previous = self.laps.last().copied().unwrap_or(0);
+        if self.laps.is_full() {
+            self.laps.remove(0);
+        }
+        let _ = self.laps.push(elapsed);
+        Some(elapsed - previous)
+    }
+
+    /// The elapsed time at each recorded lap, oldest first.
+    pub fn laps(&self) -> &[u64] {
+        &self.laps
+    }
+
+    /// The length of the most recent lap.
+    pub fn last_lap(&self) -> Option<u64> {
+        let (last, rest) = self.laps.split_last()?;
+        Some(last - rest.last().copied().unwrap_or(0))
+    }
+}
+
+/// A countdown timer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Countdown {
+    duration: u64,
+    accumulated: u64,
+    started: Option<u64>,
+    expired: bool,
+}
+
+impl Countdown {
+    pub const fn new(duration: u64) -> Self {
+        Self {
+            duration,
+            accumulated: 0,
+            started: None,
+            expired: false,
+        }
+    }
+
+    pub fn duration(&self) -> u64 {
+        self.duration
+    }
+
+    /// Changes the duration and resets the timer.
+    pub fn set_duration(&mut self, duration: u64) {
+        *self = Self::new(duration);
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.started.is_some()
+    }
+
+    pub fn is_touched(&self) -> bool {
+        self.is_running() || self.accumulated > 0
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expired
+    }
+
+    fn elapsed(&self, now: u64) -> u64 {
+        let elapsed = self.accumulated + self.started.map_or(0, |started| now.saturating_sub(started));
+        elapsed.min(self.duration)
+    }
+
+    pub fn remaining(&self, now: u64) -> u64 {
+        self.duration - self.elapsed(now)
+    }
+
+    /// How far through the countdown it is, from 0.0 to 1.0.
+    pub fn progress(&self, now: u64) -> f32 {
+        if self.duration == 0 {
+            1.0
+        } else {
+            self.elapsed(now) as f32 / self.duration as f32
+        }
+    }
+
+    /// Starts the timer, unless it has already run out.
+    pub fn start(&mut self, now: u64) {
+        if self.started.is_none() && !self.expired {
+            self.started = Some(now);
+        }
+    }
+
+    pub fn stop(&mut self, now: u64) {
+        self.accumulated = self.elapsed(now);
+        self.started = None;
+    }
+
+    pub fn toggle(&mut self, now: u64) {
+        if self.is_running() {
+            self.stop(now);
+        } else {
+            self.start(now);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.duration);
+    }
+
+    /// Returns true once, the first time it is called after the timer runs out.
+    pub fn poll(&mut self, now: u64) -> bool {
+        if self.is_running() && self.elapsed(now) >= self.duration {
+            self.stop(now);
+            self.expired = true;
+            return true;
+        }
+        false
+    }
+}
+
+/// A button press, as reported by `PressDetector`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Press {
+    Short,
+    Long,
+}
+
+/// Turns the raw state of a button into short and long presses.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PressDetector {
+    pressed_since: Option<u64>,
+    long_sent: bool,
+}
+
+impl PressDetector {
+    /// Presses shorter than this are treated as contact bounce.
+    pub const DEBOUNCE_MS: u64 = 30;
+    pub const LONG_PRESS_MS: u64 = 1000;
+
+    pub const fn new() -> Self {
+        Self {
+            pressed_since: None,
+            long_sent: false,
+        }
+    }
+
+    /// Call this regularly with whether the button is down.
+    ///
+    /// A long press is reported as soon as it has been held long enough, a short press when the
+    /// button is released.
+    pub fn update(&mut self, pressed: bool, now: u64) -> Option<Press> {
+        match (pressed, self.pressed_since) {
+            (true, None) => {
+                self.pressed_since = Some(now);
+                self.long_sent = false;
+                None
+            }
+            (true, Some(since)) if !self.long_sent && now.saturating_sub(since) >= Self::LONG_PRESS_MS => {
+                self.long_sent = true;
+                Some(Press::Long)
+            }
+            (false, Some(since)) => {
+                self.pressed_since = None;
+                (!self.long_sent && now.saturating_sub(since) >= Self::DEBOUNCE_MS).then_some(Press::Short)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// What the clock is showing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Clock,
+    Countdown,
+    Stopwatch,
+    World,
+}
+
+impl Mode {
+    pub fn next(self) -> Self {
+        match self {
+            Mode::Clock => Mode::Countdown,
+            Mode::Countdown => Mode::Stopwatch,
+            Mode::Stopwatch => Mode::World,
+            Mode::World => Mode::Clock,
+        }
+    }
+}
+
+/// The countdown and stopwatch, and which mode is being shown, driven by a single button.
+///
+/// A short press starts and stops the countdown, and starts the stopwatch or records a lap. A
+/// long press stops a running timer, resets a stopped one, or moves on to the next mode.
+#[derive(Debug, Clone)]
+pub struct Timers<const LAPS: usize> {
+    pub mode: Mode,
+    pub countdown: Countdown,
+    pub stopwatch: Stopwatch<LAPS>,
+}
+
+impl<const LAPS: usize> Timers<LAPS> {
+    pub fn new(countdown: u64) -> Self {
+        Self {
+            mode: Mode::Clock,
+            countdown: Countdown::new(countdown),
+            stopwatch: Stopwatch::new(),
+        }
+    }
+
+    /// Acts on a button press, returning true if the mode changed.
+    pub fn handle(&mut self, press: Press, now: u64) -> bool {
+        match (self.mode, press) {
+            (Mode::Clock | Mode::World, Press::Short) => {}
+            (Mode::Countdown, Press::Short) => self.countdown.toggle(now),
+            (Mode::Stopwatch, Press::Short) => {
+                if self.stopwatch.is_running() {
+                    self.stopwatch.lap(now);
+                } else {
+                    self.stopwatch.start(now);
+                }
+            }
+            (Mode::Countdown, Press::Long) if self.countdown.is_running() => self.countdown.stop(now),
+            (Mode::Countdown, Press::Long) if self.countdown.is_touched() || self.countdown.is_expired() => {
+                self.countdown.reset()
+            }
+            (Mode::Stopwatch, Press::Long) if self.stopwatch.is_running() => self.stopwatch.stop(now),
+            (Mode::Stopwatch, Press::Long) if self.stopwatch.is_touched() => self.stopwatch.reset(),
+            (_, Press::Long) => {
+                self.mode = self.mode.next();
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Checks whether the countdown has run out, returning true once when it does.
+    pub fn poll(&mut self, now: u64) -> bool {
+        self.countdown.poll(now)
+    }
+}
+
+/// Draws a ring inside the clock face, filled clockwise from 12 o'clock by `progress` (0.0 to
+/// 1.0), with the rest drawn in `track_color`.
+pub fn draw_progress_ring<D, C>(
+    target: &mut D,
+    clock_face: &Circle,
+    inset: u32,
+    width: u32,
+    progress: f32,
+    color: C,
+    track_color: C,
+) -> Result<(), D::Error>
+where
+    C: PixelColor,
+    D: DrawTarget<Color = C>,
+{
+    let diameter = clock_face.diameter.saturating_sub(2 * inset + width);
+    let sweep = progress.clamp(0.0, 1.0) * 2.0 * PI;
+    // embedded-graphics measures angles clockwise from 3 o'clock.
+    let start = -PI / 2.0;
+
+    if sweep < 2.0 * PI {
+        Arc::with_center(clock_face.center(), diameter, Angle::from_radians(start + sweep), Angle::from_radians(2.0 * PI - sweep))
+            .into_styled(PrimitiveStyle::with_stroke(track_color, width))
+            .draw(target)?;
+    }
+    if sweep > 0.0 {
+        Arc::with_center(clock_face.center(), diameter, Angle::from_radians(start), Angle::from_radians(sweep))
+            .into_styled(PrimitiveStyle::with_stroke(color, width))
+            .draw(target)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_durations() {
+        let mut out = heapless::String::<16>::new();
+        format_duration(0, &mut out).unwrap();
+        assert_eq!(out, "00:00.0");
+        format_duration(61_250, &mut out).unwrap();
+        assert_eq!(out, "01:01.2");
+        format_duration(3_599_999, &mut out).unwrap();
+        assert_eq!(out, "59:59.9");
+        format_duration(3_600_000 + 2 * 60_000 + 3000, &mut out).unwrap();
+        assert_eq!(out, "1:02:03");
+    }
+
+    #[test]
+    fn stopwatch_only_counts_while_running() {
+        let mut stopwatch = Stopwatch::<4>::new();
+        assert!(!stopwatch.is_touched());
+        stopwatch.start(1000);
+        stopwatch.start(1500);
+        assert_eq!(stopwatch.elapsed(1700), 700);
+        stopwatch.stop(2000);
+        assert_eq!(stopwatch.elapsed(9000), 1000);
+        assert!(stopwatch.is_touched());
+        stopwatch.toggle(10_000);
+        assert_eq!(stopwatch.elapsed(10_500), 1500);
+        assert_eq!(stopwatch.lap(10_500), Some(1500));
+        stopwatch.reset();
+        assert!(!stopwatch.is_touched());
+        assert!(stopwatch.laps().is_empty());
+        assert_eq!(stopwatch.lap(11_000), None);
+    }
+
+    #[test]
+    fn laps_drop_the_oldest_when_full() {
+        let mut stopwatch = Stopwatch::<3>::new();
+        stopwatch.start(0);
+        for (now, length) in [(100, 100), (300, 200), (600, 300), (1000, 400), (1500, 500)] {
+            assert_eq!(stopwatch.lap(now), Some(length));
+            assert_eq!(stopwatch.last_lap(), Some(length));
+        }
+        assert_eq!(stopwatch.laps(), &[600, 1000, 1500]);
+    }
+
+    #[test]
+    fn no_laps_without_room() {
+        let mut stopwatch = Stopwatch::<0>::new();
+        stopwatch.start(0);
+        assert_eq!(stopwatch.lap(100), None);
+        assert_eq!(stopwatch.last_lap(), None);
+    }
+
+    #[test]
+    fn countdown_expires_once() {
+        let mut countdown = Countdown::new(5000);
+        countdown.start(1000);
+        assert!(!countdown.poll(5999));
+        assert_eq!(countdown.remaining(5000), 1000);
+        assert!(countdown.poll(6000));
+        assert!(countdown.is_expired());
+        assert!(!countdown.is_running());
+        assert!(!countdown.poll(6001));
+        assert!(!countdown.poll(60_000));
+        assert_eq!(countdown.remaining(60_000), 0);
+        assert_eq!(countdown.progress(60_000), 1.0);
+
+        // It can't be started again until it is reset.
+        countdown.start(70_000);
+        assert!(!countdown.is_running());
+        countdown.reset();
+        assert!(!countdown.is_expired());
+        assert_eq!(countdown.remaining(70_000), 5000);
+    }
+
+    #[test]
+    fn countdown_pauses() {
+        let mut countdown = Countdown::new(4000);
+        countdown.toggle(0);
+        countdown.toggle(1000);
+        assert_eq!(countdown.remaining(50_000), 3000);
+        assert_eq!(countdown.progress(50_000), 0.25);
+        assert!(!countdown.poll(50_000));
+        countdown.toggle(50_000);
+        assert!(countdown.poll(53_000));
+        assert_eq!(Countdown::new(0).progress(0), 1.0);
+    }
+
+    #[test]
+    fn short_press_is_debounced() {
+        let mut presses = PressDetector::new();
+        assert_eq!(presses.update(true, 1000), None);
+        assert_eq!(presses.update(false, 1000 + PressDetector::DEBOUNCE_MS - 1), None);
+        assert_eq!(presses.update(true, 2000), None);
+        assert_eq!(presses.update(true, 2010), None);
+        assert_eq!(presses.update(false, 2000 + PressDetector::DEBOUNCE_MS), Some(Press::Short));
+        assert_eq!(presses.update(false, 3000), None);
+    }
+
+    #[test]
+    fn long_press_is_reported_once_while_held() {
+        let mut presses = PressDetector::new();
+        presses.update(true, 0);
+        assert_eq!(presses.update(true, PressDetector::LONG_PRESS_MS - 1), None);
+        assert_eq!(presses.update(true, PressDetector::LONG_PRESS_MS), Some(Press::Long));
+        assert_eq!(presses.update(true, 5000), None);
+        // Letting go after a long press isn't a short press as well.
+        assert_eq!(presses.update(false, 5100), None);
+    }
+
+    #[test]
+    fn long_press_cycles_the_modes() {
+        let mut timers = Timers::<4>::new(60_000);
+        let mut modes = [Mode::Clock; 5];
+        for mode in modes.iter_mut().skip(1) {
+            assert!(timers.handle(Press::Long, 0));
+            *mode = timers.mode;
+        }
+        assert_eq!(modes, [Mode::Clock, Mode::Countdown, Mode::Stopwatch, Mode::World, Mode::Clock]);
+        assert!(!timers.handle(Press::Short, 0));
+        assert_eq!(timers.mode, Mode::Clock);
+    }
+
+    #[test]
+    fn long_press_stops_then_resets_before_moving_on() {
+        let mut timers = Timers::<4>::new(60_000);
+        timers.mode = Mode::Countdown;
+        timers.handle(Press::Short, 0);
+        assert!(timers.countdown.is_running());
+        assert!(!timers.handle(Press::Long, 1000));
+        assert!(!timers.countdown.is_running());
+        assert!(!timers.handle(Press::Long, 2000));
+        assert!(!timers.countdown.is_touched());
+        assert!(timers.handle(Press::Long, 3000));
+        assert_eq!(timers.mode, Mode::Stopwatch);
+
+        timers.handle(Press::Short, 0);
+        timers.handle(Press::Short, 250);
+        assert_eq!(timers.stopwatch.laps(), &[250]);
+        assert!(!timers.handle(Press::Long, 500));
+        assert!(!timers.handle(Press::Long, 600));
+        assert!(!timers.stopwatch.is_touched());
+        assert!(timers.handle(Press::Long, 700));
+        assert_eq!(timers.mode, Mode::World);
+    }
+
+    #[test]
+    fn expired_countdown_is_reset_by_a_long_press() {
+        let mut timers = Timers::<4>::new(1000);
+        timers.mode = Mode::Countdown;
+        timers.handle(Press::Short, 0);
+        assert!(timers.poll(1000));
+        assert!(!timers.poll(1100));
+        assert!(!timers.handle(Press::Long, 2000));
+        assert!(!timers.countdown.is_expired());
+        assert_eq!(timers.mode, Mode::Countdown);
+    }
+}
@@ -0,0 +1,448 @@
+//! Local time from POSIX TZ strings.
+//!
+//! There is no operating system to look up time zones on bare metal, so local time is worked out
+//! from a POSIX TZ string such as `GMT0BST,M3.5.0/1,M10.5.0`, the same strings `web-idf-esp` hands
+//! to ESP-IDF. Daylight saving rules can be given in any of the POSIX forms, `Jn`, `n` or `Mm.w.d`,
+//! each with an optional `/time`.
+
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime};
+
+/// The day of a daylight saving transition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Day {
+    /// `Jn`, 1 to 365 without counting 29 February, so it is the same date every year.
+    Julian(u32),
+    /// `n`, 0 to 365 counting 29 February.
+    Ordinal(u32),
+    /// `Mm.w.d`, where week 5 means the last one in the month and weekday 0 is Sunday.
+    MonthWeek { month: u32, week: u32, weekday: u32 },
+}
+
+/// A daylight saving transition, for example `M3.5.0/1` in the TZ string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Rule {
+    day: Day,
+    /// Seconds after midnight, local time before the transition.
+    time: i32,
+}
+
+impl Rule {
+    /// The local date and time of the transition in the given year.
+    fn local(&self, year: i32) -> Option<NaiveDateTime> {
+        let date = match self.day {
+            Day::Julian(day) => {
+                let leap = NaiveDate::from_ymd_opt(year, 2, 29).is_some();
+                NaiveDate::from_yo_opt(year, if leap && day >= 60 { day + 1 } else { day })?
+            }
+            Day::Ordinal(day) => NaiveDate::from_yo_opt(year, day + 1)?,
+            Day::MonthWeek { month, week, weekday } => {
+                let first = NaiveDate::from_ymd_opt(year, month, 1)?;
+                let first_weekday = first.weekday().num_days_from_sunday();
+                let mut day = 1 + (weekday + 7 - first_weekday) % 7 + (week - 1) * 7;
+                while NaiveDate::from_ymd_opt(year, month, day).is_none() {
+                    day -= 7;
+                }
+                NaiveDate::from_ymd_opt(year, month, day)?
+            }
+        };
+
+        Some(date.and_time(NaiveTime::MIN) + chrono::Duration::seconds(self.time as i64))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Dst {
+    offset: i32,
+    start: Rule,
+    end: Rule,
+}
+
+/// A time zone described by a POSIX TZ string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PosixTz {
+    /// Seconds east of UTC outside daylight saving.
+    std_offset: i32,
+    dst: Option<Dst>,
+}
+
+impl PosixTz {
+    pub const UTC: Self = Self {
+        std_offset: 0,
+        dst: None,
+    };
+
+    /// Parses a TZ string, returning None if it isn't understood.
+    pub fn parse(tz: &str) -> Option<Self> {
+        let mut parser = Parser { rest: tz.as_bytes() };
+
+        parser.name()?;
+        // POSIX offsets are the time to add to get to UTC, so west is positive.
+        let std_offset = -parser.offset()?;
+
+        if parser.rest.is_empty() {
+            return Some(Self { std_offset, dst: None });
+        }
+
+        parser.name()?;
+        let offset = if parser.peek().is_some_and(|c| c != b',') {
+            -parser.offset()?
+        } else {
+            std_offset + 3600
+        };
+
+        // The US rules are the POSIX default when a DST name is given without rules.
+        let (start, end) = if parser.rest.is_empty() {
+            (
+                Rule { day: Day::MonthWeek { month: 3, week: 2, weekday: 0 }, time: 7200 },
+                Rule { day: Day::MonthWeek { month: 11, week: 1, weekday: 0 }, time: 7200 },
+            )
+        } else {
+            parser.expect(b',')?;
+            let start = parser.rule()?;
+            parser.expect(b',')?;
+            let end = parser.rule()?;
+            (start, end)
+        };
+
+        if !parser.rest.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            std_offset,
+            dst: Some(Dst { offset, start, end }),
+        })
+    }
+
+    /// Returns the offset from UTC in seconds at the given UTC time.
+    pub fn offset_at(&self, utc: i64) -> i32 {
+        if self.is_dst(utc) {
+            self.dst.map_or(self.std_offset, |dst| dst.offset)
+        } else {
+            self.std_offset
+        }
+    }
+
+    /// Returns true if daylight saving is in force at the given UTC time.
+    pub fn is_dst(&self, utc: i64) -> bool {
+        let Some(dst) = self.dst else {
+            return false;
+        };
+        let year = self.local_with_offset(utc, self.std_offset).year();
+        let (Some(start), Some(end)) = (dst.start.local(year), dst.end.local(year)) else {
+            return false;
+        };
+
+        // The start is given in standard time and the end in daylight saving time.
+        let start = start.and_utc().timestamp() - self.std_offset as i64;
+        let end = end.and_utc().timestamp() - dst.offset as i64;
+
+        if start < end {
+            (start..end).contains(&utc)
+        } else {
+            // Southern hemisphere, daylight saving runs over the new year.
+            utc < end || utc >= start
+        }
+    }
+
+    fn local_with_offset(&self, utc: i64, offset: i32) -> NaiveDateTime {
+        DateTime::from_timestamp(utc + offset as i64, 0)
+            .map(|dt| dt.naive_utc())
+            .unwrap_or_default()
+    }
+
+    /// Converts a UTC time in seconds since the epoch to local time.
+    pub fn to_local(&self, utc: i64) -> NaiveDateTime {
+        self.local_with_offset(utc, self.offset_at(utc))
+    }
+
+    /// Converts a local time to UTC seconds since the epoch.
+    ///
+    /// A time which happens twice when the clocks go back gives the first one. A time which is
+    /// skipped when the clocks go forward is moved forward by the length of the gap.
+    pub fn to_utc(&self, local: NaiveDateTime) -> i64 {
+        let local = local.and_utc().timestamp();
+        let std = local - self.std_offset as i64;
+        let Some(dst) = self.dst else {
+            return std;
+        };
+        let daylight = local - dst.offset as i64;
+
+        match (self.offset_at(std) == self.std_offset, self.offset_at(daylight) == dst.offset) {
+            (true, true) => std.min(daylight),
+            (false, true) => daylight,
+            // In the gap neither is right, standard time lands after the transition.
+            _ => std,
+        }
+    }
+}
+
+struct Parser<'a> {
+    rest: &'a [u8],
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<u8> {
+        self.rest.first().copied()
+    }
+
+    fn next(&mut self) -> Option<u8> {
+        let (first, rest) = self.rest.split_first()?;
+        self.rest = rest;
+        Some(*first)
+    }
+
+    fn expect(&mut self, c: u8) -> Option<()> {
+        (self.next()? == c).then_some(())
+    }
+
+    /// Skips a zone name, either letters or anything in angle brackets.
+    fn name(&mut self) -> Option<()> {
+        if self.peek() == Some(b'<') {
+            while self.next()? != b'>' {}
+            return Some(());
+        }
+        let len = self.rest.iter().take_while(|c| c.is_ascii_alphabetic()).count();
+        if len < 3 {
+            return None;
+        }
+        self.rest = &self.rest[len..];
+        Some(())
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let len = self.rest.iter().take_while(|c| c.is_ascii_digit()).count();
+        if len == 0 {
+            return None;
+        }
+        let value = self.rest[..len].iter().fold(0u32, |n, c| n * 10 + (c - b'0') as u32);
+        self.rest = &self.rest[len..];
+        Some(value)
+    }
+
+    /// Parses `[+-]hh[:mm[:ss]]` into seconds.
+    fn offset(&mut self) -> Option<i32> {
+        let sign = match self.peek() {
+            Some(b'-') => {
+                self.next();
+                -1
+            }
+            Some(b'+') => {
+                self.next();
+                1
+            }
+            _ => 1,
+        };
+        let mut seconds = self.number()? as i32 * 3600;
+        if self.peek() == Some(b':') {
+            self.next();
+            seconds += self.number()? as i32 * 60;
+            if self.peek() == Some(b':') {
+                self.next();
+                seconds += self.number()? as i32;
+            }
+        }
+        Some(sign * seconds)
+    }
+
+    fn rule(&mut self) -> Option<Rule> {
+        let day = match self.peek()? {
+            b'M' => {
+                self.next();
+                let month = self.number()?;
+                self.expect(b'.')?;
+                let week = self.number()?;
+                self.expect(b'.')?;
+                let weekday = self.number()?;
+                if !(1..=12).contains(&month) || !(1..=5).contains(&week) || weekday > 6 {
+                    return None;
+                }
+                Day::MonthWeek { month, week, weekday }
+            }
+            b'J' => {
+                self.next();
+                let day = self.number()?;
+                if !(1..=365).contains(&day) {
+                    return None;
+                }
+                Day::Julian(day)
+            }
+            _ => {
+                let day = self.number()?;
+                if day > 365 {
+                    return None;
+                }
+                Day::Ordinal(day)
+            }
+        };
+
+        let time = if self.peek() == Some(b'/') {
+            self.next();
+            self.offset()?
+        } else {
+            7200
+        };
+
+        Some(Rule { day, time })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> i64 {
+        local(year, month, day, hour, minute).and_utc().timestamp()
+    }
+
+    fn local(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap().and_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    /// Checks that daylight saving starts or ends at `at`, in UTC.
+    fn changes_at(tz: &PosixTz, at: i64, before: i32, after: i32) {
+        assert_eq!(tz.offset_at(at - 1), before, "before {}", at);
+        assert_eq!(tz.offset_at(at), after, "at {}", at);
+    }
+
+    #[test]
+    fn parses_fixed_offsets() {
+        assert_eq!(PosixTz::parse("UTC0"), Some(PosixTz::UTC));
+        // West of Greenwich is positive in a TZ string.
+        assert_eq!(PosixTz::parse("EST5").unwrap().offset_at(0), -5 * 3600);
+        assert_eq!(PosixTz::parse("<+14>-14").unwrap().offset_at(0), 14 * 3600);
+        // Offsets which aren't whole hours.
+        assert_eq!(PosixTz::parse("IST-5:30").unwrap().offset_at(0), 5 * 3600 + 30 * 60);
+        assert_eq!(PosixTz::parse("<+0545>-5:45").unwrap().offset_at(0), 5 * 3600 + 45 * 60);
+        assert_eq!(PosixTz::parse("<-0930>9:30").unwrap().offset_at(0), -(9 * 3600 + 30 * 60));
+        assert_eq!(PosixTz::parse("LMT-0:17:30").unwrap().offset_at(0), 17 * 60 + 30);
+    }
+
+    #[test]
+    fn rejects_malformed_strings() {
+        for tz in [
+            "",
+            "GMT",
+            "GM0",
+            "0",
+            "<+05",
+            "GMT+",
+            "GMT0:",
+            "GMT0BST,",
+            "GMT0BST,M3.5.0",
+            "GMT0BST;M3.5.0,M10.5.0",
+            "GMT0BST,M3.5.0,M10.5.0,",
+            "GMT0BST,M3.5.0,M10.5.0 ",
+            "GMT0BST,M13.1.0,M10.5.0",
+            "GMT0BST,M0.1.0,M10.5.0",
+            "GMT0BST,M3.6.0,M10.5.0",
+            "GMT0BST,M3.0.0,M10.5.0",
+            "GMT0BST,M3.5.7,M10.5.0",
+            "GMT0BST,M3.5,M10.5.0",
+            "GMT0BST,M3.5.0/,M10.5.0",
+            "GMT0BST,J0,J300",
+            "GMT0BST,J366,J300",
+            "GMT0BST,366,300",
+            "GMT0BST,J,J300",
+            "GMT0BST,X60,J300",
+        ] {
+            assert_eq!(PosixTz::parse(tz), None, "{:?}", tz);
+        }
+    }
+
+    #[test]
+    fn follows_month_week_day_rules() {
+        let uk = PosixTz::parse("GMT0BST,M3.5.0/1,M10.5.0").unwrap();
+        // The last Sundays in March and October 2024 are the 31st and 27th.
+        changes_at(&uk, utc(2024, 3, 31, 1, 0), 0, 3600);
+        changes_at(&uk, utc(2024, 10, 27, 1, 0), 3600, 0);
+
+        // The US rules are used when only a daylight saving name is given.
+        let us = PosixTz::parse("EST5EDT").unwrap();
+        changes_at(&us, utc(2024, 3, 10, 7, 0), -5 * 3600, -4 * 3600);
+        changes_at(&us, utc(2024, 11, 3, 6, 0), -4 * 3600, -5 * 3600);
+        assert_eq!(us, PosixTz::parse("EST5EDT4,M3.2.0/2:00:00,M11.1.0/2:00:00").unwrap());
+    }
+
+    #[test]
+    fn follows_southern_hemisphere_rules() {
+        let sydney = PosixTz::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert!(sydney.is_dst(utc(2024, 1, 15, 0, 0)));
+        assert!(!sydney.is_dst(utc(2024, 6, 15, 0, 0)));
+        assert!(sydney.is_dst(utc(2024, 12, 31, 23, 0)));
+        // 03:00 AEDT on 7 April and 02:00 AEST on 6 October.
+        changes_at(&sydney, utc(2024, 4, 6, 16, 0), 11 * 3600, 10 * 3600);
+        changes_at(&sydney, utc(2024, 10, 5, 16, 0), 10 * 3600, 11 * 3600);
+
+        // Lord Howe Island only moves its clocks by half an hour.
+        let lord_howe = PosixTz::parse("<+1030>-10:30<+11>-11,M10.1.0,M4.1.0").unwrap();
+        changes_at(&lord_howe, utc(2024, 4, 6, 15, 0), 11 * 3600, 10 * 3600 + 1800);
+        changes_at(&lord_howe, utc(2024, 10, 5, 15, 30), 10 * 3600 + 1800, 11 * 3600);
+
+        // Chile changes at midnight at the end of the first Saturday.
+        let chile = PosixTz::parse("<-04>4<-03>,M9.1.6/24,M4.1.6/24").unwrap();
+        changes_at(&chile, utc(2024, 4, 7, 3, 0), -3 * 3600, -4 * 3600);
+        changes_at(&chile, utc(2024, 9, 8, 4, 0), -4 * 3600, -3 * 3600);
+    }
+
+    #[test]
+    fn accepts_negative_transition_times() {
+        // Greenland changes at 01:00 UTC, which is still the day before in standard time.
+        let nuuk = PosixTz::parse("<-02>2<-01>,M3.5.0/-1,M10.5.0/0").unwrap();
+        changes_at(&nuuk, utc(2024, 3, 31, 1, 0), -2 * 3600, -3600);
+        changes_at(&nuuk, utc(2024, 10, 27, 1, 0), -3600, -2 * 3600);
+    }
+
+    #[test]
+    fn follows_julian_day_rules() {
+        // Iran used to change on 22 March and 22 September, J79/24 and J263/24, whether or not it was a leap year.
+        let iran = PosixTz::parse("<+0330>-3:30<+0430>,J79/24,J263/24").unwrap();
+        for year in [2019, 2020] {
+            changes_at(&iran, utc(year, 3, 20, 20, 30), 3 * 3600 + 1800, 4 * 3600 + 1800);
+            changes_at(&iran, utc(year, 9, 20, 19, 30), 4 * 3600 + 1800, 3 * 3600 + 1800);
+        }
+
+        // J60 is always 1 March.
+        let tz = PosixTz::parse("XST0XDT,J60/0,J300/0").unwrap();
+        assert!(!tz.is_dst(utc(2024, 2, 29, 12, 0)));
+        changes_at(&tz, utc(2024, 3, 1, 0, 0), 0, 3600);
+        changes_at(&tz, utc(2023, 3, 1, 0, 0), 0, 3600);
+    }
+
+    #[test]
+    fn follows_zero_based_day_rules() {
+        // Day 59 is 1 March, or 29 February in a leap year.
+        let tz = PosixTz::parse("XST0XDT,59/0,299/0").unwrap();
+        changes_at(&tz, utc(2023, 3, 1, 0, 0), 0, 3600);
+        changes_at(&tz, utc(2024, 2, 29, 0, 0), 0, 3600);
+        changes_at(&tz, utc(2023, 10, 26, 23, 0), 3600, 0);
+        changes_at(&tz, utc(2024, 10, 25, 23, 0), 3600, 0);
+
+        // Day 365 only exists in a leap year.
+        let tz = PosixTz::parse("XST0XDT,0/0,365/0").unwrap();
+        assert!(tz.is_dst(utc(2024, 12, 30, 12, 0)));
+        assert!(!tz.is_dst(utc(2023, 12, 30, 12, 0)));
+    }
+
+    #[test]
+    fn converts_local_times_to_utc() {
+        let newfoundland = PosixTz::parse("NST3:30NDT,M3.2.0,M11.1.0").unwrap();
+        let at = local(2024, 7, 1, 12, 0);
+        assert_eq!(newfoundland.to_utc(at), utc(2024, 7, 1, 14, 30));
+        assert_eq!(newfoundland.to_local(newfoundland.to_utc(at)), at);
+        // 02:30 is skipped when the clocks go forward, so it becomes 03:30.
+        assert_eq!(newfoundland.to_utc(local(2024, 3, 10, 2, 30)), utc(2024, 3, 10, 6, 0));
+        assert_eq!(newfoundland.to_local(utc(2024, 3, 10, 6, 0)), local(2024, 3, 10, 3, 30));
+        // 01:30 happens twice when they go back, and the first one is in daylight saving.
+        assert_eq!(newfoundland.to_utc(local(2024, 11, 3, 1, 30)), utc(2024, 11, 3, 4, 0));
+
+        let sydney = PosixTz::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap();
+        assert_eq!(sydney.to_utc(local(2024, 1, 1, 0, 0)), utc(2023, 12, 31, 13, 0));
+        assert_eq!(sydney.to_utc(local(2024, 7, 1, 0, 0)), utc(2024, 6, 30, 14, 0));
+        assert_eq!(sydney.to_utc(local(2024, 4, 7, 2, 30)), utc(2024, 4, 6, 15, 30));
+        assert_eq!(sydney.to_utc(local(2024, 10, 6, 2, 30)), utc(2024, 10, 5, 16, 30));
+
+        let india = PosixTz::parse("IST-5:30").unwrap();
+        assert_eq!(india.to_utc(local(2024, 1, 1, 5, 30)), utc(2024, 1, 1, 0, 0));
+    }
+}
@@ -0,0 +1,357 @@
+//! World clock showing up to four time zones at once.
+//!
+//! Each zone gets a cell with a small analog face or a digital readout, its label and a sun or
+//! moon to show whether it is day or night there. Local times come from `PosixTz`, so daylight
+//! saving is handled per zone.
+
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use embedded_graphics::{
+    mono_font::{
+        MonoTextStyle,
+        ascii::{FONT_6X10, FONT_10X20},
+    },
+    prelude::*,
+    primitives::{Circle, PrimitiveStyle, Rectangle},
+    text::{Alignment, Baseline, Text, TextStyleBuilder},
+};
+
+use crate::{
+    face::{ClockFace, Theme},
+    hand::{Blend, HandStyle},
+    sexagesimal_to_angle,
+    tz::PosixTz,
+};
+
+pub const MAX_ZONES: usize = 4;
+
+/// The hours counted as daytime.
+const DAY: core::ops::Range<u32> = 7..19;
+
+/// A labelled time zone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Zone<'a> {
+    pub label: &'a str,
+    pub tz: PosixTz,
+}
+
+impl<'a> Zone<'a> {
+    pub const fn new(label: &'a str, tz: PosixTz) -> Self {
+        Self { label, tz }
+    }
+}
+
+/// How each zone is shown.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZoneStyle {
+    Analog,
+    Digital,
+}
+
+/// Up to four zones laid out in a grid.
+pub struct WorldClock<'a, C> {
+    zones: heapless::Vec<Zone<'a>, MAX_ZONES>,
+    pub style: ZoneStyle,
+    /// Colours for zones where it is daytime.
+    pub day: Theme<C>,
+    /// Colours for zones where it is night.
+    pub night: Theme<C>,
+    /// The local minute each cell was last drawn for, so unchanged cells are skipped.
+    drawn: [Option<i64>; MAX_ZONES],
+}
+
+impl<'a, C: Blend> WorldClock<'a, C> {
+    pub fn new(style: ZoneStyle, day: Theme<C>, night: Theme<C>) -> Self {
+        Self {
+            zones: heapless::Vec::new(),
+            style,
+            day,
+            night,
+            drawn: [None; MAX_ZONES],
+        }
+    }
+
+    /// Adds a zone, or gives it back if there are already four.
+    pub fn add(&mut self, zone: Zone<'a>) -> Result<(), Zone<'a>> {
+        self.zones.push(zone)?;
+        self.reset();
+        Ok(())
+    }
+
+    pub fn zones(&self) -> &[Zone<'a>] {
+        &self.zones
+    }
+
+    /// Forces every cell to be drawn again, for example after the screen has been cleared.
+    pub fn reset(&mut self) {
+        self.drawn = [None; MAX_ZONES];
+    }
+
+    /// Splits `area` into a cell for each zone: one, two side by side (or stacked on a tall
+    /// area), or a 2x2 grid.
+    pub fn cells(&self, area: &Rectangle) -> heapless::Vec<Rectangle, MAX_ZONES> {
+        let count = self.zones.len() as u32;
+        let (columns, rows) = match count {
+            0 | 1 => (1, 1),
+            2 if area.size.height > area.size.width => (1, 2),
+            2 => (2, 1),
+            _ => (2, 2),
+        };
+        let size = Size::new(area.size.width / columns, area.size.height / rows);
+
+        (0..count)
+            .map(|i| {
+                let offset = Point::new((i % columns * size.width) as i32, (i / columns * size.height) as i32);
+                Rectangle::new(area.top_left + offset, size)
+            })
+            .collect()
+    }
+
+    /// Draws any zone whose displayed time has changed since it was last drawn.
+    ///
+    /// `utc` is in seconds since the epoch. The first zone is treated as home, and other zones
+    /// show +1 or -1 when their date is different.
+    pub fn draw<D>(&mut self, target: &mut D, area: &Rectangle, utc: i64) -> Result<(), D::Error>
+    where
+        D: DrawTarget<Color = C>,
+    {
+        let Some(home) = self.zones.first().map(|zone| zone.tz.to_local(utc)) else {
+            return Ok(());
+        };
+
+        for (index, (zone, cell)) in self.zones.iter().zip(self.cells(area)).enumerate() {
+            let local = zone.tz.to_local(utc);
+            let minute = local.and_utc().timestamp() / 60;
+            if self.drawn[index] == Some(minute) {
+                continue;
+            }
+            self.drawn[index] = Some(minute);
+
+            let theme = if DAY.contains(&local.hour()) { &self.day } else { &self.night };
+            let days = local.num_days_from_ce() - home.num_days_from_ce();
+            draw_zone(target, &cell, zone.label, &local, days, self.style, theme)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn draw_zone<D, C>(
+    target: &mut D,
+    cell: &Rectangle,
+    label: &str,
+    local: &NaiveDateTime,
+    days: i32,
+    style: ZoneStyle,
+    theme: &Theme<C>,
+) -> Result<(), D::Error>
+where
+    C: Blend,
+    D: DrawTarget<Color = C>,
+{
+    cell.into_styled(PrimitiveStyle::with_fill(theme.background)).draw(target)?;
+
+    let centered = TextStyleBuilder::new()
+        .alignment(Alignment::Center)
+        .baseline(Baseline::Middle)
+        .build();
+    let small = MonoTextStyle::new(&FONT_6X10, theme.numerals);
+    let label_height = FONT_6X10.character_size.height + 4;
+    let body = Rectangle::new(cell.top_left, cell.size - Size::new(0, label_height));
+
+    // The label goes along the bottom of the cell.
+    let bottom = cell.top_left + Point::new(cell.size.width as i32 / 2, (cell.size.height - label_height / 2) as i32);
+    Text::with_text_style(label, bottom, small, centered).draw(target)?;
+
+    match style {
+        ZoneStyle::Analog => {
+            let diameter = body.size.width.min(body.size.height).saturating_sub(6);
+            let face = ClockFace::new(Circle::with_center(body.center(), diameter), *theme)
+                .with_rim_width(1)
+                .with_tick_length((diameter / 12).max(2));
+            face.draw(target)?;
+
+            let radius = diameter as i32 / 2;
+            HandStyle::line(theme.hands, -(radius / 2))
+                .with_width(3.0)
+                .with_anti_alias(true)
+                .draw(target, &face.circle, face.hour_angle(local.hour(), local.minute()), theme.background)?;
+            HandStyle::line(theme.hands, -(radius / 5))
+                .with_width(2.0)
+                .with_anti_alias(true)
+                .draw(target, &face.circle, sexagesimal_to_angle(local.minute()), theme.background)?;
+        }
+        ZoneStyle::Digital => {
+            let mut digits = [b'0'; 5];
+            digits[0] += (local.hour() / 10) as u8;
+            digits[1] += (local.hour() % 10) as u8;
+            digits[2] = b':';
+            digits[3] += (local.minute() / 10) as u8;
+            digits[4] += (local.minute() % 10) as u8;
+            let time = core::str::from_utf8(&digits).unwrap_or("");
+
+            Text::with_text_style(time, body.center(), MonoTextStyle::new(&FONT_10X20, theme.numerals), centered)
+                .draw(target)?;
+        }
+    }
+
+    // Sun or moon in the top left corner.
+    let icon = cell.top_left + Point::new(9, 9);
+    Circle::with_center(icon, 9)
+        .into_styled(PrimitiveStyle::with_fill(theme.second_hand))
+        .draw(target)?;
+    if !DAY.contains(&local.hour()) {
+        Circle::with_center(icon + Point::new(3, -2), 8)
+            .into_styled(PrimitiveStyle::with_fill(theme.background))
+            .draw(target)?;
+    }
+
+    // Tomorrow or yesterday compared to home, in the top right corner.
+    if days != 0 {
+        let text = if days > 0 { "+1" } else { "-1" };
+        let corner = cell.top_left + Point::new(cell.size.width as i32 - 9, 9);
+        Text::with_text_style(text, corner, small, centered).draw(target)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use chrono::NaiveDate;
+    use embedded_graphics::{mock_display::MockDisplay, pixelcolor::Rgb565};
+    use std::vec::Vec;
+
+    const LABEL: Rgb565 = Rgb565::YELLOW;
+    const ICON: Rgb565 = Rgb565::RED;
+    const DAY_BACKGROUND: Rgb565 = Rgb565::WHITE;
+    const NIGHT_BACKGROUND: Rgb565 = Rgb565::BLUE;
+
+    fn utc(year: i32, month: u32, day: u32, hour: u32, minute: u32) -> i64 {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap().and_hms_opt(hour, minute, 0).unwrap().and_utc().timestamp()
+    }
+
+    fn clock(zones: &[&'static str]) -> WorldClock<'static, Rgb565> {
+        let day = Theme { numerals: LABEL, second_hand: ICON, ..Theme::plain(Rgb565::BLACK, DAY_BACKGROUND) };
+        let night = Theme { numerals: LABEL, second_hand: ICON, ..Theme::plain(Rgb565::WHITE, NIGHT_BACKGROUND) };
+        let mut clock = WorldClock::new(ZoneStyle::Analog, day, night);
+        for tz in zones {
+            clock.add(Zone::new("Zone", PosixTz::parse(tz).unwrap())).unwrap();
+        }
+        clock
+    }
+
+    /// Draws the zones side by side on a `MockDisplay`, giving the display and the two cells.
+    fn draw(clock: &mut WorldClock<'static, Rgb565>, utc: i64) -> (MockDisplay<Rgb565>, Vec<Rectangle>) {
+        let mut display = MockDisplay::new();
+        display.set_allow_overdraw(true);
+        let area = display.bounding_box();
+        clock.draw(&mut display, &area, utc).unwrap();
+        (display, clock.cells(&area).to_vec())
+    }
+
+    /// The day marker drawn in the top right corner of `cell`, if any.
+    fn marker(display: &MockDisplay<Rgb565>, cell: &Rectangle) -> Option<&'static str> {
+        let corner = cell.top_left + Point::new(cell.size.width as i32 - 9, 9);
+        let style = TextStyleBuilder::new().alignment(Alignment::Center).baseline(Baseline::Middle).build();
+        // "+1" goes first as it covers every pixel of "-1".
+        let found = ["+1", "-1"].into_iter().find(|text| {
+            let mut expected = MockDisplay::new();
+            Text::with_text_style(text, corner, MonoTextStyle::new(&FONT_6X10, LABEL), style).draw(&mut expected).unwrap();
+            expected.bounding_box().points().filter(|p| expected.get_pixel(*p).is_some()).all(|p| display.get_pixel(p) == Some(LABEL))
+        });
+        if found.is_none() {
+            let area = Rectangle::with_center(corner, Size::new(14, 12));
+            assert!(area.points().all(|p| display.get_pixel(p) != Some(LABEL)), "stray marker pixels in {:?}", cell);
+        }
+        found
+    }
+
+    #[test]
+    fn lays_out_one_to_four_zones() {
+        let wide = Rectangle::new(Point::new(10, 20), Size::new(200, 100));
+        let tall = Rectangle::new(Point::new(10, 20), Size::new(100, 200));
+        let cell = |x: i32, y: i32, width: u32, height: u32| Rectangle::new(Point::new(x, y), Size::new(width, height));
+
+        let mut clock = clock(&[]);
+        assert!(clock.cells(&wide).is_empty());
+
+        clock.add(Zone::new("1", PosixTz::UTC)).unwrap();
+        assert_eq!(clock.cells(&wide).as_slice(), &[wide]);
+
+        clock.add(Zone::new("2", PosixTz::UTC)).unwrap();
+        assert_eq!(clock.cells(&wide).as_slice(), &[cell(10, 20, 100, 100), cell(110, 20, 100, 100)]);
+        assert_eq!(clock.cells(&tall).as_slice(), &[cell(10, 20, 100, 100), cell(10, 120, 100, 100)]);
+
+        clock.add(Zone::new("3", PosixTz::UTC)).unwrap();
+        assert_eq!(clock.cells(&wide).as_slice(), &[cell(10, 20, 100, 50), cell(110, 20, 100, 50), cell(10, 70, 100, 50)]);
+
+        clock.add(Zone::new("4", PosixTz::UTC)).unwrap();
+        assert_eq!(
+            clock.cells(&wide).as_slice(),
+            &[cell(10, 20, 100, 50), cell(110, 20, 100, 50), cell(10, 70, 100, 50), cell(110, 70, 100, 50)]
+        );
+        assert_eq!(
+            clock.cells(&tall).as_slice(),
+            &[cell(10, 20, 50, 100), cell(60, 20, 50, 100), cell(10, 120, 50, 100), cell(60, 120, 50, 100)]
+        );
+
+        assert!(clock.add(Zone::new("5", PosixTz::UTC)).is_err());
+        assert_eq!(clock.zones().len(), MAX_ZONES);
+    }
+
+    #[test]
+    fn marks_the_day_across_the_date_line() {
+        let at = utc(2024, 6, 1, 20, 0);
+
+        // 08:00 tomorrow in Auckland.
+        let (display, cells) = draw(&mut clock(&["UTC0", "NZST-12NZDT,M9.5.0,M4.1.0/3"]), at);
+        assert_eq!(marker(&display, &cells[0]), None);
+        assert_eq!(marker(&display, &cells[1]), Some("+1"));
+
+        // 10:00 in both Kiritimati and Honolulu, but a day apart.
+        let (display, cells) = draw(&mut clock(&["<+14>-14", "HST10"]), at);
+        assert_eq!(marker(&display, &cells[0]), None);
+        assert_eq!(marker(&display, &cells[1]), Some("-1"));
+
+        let (display, cells) = draw(&mut clock(&["<+14>-14", "<+12>-12"]), at);
+        assert_eq!(marker(&display, &cells[1]), None);
+    }
+
+    #[test]
+    fn shows_a_sun_by_day_and_a_moon_by_night() {
+        // Day runs from 07:00 to 19:00.
+        for (zones, at) in [(["UTC0", "<-01>1"], utc(2024, 6, 1, 7, 0)), (["UTC0", "<+01>-1"], utc(2024, 6, 1, 18, 30))] {
+            let (display, cells) = draw(&mut clock(&zones), at);
+            let [day, night] = [cells[0], cells[1]];
+
+            assert_eq!(display.get_pixel(day.top_left + Point::new(1, 20)), Some(DAY_BACKGROUND));
+            assert_eq!(display.get_pixel(day.top_left + Point::new(6, 9)), Some(ICON));
+            assert_eq!(display.get_pixel(day.top_left + Point::new(12, 7)), Some(ICON));
+
+            // The moon is the sun with a bite taken out of it.
+            assert_eq!(display.get_pixel(night.top_left + Point::new(1, 20)), Some(NIGHT_BACKGROUND));
+            assert_eq!(display.get_pixel(night.top_left + Point::new(6, 9)), Some(ICON));
+            assert_eq!(display.get_pixel(night.top_left + Point::new(12, 7)), Some(NIGHT_BACKGROUND));
+        }
+    }
+
+    #[test]
+    fn only_redraws_zones_whose_minute_changed() {
+        let mut clock = clock(&["UTC0", "IST-5:30"]);
+        let at = utc(2024, 6, 1, 12, 0);
+        draw(&mut clock, at);
+
+        let (display, _) = draw(&mut clock, at + 59);
+        assert_eq!(display.affected_area().size, Size::zero());
+        // Both cells together cover the display.
+        let (display, _) = draw(&mut clock, at + 60);
+        assert_eq!(display.affected_area(), display.bounding_box());
+
+        clock.reset();
+        let (display, _) = draw(&mut clock, at + 60);
+        assert_ne!(display.affected_area().size, Size::zero());
+    }
+}
@@ -7,12 +7,14 @@
     holding buffers for the duration of a data transfer."
 )]
 
-mod clock;
-
 use core::fmt::Write;
 
+use timesync::{self, TimeSync};
 use defmt::info;
 use chrono::{NaiveTime, Timelike};
+use embassy_executor::Spawner;
+use embassy_net::{Runner, Stack, StackResources};
+use embassy_time::{Duration, Timer};
 use embedded_graphics::{
     mono_font::{ascii::FONT_10X20, MonoTextStyle},
     pixelcolor::Rgb565,
@@ -22,15 +24,22 @@ use embedded_graphics::{
 };
 use heapless::String;
 
-use {esp_backtrace as _, esp_println as _};
+use {esp_alloc as _, esp_backtrace as _, esp_println as _};
 use esp_hal::{
-    main,
     clock::CpuClock,
     delay::Delay,
+    peripherals::WIFI,
+    rng::Rng,
     rtc_cntl::Rtc,
+    timer::timg::TimerGroup,
+};
+use esp_radio::{
+    Controller,
+    wifi::{ClientConfig, ModeConfig, WifiController, WifiDevice, WifiEvent, WifiStaState},
 };
 
-use crate::clock::*;
+use clock_core::*;
+use clock_core::face::{ClockFace, Numerals, Theme};
 
 
 
@@ -41,13 +50,27 @@ esp_bootloader_esp_idf::esp_app_desc!();
 // Simple clock program for the cyd BSP, based on
 // https://github.com/embedded-graphics/examples/blob/main/eg-0.8/examples/demo-analog-clock.rs
 //
-// The CYD's RTC peripheral is used to keep track of time, but has no means of finding the real time of day so initializes
-// the time to 8:05am at start.   
- 
+// The CYD's RTC peripheral is used to keep track of time. It is set from NTP when the SSID and PASSWORD environment
+// variables are given at build time, otherwise it has no means of finding the real time of day so starts at 8:05am.
+
+// When you are okay with using a nightly compiler it's better to use https://docs.rs/static_cell/2.1.0/static_cell/macro.make_static.html
+macro_rules! mk_static {
+    ($t:ty,$val:expr) => {{
+        static STATIC_CELL: static_cell::StaticCell<$t> = static_cell::StaticCell::new();
+        #[deny(unused_attributes)]
+        let x = STATIC_CELL.uninit().write(($val));
+        x
+    }};
+}
 
+const SSID: Option<&str> = option_env!("SSID");
+const PASSWORD: Option<&str> = option_env!("PASSWORD");
+const NTP_SERVERS: &[&str] = &["pool.ntp.org", "time.google.com", "time.cloudflare.com"];
+/// Offset of the time shown from UTC, change this to your local time zone.
+const UTC_OFFSET_SECS: i64 = 0;
 
-#[main]
-fn main() -> ! {
+#[esp_rtos::main]
+async fn main(spawner: Spawner) -> ! {
     let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
     let peripherals: esp_hal::peripherals::Peripherals = esp_hal::init(config);
     let mut delay = Delay::new();
@@ -57,15 +80,21 @@ fn main() -> ! {
         .unwrap();
 
     let mut cyd = cyd_result.cyd;
+    let remainder = cyd_result.remainder;
     cyd.backlight(true);
 
+    esp_alloc::heap_allocator!(size: 72 * 1024);
 
-    let rtc = Rtc::new(cyd_result.remainder.lpwr);
-    
+    let timg0 = TimerGroup::new(remainder.timg0);
+    esp_rtos::start(timg0.timer0);
 
+    let rtc = &*mk_static!(Rtc<'static>, Rtc::new(remainder.lpwr));
+    rtc.set_current_time_us(((8 * 60) + 5) * 60 * 1000 * 1000); // set to 08:05:00.000 until NTP answers
 
-    rtc.set_current_time_us(((8 * 60) + 5) * 60 * 1000 * 1000); // set to 08:05:00.000
-    let mut elapsed_ms: u32;
+    if let (Some(ssid), Some(password)) = (SSID, PASSWORD) {
+        let stack = start_wifi(spawner, remainder.wifi, ssid, password);
+        spawner.spawn(time_sync(stack, rtc)).ok();
+    }
 
     let clock_color = Rgb565::GREEN;
     let bg_color = Rgb565::BLACK;
@@ -73,22 +102,21 @@ fn main() -> ! {
 
     cyd.display.clear(bg_color).unwrap();
 
-    let clock_face = create_face(&cyd.display);
-    draw_face(&mut cyd.display, &clock_face, clock_color).unwrap();
+    let face = ClockFace::fit(&cyd.display, 10, Theme::CLASSIC)
+        .with_minute_ticks(true)
+        .with_numerals(Numerals::Arabic);
+    face.draw(&mut cyd.display).unwrap();
 
-    let mut prev_hour = 0;
-    let mut prev_minute = 0;
-    let mut prev_second = 0;
+    let clock_face = face.circle;
+    let mut hands = face.hands();
 
     loop {
 
         let now = rtc.current_time_us() as i64;
         info!("now: {}", now);
-        elapsed_ms = (now / 1000) as u32;
-        info!("elapsed_ms: {}", elapsed_ms);
-        let secs = elapsed_ms / 1000;
-        let bedtime = 1000 * (secs + 1) - elapsed_ms;
-        let nanos = now % 1_000_000;
+        let bedtime = 1000 - (now / 1000 % 1000) as u64;
+        let secs = (now / 1_000_000 + UTC_OFFSET_SECS).rem_euclid(86_400) as u32;
+        let nanos = now % 1_000_000 * 1000;
         let time = NaiveTime::from_num_seconds_from_midnight_opt(secs, nanos as u32).unwrap();
 
         let mut time_str: String<64> = String::new();
@@ -112,29 +140,8 @@ fn main() -> ! {
         let minute = time.minute();
         let second = time.second();
 
-        if hour != prev_hour {
-             draw_hand(&mut cyd.display, &clock_face, bg_color, hour_to_angle(prev_hour), -60).unwrap();
-        }
-        if minute != prev_minute {
-             draw_hand(&mut cyd.display, &clock_face, bg_color, sexagesimal_to_angle(prev_minute), -30).unwrap();
-        }
-
-        if second != prev_second {
-            let seconds_radians = sexagesimal_to_angle(prev_second);
-            draw_hand(&mut cyd.display, &clock_face, bg_color, seconds_radians, 0).unwrap();
-            draw_second_decoration(&mut cyd.display, &clock_face, bg_color, bg_color, seconds_radians, -20).unwrap();
-        }
-
-        prev_hour = hour;
-        prev_minute = minute;
-        prev_second = second;
-
-        draw_hand(&mut cyd.display, &clock_face, clock_color, hour_to_angle(hour), -60).unwrap();
-        draw_hand(&mut cyd.display, &clock_face, clock_color, sexagesimal_to_angle(minute), -30).unwrap();
-
-        let seconds_radians = sexagesimal_to_angle(second);
-        draw_hand(&mut cyd.display, &clock_face, clock_color, seconds_radians, 0).unwrap();
-        draw_second_decoration(&mut cyd.display, &clock_face, clock_color, bg_color, seconds_radians, -20).unwrap();
+        let angles = [face.hour_angle(hour, minute), sexagesimal_to_angle(minute), sexagesimal_to_angle(second)];
+        hands.update(&mut cyd.display, &clock_face, bg_color, angles, &face).unwrap();
 
         // Draw a small circle over the hands in the center of the clock face.
         // This has to happen after the hands are drawn so they're covered up.
@@ -143,6 +150,66 @@ fn main() -> ! {
             .draw(&mut cyd.display).unwrap();
 
 
-        delay.delay_millis(bedtime);
+        Timer::after(Duration::from_millis(bedtime)).await;
     }
 }
+
+/// Starts the wifi and network stack, returning the stack once the tasks driving them are running.
+fn start_wifi(spawner: Spawner, wifi: WIFI<'static>, ssid: &'static str, password: &'static str) -> Stack<'static> {
+    let esp_radio_ctrl = &*mk_static!(Controller<'static>, esp_radio::init().unwrap());
+    let (controller, interfaces) = esp_radio::wifi::new(esp_radio_ctrl, wifi, Default::default()).unwrap();
+
+    let rng = Rng::new();
+    let seed = (rng.random() as u64) << 32 | rng.random() as u64;
+
+    let (stack, runner) = embassy_net::new(
+        interfaces.sta,
+        embassy_net::Config::dhcpv4(Default::default()),
+        mk_static!(StackResources<3>, StackResources::<3>::new()),
+        seed,
+    );
+
+    spawner.spawn(connection(controller, ssid, password)).ok();
+    spawner.spawn(net_task(runner)).ok();
+    stack
+}
+
+#[embassy_executor::task]
+async fn connection(mut controller: WifiController<'static>, ssid: &'static str, password: &'static str) {
+    loop {
+        if esp_radio::wifi::sta_state() == WifiStaState::Connected {
+            // wait until we're no longer connected
+            controller.wait_for_event(WifiEvent::StaDisconnected).await;
+            Timer::after(Duration::from_millis(5000)).await
+        }
+        if !matches!(controller.is_started(), Ok(true)) {
+            let client_config = ModeConfig::Client(
+                ClientConfig::default()
+                    .with_ssid(ssid.into())
+                    .with_password(password.into()),
+            );
+            controller.set_config(&client_config).unwrap();
+            controller.start_async().await.unwrap();
+            info!("Wifi started");
+        }
+
+        match controller.connect_async().await {
+            Ok(_) => info!("Wifi connected"),
+            Err(e) => {
+                info!("Failed to connect to wifi: {}", defmt::Debug2Format(&e));
+                Timer::after(Duration::from_millis(5000)).await
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn time_sync(stack: Stack<'static>, rtc: &'static Rtc<'static>) {
+    let mut sync = TimeSync::new(NTP_SERVERS);
+    timesync::run(stack, &mut sync, rtc).await
+}
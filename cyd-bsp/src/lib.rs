@@ -14,6 +14,10 @@ use esp_hal::time::Rate;
 use esp_hal::spi::master::Config;
 use mipidsi::{models::ILI9341Rgb565, options::{Orientation}};
 use esp_hal::spi::master::Spi;
+use embedded_graphics::{pixelcolor::Rgb565, prelude::Point};
+
+/// The BMP and QOI decoders, which live in `image-core` so they can be tested on the host.
+pub use image_core as image;
 
 /* ******************************************************************************************************************************************************
  * Board Support Package for the Cheap Yellow Display (CYD) or ESP32-2432S028R
@@ -39,13 +43,18 @@ pub struct CydResult<'a> {
 }
 
 pub struct CydRemainder<'a> {
+    /// The BOOT button, low when pressed.
+    pub gpio0: esp_hal::peripherals::GPIO0<'a>,
     pub gpio22: esp_hal::peripherals::GPIO22<'a>,
+    /// The speaker connector, through the onboard amplifier.
+    pub gpio26: esp_hal::peripherals::GPIO26<'a>,
     pub gpio27: esp_hal::peripherals::GPIO27<'a>,
     pub gpio35: esp_hal::peripherals::GPIO35<'a>,
     pub lpwr: esp_hal::peripherals::LPWR<'a>,
     pub rmt: esp_hal::peripherals::RMT<'a>,
     pub timg0: esp_hal::peripherals::TIMG0<'a>,
     pub i2c0: esp_hal::peripherals::I2C0<'a>,
+    pub wifi: esp_hal::peripherals::WIFI<'a>,
 }
 
 pub struct Cyd<'a> {
@@ -84,6 +93,16 @@ impl<'a> Cyd<'a> {
             false => self.led_blue_pin.set_high(),
         };
     }
+
+    /// Draws a BMP or QOI image onto the display, clipped to the screen.
+    pub fn draw_image<S: image::ImageSource>(
+        &mut self,
+        image: &mut image::Image<S>,
+        top_left: Point,
+        transparent: Option<Rgb565>,
+    ) -> Result<(), image::ImageError<S::Error>> {
+        image.draw(&mut self.display, top_left, transparent)
+    }
 }
 
 
@@ -244,13 +263,16 @@ impl Builder {
                 led_blue_pin: Output::new(peripherals.GPIO17, Level::High, OutputConfig::default()),
             }, 
             remainder: CydRemainder {
+                gpio0: peripherals.GPIO0,
                 gpio22: peripherals.GPIO22,
+                gpio26: peripherals.GPIO26,
                 gpio27: peripherals.GPIO27,
                 gpio35: peripherals.GPIO35,
                 lpwr: peripherals.LPWR,
                 rmt: peripherals.RMT,
                 timg0: peripherals.TIMG0,
                 i2c0: peripherals.I2C0,
+                wifi: peripherals.WIFI,
             }
         })
     }
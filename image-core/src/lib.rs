@@ -0,0 +1,740 @@
+#![no_std]
+
+//! Streaming BMP and QOI decoders which draw straight onto an `Rgb565` display.
+//!
+//! Images are read from an [`ImageSource`] a few pixels at a time so that large splash screens
+//! don't need a frame buffer. Sources can be `include_bytes!` data in flash, or any
+//! `embedded_io` reader such as a file on the SD card.
+//!
+//! ```ignore
+//! let mut icon = Image::open(include_bytes!("sun.qoi").as_slice()).unwrap();
+//! icon.draw(&mut cyd.display, Point::new(10, 10), Some(Rgb565::MAGENTA)).unwrap();
+//! ```
+
+use embedded_graphics::{
+    pixelcolor::{Rgb565, Rgb888},
+    prelude::*,
+    primitives::Rectangle,
+};
+
+/// Number of pixels decoded before they are sent to the display.
+const CHUNK_PIXELS: usize = 64;
+
+const BMP_FILE_HEADER_LEN: u32 = 14;
+const BMP_BI_RGB: u32 = 0;
+const BMP_BI_BITFIELDS: u32 = 3;
+
+const QOI_HEADER_LEN: u32 = 14;
+const QOI_OP_INDEX: u8 = 0x00;
+const QOI_OP_DIFF: u8 = 0x40;
+const QOI_OP_LUMA: u8 = 0x80;
+const QOI_OP_RGB: u8 = 0xfe;
+const QOI_OP_RGBA: u8 = 0xff;
+const QOI_MASK_2: u8 = 0xc0;
+
+#[derive(Debug)]
+pub enum ImageError<E> {
+    /// The underlying flash or SD card read failed.
+    Source(E),
+    /// The data ended before the image was complete.
+    Truncated,
+    /// The data is not a BMP or QOI image.
+    Format,
+    /// The image is valid but uses a feature we don't decode, such as RLE compression.
+    Unsupported,
+    /// The display rejected the pixels.
+    Draw,
+}
+
+/// Random access reads of encoded image data.
+pub trait ImageSource {
+    type Error;
+
+    /// Reads up to `buf.len()` bytes starting at `offset` and returns the number of bytes read.
+    /// A return value of 0 means the offset is at or past the end of the data.
+    fn read_at(&mut self, offset: u32, buf: &mut [u8]) -> Result<usize, Self::Error>;
+}
+
+impl ImageSource for &[u8] {
+    type Error = core::convert::Infallible;
+
+    fn read_at(&mut self, offset: u32, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        let start = (offset as usize).min(self.len());
+        let len = buf.len().min(self.len() - start);
+        buf[..len].copy_from_slice(&self[start..start + len]);
+        Ok(len)
+    }
+}
+
+/// Adapts a seekable `embedded_io` reader, such as an `embedded-sdmmc` file, to an [`ImageSource`].
+pub struct IoSource<T>(pub T);
+
+impl<T: embedded_io::Read + embedded_io::Seek> ImageSource for IoSource<T> {
+    type Error = T::Error;
+
+    fn read_at(&mut self, offset: u32, buf: &mut [u8]) -> Result<usize, Self::Error> {
+        self.0.seek(embedded_io::SeekFrom::Start(offset as u64))?;
+        self.0.read(buf)
+    }
+}
+
+fn read_exact_at<S: ImageSource>(source: &mut S, mut offset: u32, mut buf: &mut [u8]) -> Result<(), ImageError<S::Error>> {
+    while !buf.is_empty() {
+        let read = source.read_at(offset, buf).map_err(ImageError::Source)?;
+        if read == 0 {
+            return Err(ImageError::Truncated);
+        }
+        offset += read as u32;
+        buf = &mut buf[read..];
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Bmp,
+    Qoi,
+}
+
+/// A colour channel in a BMP bitfield, stored as a shift and width.
+#[derive(Debug, Clone, Copy)]
+struct Channel {
+    shift: u32,
+    bits: u32,
+}
+
+impl Channel {
+    fn from_mask(mask: u32) -> Self {
+        if mask == 0 {
+            return Self { shift: 0, bits: 0 };
+        }
+        Self {
+            shift: mask.trailing_zeros(),
+            bits: (mask >> mask.trailing_zeros()).trailing_ones(),
+        }
+    }
+
+    /// Extracts the channel and scales it to 8 bits.
+    fn extract(&self, value: u32) -> u8 {
+        if self.bits == 0 {
+            return 0;
+        }
+        let raw = (value >> self.shift) & (u32::MAX >> (32 - self.bits));
+        if self.bits >= 8 {
+            (raw >> (self.bits - 8)) as u8
+        } else {
+            // Replicate the high bits into the low bits so full scale maps to 255.
+            let mut result = raw << (8 - self.bits);
+            let mut filled = self.bits;
+            while filled < 8 {
+                result |= result >> filled;
+                filled *= 2;
+            }
+            result as u8
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BmpInfo {
+    pixel_offset: u32,
+    bits_per_pixel: u16,
+    row_stride: u32,
+    top_down: bool,
+    red: Channel,
+    green: Channel,
+    blue: Channel,
+    palette_len: usize,
+}
+
+/// An image whose header has been parsed, ready to be drawn.
+pub struct Image<S> {
+    source: S,
+    format: ImageFormat,
+    width: u32,
+    height: u32,
+    bmp: Option<BmpInfo>,
+    palette: [Rgb565; 256],
+}
+
+impl<S: ImageSource> Image<S> {
+    /// Parses the image header, detecting the format from its magic number.
+    pub fn open(mut source: S) -> Result<Self, ImageError<S::Error>> {
+        let mut magic = [0u8; 4];
+        read_exact_at(&mut source, 0, &mut magic)?;
+
+        let mut image = Self {
+            source,
+            format: ImageFormat::Bmp,
+            width: 0,
+            height: 0,
+            bmp: None,
+            palette: [Rgb565::BLACK; 256],
+        };
+
+        if &magic[..2] == b"BM" {
+            image.open_bmp()?;
+        } else if &magic == b"qoif" {
+            image.open_qoi()?;
+        } else {
+            return Err(ImageError::Format);
+        }
+        Ok(image)
+    }
+
+    pub fn format(&self) -> ImageFormat {
+        self.format
+    }
+
+    pub fn size(&self) -> Size {
+        Size::new(self.width, self.height)
+    }
+
+    /// Returns the underlying source, for example to close a file on the SD card.
+    pub fn into_source(self) -> S {
+        self.source
+    }
+
+    /// Draws the image with its top left corner at `top_left`, clipped to the target.
+    ///
+    /// Pixels matching `transparent`, and QOI pixels with less than 50% alpha, are not drawn so that
+    /// icons can be placed over a background.
+    pub fn draw<D>(&mut self, target: &mut D, top_left: Point, transparent: Option<Rgb565>) -> Result<(), ImageError<S::Error>>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let area = Rectangle::new(top_left, self.size()).intersection(&target.bounding_box());
+        if area.is_zero_sized() {
+            return Ok(());
+        }
+
+        // Clip window in image coordinates.
+        let clip = Rectangle::new(area.top_left - top_left, area.size);
+        let mut writer = RunWriter::new(top_left, transparent);
+
+        match self.format {
+            ImageFormat::Bmp => self.draw_bmp(target, &clip, &mut writer),
+            ImageFormat::Qoi => self.draw_qoi(target, &clip, &mut writer),
+        }?;
+        writer.flush(target)
+    }
+
+    fn open_bmp(&mut self) -> Result<(), ImageError<S::Error>> {
+        let mut header = [0u8; 54];
+        read_exact_at(&mut self.source, 0, &mut header)?;
+
+        let u16_at = |i: usize| u16::from_le_bytes([header[i], header[i + 1]]);
+        let u32_at = |i: usize| u32::from_le_bytes([header[i], header[i + 1], header[i + 2], header[i + 3]]);
+
+        let pixel_offset = u32_at(10);
+        let dib_len = u32_at(14);
+        if dib_len < 40 {
+            // OS/2 style core headers are not worth supporting.
+            return Err(ImageError::Unsupported);
+        }
+        let width = u32_at(18) as i32;
+        let height = u32_at(22) as i32;
+        let bits_per_pixel = u16_at(28);
+        let compression = u32_at(30);
+        let colors_used = u32_at(46);
+
+        if width <= 0 || height == 0 {
+            return Err(ImageError::Format);
+        }
+
+        let (red, green, blue) = match (compression, bits_per_pixel) {
+            (BMP_BI_RGB, 1 | 4 | 8 | 24) => (Channel::from_mask(0), Channel::from_mask(0), Channel::from_mask(0)),
+            (BMP_BI_RGB, 16) => (Channel::from_mask(0x7c00), Channel::from_mask(0x03e0), Channel::from_mask(0x001f)),
+            (BMP_BI_RGB, 32) => (Channel::from_mask(0x00ff_0000), Channel::from_mask(0x0000_ff00), Channel::from_mask(0x0000_00ff)),
+            (BMP_BI_BITFIELDS, 16 | 32) => {
+                // The masks follow the 40 byte info header, and are part of the larger V4/V5 headers.
+                let mut masks = [0u8; 12];
+                read_exact_at(&mut self.source, BMP_FILE_HEADER_LEN + 40, &mut masks)?;
+                let mask_at = |i: usize| u32::from_le_bytes([masks[i], masks[i + 1], masks[i + 2], masks[i + 3]]);
+                (Channel::from_mask(mask_at(0)), Channel::from_mask(mask_at(4)), Channel::from_mask(mask_at(8)))
+            },
+            _ => return Err(ImageError::Unsupported),
+        };
+
+        let mut palette_len = 0;
+        if bits_per_pixel <= 8 {
+            palette_len = if colors_used == 0 { 1 << bits_per_pixel } else { colors_used.min(256) as usize };
+            let palette_offset = BMP_FILE_HEADER_LEN + dib_len;
+            let mut entry = [0u8; 4];
+            for i in 0..palette_len {
+                read_exact_at(&mut self.source, palette_offset + 4 * i as u32, &mut entry)?;
+                self.palette[i] = Rgb565::from(Rgb888::new(entry[2], entry[1], entry[0]));
+            }
+        }
+
+        self.format = ImageFormat::Bmp;
+        self.width = width as u32;
+        self.height = height.unsigned_abs();
+        self.bmp = Some(BmpInfo {
+            pixel_offset,
+            bits_per_pixel,
+            // Rows are padded to a multiple of 4 bytes.
+            row_stride: (self.width * bits_per_pixel as u32).div_ceil(32) * 4,
+            top_down: height < 0,
+            red,
+            green,
+            blue,
+            palette_len,
+        });
+        Ok(())
+    }
+
+    fn draw_bmp<D>(&mut self, target: &mut D, clip: &Rectangle, writer: &mut RunWriter) -> Result<(), ImageError<S::Error>>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let info = self.bmp.ok_or(ImageError::Format)?;
+        let bpp = info.bits_per_pixel as u32;
+        let x_start = clip.top_left.x as u32;
+        let x_end = x_start + clip.size.width;
+        let y_start = clip.top_left.y as u32;
+        let y_end = y_start + clip.size.height;
+
+        // Read rows in file order so that SD card access is sequential.
+        for file_row in 0..self.height {
+            let y = if info.top_down { file_row } else { self.height - 1 - file_row };
+            if y < y_start || y >= y_end {
+                continue;
+            }
+            let row_offset = info.pixel_offset + file_row * info.row_stride;
+
+            let mut x = x_start;
+            while x < x_end {
+                let count = ((x_end - x) as usize).min(CHUNK_PIXELS);
+
+                // Only read the bytes covering the visible pixels in this chunk.
+                let first_bit = x * bpp;
+                let first_byte = first_bit / 8;
+                let last_byte = ((x + count as u32) * bpp).div_ceil(8);
+                let mut buf = [0u8; CHUNK_PIXELS * 4];
+                let bytes = &mut buf[..(last_byte - first_byte) as usize];
+                read_exact_at(&mut self.source, row_offset + first_byte, bytes)?;
+
+                for i in 0..count {
+                    let bit = first_bit + i as u32 * bpp - first_byte * 8;
+                    let byte = (bit / 8) as usize;
+                    let color = match bpp {
+                        1 | 4 | 8 => {
+                            let shift = 8 - bpp - bit % 8;
+                            let index = ((bytes[byte] >> shift) & ((1u16 << bpp) - 1) as u8) as usize;
+                            if index >= info.palette_len {
+                                return Err(ImageError::Format);
+                            }
+                            self.palette[index]
+                        },
+                        16 => {
+                            let value = u16::from_le_bytes([bytes[byte], bytes[byte + 1]]) as u32;
+                            Rgb565::from(Rgb888::new(info.red.extract(value), info.green.extract(value), info.blue.extract(value)))
+                        },
+                        24 => Rgb565::from(Rgb888::new(bytes[byte + 2], bytes[byte + 1], bytes[byte])),
+                        _ => {
+                            let value = u32::from_le_bytes([bytes[byte], bytes[byte + 1], bytes[byte + 2], bytes[byte + 3]]);
+                            Rgb565::from(Rgb888::new(info.red.extract(value), info.green.extract(value), info.blue.extract(value)))
+                        },
+                    };
+                    writer.push(target, Point::new((x + i as u32) as i32, y as i32), Some(color))?;
+                }
+                x += count as u32;
+            }
+        }
+        Ok(())
+    }
+
+    fn open_qoi(&mut self) -> Result<(), ImageError<S::Error>> {
+        let mut header = [0u8; QOI_HEADER_LEN as usize];
+        read_exact_at(&mut self.source, 0, &mut header)?;
+
+        let width = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        let height = u32::from_be_bytes([header[8], header[9], header[10], header[11]]);
+        let channels = header[12];
+        if width == 0 || height == 0 || !(channels == 3 || channels == 4) {
+            return Err(ImageError::Format);
+        }
+
+        self.format = ImageFormat::Qoi;
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+
+    fn draw_qoi<D>(&mut self, target: &mut D, clip: &Rectangle, writer: &mut RunWriter) -> Result<(), ImageError<S::Error>>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let mut reader = QoiReader::new(&mut self.source);
+        let mut decoder = QoiDecoder::new();
+
+        // QOI can only be decoded sequentially, so decode every pixel but stop after the last visible row.
+        let last_row = clip.top_left.y as u32 + clip.size.height;
+        let mut run = 0;
+        let mut pixel = [0u8, 0, 0, 255];
+        for y in 0..last_row {
+            for x in 0..self.width {
+                if run > 0 {
+                    run -= 1;
+                } else {
+                    let (next, repeat) = decoder.next(&mut reader)?;
+                    pixel = next;
+                    run = repeat;
+                }
+                let point = Point::new(x as i32, y as i32);
+                if clip.contains(point) {
+                    let color = if pixel[3] < 128 {
+                        None
+                    } else {
+                        Some(Rgb565::from(Rgb888::new(pixel[0], pixel[1], pixel[2])))
+                    };
+                    writer.push(target, point, color)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Buffered sequential reads for the QOI decoder.
+struct QoiReader<'a, S> {
+    source: &'a mut S,
+    offset: u32,
+    buf: [u8; 64],
+    pos: usize,
+    len: usize,
+}
+
+impl<'a, S: ImageSource> QoiReader<'a, S> {
+    fn new(source: &'a mut S) -> Self {
+        Self {
+            source,
+            offset: QOI_HEADER_LEN,
+            buf: [0; 64],
+            pos: 0,
+            len: 0,
+        }
+    }
+
+    fn next_byte(&mut self) -> Result<u8, ImageError<S::Error>> {
+        if self.pos == self.len {
+            let read = self.source.read_at(self.offset, &mut self.buf).map_err(ImageError::Source)?;
+            if read == 0 {
+                return Err(ImageError::Truncated);
+            }
+            self.offset += read as u32;
+            self.pos = 0;
+            self.len = read;
+        }
+        let b = self.buf[self.pos];
+        self.pos += 1;
+        Ok(b)
+    }
+}
+
+/// The QOI decoder state, kept separate from the reader so it can be driven from any byte stream.
+struct QoiDecoder {
+    index: [[u8; 4]; 64],
+    prev: [u8; 4],
+}
+
+impl QoiDecoder {
+    fn new() -> Self {
+        Self {
+            index: [[0; 4]; 64],
+            prev: [0, 0, 0, 255],
+        }
+    }
+
+    /// Decodes the next chunk, returning the pixel and the number of further times it repeats.
+    fn next<S: ImageSource>(&mut self, reader: &mut QoiReader<'_, S>) -> Result<([u8; 4], u32), ImageError<S::Error>> {
+        let b1 = reader.next_byte()?;
+        let mut px = self.prev;
+
+        if b1 == QOI_OP_RGB {
+            px[0] = reader.next_byte()?;
+            px[1] = reader.next_byte()?;
+            px[2] = reader.next_byte()?;
+        } else if b1 == QOI_OP_RGBA {
+            px[0] = reader.next_byte()?;
+            px[1] = reader.next_byte()?;
+            px[2] = reader.next_byte()?;
+            px[3] = reader.next_byte()?;
+        } else {
+            match b1 & QOI_MASK_2 {
+                QOI_OP_INDEX => px = self.index[b1 as usize],
+                QOI_OP_DIFF => {
+                    px[0] = px[0].wrapping_add((b1 >> 4) & 0x03).wrapping_sub(2);
+                    px[1] = px[1].wrapping_add((b1 >> 2) & 0x03).wrapping_sub(2);
+                    px[2] = px[2].wrapping_add(b1 & 0x03).wrapping_sub(2);
+                },
+                QOI_OP_LUMA => {
+                    let b2 = reader.next_byte()?;
+                    let vg = (b1 & 0x3f).wrapping_sub(32);
+                    px[0] = px[0].wrapping_add(vg.wrapping_sub(8).wrapping_add((b2 >> 4) & 0x0f));
+                    px[1] = px[1].wrapping_add(vg);
+                    px[2] = px[2].wrapping_add(vg.wrapping_sub(8).wrapping_add(b2 & 0x0f));
+                },
+                _ => {
+                    // QOI_OP_RUN repeats the previous pixel, which is not added to the index again.
+                    return Ok((px, (b1 & 0x3f) as u32));
+                },
+            }
+        }
+
+        let hash = (px[0] as usize * 3 + px[1] as usize * 5 + px[2] as usize * 7 + px[3] as usize * 11) % 64;
+        self.index[hash] = px;
+        self.prev = px;
+        Ok((px, 0))
+    }
+}
+
+/// Collects horizontally adjacent pixels so they can be sent to the display in one transfer.
+struct RunWriter {
+    offset: Point,
+    transparent: Option<Rgb565>,
+    start: Point,
+    len: usize,
+    pixels: [Rgb565; CHUNK_PIXELS],
+}
+
+impl RunWriter {
+    fn new(offset: Point, transparent: Option<Rgb565>) -> Self {
+        Self {
+            offset,
+            transparent,
+            start: Point::zero(),
+            len: 0,
+            pixels: [Rgb565::BLACK; CHUNK_PIXELS],
+        }
+    }
+
+    /// Adds a pixel at the given image position, `None` being a transparent pixel.
+    fn push<D, E>(&mut self, target: &mut D, point: Point, color: Option<Rgb565>) -> Result<(), ImageError<E>>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        let color = match color {
+            Some(color) if Some(color) != self.transparent => color,
+            _ => return self.flush(target),
+        };
+
+        if self.len == CHUNK_PIXELS || (self.len > 0 && point != self.start + Point::new(self.len as i32, 0)) {
+            self.flush(target)?;
+        }
+        if self.len == 0 {
+            self.start = point;
+        }
+        self.pixels[self.len] = color;
+        self.len += 1;
+        Ok(())
+    }
+
+    fn flush<D, E>(&mut self, target: &mut D) -> Result<(), ImageError<E>>
+    where
+        D: DrawTarget<Color = Rgb565>,
+    {
+        if self.len > 0 {
+            let area = Rectangle::new(self.offset + self.start, Size::new(self.len as u32, 1));
+            target
+                .fill_contiguous(&area, self.pixels[..self.len].iter().copied())
+                .map_err(|_| ImageError::Draw)?;
+            self.len = 0;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use embedded_graphics::mock_display::MockDisplay;
+    use std::{vec, vec::Vec};
+
+    /// The 3x4 image in each fixture, row by row.
+    const COLORS: [(u8, u8, u8); 12] = [
+        (255, 0, 0), (0, 255, 0), (0, 0, 255),
+        (255, 255, 255), (0, 0, 0), (128, 64, 32),
+        (18, 52, 86), (255, 255, 0), (0, 255, 255),
+        (255, 0, 255), (127, 127, 127), (255, 128, 0),
+    ];
+
+    fn rgb((r, g, b): (u8, u8, u8)) -> Rgb565 {
+        Rgb888::new(r, g, b).into()
+    }
+
+    fn expected() -> Vec<Option<Rgb565>> {
+        COLORS.iter().map(|&color| Some(rgb(color))).collect()
+    }
+
+    /// Draws an image at `top_left` and returns the pixels of the 3x4 area at the origin.
+    fn draw_at(data: &[u8], top_left: Point, transparent: Option<Rgb565>) -> Vec<Option<Rgb565>> {
+        let mut image = Image::open(data).unwrap();
+        assert_eq!(image.size(), Size::new(3, 4));
+        let mut display = MockDisplay::new();
+        image.draw(&mut display, top_left, transparent).unwrap();
+        (0..4).flat_map(|y| (0..3).map(move |x| Point::new(x, y))).map(|point| display.get_pixel(point)).collect()
+    }
+
+    fn draw(data: &[u8]) -> Vec<Option<Rgb565>> {
+        draw_at(data, Point::zero(), None)
+    }
+
+    #[test]
+    fn decodes_true_colour_bmps() {
+        assert_eq!(Image::open(include_bytes!("../fixtures/rgb24.bmp").as_slice()).unwrap().format(), ImageFormat::Bmp);
+        assert_eq!(draw(include_bytes!("../fixtures/rgb24.bmp")), expected());
+        assert_eq!(draw(include_bytes!("../fixtures/bgra32.bmp")), expected());
+        // 10 bits a channel in 32 bit bitfields, scaled down to 8.
+        assert_eq!(draw(include_bytes!("../fixtures/rgb101010.bmp")), expected());
+    }
+
+    #[test]
+    fn decodes_16_bit_bmps() {
+        // The 5 and 6 bit channels scaled back up to 8 bits by repeating their top bits, which for 5-5-5 is
+        // what tinybmp decodes too.
+        let rgb555 = [
+            (255, 0, 0), (0, 255, 0), (0, 0, 255),
+            (255, 255, 255), (0, 0, 0), (132, 66, 33),
+            (16, 49, 82), (255, 255, 0), (0, 255, 255),
+            (255, 0, 255), (123, 123, 123), (255, 132, 0),
+        ];
+        let rgb565 = [
+            (255, 0, 0), (0, 255, 0), (0, 0, 255),
+            (255, 255, 255), (0, 0, 0), (132, 65, 33),
+            (16, 52, 82), (255, 255, 0), (0, 255, 255),
+            (255, 0, 255), (123, 125, 123), (255, 130, 0),
+        ];
+        let colors = |colors: [(u8, u8, u8); 12]| colors.into_iter().map(|color| Some(rgb(color))).collect::<Vec<_>>();
+        assert_eq!(draw(include_bytes!("../fixtures/rgb555.bmp")), colors(rgb555));
+        assert_eq!(draw(include_bytes!("../fixtures/rgb565.bmp")), colors(rgb565));
+    }
+
+    #[test]
+    fn decodes_palette_bmps() {
+        // A top down 8 bit image with a 12 colour palette.
+        assert_eq!(draw(include_bytes!("../fixtures/indexed8.bmp")), expected());
+        // A bottom up 4 bit image with a full 16 colour palette.
+        assert_eq!(draw(include_bytes!("../fixtures/indexed4.bmp")), expected());
+        let checks: Vec<_> = (0..12).map(|i| Some(if (i % 3 + i / 3) % 2 == 0 { Rgb565::BLACK } else { Rgb565::WHITE })).collect();
+        assert_eq!(draw(include_bytes!("../fixtures/mono1.bmp")), checks);
+    }
+
+    #[test]
+    fn decodes_qoi() {
+        let data = include_bytes!("../fixtures/rgba.qoi");
+        assert_eq!(Image::open(data.as_slice()).unwrap().format(), ImageFormat::Qoi);
+        // The grey pixel is fully transparent.
+        let mut pixels = expected();
+        pixels[10] = None;
+        assert_eq!(draw(data), pixels);
+    }
+
+    #[test]
+    fn skips_the_transparent_colour() {
+        let mut pixels = expected();
+        pixels[4] = None;
+        assert_eq!(draw_at(include_bytes!("../fixtures/rgb24.bmp"), Point::zero(), Some(Rgb565::BLACK)), pixels);
+    }
+
+    #[test]
+    fn clips_to_the_display() {
+        // Moved up and left by one, so only the bottom right 2x3 of the image is drawn.
+        let shown = |data: &[u8]| draw_at(data, Point::new(-1, -1), None);
+        let mut pixels = vec![None; 12];
+        for y in 0..3 {
+            for x in 0..2 {
+                pixels[y * 3 + x] = Some(rgb(COLORS[(y + 1) * 3 + x + 1]));
+            }
+        }
+        assert_eq!(shown(include_bytes!("../fixtures/rgb24.bmp")), pixels);
+        assert_eq!(shown(include_bytes!("../fixtures/indexed8.bmp")), pixels);
+        let mut qoi = vec![None; 12];
+        for y in 0..3 {
+            for x in 0..2 {
+                let i = (y + 1) * 3 + x + 1;
+                qoi[y * 3 + x] = (i != 10).then(|| rgb(COLORS[i]));
+            }
+        }
+        assert_eq!(shown(include_bytes!("../fixtures/rgba.qoi")), qoi);
+
+        let mut display = MockDisplay::<Rgb565>::new();
+        let mut image = Image::open(include_bytes!("../fixtures/rgb24.bmp").as_slice()).unwrap();
+        image.draw(&mut display, Point::new(-10, -10), None).unwrap();
+        assert_eq!(display.affected_area(), Rectangle::zero());
+    }
+
+    #[test]
+    fn rejects_bad_data() {
+        let rgb24 = include_bytes!("../fixtures/rgb24.bmp");
+        assert!(matches!(Image::open(b"GIF89a".as_slice()), Err(ImageError::Format)));
+        assert!(matches!(Image::open(b"BM".as_slice()), Err(ImageError::Truncated)));
+
+        // RLE compression.
+        let mut rle = rgb24.to_vec();
+        rle[30] = 1;
+        assert!(matches!(Image::open(rle.as_slice()), Err(ImageError::Unsupported)));
+
+        // The header is fine but the pixels run out, which is only found when drawing.
+        let short = &rgb24[..rgb24.len() - 4];
+        let mut image = Image::open(short).unwrap();
+        assert!(matches!(image.draw(&mut MockDisplay::new(), Point::zero(), None), Err(ImageError::Truncated)));
+
+        let qoi = include_bytes!("../fixtures/rgba.qoi");
+        let mut image = Image::open(&qoi[..20]).unwrap();
+        assert!(matches!(image.draw(&mut MockDisplay::new(), Point::zero(), None), Err(ImageError::Truncated)));
+        let mut two_channels = qoi.to_vec();
+        two_channels[12] = 2;
+        assert!(matches!(Image::open(two_channels.as_slice()), Err(ImageError::Format)));
+    }
+
+    #[test]
+    fn scales_bitfield_channels() {
+        // A channel can be the full 32 bits.
+        let full = Channel::from_mask(u32::MAX);
+        assert_eq!(full.extract(0xff00_0000), 0xff);
+        assert_eq!(full.extract(0x7fff_ffff), 0x7f);
+        assert_eq!(Channel::from_mask(0).extract(u32::MAX), 0);
+        let one = Channel::from_mask(0x8000_0000);
+        assert_eq!((one.extract(0x8000_0000), one.extract(0x7fff_ffff)), (0xff, 0));
+        // 3 bits replicate out to 8.
+        assert_eq!(Channel::from_mask(0x70).extract(0x50), 0b1011_0110);
+    }
+
+    #[test]
+    fn matches_the_reference_qoi_encoder() {
+        // A 37x23 image with gradients, noise, repeats and transparency, so every QOI op is used.
+        let (width, height) = (37u32, 23u32);
+        let mut seed = 1u32;
+        let mut pixels = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                let noise = (seed >> 16) as u8;
+                let pixel = match (y / 6, x % 5) {
+                    (0, _) => [x as u8 * 7, y as u8 * 3, 40, 255],
+                    (1, 0 | 1) => [200, 10, 10, 255],
+                    (1, _) => [noise, noise / 2, 255 - noise, 255],
+                    (2, _) => [x as u8 * 2 + 100, x as u8 * 2 + 98, x as u8 * 2 + 105, if x % 3 == 0 { 0 } else { 255 }],
+                    _ => [noise, noise, noise, noise],
+                };
+                pixels.push(pixel);
+            }
+        }
+        let flat: Vec<u8> = pixels.iter().flatten().copied().collect();
+        let data = qoi::encode_to_vec(&flat, width, height).unwrap();
+
+        let mut image = Image::open(data.as_slice()).unwrap();
+        assert_eq!(image.size(), Size::new(width, height));
+        let mut display = MockDisplay::new();
+        image.draw(&mut display, Point::zero(), None).unwrap();
+        for (i, pixel) in pixels.iter().enumerate() {
+            let point = Point::new((i as u32 % width) as i32, (i as u32 / width) as i32);
+            let expected = (pixel[3] >= 128).then(|| rgb((pixel[0], pixel[1], pixel[2])));
+            assert_eq!(display.get_pixel(point), expected, "at {}", point);
+        }
+    }
+}
@@ -9,8 +9,11 @@
 
 use defmt::info;
 use core::fmt::Write;
-use chrono::NaiveTime;
 use chrono::Timelike;
+use timesync::{self, TimeSync};
+use embassy_executor::Spawner;
+use embassy_net::{Runner, Stack, StackResources};
+use embassy_time::{Duration, Timer};
 use embedded_graphics::primitives::Circle;
 use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
 use esp_hal::rtc_cntl::Rtc;
@@ -20,12 +23,24 @@ use embedded_graphics::text::Text;
 use embedded_graphics::mono_font::MonoTextStyle;
 use embedded_graphics::mono_font::ascii::FONT_10X20;
 // use embedded_graphics::prelude::*;
+use clock_core::alarm::{Actions, Alarm, AlarmClock, AlarmEvent, Beep, Repeat, StripPattern, Weekdays};
+use clock_core::face::{ClockFace, Numerals, Theme};
+use clock_core::timer::{Mode, Press, PressDetector, Timers, draw_progress_ring, format_duration};
+use clock_core::tz::PosixTz;
+use clock_core::world::{WorldClock, Zone, ZoneStyle};
+use esp_alloc as _;
 use esp_backtrace as _;
 use esp_hal::clock::CpuClock;
 use esp_hal::delay::Delay;
-use esp_hal::gpio::{Level, Output, OutputConfig};
-use esp_hal::main;
+use esp_hal::gpio::{Input, InputConfig, Level, Output, OutputConfig, Pull};
+use esp_hal::peripherals::WIFI;
 use esp_hal::rmt::Rmt;
+use esp_hal::rng::Rng;
+use esp_hal::timer::timg::TimerGroup;
+use esp_radio::{
+    Controller,
+    wifi::{ClientConfig, ModeConfig, WifiController, WifiDevice, WifiEvent, WifiStaState},
+};
 use esp_hal::time::Rate;
 use esp_hal_smartled::{SmartLedsAdapter, smart_led_buffer};
 use smart_leds::{RGB8,SmartLedsWrite};
@@ -33,7 +48,7 @@ use esp_hal::spi::master::Config;
 use mipidsi::{Builder, models::ILI9341Rgb565, options::{Orientation, Rotation}};
 use embedded_graphics::{pixelcolor::Rgb565, prelude::*};
 use esp_hal::spi::master::Spi;
-use timer_blink_cyd::*;
+use clock_core::*;
 
 // This creates a default app-descriptor required by the esp-idf bootloader.
 // For more information see: <https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-reference/system/app_image_format.html#application-description>
@@ -54,13 +69,28 @@ esp_bootloader_esp_idf::esp_app_desc!();
  * Since the CYD does not output 5V on CN1 you will need to power the WS2812 strip separately with 5V and connect the grounds together (by connecting the
  * CN! GND pin to the strip and then connecting a separate 5V power supply to the WS2812 additional power wires).
  * 
- * The CYD's RTC peripheral is used to keep track of time, but has no means of finding the real time of day so initializes the time to 8:05am at start.    
+ * The CYD's RTC peripheral is used to keep track of time. When the SSID and PASSWORD environment variables are set at build time it joins that
+ * network and keeps the RTC in step with NTP, otherwise it has no means of finding the real time of day so initializes the time to 8:05am at start.
+ * When the ALARM environment variable is set at build time to a time like 07:30 an alarm rings then every day.
  ****************************************************************************************************************************************************** */
 
+// When you are okay with using a nightly compiler it's better to use https://docs.rs/static_cell/2.1.0/static_cell/macro.make_static.html
+macro_rules! mk_static {
+    ($t:ty,$val:expr) => {{
+        static STATIC_CELL: static_cell::StaticCell<$t> = static_cell::StaticCell::new();
+        #[deny(unused_attributes)]
+        let x = STATIC_CELL.uninit().write(($val));
+        x
+    }};
+}
 
+const SSID: Option<&str> = option_env!("SSID");
+const PASSWORD: Option<&str> = option_env!("PASSWORD");
+const ALARM: Option<&str> = option_env!("ALARM");
+const NTP_SERVERS: &[&str] = &["pool.ntp.org", "time.google.com", "time.cloudflare.com"];
 
-#[main]
-fn main() -> ! {
+#[esp_rtos::main]
+async fn main(spawner: Spawner) -> ! {
     // generator version: 0.6.0
 
     esp_println::logger::init_logger_from_env();
@@ -222,12 +252,42 @@ fn main() -> ! {
     //     text.draw(&mut display).unwrap();
     // }
 
-    let rtc = Rtc::new(cyd_result.remainder.lpwr);
-    
+    esp_alloc::heap_allocator!(size: 72 * 1024);
+
+    let timg0 = TimerGroup::new(cyd_result.remainder.timg0);
+    esp_rtos::start(timg0.timer0);
+
+    let rtc = &*mk_static!(Rtc<'static>, Rtc::new(cyd_result.remainder.lpwr));
+
+    // The BOOT button snoozes a ringing alarm and drives the timers, the speaker connector beeps.
+    let button = Input::new(cyd_result.remainder.gpio0, InputConfig::default().with_pull(Pull::Up));
+    let mut speaker = Output::new(cyd_result.remainder.gpio26, Level::Low, OutputConfig::default());
+
+    // The RTC keeps UTC, the clock shows UK time and the alarms are set in it.
+    let tz = PosixTz::parse("GMT0BST,M3.5.0/1,M10.5.0/2").unwrap();
+    let mut alarms = AlarmClock::<4>::new(tz);
+    let alarm = ALARM
+        .and_then(|alarm| alarm.split_once(':'))
+        .and_then(|(hour, minute)| Alarm::new(
+            hour.parse().ok()?, minute.parse().ok()?,
+            Repeat::Weekly(Weekdays::EVERY_DAY),
+            Actions::new()
+                .with_strip(StripPattern::Rainbow { brightness: LEVEL })
+                .with_rgb_led(true, false, false)
+                .with_speaker(Beep::DEFAULT),
+        ));
+    match (ALARM, alarm) {
+        (Some(_), Some(alarm)) => { alarms.add(alarm).ok(); }
+        (Some(text), None) => info!("ALARM {} is not a time like 07:30", text),
+        (None, _) => {}
+    }
 
+    rtc.set_current_time_us(((8 * 60) + 5) * 60 * 1000 * 1000); // set to 08:05:00.000 until NTP answers
 
-    rtc.set_current_time_us(((8 * 60) + 5) * 60 * 1000 * 1000); // set to 08:05:00.000
-    let mut elapsed_ms: u32;
+    if let (Some(ssid), Some(password)) = (SSID, PASSWORD) {
+        let stack = start_wifi(spawner, cyd_result.remainder.wifi, ssid, password);
+        spawner.spawn(time_sync(stack, rtc)).ok();
+    }
     let mut bg = 0;
 
     cyd.display.clear(background[bg]).unwrap();
@@ -239,27 +299,49 @@ fn main() -> ! {
 
     cyd.display.clear(bg_color).unwrap();
 
-    let clock_face = create_face(&cyd.display);
-    draw_face(&mut cyd.display, &clock_face, clock_color).unwrap();
+    let face = ClockFace::fit(&cyd.display, 10, Theme::CLASSIC)
+        .with_minute_ticks(true)
+        .with_numerals(Numerals::Arabic);
+    face.draw(&mut cyd.display).unwrap();
 
-    let mut prev_hour = 0;
-    let mut prev_minute = 0;
-    let mut prev_second = 0;
+    let clock_face = face.circle;
+    let mut hands = face.hands();
 
     let mut led_cnt=0;
+    let mut last_secs = 0;
+    let mut last_now = rtc.current_time_us() as i64;
+
+    let mut timers = Timers::<8>::new(5 * 60 * 1000);
+    let mut presses = PressDetector::new();
+    let mut expired_at: Option<u64> = None;
+
+    let mut world = WorldClock::new(ZoneStyle::Analog, Theme::CLASSIC, Theme::NIGHT);
+    world.add(Zone::new("London", tz)).unwrap();
+    world.add(Zone::new("New York", PosixTz::parse("EST5EDT,M3.2.0/2,M11.1.0/2").unwrap())).unwrap();
+    world.add(Zone::new("Tokyo", PosixTz::parse("JST-9").unwrap())).unwrap();
+    world.add(Zone::new("Sydney", PosixTz::parse("AEST-10AEDT,M10.1.0/2,M4.1.0/3").unwrap())).unwrap();
+    // Everything below the time text.
+    let world_area = Rectangle::new(Point::new(0, 40), cyd.display.bounding_box().size - Size::new(0, 40));
 
     loop {
         // let now = Timestamp::from_microsecond(rtc.current_time_us() as i64)?;
         let now = rtc.current_time_us() as i64;
         info!("now: {}", now);
-        elapsed_ms = (now / 1000) as u32;
-        info!("elapsed_ms: {}", elapsed_ms);
-        let secs = elapsed_ms / 1000;
-        let bedtime = 1000 * (secs + 1) - elapsed_ms;
-        let nanos = now % 1_000_000;
-        // let duration = chrono::Duration::milliseconds(now);
-        // let time = NaiveTime::from_hms_milli_opt(0, 0, secs, ms).unwrap();
-        let time = NaiveTime::from_num_seconds_from_midnight_opt(secs, nanos as u32).unwrap();
+        let secs = now / 1_000_000;
+
+        // A big jump means NTP has set the clock, so work out the alarms again from the new time.
+        if (now - last_now).abs() > 5_000_000 {
+            info!("Clock set");
+            alarms.clock_changed();
+        }
+        last_now = now;
+        let now_ms = (now / 1000) as u64;
+        // The timers tick ten times a second so the tenths are shown.
+        let bedtime = match timers.mode {
+            Mode::Clock | Mode::World => 1000 - (now_ms % 1000) as u32,
+            _ => 100 - (now_ms % 100) as u32,
+        };
+        let time = tz.to_local(secs).time();
 
         let mut time_str: String<64> = String::new();
         write!(time_str, "Time: {:02}:{:02}:{:02}", time.hour(), time.minute(), time.second()).unwrap();
@@ -283,61 +365,163 @@ fn main() -> ! {
         let text = Text::new(time_str.as_str(), Point::new(0, 30), text_style);
         text.draw(&mut cyd.display).unwrap();
 
-        led.write([color, color2, color3].into_iter()).unwrap();
-        delay.delay_millis(bedtime);
-        // elapsed_ms += 1000;
-
-        color3 = color2;
-        color2 = color;
-        let tmp = color.r;
-        color.r = color.g;
-        color.g = color.b;
-        color.b = tmp;
-
-        led_cnt = if led_cnt == 0 {
-            cyd.led_red(true);
-            cyd.led_blue(false);
-            1
-        } else if led_cnt == 1 {
-            cyd.led_green(true);
-            cyd.led_red(false);
-            2
-        } else {
-            cyd.led_blue(true);
-            cyd.led_green(false);
-            0
-        };
+        match alarms.poll(now / 1_000_000) {
+            Some(AlarmEvent::Started(index)) => info!("Alarm {} ringing", index),
+            Some(AlarmEvent::TimedOut(index)) => info!("Alarm {} timed out", index),
+            None => {}
+        }
 
-        // display.clear(background[bg]).unwrap();
-        // bg = (bg + 1) % background.len();
+        if timers.poll(now_ms) {
+            info!("Countdown finished");
+            expired_at = Some(now_ms);
+        }
 
-        let hour = time.hour();
-        let minute = time.minute();
-        let second = time.second();
+        let mut press = None;
+
+        if let Some((ringing, actions)) = alarms.ringing().zip(alarms.ringing_actions().copied()) {
+            // Play the alarm until the next second instead of sleeping.
+            let mut alarm_ms = ringing.elapsed_ms(now / 1000);
+            let end_ms = alarm_ms + bedtime;
+            while alarm_ms < end_ms {
+                if let Some(pattern) = actions.strip {
+                    led.write((0..3).map(|i| {
+                        let [r, g, b] = pattern.color(i, 3, alarm_ms);
+                        RGB8::new(r, g, b)
+                    })).unwrap();
+                }
+                if let Some([r, g, b]) = actions.rgb_led_at(alarm_ms) {
+                    cyd.led_red(r);
+                    cyd.led_green(g);
+                    cyd.led_blue(b);
+                }
+
+                let step = 20.min(end_ms - alarm_ms);
+                match actions.speaker.filter(|beep| beep.remaining_ms(alarm_ms).is_some()) {
+                    Some(beep) => {
+                        // Toggle a square wave into the speaker amplifier, awaiting each half period so the Wi-Fi and NTP tasks
+                        // keep running while the alarm rings.
+                        let half_period_us = 500_000 / beep.frequency_hz.max(1);
+                        for _ in 0..(step * 1000 / (2 * half_period_us)).max(1) {
+                            speaker.set_high();
+                            Timer::after(Duration::from_micros(half_period_us as u64)).await;
+                            speaker.set_low();
+                            Timer::after(Duration::from_micros(half_period_us as u64)).await;
+                        }
+                    }
+                    None => Timer::after(Duration::from_millis(step as u64)).await,
+                }
+                alarm_ms += step;
+                press = press.or(presses.update(button.is_low(), rtc.current_time_us() / 1000));
+            }
+        } else {
+            match expired_at.filter(|&at| now_ms.saturating_sub(at) < 5000) {
+                Some(at) => {
+                    // Flash the strip when the countdown runs out.
+                    let pattern = StripPattern::Flash([LEVEL, 0, 0]);
+                    led.write((0..3).map(|i| {
+                        let [r, g, b] = pattern.color(i, 3, now_ms.saturating_sub(at) as u32);
+                        RGB8::new(r, g, b)
+                    })).unwrap();
+                }
+                None => led.write([color, color2, color3].into_iter()).unwrap(),
+            }
+
+            // Sleep until the next tick, watching the button.
+            let mut waited = 0;
+            while waited < bedtime && press.is_none() {
+                let step = 10.min(bedtime - waited);
+                Timer::after(Duration::from_millis(step as u64)).await;
+                waited += step;
+                press = presses.update(button.is_low(), rtc.current_time_us() / 1000);
+            }
+            // elapsed_ms += 1000;
+        }
 
-        if hour != prev_hour {
-             draw_hand(&mut cyd.display, &clock_face, bg_color, hour_to_angle(prev_hour), -60).unwrap();
+        if let Some(press) = press {
+            if alarms.ringing().is_some() {
+                info!("Alarm snoozed");
+                alarms.snooze(now / 1_000_000);
+            } else if timers.handle(press, rtc.current_time_us() / 1000) || press == Press::Long {
+                // Clear whatever the last mode or lap drew.
+                cyd.display.clear(bg_color).unwrap();
+                if timers.mode == Mode::World {
+                    world.reset();
+                } else {
+                    face.draw(&mut cyd.display).unwrap();
+                    hands.reset();
+                }
+            }
         }
-        if minute != prev_minute {
-             draw_hand(&mut cyd.display, &clock_face, bg_color, sexagesimal_to_angle(prev_minute), -30).unwrap();
+
+        if secs != last_secs && alarms.ringing().is_none() {
+            last_secs = secs;
+
+            color3 = color2;
+            color2 = color;
+            let tmp = color.r;
+            color.r = color.g;
+            color.g = color.b;
+            color.b = tmp;
+
+            led_cnt = if led_cnt == 0 {
+                cyd.led_red(true);
+                cyd.led_blue(false);
+                1
+            } else if led_cnt == 1 {
+                cyd.led_green(true);
+                cyd.led_red(false);
+                2
+            } else {
+                cyd.led_blue(true);
+                cyd.led_green(false);
+                0
+            };
         }
 
-        if second != prev_second {
-            let seconds_radians = sexagesimal_to_angle(prev_second);
-            draw_hand(&mut cyd.display, &clock_face, bg_color, seconds_radians, 0).unwrap();
-            draw_second_decoration(&mut cyd.display, &clock_face, bg_color, bg_color, seconds_radians, -20).unwrap();
+        if timers.mode == Mode::World {
+            world.draw(&mut cyd.display, &world_area, now / 1_000_000).unwrap();
+            continue;
         }
 
-        prev_hour = hour;
-        prev_minute = minute;
-        prev_second = second;
+        if timers.mode != Mode::Clock {
+            let now_ms = rtc.current_time_us() / 1000;
+            let (shown, progress) = match timers.mode {
+                Mode::Countdown => (timers.countdown.remaining(now_ms), timers.countdown.progress(now_ms)),
+                _ => {
+                    let elapsed = timers.stopwatch.elapsed(now_ms);
+                    (elapsed, (elapsed % 60_000) as f32 / 60_000.0)
+                }
+            };
+
+            draw_progress_ring(&mut cyd.display, &clock_face, face.tick_length + 22, 6, progress, clock_color, bg_color).unwrap();
+
+            let mut duration_str: String<16> = String::new();
+            format_duration(shown, &mut duration_str).unwrap();
+            draw_digital_clock(&mut cyd.display, &clock_face, clock_color, bg_color, duration_str.as_str()).unwrap();
+
+            if let Some(lap) = timers.stopwatch.last_lap().filter(|_| timers.mode == Mode::Stopwatch) {
+                let mut lap_str: String<32> = String::new();
+                format_duration(lap, &mut duration_str).unwrap();
+                write!(lap_str, "Lap {} {}", timers.stopwatch.laps().len(), duration_str.as_str()).unwrap();
+
+                let lap_pos = clock_face.center() + Point::new(-70, 40);
+                Rectangle::new(lap_pos - Point::new(0, 16), Size::new(140, 22))
+                    .into_styled(PrimitiveStyle::with_fill(bg_color))
+                    .draw(&mut cyd.display).unwrap();
+                Text::new(lap_str.as_str(), lap_pos, text_style).draw(&mut cyd.display).unwrap();
+            }
+            continue;
+        }
 
-        draw_hand(&mut cyd.display, &clock_face, clock_color, hour_to_angle(hour), -60).unwrap();
-        draw_hand(&mut cyd.display, &clock_face, clock_color, sexagesimal_to_angle(minute), -30).unwrap();
+        // display.clear(background[bg]).unwrap();
+        // bg = (bg + 1) % background.len();
+
+        let hour = time.hour();
+        let minute = time.minute();
+        let second = time.second();
 
-        let seconds_radians = sexagesimal_to_angle(second);
-        draw_hand(&mut cyd.display, &clock_face, clock_color, seconds_radians, 0).unwrap();
-        draw_second_decoration(&mut cyd.display, &clock_face, clock_color, bg_color, seconds_radians, -20).unwrap();
+        let angles = [face.hour_angle(hour, minute), sexagesimal_to_angle(minute), sexagesimal_to_angle(second)];
+        hands.update(&mut cyd.display, &clock_face, bg_color, angles, &face).unwrap();
 
         // Draw a small circle over the hands in the center of the clock face.
         // This has to happen after the hands are drawn so they're covered up.
@@ -362,3 +546,63 @@ fn main() -> ! {
 
     // for inspiration have a look at the examples at https://github.com/esp-rs/esp-hal/tree/esp-hal-v1.0.0-rc.1/examples/src/bin
 }
+
+/// Starts the wifi and network stack, returning the stack once the tasks driving them are running.
+fn start_wifi(spawner: Spawner, wifi: WIFI<'static>, ssid: &'static str, password: &'static str) -> Stack<'static> {
+    let esp_radio_ctrl = &*mk_static!(Controller<'static>, esp_radio::init().unwrap());
+    let (controller, interfaces) = esp_radio::wifi::new(esp_radio_ctrl, wifi, Default::default()).unwrap();
+
+    let rng = Rng::new();
+    let seed = (rng.random() as u64) << 32 | rng.random() as u64;
+
+    let (stack, runner) = embassy_net::new(
+        interfaces.sta,
+        embassy_net::Config::dhcpv4(Default::default()),
+        mk_static!(StackResources<3>, StackResources::<3>::new()),
+        seed,
+    );
+
+    spawner.spawn(connection(controller, ssid, password)).ok();
+    spawner.spawn(net_task(runner)).ok();
+    stack
+}
+
+#[embassy_executor::task]
+async fn connection(mut controller: WifiController<'static>, ssid: &'static str, password: &'static str) {
+    loop {
+        if esp_radio::wifi::sta_state() == WifiStaState::Connected {
+            // wait until we're no longer connected
+            controller.wait_for_event(WifiEvent::StaDisconnected).await;
+            Timer::after(Duration::from_millis(5000)).await
+        }
+        if !matches!(controller.is_started(), Ok(true)) {
+            let client_config = ModeConfig::Client(
+                ClientConfig::default()
+                    .with_ssid(ssid.into())
+                    .with_password(password.into()),
+            );
+            controller.set_config(&client_config).unwrap();
+            controller.start_async().await.unwrap();
+            info!("Wifi started");
+        }
+
+        match controller.connect_async().await {
+            Ok(_) => info!("Wifi connected"),
+            Err(e) => {
+                info!("Failed to connect to wifi: {}", defmt::Debug2Format(&e));
+                Timer::after(Duration::from_millis(5000)).await
+            }
+        }
+    }
+}
+
+#[embassy_executor::task]
+async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn time_sync(stack: Stack<'static>, rtc: &'static Rtc<'static>) {
+    let mut sync = TimeSync::new(NTP_SERVERS);
+    timesync::run(stack, &mut sync, rtc).await
+}
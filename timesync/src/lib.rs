@@ -0,0 +1,447 @@
+#![no_std]
+
+//! Keeps the RTC in step with NTP servers.
+//!
+//! `TimeSync` decides when to sync, which server to ask and how fast the RTC drifts, and applies
+//! the corrections. It does no networking itself, so the logic works with any UDP stack. With the
+//! `ntp` feature, `request` asks a server over embassy-net using sntpc and `run` keeps the clock in
+//! step from an embassy task. The `esp32` feature makes the RTC a `Clock`:
+//!
+//! ```rust,ignore
+//! #[embassy_executor::task]
+//! async fn time_sync(stack: Stack<'static>, rtc: &'static Rtc<'static>) {
+//!     let mut sync = TimeSync::new(&["pool.ntp.org", "time.google.com"]);
+//!     timesync::run(stack, &mut sync, rtc).await
+//! }
+//! ```
+
+#[cfg(feature = "esp32")]
+use esp_hal::rtc_cntl::Rtc;
+
+pub const NTP_PORT: u16 = 123;
+
+/// Microseconds in a second.
+const USEC_IN_SEC: u64 = 1_000_000;
+
+/// Something which keeps time in microseconds since the Unix epoch, normally the RTC.
+pub trait Clock {
+    fn now_us(&self) -> u64;
+    fn set_us(&self, us: u64);
+}
+
+#[cfg(feature = "esp32")]
+impl Clock for Rtc<'_> {
+    fn now_us(&self) -> u64 {
+        self.current_time_us()
+    }
+
+    fn set_us(&self, us: u64) {
+        self.set_current_time_us(us);
+    }
+}
+
+/// The result of asking a server for the time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    /// How far the server is ahead of the local clock, in microseconds.
+    pub offset_us: i64,
+    pub roundtrip_us: u64,
+}
+
+/// NTP scheduling, server failover and drift correction for one clock.
+///
+/// After the first sync each sample's offset is divided by the time since the last one to give
+/// the drift of the clock, which `trim` then takes out between syncs. Drift corrections only get
+/// applied a millisecond at a time, so the clock never jumps by more than that between syncs.
+#[derive(Debug, Clone)]
+pub struct TimeSync<'a> {
+    servers: &'a [&'a str],
+    server: usize,
+    /// Failed requests since the last success.
+    failures: u32,
+    /// Time between successful syncs, in microseconds.
+    pub interval_us: u64,
+    /// Time before the first retry after a failure, doubling each time up to `interval_us`.
+    pub retry_us: u64,
+    /// Requests to try on one server before moving on to the next.
+    pub tries_per_server: u32,
+    /// Local time of the next sync, None to sync straight away.
+    next_sync: Option<u64>,
+    last_sync: Option<u64>,
+    last_trim: u64,
+    /// Drift of the clock in parts per million, positive when it runs slow.
+    drift_ppm: Option<f32>,
+}
+
+impl<'a> TimeSync<'a> {
+    pub const DEFAULT_INTERVAL_US: u64 = 60 * 60 * USEC_IN_SEC;
+    pub const DEFAULT_RETRY_US: u64 = 5 * USEC_IN_SEC;
+    /// Samples closer together than this are too noisy to estimate the drift from.
+    const MIN_DRIFT_SPAN_US: u64 = 10 * 60 * USEC_IN_SEC;
+    /// Drift estimates further out than this are treated as a bad sample.
+    const MAX_DRIFT_PPM: f32 = 500.0;
+
+    pub const fn new(servers: &'a [&'a str]) -> Self {
+        Self {
+            servers,
+            server: 0,
+            failures: 0,
+            interval_us: Self::DEFAULT_INTERVAL_US,
+            retry_us: Self::DEFAULT_RETRY_US,
+            tries_per_server: 2,
+            next_sync: None,
+            last_sync: None,
+            last_trim: 0,
+            drift_ppm: None,
+        }
+    }
+
+    pub const fn with_interval(mut self, interval_us: u64) -> Self {
+        self.interval_us = interval_us;
+        self
+    }
+
+    pub const fn with_retry(mut self, retry_us: u64) -> Self {
+        self.retry_us = retry_us;
+        self
+    }
+
+    /// The server to ask next, or None if there are no servers.
+    pub fn server(&self) -> Option<&'a str> {
+        self.servers.get(self.server).copied()
+    }
+
+    /// True once the clock has been set from a server.
+    pub fn is_synced(&self) -> bool {
+        self.last_sync.is_some()
+    }
+
+    /// Local time of the last successful sync.
+    pub fn last_sync(&self) -> Option<u64> {
+        self.last_sync
+    }
+
+    pub fn drift_ppm(&self) -> Option<f32> {
+        self.drift_ppm
+    }
+
+    pub fn is_due(&self, now_us: u64) -> bool {
+        self.next_sync.is_none_or(|next| now_us >= next)
+    }
+
+    /// Microseconds until the next sync is due.
+    pub fn until_due(&self, now_us: u64) -> u64 {
+        self.next_sync.map_or(0, |next| next.saturating_sub(now_us))
+    }
+
+    /// Corrects `clock` from a server's answer and schedules the next sync.
+    pub fn apply(&mut self, clock: &impl Clock, sample: Sample) {
+        let now = clock.now_us();
+
+        // A large step, such as the first sync after power up, says nothing about drift.
+        if let Some(last) = self.last_sync {
+            let span = now.saturating_sub(last);
+            if span >= Self::MIN_DRIFT_SPAN_US {
+                let residual = sample.offset_us as f32 / span as f32 * USEC_IN_SEC as f32;
+                if residual.abs() <= Self::MAX_DRIFT_PPM {
+                    // Earlier trims already took out the old estimate, so this is what is left.
+                    self.drift_ppm = Some(self.drift_ppm.unwrap_or(0.0) + residual);
+                }
+            }
+        }
+
+        let now = now.saturating_add_signed(sample.offset_us);
+        clock.set_us(now);
+
+        self.failures = 0;
+        self.last_sync = Some(now);
+        self.last_trim = now;
+        self.next_sync = Some(now + self.interval_us);
+    }
+
+    /// Records a failed request, moving on to the next server after `tries_per_server` failures
+    /// and backing off before the next try.
+    pub fn failed(&mut self, now_us: u64) {
+        self.failures += 1;
+        if self.failures.is_multiple_of(self.tries_per_server.max(1)) && !self.servers.is_empty() {
+            self.server = (self.server + 1) % self.servers.len();
+        }
+
+        let backoff = self.retry_us.saturating_mul(1 << (self.failures - 1).min(16));
+        self.next_sync = Some(now_us + backoff.min(self.interval_us));
+    }
+
+    /// Takes the estimated drift since the last trim out of `clock`, once it has built up to a
+    /// millisecond. Call this regularly between syncs.
+    pub fn trim(&mut self, clock: &impl Clock) {
+        let (Some(ppm), Some(_)) = (self.drift_ppm, self.last_sync) else {
+            return;
+        };
+        let now = clock.now_us();
+        let correction = (now.saturating_sub(self.last_trim) as f32 * ppm / USEC_IN_SEC as f32) as i64;
+        if correction.abs() >= 1000 {
+            let now = now.saturating_add_signed(correction);
+            clock.set_us(now);
+            self.last_trim = now;
+        }
+    }
+}
+
+#[cfg(feature = "ntp")]
+pub use ntp::{SyncError, request, run};
+
+#[cfg(feature = "ntp")]
+mod ntp {
+    use core::net::{IpAddr, SocketAddr};
+
+    use embassy_net::{
+        Stack,
+        dns::DnsQueryType,
+        udp::{PacketMetadata, UdpSocket},
+    };
+    use embassy_time::{Duration, Timer, with_timeout};
+    use sntpc::{NtpContext, NtpTimestampGenerator, get_time};
+
+    use super::{Clock, NTP_PORT, Sample, TimeSync, USEC_IN_SEC};
+
+    /// How long to wait for a server to answer.
+    const TIMEOUT: Duration = Duration::from_secs(5);
+    /// The longest `run` sleeps between trims.
+    const TRIM_INTERVAL: Duration = Duration::from_secs(60);
+
+    #[derive(Debug)]
+    pub enum SyncError {
+        Dns(embassy_net::dns::Error),
+        NoAddress,
+        Timeout,
+        Ntp(sntpc::Error),
+    }
+
+    struct Timestamp<'a, C> {
+        clock: &'a C,
+        current_time_us: u64,
+    }
+
+    // Derived impls would need `C: Copy`, but only the reference is copied.
+    impl<C> Clone for Timestamp<'_, C> {
+        fn clone(&self) -> Self {
+            *self
+        }
+    }
+
+    impl<C> Copy for Timestamp<'_, C> {}
+
+    impl<C: Clock> NtpTimestampGenerator for Timestamp<'_, C> {
+        fn init(&mut self) {
+            self.current_time_us = self.clock.now_us();
+        }
+
+        fn timestamp_sec(&self) -> u64 {
+            self.current_time_us / USEC_IN_SEC
+        }
+
+        fn timestamp_subsec_micros(&self) -> u32 {
+            (self.current_time_us % USEC_IN_SEC) as u32
+        }
+    }
+
+    /// Asks `server` for the time, measured against `clock`. The socket must be bound.
+    pub async fn request(
+        stack: Stack<'_>,
+        socket: &UdpSocket<'_>,
+        server: &str,
+        clock: &impl Clock,
+    ) -> Result<Sample, SyncError> {
+        let addrs = stack.dns_query(server, DnsQueryType::A).await.map_err(SyncError::Dns)?;
+        let addr: IpAddr = (*addrs.first().ok_or(SyncError::NoAddress)?).into();
+
+        let context = NtpContext::new(Timestamp { clock, current_time_us: 0 });
+        let result = with_timeout(TIMEOUT, get_time(SocketAddr::from((addr, NTP_PORT)), socket, context))
+            .await
+            .map_err(|_| SyncError::Timeout)?
+            .map_err(SyncError::Ntp)?;
+
+        Ok(Sample {
+            offset_us: result.offset(),
+            roundtrip_us: result.roundtrip(),
+        })
+    }
+
+    /// Keeps `clock` in step for ever, syncing whenever `sync` says it is due and trimming the
+    /// drift in between. Waits for the network to come up first.
+    pub async fn run(stack: Stack<'_>, sync: &mut TimeSync<'_>, clock: &impl Clock) -> ! {
+        let mut rx_meta = [PacketMetadata::EMPTY; 4];
+        let mut rx_buffer = [0; 256];
+        let mut tx_meta = [PacketMetadata::EMPTY; 4];
+        let mut tx_buffer = [0; 256];
+        let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buffer, &mut tx_meta, &mut tx_buffer);
+        socket.bind(NTP_PORT).unwrap();
+
+        loop {
+            stack.wait_config_up().await;
+
+            let Some(server) = sync.server() else {
+                // Nothing to sync with, but the drift can still be trimmed.
+                sync.trim(clock);
+                Timer::after(TRIM_INTERVAL).await;
+                continue;
+            };
+
+            if sync.is_due(clock.now_us()) {
+                match request(stack, &socket, server, clock).await {
+                    Ok(sample) => {
+                        defmt::info!("NTP {}: offset {}us, roundtrip {}us", server, sample.offset_us, sample.roundtrip_us);
+                        sync.apply(clock, sample);
+                    }
+                    Err(e) => {
+                        defmt::warn!("NTP {} failed: {}", server, defmt::Debug2Format(&e));
+                        sync.failed(clock.now_us());
+                    }
+                }
+            }
+
+            sync.trim(clock);
+
+            let wait = Duration::from_micros(sync.until_due(clock.now_us())).min(TRIM_INTERVAL);
+            Timer::after(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::cell::Cell;
+
+    const SEC: u64 = USEC_IN_SEC;
+
+    struct TestClock(Cell<u64>);
+
+    impl Clock for TestClock {
+        fn now_us(&self) -> u64 {
+            self.0.get()
+        }
+
+        fn set_us(&self, us: u64) {
+            self.0.set(us);
+        }
+    }
+
+    impl TestClock {
+        fn advance(&self, us: u64) {
+            self.0.set(self.0.get() + us);
+        }
+    }
+
+    fn sample(offset_us: i64) -> Sample {
+        Sample { offset_us, roundtrip_us: 20_000 }
+    }
+
+    #[test]
+    fn syncs_straight_away_then_every_interval() {
+        let clock = TestClock(Cell::new(5 * SEC));
+        let mut sync = TimeSync::new(&["a"]).with_interval(60 * SEC);
+        assert!(sync.is_due(clock.now_us()));
+        assert_eq!(sync.until_due(clock.now_us()), 0);
+        assert!(!sync.is_synced());
+
+        // The first answer steps the clock to the real time.
+        let real = 1_700_000_000 * SEC;
+        sync.apply(&clock, sample((real - 5 * SEC) as i64));
+        assert_eq!(clock.now_us(), real);
+        assert_eq!(sync.last_sync(), Some(real));
+        assert!(!sync.is_due(real + 59 * SEC));
+        assert_eq!(sync.until_due(real + 59 * SEC), SEC);
+        assert!(sync.is_due(real + 60 * SEC));
+        // A step says nothing about how fast the clock runs.
+        assert_eq!(sync.drift_ppm(), None);
+    }
+
+    #[test]
+    fn backs_off_and_fails_over() {
+        let mut sync = TimeSync::new(&["a", "b"]).with_interval(60 * SEC).with_retry(5 * SEC);
+        let mut now = 0;
+        let mut tries = [("", 0); 6];
+        for attempt in tries.iter_mut() {
+            let server = sync.server().unwrap();
+            sync.failed(now);
+            let wait = sync.until_due(now);
+            *attempt = (server, wait / SEC);
+            now += wait;
+        }
+        // Two tries on each server, waiting twice as long each time up to the interval.
+        assert_eq!(tries, [("a", 5), ("a", 10), ("b", 20), ("b", 40), ("a", 60), ("a", 60)]);
+
+        // A success resets the back off but stays on the server which answered.
+        let clock = TestClock(Cell::new(now));
+        sync.failed(now);
+        assert_eq!(sync.server(), Some("b"));
+        sync.apply(&clock, sample(0));
+        sync.failed(clock.now_us());
+        assert_eq!(sync.until_due(clock.now_us()), 5 * SEC);
+        assert_eq!(sync.server(), Some("b"));
+    }
+
+    #[test]
+    fn copes_without_servers() {
+        let mut sync = TimeSync::new(&[]);
+        assert_eq!(sync.server(), None);
+        sync.failed(0);
+        sync.failed(0);
+        assert_eq!(sync.server(), None);
+    }
+
+    #[test]
+    fn estimates_and_trims_drift() {
+        let clock = TestClock(Cell::new(1000 * SEC));
+        let mut sync = TimeSync::new(&["a"]);
+        sync.apply(&clock, sample(0));
+
+        // 100ms slow after 1000s is 100 parts per million.
+        clock.advance(1000 * SEC);
+        sync.apply(&clock, sample(100_000));
+        let ppm = sync.drift_ppm().unwrap();
+        assert!((ppm - 100.0).abs() < 0.01, "{}", ppm);
+
+        // Corrections wait until they add up to a millisecond.
+        let synced = clock.now_us();
+        clock.advance(5 * SEC);
+        sync.trim(&clock);
+        assert_eq!(clock.now_us(), synced + 5 * SEC);
+        clock.advance(5 * SEC);
+        sync.trim(&clock);
+        assert_eq!(clock.now_us(), synced + 10 * SEC + 1000);
+        clock.advance(5 * SEC);
+        sync.trim(&clock);
+        assert_eq!(clock.now_us(), synced + 15 * SEC + 1000);
+
+        // What the trims missed is added to the estimate at the next sync.
+        clock.advance(985 * SEC);
+        sync.apply(&clock, sample(10_000));
+        let ppm = sync.drift_ppm().unwrap();
+        assert!((ppm - 110.0).abs() < 0.01, "{}", ppm);
+    }
+
+    #[test]
+    fn ignores_unreliable_drift() {
+        let clock = TestClock(Cell::new(0));
+        let mut sync = TimeSync::new(&["a"]);
+        sync.apply(&clock, sample(0));
+
+        // Too soon after the last sync.
+        clock.advance(60 * SEC);
+        sync.apply(&clock, sample(50_000));
+        assert_eq!(sync.drift_ppm(), None);
+
+        // Further out than any crystal drifts.
+        clock.advance(1000 * SEC);
+        sync.apply(&clock, sample(SEC as i64));
+        assert_eq!(sync.drift_ppm(), None);
+
+        // With no estimate there is nothing to trim.
+        clock.advance(3600 * SEC);
+        let now = clock.now_us();
+        sync.trim(&clock);
+        assert_eq!(clock.now_us(), now);
+    }
+}
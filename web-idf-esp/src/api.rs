@@ -0,0 +1,191 @@
+use std::sync::Arc;
+
+use esp_idf_svc::http::{Headers, Method};
+use esp_idf_svc::http::server::{EspHttpConnection, Request};
+use indexmap::IndexMap;
+use log::info;
+
+use crate::backup;
+use crate::config::ConfigManager;
+use crate::http::HttpServerManager;
+
+/// The largest request body accepted, enough for a feature with a few PEM certificates.
+const MAX_BODY_LEN: usize = 32 * 1024;
+
+const CONFIG_PREFIX: &str = "/api/config/";
+
+/// The header carrying the passphrase for encrypted secrets in backups. It is a header rather than part of the URI
+/// so it doesn't end up in the request logs.
+const PASSPHRASE_HEADER: &str = "X-Config-Passphrase";
+
+/// Registers the JSON API, which reads and writes the same config as the /config page:
+///
+/// - `GET /api/config` returns every feature's config, keyed by feature name.
+/// - `GET /api/config/{feature}` returns one feature's config.
+/// - `PUT /api/config/{feature}` replaces one feature's config, values left out are cleared.
+/// - `PATCH /api/config/{feature}` changes only the values given.
+/// - `GET /api/schema` describes the features and the kind and rules of each value.
+/// - `GET /api/export` downloads every feature's config and the saved networks as a backup, see `backup::export`.
+/// - `POST /api/import` loads a backup, see `backup::import`.
+///
+/// Writes take `{"enabled": true, "values": {"name": value, ...}}`, both parts optional. They are checked the same
+/// way as the config form and nothing is saved unless all of it is valid, otherwise the response is a 400 with
+/// `{"errors": {"name": "message", ...}}`. Secrets are never returned, `secrets_set` lists the ones which are saved.
+/// Leaving a secret out keeps it and setting it to null clears it. Backups leave secrets out unless the
+/// `X-Config-Passphrase` header is given, in which case they are encrypted with it.
+pub fn create_pages(config_manager: &Arc<ConfigManager>, server_manager: &mut HttpServerManager) -> anyhow::Result<()> {
+    let config_manager_clone = config_manager.clone();
+
+    server_manager.fn_handler("/api/config", Method::Get, move |req| {
+        info!("Received {:?} request for {}", req.method(), req.uri());
+
+        send_json(req, 200, &config_manager_clone.config_json())
+    })?;
+
+    let config_manager_clone = config_manager.clone();
+
+    server_manager.fn_handler("/api/config/*", Method::Get, move |req| {
+        info!("Received {:?} request for {}", req.method(), req.uri());
+
+        let feature_name = feature_name(req.uri()).to_string();
+        match config_manager_clone.feature_json(&feature_name) {
+            Some(json) => send_json(req, 200, &json),
+            None => send_error(req, 404, &format!("No feature {}", feature_name)),
+        }
+    })?;
+
+    for (method, replace) in [(Method::Put, true), (Method::Patch, false)] {
+        let config_manager_clone = config_manager.clone();
+
+        server_manager.fn_handler("/api/config/*", method, move |mut req| {
+            info!("Received {:?} request for {}", req.method(), req.uri());
+
+            let feature_name = feature_name(req.uri()).to_string();
+            if config_manager_clone.feature_json(&feature_name).is_none() {
+                return send_error(req, 404, &format!("No feature {}", feature_name));
+            }
+
+            let body = match read_body(&mut req)? {
+                Some(body) => body,
+                None => return send_error(req, 413, &format!("The body is over {} bytes", MAX_BODY_LEN)),
+            };
+            let update = match serde_json::from_slice::<serde_json::Value>(&body) {
+                Ok(update) => update,
+                Err(e) => return send_error(req, 400, &format!("Invalid JSON: {}", e)),
+            };
+
+            let errors = config_manager_clone.update_feature_json(&feature_name, &update, replace)?;
+            if !errors.is_empty() {
+                return send_errors(req, 400, errors);
+            }
+            match config_manager_clone.feature_json(&feature_name) {
+                Some(json) => send_json(req, 200, &json),
+                None => send_error(req, 404, &format!("No feature {}", feature_name)),
+            }
+        })?;
+    }
+
+    let config_manager_clone = config_manager.clone();
+
+    server_manager.fn_handler("/api/schema", Method::Get, move |req| {
+        info!("Received {:?} request for {}", req.method(), req.uri());
+
+        send_json(req, 200, &config_manager_clone.schema_json())
+    })?;
+
+    let config_manager_clone = config_manager.clone();
+
+    server_manager.fn_handler("/api/export", Method::Get, move |req| {
+        info!("Received {:?} request for {}", req.method(), req.uri());
+
+        let passphrase = req.header(PASSPHRASE_HEADER).map(|passphrase| passphrase.to_string());
+        let export = match backup::export(&config_manager_clone, passphrase.as_deref(), backup::fill_random) {
+            Ok(export) => export,
+            Err(e) => return send_error(req, 400, &e.to_string()),
+        };
+        let mut resp = req.into_response(
+            200,
+            None,
+            &[
+                ("Content-Type", "application/json"),
+                ("Content-Disposition", "attachment; filename=\"config.json\""),
+            ],
+        )?;
+        resp.write(serde_json::to_string_pretty(&export)?.as_bytes())?;
+        Ok(())
+    })?;
+
+    let config_manager_clone = config_manager.clone();
+
+    server_manager.fn_handler("/api/import", Method::Post, move |mut req| {
+        info!("Received {:?} request for {}", req.method(), req.uri());
+
+        let passphrase = req.header(PASSPHRASE_HEADER).map(|passphrase| passphrase.to_string());
+        let body = match read_body(&mut req)? {
+            Some(body) => body,
+            None => return send_error(req, 413, &format!("The body is over {} bytes", MAX_BODY_LEN)),
+        };
+        let document = match serde_json::from_slice::<serde_json::Value>(&body) {
+            Ok(document) => document,
+            Err(e) => return send_error(req, 400, &format!("Invalid JSON: {}", e)),
+        };
+
+        let errors = backup::import(&config_manager_clone, &document, passphrase.as_deref())?;
+        if !errors.is_empty() {
+            return send_errors(req, 400, errors);
+        }
+        send_json(req, 200, &config_manager_clone.config_json())
+    })?;
+
+    Ok(())
+}
+
+/// The feature name from a `/api/config/{feature}` URI, without any query string.
+fn feature_name(uri: &str) -> &str {
+    let path = uri.split('?').next().unwrap_or("");
+    path.strip_prefix(CONFIG_PREFIX).unwrap_or("").trim_end_matches('/')
+}
+
+/// Reads the whole request body, or returns None if it is longer than `MAX_BODY_LEN`.
+fn read_body(req: &mut Request<&mut EspHttpConnection<'_>>) -> anyhow::Result<Option<Vec<u8>>> {
+    let mut body = Vec::new();
+    let mut buf = [0u8; 256];
+
+    loop {
+        let read = req.read(&mut buf)?;
+        if read == 0 {
+            break;
+        }
+        if body.len() + read > MAX_BODY_LEN {
+            return Ok(None);
+        }
+        body.extend_from_slice(&buf[..read]);
+    }
+    Ok(Some(body))
+}
+
+fn send_json(req: Request<&mut EspHttpConnection<'_>>, status: u16, json: &serde_json::Value) -> anyhow::Result<()> {
+    let mut resp = req.into_response(
+        status,
+        None,
+        &[("Content-Type", "application/json")],
+    )?;
+    resp.write(serde_json::to_string(json)?.as_bytes())?;
+    Ok(())
+}
+
+/// Sends `{"errors": {...}}` with a message for each bad value, keyed by value name.
+fn send_errors(req: Request<&mut EspHttpConnection<'_>>, status: u16, errors: IndexMap<String, String>) -> anyhow::Result<()> {
+    let errors = errors.into_iter()
+        .map(|(name, message)| (name, serde_json::Value::from(message)))
+        .collect::<serde_json::Map<_, _>>();
+    send_json(req, status, &serde_json::json!({ "errors": errors }))
+}
+
+/// Sends an error which isn't about any one value, in the same shape as the validation errors.
+fn send_error(req: Request<&mut EspHttpConnection<'_>>, status: u16, message: &str) -> anyhow::Result<()> {
+    log::warn!("API request for {} failed: {}", req.uri(), message);
+    let mut errors = IndexMap::new();
+    errors.insert("request".to_string(), message.to_string());
+    send_errors(req, status, errors)
+}
@@ -0,0 +1,627 @@
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce, aead::{Aead, Payload}};
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use indexmap::IndexMap;
+use log::info;
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::config::ConfigManager;
+use crate::networks::{AuthMode, SavedNetwork};
+
+/// Identifies an export document, so some other JSON file isn't mistaken for one.
+const EXPORT_FORMAT: &str = "web-idf-esp-config";
+/// Bumped when the document changes in a way older firmware can't read.
+pub const EXPORT_VERSION: u64 = 1;
+/// The document field for the saved networks, also the start of the AAD for their passwords. No feature can be
+/// called this, it is the networks' NVS namespace.
+const NETWORKS: &str = "networks";
+
+/// PBKDF2 rounds for the key which encrypts secrets. Deriving it takes a few seconds on an ESP32, which is fine for
+/// an occasional export but slows guessing the passphrase from a leaked file. It only slows it: a short or common
+/// passphrase can still be guessed offline, so use a long random one for files which leave your hands.
+const PBKDF2_ITERATIONS: u32 = 100_000;
+/// The most rounds an imported document can ask for, so a file can't tie the device up for minutes. Files from
+/// older firmware used fewer, and still import.
+const MAX_PBKDF2_ITERATIONS: u32 = 2 * PBKDF2_ITERATIONS;
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+/// The shortest passphrase accepted for encrypting secrets.
+pub const MIN_PASSPHRASE_LEN: usize = 8;
+
+/// Exports every feature's config and the saved networks as a versioned JSON document, for loading onto another
+/// device with `import`:
+///
+/// ```json
+/// {
+///     "format": "web-idf-esp-config",
+///     "version": 1,
+///     "secrets": "excluded",
+///     "features": { "core": { "enabled": true, "values": { "hostname": "sparko", ... } }, ... },
+///     "networks": [ { "ssid": "home", "priority": 0, "auth": "Auto", "identity": "", "username": "" }, ... ]
+/// }
+/// ```
+///
+/// Without a passphrase secrets are left out, and importing the document keeps the secrets already on the device.
+/// Network passwords count as secrets. With one each secret is encrypted with AES-256-GCM under a key derived from
+/// the passphrase, and shows in `values`, or as a network's `password`, as `{"encrypted": "<base64 nonce and
+/// ciphertext>"}`. The salt and rounds are in `kdf`. Open networks have an empty password, which is kept as it is.
+///
+/// `fill_random` makes the salt and nonces, `fill_random` below on the device.
+pub fn export(config_manager: &ConfigManager, passphrase: Option<&str>, mut fill_random: impl FnMut(&mut [u8])) -> anyhow::Result<serde_json::Value> {
+    let serde_json::Value::Object(mut features) = config_manager.config_json() else {
+        anyhow::bail!("Config is not a JSON object");
+    };
+    for feature in features.values_mut() {
+        if let Some(feature) = feature.as_object_mut() {
+            feature.remove("secrets_set");
+        }
+    }
+
+    let mut export = serde_json::json!({
+        "format": EXPORT_FORMAT,
+        "version": EXPORT_VERSION,
+        "secrets": "excluded",
+    });
+
+    let cipher = match passphrase {
+        Some(passphrase) => {
+            if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+                anyhow::bail!("The passphrase must be at least {} characters", MIN_PASSPHRASE_LEN);
+            }
+
+            let mut salt = [0u8; SALT_LEN];
+            fill_random(&mut salt);
+            export["secrets"] = "encrypted".into();
+            export["kdf"] = serde_json::json!({
+                "name": "pbkdf2-sha256",
+                "iterations": PBKDF2_ITERATIONS,
+                "salt": BASE64.encode(salt),
+            });
+            Some(derive_key(passphrase, &salt, PBKDF2_ITERATIONS)?)
+        },
+        None => None,
+    };
+    let mut encrypt = |aad: &str, secret: &str| -> anyhow::Result<Option<serde_json::Value>> {
+        let Some(cipher) = &cipher else {
+            return Ok(None);
+        };
+        let mut nonce = [0u8; NONCE_LEN];
+        fill_random(&mut nonce);
+        Ok(Some(serde_json::json!({ "encrypted": encrypt_secret(cipher, &nonce, aad, secret)? })))
+    };
+
+    for (feature_name, name, secret) in config_manager.secrets() {
+        let Some(values) = features.get_mut(&feature_name).and_then(|feature| feature.get_mut("values")).and_then(|values| values.as_object_mut()) else {
+            continue;
+        };
+        if let Some(encrypted) = encrypt(&secret_aad(&feature_name, &name), secret.expose())? {
+            values.insert(name, encrypted);
+        }
+    }
+
+    let mut networks = Vec::new();
+    for network in config_manager.networks.lock().unwrap().networks() {
+        let mut json = serde_json::to_value(network)?;
+        if !network.password.is_empty() {
+            match encrypt(&secret_aad(NETWORKS, &network.ssid), &network.password)? {
+                Some(encrypted) => json["password"] = encrypted,
+                None => {
+                    if let Some(json) = json.as_object_mut() {
+                        json.remove("password");
+                    }
+                },
+            }
+        }
+        networks.push(json);
+    }
+
+    export["features"] = features.into();
+    export[NETWORKS] = networks.into();
+    Ok(export)
+}
+
+/// A saved network as `export` writes it, the password is missing if secrets were left out.
+#[derive(Deserialize)]
+struct ExportedNetwork {
+    ssid: String,
+    password: Option<String>,
+    #[serde(default)]
+    priority: i32,
+    #[serde(default)]
+    auth: AuthMode,
+    #[serde(default)]
+    identity: String,
+    #[serde(default)]
+    username: String,
+}
+
+/// Loads a document made by `export`. Each feature in it has its config replaced, features left out are not
+/// changed, and secrets left out keep their current value. The saved networks are replaced by the document's if it
+/// has them, a network whose password was left out keeps the one saved for the same SSID, or has none. Encrypted
+/// secrets need the passphrase they were exported with. Everything is checked before anything is saved, so a bad
+/// document changes nothing. Returns the problems keyed by `feature.value` or `networks.<index>`, or by the
+/// document field for problems with the document itself.
+pub fn import(config_manager: &ConfigManager, document: &serde_json::Value, passphrase: Option<&str>) -> anyhow::Result<IndexMap<String, String>> {
+    let mut errors = IndexMap::new();
+
+    let Some(document) = document.as_object() else {
+        errors.insert("body".to_string(), "Expected a JSON object".to_string());
+        return Ok(errors);
+    };
+    if document.get("format").and_then(|format| format.as_str()) != Some(EXPORT_FORMAT) {
+        errors.insert("format".to_string(), format!("Not a config export, expected format {}", EXPORT_FORMAT));
+        return Ok(errors);
+    }
+    match document.get("version").and_then(|version| version.as_u64()) {
+        Some(version) if version <= EXPORT_VERSION => {},
+        Some(version) => {
+            errors.insert("version".to_string(), format!("Version {} is newer than this firmware reads, which is up to {}", version, EXPORT_VERSION));
+            return Ok(errors);
+        },
+        None => {
+            errors.insert("version".to_string(), "Missing version".to_string());
+            return Ok(errors);
+        },
+    }
+    let Some(features) = document.get("features").and_then(|features| features.as_object()) else {
+        errors.insert("features".to_string(), "Expected an object of features".to_string());
+        return Ok(errors);
+    };
+
+    // Only derive the key if there is something to decrypt, it is slow.
+    let encrypted = document.get("secrets").and_then(|secrets| secrets.as_str()) == Some("encrypted");
+    let cipher = match (encrypted, passphrase) {
+        (false, _) => None,
+        (true, None) => {
+            errors.insert("passphrase".to_string(), "The secrets are encrypted, give the passphrase they were exported with".to_string());
+            return Ok(errors);
+        },
+        (true, Some(passphrase)) => match import_key(document, passphrase) {
+            Ok(cipher) => Some(cipher),
+            Err(e) => {
+                errors.insert("kdf".to_string(), e.to_string());
+                return Ok(errors);
+            },
+        },
+    };
+
+    let mut updates = IndexMap::new();
+    for (feature_name, feature) in features {
+        let mut feature = feature.clone();
+        if let Some(values) = feature.get_mut("values").and_then(|values| values.as_object_mut()) {
+            for (name, value) in values.iter_mut() {
+                if let Err(e) = decrypt_value(value, cipher.as_ref(), &secret_aad(feature_name, name)) {
+                    errors.insert(format!("{}.{}", feature_name, name), e.to_string());
+                }
+            }
+        }
+        updates.insert(feature_name.clone(), feature);
+    }
+
+    let networks = match document.get(NETWORKS) {
+        None => None,
+        Some(serde_json::Value::Array(networks)) => Some(import_networks(config_manager, networks, cipher.as_ref(), &mut errors)),
+        Some(_) => {
+            errors.insert(NETWORKS.to_string(), "Expected a list of networks".to_string());
+            None
+        },
+    };
+
+    if !errors.is_empty() {
+        log::warn!("Config import rejected: {:?}", errors);
+        return Ok(errors);
+    }
+
+    info!("Importing config for features {:?}", updates.keys().collect::<Vec<_>>());
+    config_manager.import_features(&updates, networks.as_deref())
+}
+
+/// Reads the saved networks from a document, adding the problems with them to `errors`.
+fn import_networks(config_manager: &ConfigManager, networks: &[serde_json::Value], cipher: Option<&Aes256Gcm>, errors: &mut IndexMap<String, String>) -> Vec<SavedNetwork> {
+    let saved = config_manager.networks.lock().unwrap().networks().to_vec();
+    let mut imported = Vec::new();
+    for (i, network) in networks.iter().enumerate() {
+        let key = format!("{}.{}", NETWORKS, i);
+        let mut network = network.clone();
+        let ssid = network.get("ssid").and_then(|ssid| ssid.as_str()).unwrap_or_default().to_string();
+        if let Some(password) = network.get_mut("password") {
+            if let Err(e) = decrypt_value(password, cipher, &secret_aad(NETWORKS, &ssid)) {
+                errors.insert(key, e.to_string());
+                continue;
+            }
+        }
+
+        let result = serde_json::from_value::<ExportedNetwork>(network)
+            .map_err(anyhow::Error::from)
+            .and_then(|network| {
+                let password = network.password
+                    .or_else(|| saved.iter().find(|saved| saved.ssid == network.ssid).map(|saved| saved.password.clone()))
+                    .unwrap_or_default();
+                SavedNetwork::new(&network.ssid, &password, network.priority, network.auth)?.with_eap(&network.identity, &network.username)
+            });
+        match result {
+            Ok(network) => imported.push(network),
+            Err(e) => {
+                errors.insert(key, e.to_string());
+            },
+        }
+    }
+    imported
+}
+
+/// Replaces an encrypted secret in a document with the secret, other values are left as they are.
+fn decrypt_value(value: &mut serde_json::Value, cipher: Option<&Aes256Gcm>, aad: &str) -> anyhow::Result<()> {
+    let Some(encrypted) = value.get("encrypted").and_then(|encrypted| encrypted.as_str()) else {
+        return Ok(());
+    };
+    let Some(cipher) = cipher else {
+        anyhow::bail!("Encrypted, but the document says secrets are not");
+    };
+    let secret = decrypt_secret(cipher, aad, encrypted)?;
+    *value = secret.into();
+    Ok(())
+}
+
+/// Derives the key from the passphrase and the document's `kdf` settings.
+fn import_key(document: &serde_json::Map<String, serde_json::Value>, passphrase: &str) -> anyhow::Result<Aes256Gcm> {
+    let kdf = document.get("kdf").ok_or_else(|| anyhow::anyhow!("Missing the key derivation settings"))?;
+    if kdf.get("name").and_then(|name| name.as_str()) != Some("pbkdf2-sha256") {
+        anyhow::bail!("Unknown key derivation, expected pbkdf2-sha256");
+    }
+    let iterations = kdf.get("iterations")
+        .and_then(|iterations| iterations.as_u64())
+        .and_then(|iterations| u32::try_from(iterations).ok())
+        .filter(|iterations| (1..=MAX_PBKDF2_ITERATIONS).contains(iterations))
+        .ok_or_else(|| anyhow::anyhow!("Missing or unreasonable iterations"))?;
+    let salt = kdf.get("salt")
+        .and_then(|salt| salt.as_str())
+        .and_then(|salt| BASE64.decode(salt).ok())
+        .ok_or_else(|| anyhow::anyhow!("Missing or invalid salt"))?;
+    derive_key(passphrase, &salt, iterations)
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> anyhow::Result<Aes256Gcm> {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut key);
+    Aes256Gcm::new_from_slice(&key).map_err(|e| anyhow::anyhow!("Invalid key: {}", e))
+}
+
+/// Ties each encrypted secret to where it belongs, so one can't be moved to another value in the document.
+fn secret_aad(feature_name: &str, name: &str) -> String {
+    format!("{}/{}", feature_name, name)
+}
+
+/// Encrypts `secret`, returning the nonce followed by the ciphertext, in base64.
+fn encrypt_secret(cipher: &Aes256Gcm, nonce: &[u8; NONCE_LEN], aad: &str, secret: &str) -> anyhow::Result<String> {
+    let ciphertext = cipher.encrypt(Nonce::from_slice(nonce), Payload { msg: secret.as_bytes(), aad: aad.as_bytes() })
+        .map_err(|_| anyhow::anyhow!("Failed to encrypt {}", aad))?;
+    let mut encrypted = nonce.to_vec();
+    encrypted.extend_from_slice(&ciphertext);
+    Ok(BASE64.encode(encrypted))
+}
+
+fn decrypt_secret(cipher: &Aes256Gcm, aad: &str, encrypted: &str) -> anyhow::Result<String> {
+    let encrypted = BASE64.decode(encrypted).map_err(|_| anyhow::anyhow!("Not valid base64"))?;
+    if encrypted.len() < NONCE_LEN {
+        anyhow::bail!("Too short to be an encrypted secret");
+    }
+    let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+    let secret = cipher.decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: aad.as_bytes() })
+        .map_err(|_| anyhow::anyhow!("Can't decrypt, wrong passphrase?"))?;
+    String::from_utf8(secret).map_err(|_| anyhow::anyhow!("Decrypted to something which isn't text"))
+}
+
+/// Fills `buf` from the hardware random number generator.
+#[cfg(target_os = "espidf")]
+pub fn fill_random(buf: &mut [u8]) {
+    unsafe { esp_idf_sys::esp_fill_random(buf.as_mut_ptr() as *mut core::ffi::c_void, buf.len()) };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::{Feature, config, dyndns2::DynDns2, networks::ScanResults, storage::{ConfigStore, MemoryStore}};
+
+    const PASSPHRASE: &str = "correct horse battery";
+
+    /// The NVS partition of a test device, kept so a device can be loaded again from what it saved. The next write
+    /// to the namespace in `failing` fails, to stop an import part way through.
+    #[derive(Clone, Default)]
+    struct Nvs {
+        namespaces: Arc<Mutex<HashMap<String, Arc<MemoryStore>>>>,
+        failing: Arc<Mutex<Option<String>>>,
+    }
+
+    impl Nvs {
+        fn open(&self, namespace: &str) -> NamespaceStore {
+            let store = self.namespaces.lock().unwrap().entry(namespace.to_string()).or_default().clone();
+            NamespaceStore { namespace: namespace.to_string(), store, failing: self.failing.clone() }
+        }
+
+        fn fail_next_write_to(&self, namespace: &str) {
+            *self.failing.lock().unwrap() = Some(namespace.to_string());
+        }
+    }
+
+    struct NamespaceStore {
+        namespace: String,
+        store: Arc<MemoryStore>,
+        failing: Arc<Mutex<Option<String>>>,
+    }
+
+    impl NamespaceStore {
+        fn check_write(&self) -> anyhow::Result<()> {
+            let mut failing = self.failing.lock().unwrap();
+            if failing.as_deref() == Some(self.namespace.as_str()) {
+                *failing = None;
+                anyhow::bail!("Write to {} failed", self.namespace);
+            }
+            Ok(())
+        }
+    }
+
+    impl ConfigStore for NamespaceStore {
+        fn contains(&self, key: &str) -> anyhow::Result<bool> {
+            self.store.contains(key)
+        }
+
+        fn get_str(&self, key: &str, max_len: usize) -> anyhow::Result<Option<String>> {
+            self.store.get_str(key, max_len)
+        }
+
+        fn set_str(&self, key: &str, value: &str) -> anyhow::Result<()> {
+            self.check_write()?;
+            self.store.set_str(key, value)
+        }
+
+        fn get_i32(&self, key: &str) -> anyhow::Result<Option<i32>> {
+            self.store.get_i32(key)
+        }
+
+        fn set_i32(&self, key: &str, value: i32) -> anyhow::Result<()> {
+            self.check_write()?;
+            self.store.set_i32(key, value)
+        }
+
+        fn get_i64(&self, key: &str) -> anyhow::Result<Option<i64>> {
+            self.store.get_i64(key)
+        }
+
+        fn set_i64(&self, key: &str, value: i64) -> anyhow::Result<()> {
+            self.check_write()?;
+            self.store.set_i64(key, value)
+        }
+
+        fn get_u8(&self, key: &str) -> anyhow::Result<Option<u8>> {
+            self.store.get_u8(key)
+        }
+
+        fn set_u8(&self, key: &str, value: u8) -> anyhow::Result<()> {
+            self.check_write()?;
+            self.store.set_u8(key, value)
+        }
+
+        fn get_u32(&self, key: &str) -> anyhow::Result<Option<u32>> {
+            self.store.get_u32(key)
+        }
+
+        fn set_u32(&self, key: &str, value: u32) -> anyhow::Result<()> {
+            self.check_write()?;
+            self.store.set_u32(key, value)
+        }
+
+        fn remove(&self, key: &str) -> anyhow::Result<bool> {
+            self.check_write()?;
+            self.store.remove(key)
+        }
+
+        fn erase_all(&self) -> anyhow::Result<()> {
+            self.check_write()?;
+            self.store.erase_all()
+        }
+
+        fn keys(&self) -> anyhow::Result<Vec<(String, String)>> {
+            self.store.keys()
+        }
+    }
+
+    fn device(nvs: &Nvs) -> Arc<ConfigManager> {
+        let features: Vec<Box<dyn Feature>> = vec![Box::new(DynDns2::default())];
+        ConfigManager::with_stores(|namespace| Ok(Box::new(nvs.open(namespace))), &features,
+            Arc::new(Mutex::new(None)), Arc::new(Mutex::new(false)), Arc::new(Mutex::new(false)), Arc::new(ScanResults::new())).unwrap()
+    }
+
+    /// A device with a valid core config, a secret in each feature and a few saved networks.
+    fn configured_device(nvs: &Nvs) -> Arc<ConfigManager> {
+        let config_manager = device(nvs);
+        config_manager.set_core_config(config::SSID, "home").unwrap();
+        config_manager.set_core_config(config::WIFI_PASSWORD, "hunter2hunter2").unwrap();
+        config_manager.set_core_config(config::MDNS_HOSTNAME, "sparko").unwrap();
+        config_manager.set_core_config(config::AP_PASSWORD, "setup-password").unwrap();
+        let errors = config_manager.update_feature_json("DynDNS2", &serde_json::json!({ "values": { "password": "dyn-password" } }), false).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        let mut networks = config_manager.networks.lock().unwrap();
+        networks.add(SavedNetwork::new("office", "office-password", 1, AuthMode::Auto).unwrap()).unwrap();
+        networks.add(SavedNetwork::new("cafe", "", 0, AuthMode::Open).unwrap()).unwrap();
+        networks.add(SavedNetwork::new("work", "eap-password", 2, AuthMode::Wpa2Enterprise).unwrap().with_eap("anonymous", "alice").unwrap()).unwrap();
+        drop(networks);
+        config_manager
+    }
+
+    /// Counts up, so exports are repeatable.
+    fn not_random() -> impl FnMut(&mut [u8]) {
+        let mut next = 0u8;
+        move |buf: &mut [u8]| {
+            for byte in buf {
+                *byte = next;
+                next = next.wrapping_add(1);
+            }
+        }
+    }
+
+    fn secrets(config_manager: &ConfigManager) -> Vec<(String, String, String)> {
+        config_manager.secrets().into_iter().map(|(feature_name, name, secret)| (feature_name, name, secret.expose().to_string())).collect()
+    }
+
+    fn networks(config_manager: &ConfigManager) -> Vec<SavedNetwork> {
+        config_manager.networks.lock().unwrap().networks().to_vec()
+    }
+
+    #[test]
+    fn round_trips_with_a_passphrase() {
+        let source = configured_device(&Nvs::default());
+        let document = export(&source, Some(PASSPHRASE), not_random()).unwrap();
+
+        let text = document.to_string();
+        for secret in ["hunter2hunter2", "setup-password", "dyn-password", "office-password", "eap-password"] {
+            assert!(!text.contains(secret), "{} in {}", secret, text);
+        }
+        assert_eq!(document["secrets"], "encrypted");
+        assert_eq!(document["kdf"]["iterations"], PBKDF2_ITERATIONS);
+        assert_eq!(document["networks"][1]["password"], "");
+
+        let nvs = Nvs::default();
+        let target = device(&nvs);
+        let errors = import(&target, &document, Some(PASSPHRASE)).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(secrets(&target), secrets(&source));
+        assert_eq!(networks(&target), networks(&source));
+        assert_eq!(target.get_valid_core_config(config::SSID).unwrap(), "home");
+
+        // And it was all saved.
+        let reloaded = device(&nvs);
+        assert_eq!(secrets(&reloaded), secrets(&source));
+        assert_eq!(networks(&reloaded), networks(&source));
+    }
+
+    #[test]
+    fn leaves_secrets_out_without_a_passphrase() {
+        let source = configured_device(&Nvs::default());
+        let document = export(&source, None, not_random()).unwrap();
+        assert_eq!(document["secrets"], "excluded");
+        assert!(document.get("kdf").is_none());
+        assert!(document["networks"][0].get("password").is_none());
+        assert!(!document.to_string().contains("office-password"));
+
+        // The device keeps its own secrets, and passwords for the networks it already has.
+        let target = configured_device(&Nvs::default());
+        target.set_core_config(config::WIFI_PASSWORD, "other-password").unwrap();
+        target.networks.lock().unwrap().replace(vec![SavedNetwork::new("work", "kept-password", 0, AuthMode::Wpa2Enterprise).unwrap()]).unwrap();
+        let errors = import(&target, &document, None).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(target.get_valid_core_config(config::WIFI_PASSWORD).unwrap(), "other-password");
+        let imported = networks(&target);
+        assert_eq!(imported.iter().map(|network| (network.ssid.as_str(), network.password.as_str())).collect::<Vec<_>>(),
+            [("office", ""), ("cafe", ""), ("work", "kept-password")]);
+        assert_eq!(imported[2].username, "alice");
+    }
+
+    #[test]
+    fn rejects_the_wrong_passphrase() {
+        let document = export(&configured_device(&Nvs::default()), Some(PASSPHRASE), not_random()).unwrap();
+        let target = device(&Nvs::default());
+        let errors = import(&target, &document, Some("incorrect horse")).unwrap();
+        assert!(errors.contains_key("core.wifi_password"), "{:?}", errors);
+        assert!(errors.contains_key("networks.0"), "{:?}", errors);
+        assert!(secrets(&target).is_empty());
+        assert!(networks(&target).is_empty());
+
+        let errors = import(&target, &document, None).unwrap();
+        assert_eq!(errors.keys().collect::<Vec<_>>(), ["passphrase"]);
+        assert!(export(&target, Some("short"), not_random()).is_err());
+    }
+
+    #[test]
+    fn rejects_secrets_moved_to_another_value() {
+        let mut document = export(&configured_device(&Nvs::default()), Some(PASSPHRASE), not_random()).unwrap();
+        let values = &mut document["features"]["core"]["values"];
+        let wifi_password = values["wifi_password"].take();
+        values["wifi_password"] = values["ap_password"].take();
+        values["ap_password"] = wifi_password;
+        let networks = &mut document["networks"];
+        let office_password = networks[0]["password"].take();
+        networks[0]["password"] = networks[2]["password"].take();
+        networks[2]["password"] = office_password;
+
+        let target = device(&Nvs::default());
+        let errors = import(&target, &document, Some(PASSPHRASE)).unwrap();
+        let mut keys: Vec<&str> = errors.keys().map(|key| key.as_str()).collect();
+        keys.sort();
+        assert_eq!(keys, ["core.ap_password", "core.wifi_password", "networks.0", "networks.2"]);
+        assert!(secrets(&target).is_empty());
+    }
+
+    #[test]
+    fn rejects_other_documents() {
+        let document = export(&configured_device(&Nvs::default()), Some(PASSPHRASE), not_random()).unwrap();
+        let target = device(&Nvs::default());
+        let rejected = |change: &dyn Fn(&mut serde_json::Value)| {
+            let mut document = document.clone();
+            change(&mut document);
+            import(&target, &document, Some(PASSPHRASE)).unwrap().into_keys().collect::<Vec<_>>()
+        };
+
+        assert_eq!(rejected(&|document| document["format"] = "something-else".into()), ["format"]);
+        assert_eq!(rejected(&|document| document["version"] = (EXPORT_VERSION + 1).into()), ["version"]);
+        assert_eq!(rejected(&|document| { document.as_object_mut().unwrap().remove("version"); }), ["version"]);
+        assert_eq!(rejected(&|document| document["features"] = serde_json::json!([])), ["features"]);
+        assert_eq!(rejected(&|document| document["kdf"]["name"] = "scrypt".into()), ["kdf"]);
+        assert_eq!(rejected(&|document| document["kdf"]["iterations"] = (MAX_PBKDF2_ITERATIONS + 1).into()), ["kdf"]);
+        assert_eq!(rejected(&|document| document["kdf"]["iterations"] = 0.into()), ["kdf"]);
+        assert_eq!(rejected(&|document| document["kdf"]["salt"] = "not base64!".into()), ["kdf"]);
+        assert_eq!(rejected(&|document| { document.as_object_mut().unwrap().remove("kdf"); }), ["kdf"]);
+        assert_eq!(rejected(&|document| document["networks"] = serde_json::json!({})), ["networks"]);
+        assert_eq!(rejected(&|document| document["networks"][1]["ssid"] = "".into()), ["networks.1"]);
+        assert_eq!(import(&target, &serde_json::json!([]), None).unwrap().into_keys().collect::<Vec<_>>(), ["body"]);
+        assert!(secrets(&target).is_empty());
+    }
+
+    #[test]
+    fn reads_older_documents() {
+        // Before the networks were exported, with fewer rounds.
+        let mut document = export(&configured_device(&Nvs::default()), None, not_random()).unwrap();
+        document.as_object_mut().unwrap().remove("networks");
+        let target = configured_device(&Nvs::default());
+        let errors = import(&target, &document, None).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(networks(&target).len(), 3);
+
+        let salt = [7u8; SALT_LEN];
+        let cipher = derive_key(PASSPHRASE, &salt, 10_000).unwrap();
+        document["secrets"] = "encrypted".into();
+        document["kdf"] = serde_json::json!({ "name": "pbkdf2-sha256", "iterations": 10_000, "salt": BASE64.encode(salt) });
+        document["features"]["core"]["values"]["wifi_password"] = serde_json::json!({
+            "encrypted": encrypt_secret(&cipher, &[1; NONCE_LEN], "core/wifi_password", "old-password").unwrap(),
+        });
+        let errors = import(&target, &document, Some(PASSPHRASE)).unwrap();
+        assert!(errors.is_empty(), "{:?}", errors);
+        assert_eq!(target.get_valid_core_config(config::WIFI_PASSWORD).unwrap(), "old-password");
+    }
+
+    #[test]
+    fn puts_everything_back_when_saving_fails_part_way() {
+        let document = export(&configured_device(&Nvs::default()), Some(PASSPHRASE), not_random()).unwrap();
+
+        for failing in ["DynDNS2", "networks"] {
+            let nvs = Nvs::default();
+            let target = device(&nvs);
+            target.set_core_config(config::SSID, "before").unwrap();
+            let before = export(&target, None, not_random()).unwrap();
+
+            nvs.fail_next_write_to(failing);
+            assert!(import(&target, &document, Some(PASSPHRASE)).is_err(), "{}", failing);
+            assert_eq!(export(&target, None, not_random()).unwrap(), before, "{}", failing);
+            assert!(secrets(&target).is_empty(), "{}", failing);
+
+            // NVS is as it was too, not only the config in memory.
+            let reloaded = device(&nvs);
+            assert_eq!(export(&reloaded, None, not_random()).unwrap(), before, "{}", failing);
+            assert!(secrets(&reloaded).is_empty(), "{}", failing);
+            assert!(networks(&reloaded).is_empty(), "{}", failing);
+        }
+    }
+}
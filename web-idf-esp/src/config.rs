@@ -1,11 +1,16 @@
+#[cfg(target_os = "espidf")]
 use esp_idf_svc::http::Method;
+#[cfg(target_os = "espidf")]
 use esp_idf_svc::http::server::EspHttpConnection;
 
 use indexmap::IndexMap;
 use log::info;
+#[cfg(target_os = "espidf")]
 use url::form_urlencoded;
-use crate::{Feature, HOSTNAME_LEN, PASSWORD_LEN, SSID_LEN, tz::{TIMEZONE_LEN, TimeZone}};
-use std::{sync::{Arc, Mutex}};
+use crate::{Feature, HOSTNAME_LEN, PASSWORD_LEN, SSID_LEN, ipconfig::{IpConfigError, StaticIpConfig, parse_static_ip}, eap::{EapCredentials, normalize_pem}, networks::{AuthMode, EAP_NAME_LEN, NetworkList, SavedNetwork, ScanResults}, tz::{TIMEZONE_LEN, TimeZone}, values::{self, REDACTED, Rules, SecretString}};
+#[cfg(target_os = "espidf")]
+use crate::values::escape_html;
+use std::{net::{Ipv4Addr, Ipv6Addr}, sync::{Arc, Mutex}, time::Duration};
 
 use anyhow::anyhow;
 
@@ -37,13 +42,24 @@ impl From<bool> for EnabledState {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TypedValue {
     String(usize, Option<String>),
     Int32(Option<i32>),
     Int64(Option<i64>),
     Bool(bool),
     TimeZone(TimeZone),
+    Float(Option<f32>),
+    /// One of a fixed list of choices.
+    Enum(&'static [&'static str], Option<&'static str>),
+    Ipv4(Option<Ipv4Addr>),
+    Ipv6(Option<Ipv6Addr>),
+    /// An http or https URL of at most this many bytes.
+    Url(usize, Option<String>),
+    /// Entered and shown as 90s, 15m, 1h30m and so on, stored as whole seconds.
+    Duration(Option<Duration>),
+    /// A string of at most this many bytes which is never sent back to the browser or logged, such as a password.
+    Secret(usize, Option<SecretString>),
 }
 
 impl TypedValue {
@@ -68,31 +84,25 @@ impl TypedValue {
             TypedValue::Int64(val) => val.is_none(),
             TypedValue::Bool(_) => false, // Bool is never None, it defaults to false
             TypedValue::TimeZone(_) => false, // TimeZone is never None, it defaults to a specific timezone
+            TypedValue::Float(val) => val.is_none(),
+            TypedValue::Enum(_choices, val) => val.is_none(),
+            TypedValue::Ipv4(val) => val.is_none(),
+            TypedValue::Ipv6(val) => val.is_none(),
+            TypedValue::Url(_len, val) => val.is_none(),
+            TypedValue::Duration(val) => val.is_none(),
+            TypedValue::Secret(_len, val) => val.is_none(),
         }
     }
     
-    fn read_from_nvs(&self, nvs: &EspNvs<NvsDefault>, name: &str) -> TypedValue {
+    pub(crate) fn read_from_store(&self, store: &dyn ConfigStore, name: &str) -> TypedValue {
         info!("Reading config value {} from NVS", name);
         let result = match self {
-            TypedValue::String(len, _) => {
-                let mut buf = vec![0u8; *len as usize];
-
-                // let x = nvs.get_str(name, buf.as_mut_slice());
-                // match x {
-                //     Ok(str) => log::info!("Read string value for {} from NVS: {:?}", name, str),
-                //     Err(e) => log::info!("No string value for {} in NVS: {:?}", name, e),
-                // }
-
-                if let Some(str)= nvs.get_str(name, buf.as_mut_slice()).ok().flatten() {
-                    TypedValue::String(*len, Some(str.to_string()))
-                } else {
-                    TypedValue::String(*len, None)
-                }
-            },
-            TypedValue::Int32(_) => TypedValue::Int32(nvs.get_i32(name).ok().flatten()),
-            TypedValue::Int64(_) => TypedValue::Int64(nvs.get_i64(name).ok().flatten()),
+            // One more than the length for the nul NVS stores after the string.
+            TypedValue::String(len, _) => TypedValue::String(*len, store.get_str(name, *len + 1).ok().flatten()),
+            TypedValue::Int32(_) => TypedValue::Int32(store.get_i32(name).ok().flatten()),
+            TypedValue::Int64(_) => TypedValue::Int64(store.get_i64(name).ok().flatten()),
             TypedValue::Bool(_) => {
-                let v = if let Some(value) = nvs.get_u8(name).ok().flatten() {
+                let v = if let Some(value) = store.get_u8(name).ok().flatten() {
                     value != 0
                 } else {
                     false
@@ -100,8 +110,8 @@ impl TypedValue {
                 TypedValue::Bool(v)
             },
             TypedValue::TimeZone(_) => {
-                if let Some(str) = nvs.get_str(name, &mut [0u8; TIMEZONE_LEN as usize]).ok().flatten() {
-                    if let Some(tz) = TimeZone::from_str(str) {
+                if let Some(str) = store.get_str(name, TIMEZONE_LEN).ok().flatten() {
+                    if let Some(tz) = TimeZone::from_str(&str) {
                         TypedValue::TimeZone(tz)
                     } else {
                         TypedValue::TimeZone(TimeZone::Utc)
@@ -110,37 +120,173 @@ impl TypedValue {
                     TypedValue::TimeZone(TimeZone::Utc)
                 }
             },
+            TypedValue::Float(_) => TypedValue::Float(store.get_u32(name).ok().flatten().map(f32::from_bits)),
+            TypedValue::Enum(choices, _) => {
+                let max_len = choices.iter().map(|choice| choice.len()).max().unwrap_or(0) + 1;
+                // A choice which has since been removed reads as None.
+                let choice = store.get_str(name, max_len).ok().flatten()
+                    .and_then(|str| values::parse_choice(choices, &str).ok());
+                TypedValue::Enum(choices, choice)
+            },
+            // Addresses are stored as strings, so they read back the same as the String values they often replace.
+            TypedValue::Ipv4(_) => TypedValue::Ipv4(store.get_str(name, 16).ok().flatten().and_then(|str| str.parse().ok())),
+            TypedValue::Ipv6(_) => TypedValue::Ipv6(store.get_str(name, 40).ok().flatten().and_then(|str| str.parse().ok())),
+            TypedValue::Url(len, _) => TypedValue::Url(*len, store.get_str(name, *len + 1).ok().flatten()),
+            // Seconds as an i64, the same as the Int64 values durations used to be kept in.
+            TypedValue::Duration(_) => TypedValue::Duration(store.get_i64(name).ok().flatten()
+                .and_then(|secs| u64::try_from(secs).ok())
+                .map(Duration::from_secs)),
+            TypedValue::Secret(len, _) => TypedValue::Secret(*len, store.get_str(name, *len + 1).ok().flatten().map(|str| SecretString::new(&str))),
         };
         info!("Finished reading config value {} from NVS: {:?}", name, result);
         result
     }
     
-    fn to_string(&self) -> String {
+    pub(crate) fn write_to_store(&self, store: &dyn ConfigStore, name: &str) -> anyhow::Result<()> {
         match self {
-            TypedValue::String(_len, Some(val)) => val.clone(),
-            TypedValue::Int32(Some(val)) => val.to_string(),
-            TypedValue::Int64(Some(val)) => val.to_string(),
-            TypedValue::Bool(val) => val.to_string(),
-            TypedValue::TimeZone(tz) => tz.to_str().to_string(),
-            _ => "".to_string(),
+            TypedValue::String(_len, Some(val)) => {
+                info!("Saving string value for {} to NVS: {}", name, val);
+                store.set_str(name, val)?
+            },
+            TypedValue::Int32(Some(val)) => {
+                info!("Saving int32 value for {} to NVS: {}", name, val);
+                store.set_i32(name, *val)?
+            },
+            TypedValue::Int64(Some(val)) => {
+                info!("Saving int64 value for {} to NVS: {}", name, val);
+                store.set_i64(name, *val)?
+            },
+            TypedValue::Bool(val) => {
+                info!("Saving bool value for {} to NVS: {}", name, val);
+                store.set_u8(name, if *val { 1 } else { 0 })?
+            },
+            TypedValue::TimeZone(tz) => {
+                info!("Saving TimeZone value for {} to NVS: {}", name, tz.to_str());
+                store.set_str(name, tz.to_str())?
+            },
+            TypedValue::Float(Some(val)) => {
+                info!("Saving float value for {} to NVS: {}", name, val);
+                store.set_u32(name, val.to_bits())?
+            },
+            TypedValue::Enum(_choices, Some(val)) => {
+                info!("Saving enum value for {} to NVS: {}", name, val);
+                store.set_str(name, val)?
+            },
+            TypedValue::Ipv4(Some(val)) => {
+                info!("Saving IPv4 value for {} to NVS: {}", name, val);
+                store.set_str(name, &val.to_string())?
+            },
+            TypedValue::Ipv6(Some(val)) => {
+                info!("Saving IPv6 value for {} to NVS: {}", name, val);
+                store.set_str(name, &val.to_string())?
+            },
+            TypedValue::Url(_len, Some(val)) => {
+                info!("Saving URL value for {} to NVS: {}", name, val);
+                store.set_str(name, val)?
+            },
+            TypedValue::Duration(Some(val)) => {
+                info!("Saving duration value for {} to NVS: {:?}", name, val);
+                store.set_i64(name, i64::try_from(val.as_secs())?)?
+            },
+            TypedValue::Secret(_len, Some(val)) => {
+                info!("Saving secret value for {} to NVS", name);
+                store.set_str(name, val.expose())?
+            },
+            _ => anyhow::bail!("Invalid config value for {}: {:?}", name, self),
+        };
+        Ok(())
+    }
+
+    /// The name of the kind in the JSON schema.
+    pub(crate) fn kind(&self) -> &'static str {
+        match self {
+            TypedValue::String(_, _) => "string",
+            TypedValue::Int32(_) => "int32",
+            TypedValue::Int64(_) => "int64",
+            TypedValue::Bool(_) => "bool",
+            TypedValue::TimeZone(_) => "time_zone",
+            TypedValue::Float(_) => "float",
+            TypedValue::Enum(_, _) => "enum",
+            TypedValue::Ipv4(_) => "ipv4",
+            TypedValue::Ipv6(_) => "ipv6",
+            TypedValue::Url(_, _) => "url",
+            TypedValue::Duration(_) => "duration",
+            TypedValue::Secret(_, _) => "secret",
+        }
+    }
+
+    /// The value as typed JSON, null if it is not set. Durations are whole seconds. Secrets are always null, they
+    /// can be written but not read back.
+    fn to_json(&self) -> serde_json::Value {
+        match self {
+            TypedValue::Int32(Some(val)) => (*val).into(),
+            TypedValue::Int64(Some(val)) => (*val).into(),
+            // Going through the shortest text form keeps 1.2 from coming out as 1.2000000476837158.
+            TypedValue::Float(Some(val)) => val.to_string().parse::<f64>().map(serde_json::Value::from).unwrap_or_default(),
+            TypedValue::Bool(val) => (*val).into(),
+            TypedValue::Duration(Some(val)) => val.as_secs().into(),
+            TypedValue::Secret(_len, _) => serde_json::Value::Null,
+            _ if self.is_none() => serde_json::Value::Null,
+            _ => self.to_string().into(),
+        }
+    }
+
+    /// Turns a JSON value into the text the config form would post for it, so it goes through the same checks.
+    /// Null clears the value.
+    fn json_to_form_value(&self, json: &serde_json::Value) -> Result<String, String> {
+        use serde_json::Value;
+        match (self, json) {
+            (_, Value::Null) => Ok(String::new()),
+            (TypedValue::Int32(_) | TypedValue::Int64(_), Value::Number(number)) if number.is_i64() || number.is_u64() => Ok(number.to_string()),
+            (TypedValue::Int32(_) | TypedValue::Int64(_), _) => Err("Expected a whole number".to_string()),
+            (TypedValue::Float(_), Value::Number(number)) => Ok(number.to_string()),
+            (TypedValue::Float(_), _) => Err("Expected a number".to_string()),
+            (TypedValue::Bool(_), Value::Bool(val)) => Ok(val.to_string()),
+            (TypedValue::Bool(_), _) => Err("Expected true or false".to_string()),
+            // Seconds, or text such as 1h30m.
+            (TypedValue::Duration(_), Value::Number(number)) if number.is_u64() => Ok(number.to_string()),
+            (TypedValue::Duration(_), Value::String(val)) => Ok(val.clone()),
+            (TypedValue::Duration(_), _) => Err("Expected a number of seconds or a duration such as 1h30m".to_string()),
+            (_, Value::String(val)) => Ok(val.clone()),
+            (_, _) => Err("Expected a string".to_string()),
+        }
+    }
+
+    /// An example shown in the empty field, for the types where the form isn't obvious.
+    #[cfg(target_os = "espidf")]
+    fn example(&self) -> Option<&'static str> {
+        match self {
+            TypedValue::Ipv4(_) => Some("192.168.1.10"),
+            TypedValue::Ipv6(_) => Some("fd00::10"),
+            TypedValue::Url(_len, _) => Some("https://"),
+            TypedValue::Duration(_) => Some("e.g. 90s, 15m or 1h30m"),
+            _ => None,
         }
     }
 
     fn to_none(&self) -> Self {
         match self {
-            TypedValue::String(len, val) => TypedValue::String(*len, None),
-            TypedValue::Int32(val) => TypedValue::Int32(None),
-            TypedValue::Int64(val) => TypedValue::Int64(None),
-            TypedValue::Bool(val) => TypedValue::Bool(false),
+            TypedValue::String(len, _) => TypedValue::String(*len, None),
+            TypedValue::Int32(_) => TypedValue::Int32(None),
+            TypedValue::Int64(_) => TypedValue::Int64(None),
+            TypedValue::Bool(_) => TypedValue::Bool(false),
             TypedValue::TimeZone(_) => TypedValue::TimeZone(TimeZone::Utc),
+            TypedValue::Float(_) => TypedValue::Float(None),
+            TypedValue::Enum(choices, _) => TypedValue::Enum(choices, None),
+            TypedValue::Ipv4(_) => TypedValue::Ipv4(None),
+            TypedValue::Ipv6(_) => TypedValue::Ipv6(None),
+            TypedValue::Url(len, _) => TypedValue::Url(*len, None),
+            TypedValue::Duration(_) => TypedValue::Duration(None),
+            TypedValue::Secret(len, _) => TypedValue::Secret(*len, None),
         }
     }
     
-    fn from_str(&self, str_val: &str) -> anyhow::Result<TypedValue> {
+    /// Parses text from the form into a value of the same kind as this one.
+    fn parse(&self, str_val: &str) -> anyhow::Result<TypedValue> {
         Ok(match self {
 
             TypedValue::String(len, _) => {
-                if str_val.len() > *len as usize {
+                if str_val.len() > *len {
                     anyhow::bail!("String value too long: max length is {}", len);
                 } else {
                     TypedValue::String(*len, Some(str_val.to_string()))
@@ -150,7 +296,8 @@ impl TypedValue {
             },
             TypedValue::Int32(_) => TypedValue::Int32(Some(str_val.parse::<i32>()?)),
             TypedValue::Int64(_) => TypedValue::Int64(Some(str_val.parse::<i64>()?)),
-            TypedValue::Bool(_) => TypedValue::Bool(str_val.parse::<bool>()?),
+            // Checkboxes post "on" when checked
+            TypedValue::Bool(_) => TypedValue::Bool(str_val == "on" || str_val.parse::<bool>()?),
             TypedValue::TimeZone(_) => {
                 if let Some(tz) = TimeZone::from_str(str_val) {
                     TypedValue::TimeZone(tz)
@@ -158,33 +305,213 @@ impl TypedValue {
                     anyhow::bail!("Invalid timezone value: {}", str_val);
                 }
             },
+            TypedValue::Float(_) => TypedValue::Float(Some(values::parse_float(str_val)?)),
+            TypedValue::Enum(choices, _) => TypedValue::Enum(choices, Some(values::parse_choice(choices, str_val)?)),
+            TypedValue::Ipv4(_) => TypedValue::Ipv4(Some(str_val.trim().parse().map_err(|_| anyhow!("\"{}\" is not an IPv4 address", str_val))?)),
+            TypedValue::Ipv6(_) => TypedValue::Ipv6(Some(str_val.trim().parse().map_err(|_| anyhow!("\"{}\" is not an IPv6 address", str_val))?)),
+            TypedValue::Url(len, _) => {
+                if str_val.len() > *len {
+                    anyhow::bail!("URL too long: max length is {}", len);
+                }
+                values::parse_url(str_val)?;
+                // Keep what was entered rather than the normalised form, which may add a trailing slash.
+                TypedValue::Url(*len, Some(str_val.trim().to_string()))
+            },
+            TypedValue::Duration(_) => TypedValue::Duration(Some(values::parse_duration(str_val)?)),
+            TypedValue::Secret(len, _) => {
+                if str_val.len() > *len {
+                    anyhow::bail!("Too long: max length is {}", len);
+                }
+                TypedValue::Secret(*len, Some(SecretString::new(str_val)))
+            },
         })
     }
 }
 
 
+impl std::fmt::Display for TypedValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let text = match self {
+            TypedValue::String(_len, Some(val)) => val.clone(),
+            TypedValue::Int32(Some(val)) => val.to_string(),
+            TypedValue::Int64(Some(val)) => val.to_string(),
+            TypedValue::Bool(val) => val.to_string(),
+            TypedValue::TimeZone(tz) => tz.to_str().to_string(),
+            TypedValue::Float(Some(val)) => val.to_string(),
+            TypedValue::Enum(_choices, Some(val)) => val.to_string(),
+            TypedValue::Ipv4(Some(val)) => val.to_string(),
+            TypedValue::Ipv6(Some(val)) => val.to_string(),
+            TypedValue::Url(_len, Some(val)) => val.clone(),
+            TypedValue::Duration(Some(val)) => values::format_duration(*val),
+            TypedValue::Secret(_len, Some(val)) => val.expose().to_string(),
+            _ => "".to_string(),
+        };
+        f.write_str(&text)
+    }
+}
+
 #[derive(Debug)]
 pub struct ConfigValue {
     pub value: TypedValue,
     pub required: bool,
+    /// Shown on the config page instead of the key.
+    pub label: Option<&'static str>,
+    /// A sentence or two under the field saying what it is for.
+    pub help: Option<&'static str>,
+    /// Shown in the empty field, the default or an example for the type if not set.
+    pub placeholder: Option<&'static str>,
+    pub rules: Rules,
+    /// The value while nothing is saved, and after an optional value is cleared.
+    pub default: Option<TypedValue>,
 }
 
 impl ConfigValue {
-    fn read_from_nvs(&mut self, nvs: &EspNvs<NvsDefault>, name: &str) {
-        let nv = self.value.read_from_nvs(nvs, name);
+    pub fn new(value: TypedValue, required: bool) -> Self {
+        Self {
+            value,
+            required,
+            label: None,
+            help: None,
+            placeholder: None,
+            rules: Rules::default(),
+            default: None,
+        }
+    }
+
+    pub fn with_label(mut self, label: &'static str) -> Self {
+        self.label = Some(label);
+        self
+    }
+
+    pub fn with_help(mut self, help: &'static str) -> Self {
+        self.help = Some(help);
+        self
+    }
+
+    pub fn with_placeholder(mut self, placeholder: &'static str) -> Self {
+        self.placeholder = Some(placeholder);
+        self
+    }
+
+    /// Bounds for Int32, Int64, Float and Duration values, durations in seconds.
+    pub fn with_range(mut self, min: Option<f64>, max: Option<f64>) -> Self {
+        self.rules.min = min;
+        self.rules.max = max;
+        self
+    }
+
+    /// The shortest String or Url value allowed.
+    pub fn with_min_len(mut self, min_len: usize) -> Self {
+        self.rules.min_len = Some(min_len);
+        self
+    }
+
+    /// A regular expression String and Url values must match in full, and the message to show when one doesn't.
+    pub fn with_pattern(mut self, pattern: &'static str, message: &'static str) -> Self {
+        self.rules.pattern = Some((pattern, message));
+        self
+    }
+
+    /// The value used until one is saved. It is also the starting value, so a required value with a default is
+    /// valid from the start.
+    pub fn with_default(mut self, default: TypedValue) -> Self {
+        self.value = default.clone();
+        self.default = Some(default);
+        self
+    }
+
+    pub fn label<'a>(&'a self, name: &'a str) -> &'a str {
+        self.label.unwrap_or(name)
+    }
+
+    /// Describes the value for the JSON schema.
+    fn schema_json(&self, name: &str) -> serde_json::Value {
+        let mut schema = serde_json::Map::new();
+        schema.insert("name".to_string(), name.into());
+        schema.insert("kind".to_string(), self.value.kind().into());
+        schema.insert("label".to_string(), self.label(name).into());
+        schema.insert("required".to_string(), self.required.into());
+        match self.value {
+            TypedValue::String(len, _) | TypedValue::Url(len, _) | TypedValue::Secret(len, _) => {
+                schema.insert("max_len".to_string(), len.into());
+            },
+            TypedValue::Enum(choices, _) => {
+                schema.insert("choices".to_string(), choices.into());
+            },
+            TypedValue::TimeZone(_) => {
+                schema.insert("choices".to_string(), TimeZone::iter().map(|tz| tz.to_str()).collect::<Vec<_>>().into());
+            },
+            _ => {},
+        }
+        if let Some(help) = self.help {
+            schema.insert("help".to_string(), help.into());
+        }
+        if let Some(placeholder) = self.placeholder {
+            schema.insert("placeholder".to_string(), placeholder.into());
+        }
+        if let Some(min) = self.rules.min {
+            schema.insert("min".to_string(), min.into());
+        }
+        if let Some(max) = self.rules.max {
+            schema.insert("max".to_string(), max.into());
+        }
+        if let Some(min_len) = self.rules.min_len {
+            schema.insert("min_len".to_string(), min_len.into());
+        }
+        if let Some((pattern, message)) = self.rules.pattern {
+            schema.insert("pattern".to_string(), pattern.into());
+            schema.insert("pattern_message".to_string(), message.into());
+        }
+        if let Some(default) = &self.default {
+            schema.insert("default".to_string(), default.to_json());
+        }
+        schema.into()
+    }
+
+    pub fn is_secret(&self) -> bool {
+        matches!(self.value, TypedValue::Secret(_, _))
+    }
+
+    fn read_from_store(&mut self, store: &dyn ConfigStore, name: &str) {
+        if let (Some(default), Ok(false)) = (&self.default, store.contains(name)) {
+            info!("No saved value for {}, using the default {:?}", name, default);
+            self.value = default.clone();
+            return;
+        }
+        let nv = self.value.read_from_store(store, name);
         self.value = nv;
     }
+
+    /// What the value becomes when its field is cleared.
+    fn cleared(&self) -> TypedValue {
+        self.default.clone().unwrap_or_else(|| self.value.to_none())
+    }
+
+    /// Parses a value from the config form and checks it against the rules. The error is for showing by the field.
+    fn parse_form_value(&self, str_val: &str) -> Result<TypedValue, String> {
+        let value = self.value.parse(str_val).map_err(|e| e.to_string())?;
+        match &value {
+            TypedValue::Int32(Some(val)) => self.rules.check_number(*val as f64, |bound| bound.to_string())?,
+            TypedValue::Int64(Some(val)) => self.rules.check_number(*val as f64, |bound| bound.to_string())?,
+            TypedValue::Float(Some(val)) => self.rules.check_number(*val as f64, |bound| bound.to_string())?,
+            TypedValue::Duration(Some(val)) => self.rules.check_number(val.as_secs_f64(), |bound| values::format_duration(Duration::from_secs_f64(bound.max(0.0))))?,
+            TypedValue::String(_len, Some(val)) | TypedValue::Url(_len, Some(val)) => self.rules.check_text(val)?,
+            TypedValue::Secret(_len, Some(val)) => self.rules.check_text(val.expose())?,
+            _ => {},
+        }
+        Ok(value)
+    }
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Config {
     map: IndexMap<String, ConfigValue>,
 }
 
 impl Config {
     pub fn new() -> Self {
-        Self { map: IndexMap::new() }
+        Self::default()
     }
 
     pub fn insert(&mut self, name: String, value: ConfigValue) -> anyhow::Result<()> {
@@ -199,6 +526,12 @@ impl Config {
         if name.starts_with("_") {
             anyhow::bail!("Config name \"{}\" is invalid: cannot start with _", name);
         }   
+
+        if let Some((pattern, _message)) = value.rules.pattern {
+            if let Err(e) = values::full_match_regex(pattern) {
+                anyhow::bail!("Config \"{}\" has an invalid pattern {}: {}", name, pattern, e);
+            }
+        }
         
         self.map.insert(name, value);
         Ok(())
@@ -220,17 +553,29 @@ impl Config {
 pub struct FeatureDescriptor {
     pub name: String,
     pub config: Config,
+    /// Bump this when a value is renamed or changes type, and handle the old values in `Feature::migrate`.
+    pub version: u32,
+}
+
+/// A copy of a feature's config taken by `FeatureConfig::snapshot`.
+struct Snapshot {
+    enabled: bool,
+    /// Whether NVS had the enabled flag.
+    enabled_saved: bool,
+    /// Each value, and whether NVS had it.
+    values: IndexMap<String, (TypedValue, bool)>,
 }
 
 pub struct FeatureConfig {
     pub name: String,
     pub enabled: EnabledState,
     pub config: Config,
-    nvs_namespace: EspNvs<NvsDefault>,
+    store: Box<dyn ConfigStore>,
 }
 
 impl FeatureConfig {
-    pub fn from_descriptor(feature_descriptor: FeatureDescriptor, nvs_partition: EspNvsPartition<NvsDefault>, feature_namespace: &EspNvs<NvsDefault>) -> anyhow::Result<Self> {
+    /// Loads a feature's config from `store`, and whether it is enabled from `feature_namespace`.
+    pub fn from_descriptor(feature_descriptor: FeatureDescriptor, store: Box<dyn ConfigStore>, feature_namespace: &dyn ConfigStore) -> anyhow::Result<Self> {
         let enabled = if let Some(value) = feature_namespace.get_u8(&feature_descriptor.name).ok().flatten() {
             info!("Read feature enabled value for {} from NVS: {}", feature_descriptor.name, value);
             value != 0
@@ -240,28 +585,19 @@ impl FeatureConfig {
         };
 
         info!("feature.enabled for {}: {}", feature_descriptor.name, enabled);
-        Self::new(feature_descriptor.name, EnabledState::from(enabled), feature_descriptor.config, nvs_partition)
+        Self::new(feature_descriptor.name, EnabledState::from(enabled), feature_descriptor.config, store)
     }
 
-    pub fn new(name: String, enabled: EnabledState, mut config: Config, nvs_partition: EspNvsPartition<NvsDefault>) -> anyhow::Result<Self> {
-
-        let nvs_namespace = EspNvs::new(nvs_partition, &name, true)?;
+    pub fn new(name: String, enabled: EnabledState, mut config: Config, store: Box<dyn ConfigStore>) -> anyhow::Result<Self> {
 
-        {
-            info!("Iterating over feature {} NVS items for debugging:", &name);
-            let mut keys = nvs_namespace.keys(None).unwrap();
-
-            loop {
-                match keys.next_key() {
-                    Some((key, data_type)) => log::info!("NVS item: {} of type {:?}", key, data_type),
-                    None => break,
-                }
-            }
+        info!("Iterating over feature {} NVS items for debugging:", &name);
+        for (key, data_type) in store.keys()? {
+            log::info!("NVS item: {} of type {}", key, data_type);
         }
 
         info!("Loading feature {} config from NVS", &name);
         for (name, config_value) in config.map.iter_mut() {
-            config_value.read_from_nvs(&nvs_namespace, name);
+            config_value.read_from_store(store.as_ref(), name);
         }
         info!("Finished loading config: {:?}", config);
 
@@ -269,7 +605,7 @@ impl FeatureConfig {
             name,
             enabled,
             config,
-            nvs_namespace,
+            store,
         })
     }
 
@@ -285,9 +621,13 @@ impl FeatureConfig {
         true
     }
 
-    fn create_config_page(&self, resp: &mut esp_idf_svc::http::server::Response<&mut EspHttpConnection<'_>>) -> anyhow::Result<()> {
+    /// Writes this feature's part of the config form. `submitted` is a form which was rejected, its values are shown
+    /// instead of the saved ones so nothing typed is lost, along with the `errors` for its bad fields.
+    #[cfg(target_os = "espidf")]
+    fn create_config_page(&self, resp: &mut esp_idf_svc::http::server::Response<&mut EspHttpConnection<'_>>,
+        submitted: Option<&IndexMap<String, String>>, errors: &IndexMap<String, String>) -> anyhow::Result<()> {
         info!("Creating config page for feature: {}", &self.name);
-        let feature_name = &self.name;
+        let feature_name = escape_html(&self.name);
         if let EnabledState::Required = self.enabled {
             // Required features are always enabled, so we just show the config page without a checkbox
         }
@@ -295,53 +635,338 @@ impl FeatureConfig {
             info!("feature.enabled for {}: {}", &self.name, self.enabled.is_enabled());
 
             let name = format!("feature_{}", &self.name);
-            let checked = if self.enabled.is_enabled() {
+            let enabled = match submitted {
+                Some(form) => form.get(&name).is_some_and(|value| value == "on"),
+                None => self.enabled.is_enabled(),
+            };
+            let checked = if enabled {
                 " checked"
             } else {
                 ""
             };
 
             resp.write(format!(r#"
-                        <label for="{name}">{name}</label>
+                        <label for="{name}">Enable {feature_name}</label>
                         <input id="{name}" name="{name}" type="checkbox"{checked}>
                         <h2>{feature_name}</h2>
             "#).as_bytes())?;
         }
 
         for (name, config_value) in &self.config.map {
-            let input_type_buf: String;
-            let input_type = match config_value.value {
-                TypedValue::String(len, _) => {
-                    input_type_buf = format!("text\" maxlength=\"{}", len);
-                    &input_type_buf
+            let label = escape_html(config_value.label(name));
+            let current = match submitted {
+                Some(form) => form.get(name).map(|s| s.trim().to_string()).unwrap_or_default(),
+                None => config_value.value.to_string(),
+            };
+            let invalid = if errors.contains_key(name) { r#" class="invalid""# } else { "" };
+            let mut notes = String::new();
+            if let Some(help) = config_value.help {
+                notes.push_str(&format!(r#"<small class="help">{}</small>"#, escape_html(help)));
+            }
+            if let Some(error) = errors.get(name) {
+                notes.push_str(&format!(r#"<div class="field-error">{}</div>"#, escape_html(error)));
+            }
+
+            let mut attrs = match config_value.value {
+                TypedValue::String(len, _) => format!(r#"type="text" maxlength="{}""#, len),
+                TypedValue::Int32(_) | TypedValue::Int64(_) => r#"type="number""#.to_string(),
+                TypedValue::Float(_) => r#"type="number" step="any""#.to_string(),
+                TypedValue::Ipv4(_) => r#"type="text" maxlength="15""#.to_string(),
+                TypedValue::Ipv6(_) => r#"type="text" maxlength="39""#.to_string(),
+                TypedValue::Url(len, _) => format!(r#"type="url" maxlength="{}""#, len),
+                TypedValue::Duration(_) => r#"type="text""#.to_string(),
+                TypedValue::Secret(len, ref saved) => {
+                    // The saved value is never sent back, nor is one typed into a rejected form.
+                    let placeholder = match (saved, config_value.placeholder) {
+                        (Some(_), _) => "leave blank to keep",
+                        (None, Some(placeholder)) => placeholder,
+                        (None, None) => "",
+                    };
+                    resp.write(format!(r#"
+                        <label for="{name}">{label}</label>
+                        <input id="{name}" name="{name}" type="password" maxlength="{len}"{invalid} autocomplete="new-password" placeholder="{placeholder}" value="">{notes}
+            "#).as_bytes())?;
+                    if saved.is_some() && !config_value.required {
+                        resp.write(format!(r#"
+                        <label for="{name}{CLEAR_SUFFIX}">Clear saved {label}</label>
+                        <input id="{name}{CLEAR_SUFFIX}" name="{name}{CLEAR_SUFFIX}" type="checkbox">
+            "#).as_bytes())?;
+                    }
+                    continue;
+                },
+                TypedValue::Enum(choices, _) => {
+                    resp.write(format!(r#"
+                        <label for="{name}">{label}</label>
+                        <select id="{name}" name="{name}"{invalid}>"#).as_bytes())?;
+                    // Optional values can be cleared, and required ones start out unset.
+                    if !config_value.required || current.is_empty() {
+                        let selected_attr = if current.is_empty() { " selected" } else { "" };
+                        resp.write(format!(r#"<option value=""{}></option>"#, selected_attr).as_bytes())?;
+                    }
+                    for choice in choices {
+                        let selected_attr = if *choice == current { " selected" } else { "" };
+                        resp.write(format!(r#"<option value="{}"{}>{}</option>"#, choice, selected_attr, choice).as_bytes())?;
+                    }
+                    resp.write(format!(r#"</select>{notes}"#).as_bytes())?;
+                    continue;
+                },
+                TypedValue::Bool(saved) => {
+                    let checked = match submitted {
+                        Some(_) => current == "on",
+                        None => saved,
+                    };
+                    let checked = if checked { " checked" } else { "" };
+                    resp.write(format!(r#"
+                        <label for="{name}">{label}</label>
+                        <input id="{name}" name="{name}" type="checkbox"{checked}>{notes}
+            "#).as_bytes())?;
+                    continue;
                 },
-                TypedValue::Int32(_) | TypedValue::Int64(_) => "number",
-                TypedValue::Bool(_) => "checkbox",
-                TypedValue::TimeZone(current) => {
+                TypedValue::TimeZone(_) => {
                     info!("Config value {} is a TimeZone,", name);
 
                     resp.write(format!(r#"
-                        <label for="{name}">{name}</label>
-                        <select id="{name}" name="{name}">"#).as_bytes())?;
+                        <label for="{name}">{label}</label>
+                        <select id="{name}" name="{name}"{invalid}>"#).as_bytes())?;
                     for tz in TimeZone::iter() {
-                        let selected_attr = if *tz == current { " selected" } else { "" };
+                        let selected_attr = if tz.to_str() == current { " selected" } else { "" };
                         resp.write(format!(r#"<option value="{}"{}>{}</option>"#, tz.to_str(), selected_attr, tz.to_str()).as_bytes())?;
                     }
-                    resp.write(format!(r#"</select>"#).as_bytes())?;
+                    resp.write(format!(r#"</select>{notes}"#).as_bytes())?;
                     continue;
                 },
             };
-            let value = config_value.value.to_string();
+
+            if matches!(config_value.value, TypedValue::Int32(_) | TypedValue::Int64(_) | TypedValue::Float(_)) {
+                if let Some(min) = config_value.rules.min {
+                    attrs.push_str(&format!(r#" min="{}""#, min));
+                }
+                if let Some(max) = config_value.rules.max {
+                    attrs.push_str(&format!(r#" max="{}""#, max));
+                }
+            }
+            if let Some(min_len) = config_value.rules.min_len {
+                attrs.push_str(&format!(r#" minlength="{}""#, min_len));
+            }
+            let placeholder = match (config_value.placeholder, &config_value.default) {
+                (Some(placeholder), _) => Some(placeholder.to_string()),
+                (None, Some(default)) => Some(default.to_string()),
+                (None, None) => config_value.value.example().map(|example| example.to_string()),
+            };
+            if let Some(placeholder) = placeholder {
+                attrs.push_str(&format!(r#" placeholder="{}""#, escape_html(&placeholder)));
+            }
+
+            let value = escape_html(&current);
             resp.write(format!(r#"
-                        <label for="{name}">{name}</label>
-                        <input id="{name}" name="{name}" type="{input_type}" autocomplete="off" value="{value}">
+                        <label for="{name}">{label}</label>
+                        <input id="{name}" name="{name}" {attrs}{invalid} autocomplete="off" value="{value}">{notes}
             "#).as_bytes())?;
         }
 
         Ok(())
     }
 
-    pub fn handle_config_form(&mut self, form: &IndexMap<String, String>, feature_namespace: &EspNvs<NvsDefault>) -> anyhow::Result<()> {
+    /// The feature's values as typed JSON. Secrets are left out of `values`, `secrets_set` lists the ones which have
+    /// a value saved.
+    pub fn to_json(&self) -> serde_json::Value {
+        let mut values = serde_json::Map::new();
+        let mut secrets_set = Vec::new();
+        for (name, config_value) in &self.config.map {
+            if config_value.is_secret() {
+                if !config_value.value.is_none() {
+                    secrets_set.push(name.clone());
+                }
+            }
+            else {
+                values.insert(name.clone(), config_value.value.to_json());
+            }
+        }
+        serde_json::json!({
+            "enabled": self.enabled.is_enabled(),
+            "values": values,
+            "secrets_set": secrets_set,
+        })
+    }
+
+    pub fn schema_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "name": self.name,
+            // Required features are always on.
+            "can_disable": !matches!(self.enabled, EnabledState::Required),
+            "values": self.config.map.iter().map(|(name, config_value)| config_value.schema_json(name)).collect::<Vec<_>>(),
+        })
+    }
+
+    /// Builds the config form equivalent of a JSON update such as `{"enabled": true, "values": {...}}`, so it can be
+    /// checked and saved the same way as the form. With `replace` values left out of the update are cleared,
+    /// otherwise they keep their current value. Secrets left out always keep theirs, since they can't be read back
+    /// to send. Returns the problems keyed by value name if the update doesn't fit the schema.
+    pub fn json_to_form(&self, update: &serde_json::Value, replace: bool) -> Result<IndexMap<String, String>, IndexMap<String, String>> {
+        let mut errors = IndexMap::new();
+        let mut form = IndexMap::new();
+
+        let Some(update) = update.as_object() else {
+            errors.insert("body".to_string(), "Expected a JSON object".to_string());
+            return Err(errors);
+        };
+        for key in update.keys() {
+            if key != "enabled" && key != "values" {
+                errors.insert(key.clone(), "Unknown field, expected enabled or values".to_string());
+            }
+        }
+
+        let enabled = match update.get("enabled") {
+            None => self.enabled.is_enabled(),
+            Some(serde_json::Value::Bool(enabled)) => *enabled,
+            Some(_) => {
+                errors.insert("enabled".to_string(), "Expected true or false".to_string());
+                false
+            },
+        };
+        if enabled {
+            form.insert(format!("feature_{}", self.name), "on".to_string());
+        }
+
+        let no_values = serde_json::Map::new();
+        let values = match update.get("values") {
+            None => &no_values,
+            Some(serde_json::Value::Object(values)) => values,
+            Some(_) => {
+                errors.insert("values".to_string(), "Expected an object".to_string());
+                &no_values
+            },
+        };
+        for name in values.keys() {
+            if !self.config.map.contains_key(name) {
+                errors.insert(name.clone(), format!("No config value {} in feature {}", name, self.name));
+            }
+        }
+
+        for (name, config_value) in &self.config.map {
+            match values.get(name) {
+                Some(json) => match config_value.value.json_to_form_value(json) {
+                    Ok(str_val) => {
+                        if str_val.is_empty() && config_value.is_secret() {
+                            form.insert(format!("{}{}", name, CLEAR_SUFFIX), "on".to_string());
+                        }
+                        form.insert(name.clone(), str_val);
+                    },
+                    Err(e) => {
+                        errors.insert(name.clone(), e);
+                    },
+                },
+                // A blank secret keeps the saved one.
+                None if config_value.is_secret() => {},
+                None if replace => {
+                    form.insert(name.clone(), String::new());
+                },
+                None => {
+                    form.insert(name.clone(), config_value.value.to_string());
+                },
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(form)
+        }
+        else {
+            Err(errors)
+        }
+    }
+
+    /// A copy of whether the feature is on and of its values, so a failed import can put them back with `restore`.
+    fn snapshot(&self, feature_namespace: &dyn ConfigStore) -> anyhow::Result<Snapshot> {
+        let mut values = IndexMap::new();
+        for (name, config_value) in &self.config.map {
+            values.insert(name.clone(), (config_value.value.clone(), self.store.contains(name)?));
+        }
+        Ok(Snapshot {
+            enabled: self.enabled.is_enabled(),
+            enabled_saved: feature_namespace.contains(&self.name)?,
+            values,
+        })
+    }
+
+    /// Puts back the state saved by `snapshot`, both in memory and in NVS. Values which weren't saved are removed
+    /// again, so their defaults still apply. A value which was saved but couldn't be read has nothing to put back,
+    /// so it is left alone.
+    fn restore(&mut self, snapshot: &Snapshot, feature_namespace: &dyn ConfigStore) -> anyhow::Result<()> {
+        if !matches!(self.enabled, EnabledState::Required) {
+            if snapshot.enabled_saved {
+                feature_namespace.set_u8(&self.name, if snapshot.enabled { 1 } else { 0 })?;
+            }
+            else {
+                feature_namespace.remove(&self.name)?;
+            }
+            self.enabled = snapshot.enabled.into();
+        }
+        for (name, (value, saved)) in &snapshot.values {
+            let Some(config_value) = self.config.map.get_mut(name) else {
+                continue;
+            };
+            if !saved {
+                self.store.remove(name)?;
+            }
+            else if !value.is_none() {
+                value.write_to_store(self.store.as_ref(), name)?;
+            }
+            config_value.value = value.clone();
+        }
+        Ok(())
+    }
+
+    /// Sets one config value from its string form and saves it to NVS.
+    pub fn set_value(&mut self, name: &str, str_val: &str) -> anyhow::Result<()> {
+        let Some(config_value) = self.config.map.get_mut(name) else {
+            anyhow::bail!("No config value {} in feature {}", name, self.name);
+        };
+        config_value.value = config_value.value.parse(str_val)?;
+        config_value.value.write_to_store(self.store.as_ref(), name)
+    }
+
+    /// Checks this feature's part of the config form without saving anything. Adds a message to `errors` for each
+    /// field which can't be saved, keyed by the field name.
+    pub fn validate_form(&self, form: &IndexMap<String, String>, errors: &mut IndexMap<String, String>) {
+        let enabled = match self.enabled {
+            EnabledState::Required => true,
+            _ => form.get(&format!("feature_{}", &self.name)).is_some_and(|value| value == "on"),
+        };
+
+        for (name, config_value) in &self.config.map {
+            let str_val = Self::form_value(form, name, config_value);
+            if str_val.is_empty() {
+                // A blank secret keeps the saved one.
+                let kept = config_value.is_secret() && !config_value.value.is_none();
+                // Required values only matter once the feature is turned on.
+                if config_value.required && config_value.default.is_none() && enabled && !kept {
+                    errors.insert(name.clone(), "This is required".to_string());
+                }
+            }
+            else if let Err(e) = config_value.parse_form_value(str_val) {
+                errors.insert(name.clone(), e);
+            }
+        }
+    }
+
+    /// The value posted for a field, trimmed. An unchecked checkbox posts nothing, which means false.
+    fn form_value<'a>(form: &'a IndexMap<String, String>, name: &str, config_value: &ConfigValue) -> &'a str {
+        let str_val = form.get(name).map(|s| s.as_str()).unwrap_or("").trim();
+        if str_val.is_empty() && matches!(config_value.value, TypedValue::Bool(_)) {
+            "false"
+        }
+        else {
+            str_val
+        }
+    }
+
+    /// Whether the box to clear a saved secret was ticked.
+    fn clear_requested(form: &IndexMap<String, String>, name: &str) -> bool {
+        form.get(&format!("{}{}", name, CLEAR_SUFFIX)).is_some_and(|value| value == "on")
+    }
+
+    /// Saves this feature's part of the config form, which should have been checked with `validate_form` first.
+    pub fn handle_config_form(&mut self, form: &IndexMap<String, String>, feature_namespace: &dyn ConfigStore) -> anyhow::Result<()> {
         info!("Handling config form for feature: {}", self.name);
         if let EnabledState::Required = self.enabled {
             // Required features are always enabled, so we just show the config page without a checkbox
@@ -352,57 +977,40 @@ impl FeatureConfig {
             let enabled = str_val == "on";
             info!("Feature {} enabled value from form: {} -> enabled={}", &self.name, str_val, enabled);
                 feature_namespace.set_u8(&self.name, if enabled { 1 } else { 0 })?;
+            self.enabled = enabled.into();
         }
 
         for (name, config_value) in self.config.map.iter_mut() {
             info!("Processing config value: {}", name);
-            let str_val = form.get(name).map(|s| s.as_str()).unwrap_or("").trim();
-            if str_val.len() == 0 {
-                if config_value.required {
+            let str_val = Self::form_value(form, name, config_value);
+            let shown = if config_value.is_secret() { REDACTED } else { str_val };
+            if str_val.is_empty() && config_value.is_secret() && !Self::clear_requested(form, name) {
+                log::info!("Keeping saved secret {}", name);
+            }
+            else if str_val.is_empty() {
+                if config_value.required && config_value.default.is_none() {
                     log::error!("Missing required config value: {}", name);
                 }
                 else {
                     log::info!("Config value {} is None", name);
                     if ! config_value.value.is_none() {
-                        log::info!("Setting optional config value {} to None", name);
-                        config_value.value = config_value.value.to_none();
-                        self.nvs_namespace.remove(name)?;
+                        log::info!("Clearing config value {}", name);
+                        config_value.value = config_value.cleared();
+                        self.store.remove(name)?;
                     }
                 }
             }
             else {
-                log::info!("Config value {} is {}", name, str_val);
-                match config_value.value.from_str(str_val) {
+                log::info!("Config value {} is {}", name, shown);
+                match config_value.parse_form_value(str_val) {
                     Ok(new_value) => {
                         if config_value.value.is_none() || new_value != config_value.value {
                             log::info!("Config value {} changed from {:?} to {:?}", name, config_value.value, new_value);
 
                             config_value.value = new_value;
                             // Save to NVS
-                            log::info!("Save to NVS Config value {} is {}", name, str_val);
-                            match &config_value.value {
-                                TypedValue::String(_len, Some(val)) => {
-                                    info!("Saving string value for {} to NVS: {}", name, val);
-                                    self.nvs_namespace.set_str(name, val)?
-                                },
-                                TypedValue::Int32(Some(val)) => {
-                                    info!("Saving int32 value for {} to NVS: {}", name, val);
-                                    self.nvs_namespace.set_i32(name, *val)?
-                                },
-                                TypedValue::Int64(Some(val)) => {
-                                    info!("Saving int64 value for {} to NVS: {}", name, val);
-                                    self.nvs_namespace.set_i64(name, *val)?
-                                },
-                                TypedValue::Bool(val) => {
-                                    info!("Saving bool value for {} to NVS: {}", name, val);
-                                    self.nvs_namespace.set_u8(name, if *val { 1 } else { 0 })?
-                                },
-                                TypedValue::TimeZone(tz) => {
-                                    info!("Saving TimeZone value for {} to NVS: {}", name, tz.to_str());
-                                    self.nvs_namespace.set_str(name, tz.to_str())?
-                                },
-                                _ => anyhow::bail!("Invalid config value for {}: {:?}", name, config_value.value),
-                            };
+                            log::info!("Save to NVS Config value {} is {}", name, shown);
+                            config_value.value.write_to_store(self.store.as_ref(), name)?;
                         }
                         else {
                             log::info!("Config value {} unchanged: {:?}", name, config_value.value);
@@ -418,13 +1026,8 @@ impl FeatureConfig {
         info!("Finished handling form config: {:?}", &self.config);
 
         info!("Iterating over NVS items for debugging:");
-        let mut keys = self.nvs_namespace.keys(None).unwrap();
-
-        loop {
-            match keys.next_key() {
-                Some((key, data_type)) => log::info!("NVS item: {} of type {:?}", key, data_type),
-                None => break,
-            }
+        for (key, data_type) in self.store.keys()? {
+            log::info!("NVS item: {} of type {}", key, data_type);
         }
 
         Ok(())
@@ -433,53 +1036,141 @@ impl FeatureConfig {
 
 
 
+#[cfg(target_os = "espidf")]
 use esp_idf_svc::nvs::*;
+#[cfg(target_os = "espidf")]
 use crate::http::HttpServerManager;
+use crate::migration::{self, MigrationReport};
+#[cfg(target_os = "espidf")]
+use crate::storage::NvsStore;
+use crate::storage::{self, ConfigStore};
 
 pub struct ConfigManager {
     pub features: IndexMap<String, Mutex<FeatureConfig>>,
-    feature_namespace: EspNvs<NvsDefault>,
+    feature_namespace: Box<dyn ConfigStore>,
     pub failure_reason: Arc<Mutex<Option<String>>>,
     ap_mode: Arc<Mutex<bool>>,
+    // Only the config page handlers use these.
+    #[cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+    setup_ap: Arc<Mutex<bool>>,
+    pub networks: Mutex<NetworkList>,
+    #[cfg_attr(not(target_os = "espidf"), allow(dead_code))]
+    scan_results: Arc<ScanResults>,
+    /// What happened to each feature's saved config at startup, for /status/config.
+    pub migrations: Vec<MigrationReport>,
 }
 
 impl ConfigManager {
+    #[cfg(target_os = "espidf")]
     pub fn new(nvs_partition: EspNvsPartition<NvsDefault>, 
         p_features: &Vec::<Box<dyn Feature>>, 
         failure_reason: Arc<Mutex<Option<String>>>, 
-        ap_mode: Arc<Mutex<bool>>) -> anyhow::Result<Arc<ConfigManager>> {
+        ap_mode: Arc<Mutex<bool>>,
+        setup_ap: Arc<Mutex<bool>>,
+        scan_results: Arc<ScanResults>) -> anyhow::Result<Arc<ConfigManager>> {
+        let open_store = |namespace: &str| -> anyhow::Result<Box<dyn ConfigStore>> {
+            Ok(Box::new(NvsStore::new(nvs_partition.clone(), namespace)?))
+        };
+        Self::with_stores(open_store, p_features, failure_reason, ap_mode, setup_ap, scan_results)
+    }
+
+    /// Loads the config from the stores `open_store` gives for each NVS namespace, `MemoryStore`s in the tests.
+    pub fn with_stores(open_store: impl Fn(&str) -> anyhow::Result<Box<dyn ConfigStore>>,
+        p_features: &Vec::<Box<dyn Feature>>,
+        failure_reason: Arc<Mutex<Option<String>>>,
+        ap_mode: Arc<Mutex<bool>>,
+        setup_ap: Arc<Mutex<bool>>,
+        scan_results: Arc<ScanResults>) -> anyhow::Result<Arc<ConfigManager>> {
 
         let mut features: IndexMap<String, Mutex<FeatureConfig>> = IndexMap::new();
-        let feature_namespace = EspNvs::new(nvs_partition.clone(), FEATURE_NAMESPACE_NAME, true)?;
+        let feature_namespace = open_store(FEATURE_NAMESPACE_NAME)?;
 
         let mut core_config = Config::new();
 
-        core_config.insert(SSID.to_string(), ConfigValue { value: TypedValue::String(SSID_LEN, None), required: true })?;
-        core_config.insert(WIFI_PASSWORD.to_string(), ConfigValue { value: TypedValue::String(PASSWORD_LEN, None), required: true })?;
-        core_config.insert(MDNS_HOSTNAME.to_string(), ConfigValue { value: TypedValue::String(HOSTNAME_LEN, None), required: true })?;
-        core_config.insert(TIMEZONE.to_string(), ConfigValue { value: TypedValue::TimeZone(TimeZone::Utc), required: true })?;
+        core_config.insert(SSID.to_string(), ConfigValue::new(TypedValue::String(SSID_LEN, None), true)
+            .with_label("Wi-Fi network"))?;
+        core_config.insert(WIFI_PASSWORD.to_string(), ConfigValue::new(TypedValue::Secret(PASSWORD_LEN, None), true)
+            .with_label("Wi-Fi password")
+            .with_help("For enterprise networks this is the 802.1X password."))?;
+        core_config.insert(MDNS_HOSTNAME.to_string(), ConfigValue::new(TypedValue::String(HOSTNAME_LEN, None), true)
+            .with_label("Device name")
+            .with_help("The device is reachable as <name>.local.")
+            .with_pattern("[A-Za-z0-9]([A-Za-z0-9-]*[A-Za-z0-9])?", "Use letters, digits and hyphens, not starting or ending with a hyphen"))?;
+        core_config.insert(TIMEZONE.to_string(), ConfigValue::new(TypedValue::TimeZone(TimeZone::Utc), true)
+            .with_label("Time zone"))?;
+        core_config.insert(AP_FALLBACK_AFTER.to_string(), ConfigValue::new(TypedValue::Int32(None), false)
+            .with_label("Setup mode after failed reconnects")
+            .with_help("Go back to the setup access point after this many failed reconnects, 0 to keep trying. The networks are still tried every so often from the access point.")
+            .with_placeholder("10")
+            .with_range(Some(0.0), None))?;
+        core_config.insert(AP_SSID.to_string(), ConfigValue::new(TypedValue::String(SSID_LEN, None), false)
+            .with_label("Setup access point name")
+            .with_placeholder("Sparko-XXXX"))?;
+        core_config.insert(AP_PASSWORD.to_string(), ConfigValue::new(TypedValue::Secret(PASSWORD_LEN, None), false)
+            .with_label("Setup access point password")
+            .with_help("Generated when the access point first starts if not set. The setup access point page shows it.")
+            .with_min_len(8))?;
+        core_config.insert(AP_CHANNEL.to_string(), ConfigValue::new(TypedValue::Int32(None), false)
+            .with_label("Setup access point channel")
+            .with_placeholder("1")
+            .with_range(Some(1.0), Some(13.0)))?;
+        core_config.insert(AP_OPEN.to_string(), ConfigValue::new(TypedValue::Bool(false), false)
+            .with_label("Open setup access point")
+            .with_help("Run the setup access point without a password."))?;
+        core_config.insert(STATIC_IP.to_string(), ConfigValue::new(TypedValue::Ipv4(None), false)
+            .with_label("Static IP address")
+            .with_help("Leave empty to get an address by DHCP."))?;
+        core_config.insert(NETMASK.to_string(), ConfigValue::new(TypedValue::String(IPV4_LEN, None), false)
+            .with_label("Netmask")
+            .with_placeholder("255.255.255.0 or /24"))?;
+        core_config.insert(GATEWAY.to_string(), ConfigValue::new(TypedValue::Ipv4(None), false)
+            .with_label("Gateway"))?;
+        core_config.insert(DNS.to_string(), ConfigValue::new(TypedValue::Ipv4(None), false)
+            .with_label("DNS server")
+            .with_help("The gateway is used if not set."))?;
+        core_config.insert(SECONDARY_DNS.to_string(), ConfigValue::new(TypedValue::Ipv4(None), false)
+            .with_label("Secondary DNS server"))?;
+        core_config.insert(WIFI_AUTH.to_string(), ConfigValue::new(TypedValue::String(AUTH_MODE_LEN, None), false)
+            .with_label("Wi-Fi security")
+            .with_help("auto, open, wpa2, wpa3, wpa2_wpa3 or wpa2_enterprise. Auto uses what the network advertises.")
+            .with_placeholder("auto"))?;
+        core_config.insert(EAP_IDENTITY.to_string(), ConfigValue::new(TypedValue::String(EAP_NAME_LEN, None), false)
+            .with_label("Enterprise identity"))?;
+        core_config.insert(EAP_USERNAME.to_string(), ConfigValue::new(TypedValue::String(EAP_NAME_LEN, None), false)
+            .with_label("Enterprise username")
+            .with_help("The identity is used if not set. The password is the Wi-Fi password."))?;
+        core_config.insert(EAP_CA_CERT.to_string(), ConfigValue::new(TypedValue::String(PEM_LEN, None), false)
+            .with_label("Enterprise CA certificate")
+            .with_help("PEM. The authentication server is not checked if not set."))?;
+        core_config.insert(EAP_CLIENT_CERT.to_string(), ConfigValue::new(TypedValue::String(PEM_LEN, None), false)
+            .with_label("Enterprise client certificate")
+            .with_help("PEM, for EAP-TLS."))?;
+        core_config.insert(EAP_CLIENT_KEY.to_string(), ConfigValue::new(TypedValue::Secret(PEM_LEN, None), false)
+            .with_label("Enterprise client key")
+            .with_help("PEM, for EAP-TLS."))?;
 
         // let core_namespace = EspNvs::new(nvs_partition, "core", true)?;
+        let mut migrations = Vec::new();
+        let core_store = open_store(CORE_FEATURE_NAME)?;
+        migrations.push(migration::migrate(CORE_FEATURE_NAME, None, CORE_SCHEMA_VERSION, core_store.as_ref()));
         let core_feature_config = FeatureConfig::new(
             CORE_FEATURE_NAME.to_string(),
             EnabledState::Required,
             core_config,
-            nvs_partition.clone())?;
+            core_store)?;
         
         features.insert(CORE_FEATURE_NAME.to_string(), Mutex::new(core_feature_config));
         
         for feature in p_features {
             let descriptor = feature.create_descriptor()?;
-            for reserved_name in RESERVED_FEATURE_NAMES.iter() {
-                if descriptor.name == *reserved_name {
-                    return Err(anyhow::anyhow!("Feature name '{}' is reserved and cannot be used", descriptor.name));
-                }
-            }
-            let feature_config = FeatureConfig::from_descriptor(descriptor, nvs_partition.clone(), &feature_namespace)?;
+            check_feature_name(&descriptor.name)?;
+            let store = open_store(&descriptor.name)?;
+            migrations.push(migration::migrate(&descriptor.name, Some(feature.as_ref()), descriptor.version, store.as_ref()));
+            let feature_config = FeatureConfig::from_descriptor(descriptor, store, feature_namespace.as_ref())?;
             features.insert(feature_config.name.clone(), Mutex::new(feature_config));
         }
         
-        
+        let networks = NetworkList::new(open_store(NETWORKS_NAMESPACE_NAME)?)?;
 
         Ok(Arc::new(ConfigManager {
             // config_impl: Mutex::new(ConfigManagerImpl::new(nvs_partition, features)?),
@@ -487,9 +1178,14 @@ impl ConfigManager {
             feature_namespace,
             failure_reason,
             ap_mode,
+            setup_ap,
+            networks: Mutex::new(networks),
+            scan_results,
+            migrations,
         }))
     }
 
+    #[cfg(target_os = "espidf")]
     pub fn set_system_timezone(&self) -> anyhow::Result<()> {
         let locked_config = self.features.get(CORE_FEATURE_NAME).unwrap().lock().unwrap();
         let opt_config = locked_config.config.map.get(TIMEZONE);
@@ -516,6 +1212,57 @@ impl ConfigManager {
         }
     }
 
+    /// The static address for the station, or None to use DHCP.
+    pub fn static_ip_config(&self) -> anyhow::Result<Option<StaticIpConfig>> {
+        Ok(parse_static_ip(
+            &self.get_valid_core_config(STATIC_IP)?,
+            &self.get_valid_core_config(NETMASK)?,
+            &self.get_valid_core_config(GATEWAY)?,
+            &self.get_valid_core_config(DNS)?,
+            &self.get_valid_core_config(SECONDARY_DNS)?,
+        )?)
+    }
+
+    /// The 802.1X credentials for an enterprise network. The certificates come from the core config and are shared
+    /// by all enterprise networks.
+    pub fn eap_credentials(&self, network: &SavedNetwork) -> anyhow::Result<EapCredentials> {
+        let mut credentials = EapCredentials::new(&network.identity, &network.username, &network.password);
+
+        let ca_cert = self.get_valid_core_config(EAP_CA_CERT).unwrap_or_default();
+        if !ca_cert.trim().is_empty() {
+            credentials = credentials.with_ca_cert(&ca_cert)?;
+        }
+
+        let client_cert = self.get_valid_core_config(EAP_CLIENT_CERT).unwrap_or_default();
+        let client_key = self.get_valid_core_config(EAP_CLIENT_KEY).unwrap_or_default();
+        if !client_cert.trim().is_empty() {
+            credentials = credentials.with_client_cert(&client_cert, &client_key)?;
+        }
+        Ok(credentials)
+    }
+
+    pub fn set_core_config(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        self.features.get(CORE_FEATURE_NAME).unwrap().lock().unwrap().set_value(key, value)
+    }
+
+    /// All the networks we know how to join, the saved networks followed by the one in the core config.
+    /// The core network has priority 0 unless it is also in the saved list.
+    pub fn known_networks(&self) -> Vec<SavedNetwork> {
+        let mut networks = self.networks.lock().unwrap().networks().to_vec();
+
+        let ssid = self.get_valid_core_config(SSID).unwrap_or_default();
+        let password = self.get_valid_core_config(WIFI_PASSWORD).unwrap_or_default();
+        let auth = AuthMode::from_str(&self.get_valid_core_config(WIFI_AUTH).unwrap_or_default()).unwrap_or_default();
+        let network = SavedNetwork::new(&ssid, &password, 0, auth).and_then(|network| network.with_eap(
+            &self.get_valid_core_config(EAP_IDENTITY).unwrap_or_default(),
+            &self.get_valid_core_config(EAP_USERNAME).unwrap_or_default()));
+        match network {
+            Ok(network) => networks.push(network),
+            Err(e) => log::warn!("Ignoring core network {}: {}", ssid, e),
+        }
+        networks
+    }
+
     pub fn is_valid(&self) -> bool {
         for (_feature_name, feature_config_mutex) in &self.features {
             let feature_config = feature_config_mutex.lock().unwrap();
@@ -541,7 +1288,15 @@ impl ConfigManager {
         false
     }
 
+    #[cfg(target_os = "espidf")]
     fn show_config_page(config_manager_clone: &Arc<ConfigManager>, req: esp_idf_svc::http::server::Request<&mut EspHttpConnection<'_>>) -> anyhow::Result<()> {
+        Self::show_rejected_config_page(config_manager_clone, req, None, &IndexMap::new())
+    }
+
+    /// Shows the config page with a rejected form's values in place of the saved ones, and its errors by the fields.
+    #[cfg(target_os = "espidf")]
+    fn show_rejected_config_page(config_manager_clone: &Arc<ConfigManager>, req: esp_idf_svc::http::server::Request<&mut EspHttpConnection<'_>>,
+        submitted: Option<&IndexMap<String, String>>, errors: &IndexMap<String, String>) -> anyhow::Result<()> {
 
 
             let mut resp = req.into_ok_response()?;
@@ -561,29 +1316,53 @@ impl ConfigManager {
                 info!("Failure reason present, showing error message on config page: {}", reason);
                 resp.write(format!(r#"
                     <div style="background: #ffdddd; border: 1px solid #ff5c5c; padding: 10px; margin-bottom: 18px; border-radius: 8px;">
-                        <strong>Error:</strong> {reason}
+                        <strong>Error:</strong> {}
                     </div>
-                "#).as_bytes())?;
+                "#, escape_html(reason)).as_bytes())?;
             }
             else {
                 info!("No failure reason, not showing error message on config page");
             }
+            if !errors.is_empty() {
+                resp.write(r#"
+                    <div style="background: #ffdddd; border: 1px solid #ff5c5c; padding: 10px; margin-bottom: 18px; border-radius: 8px;">
+                        <strong>Not saved:</strong> some settings need fixing, see below.
+                    </div>
+                "#.as_bytes())?;
+            }
+            resp.write(r#"
+                        <h1>ESP32 Setup</h1>"#.as_bytes())?;
+
+            {
+                let scan_results = config_manager_clone.scan_results.networks();
+                crate::networks::create_scan_list(&mut resp, &scan_results, SSID)?;
+                crate::networks::create_scan_datalist(&mut resp, &scan_results)?;
+            }
+
             resp.write(r#"
-                        <h1>ESP32 Setup</h1>
                         <form method="POST" action="/update_config">"#.as_bytes())?;
             for (_feature_name, feature_config_mutex) in &config_manager_clone.features {
                 let feature_config = feature_config_mutex.lock().unwrap();
-                feature_config.create_config_page(&mut resp)?;
+                feature_config.create_config_page(&mut resp, submitted, errors)?;
             }
 
             
-            resp.write(format!(r#"<button type="submit">Save</button>
-                        </form>
+            resp.write(r#"<button type="submit">Save</button>
+                        </form>"#.as_bytes())?;
+
+            config_manager_clone.networks.lock().unwrap().create_config_page(&mut resp)?;
+
+            resp.write(r#"
+                        <p><a href="/setup_ap">Setup access point password and join code</a></p>"#.as_bytes())?;
+
+            resp.write(format!(r#"
                         <form method="POST" action="/command">
                         <label for="command">Command</label>
                             <select name="command" id="command">
                                 <option value="restart">Restart</option>
                                 <option value="factory_reset">Factory Reset</option>
+                                <option value="setup_ap_start">Start Setup Access Point</option>
+                                <option value="setup_ap_stop">Stop Setup Access Point</option>
                             </select>
                             <button type="submit">Execute</button>
                         </form>
@@ -594,6 +1373,7 @@ impl ConfigManager {
             Ok(())
     }
 
+    #[cfg(target_os = "espidf")]
     pub fn create_pages(config_manager: &Arc<Self>, server_manager: &mut HttpServerManager<'_>) -> anyhow::Result<()> {
         let config_manager_clone = config_manager.clone();
 
@@ -656,6 +1436,19 @@ impl ConfigManager {
                         });
                     }
                 },
+                Some(command @ ("setup_ap_start" | "setup_ap_stop")) => {
+                    let start = command == "setup_ap_start";
+                    info!("Setup access point command received, start={}", start);
+                    *config_manager_clone.setup_ap.lock().unwrap() = start;
+
+                    let mut resp = req.into_ok_response()?;
+                    if start {
+                        resp.write(b"<!doctype html><html><head><meta http-equiv=\"refresh\" content=\"10;url=/config\" /><title>Setup access point</title></head><body><p>The setup access point will start within a few seconds and stop after 15 minutes.</p></body></html>")?;
+                    }
+                    else {
+                        resp.write(b"<!doctype html><html><head><meta http-equiv=\"refresh\" content=\"10;url=/config\" /><title>Setup access point</title></head><body><p>The setup access point will stop within a few seconds.</p></body></html>")?;
+                    }
+                },
                 Some(cmd) => {
                     log::warn!("Unknown command received: {}", cmd);
                         let mut resp = req.into_ok_response()?;
@@ -695,9 +1488,14 @@ impl ConfigManager {
                 .into_owned()
                 .collect::<IndexMap<String, String>>();
 
-            config_manager_clone.handle_config_form(&form)?;
+            let errors = config_manager_clone.handle_config_form(&form)?;
 
-            Self::show_config_page(&config_manager_clone, req)
+            if errors.is_empty() {
+                Self::show_config_page(&config_manager_clone, req)
+            }
+            else {
+                Self::show_rejected_config_page(&config_manager_clone, req, Some(&form), &errors)
+            }
 
             // let mut resp = req.into_ok_response()?;
             // resp.write(b"Saved!. Rebooting...(NOT)")?;
@@ -710,6 +1508,57 @@ impl ConfigManager {
             // Ok(())
         })?;
 
+        let config_manager_clone = config_manager.clone();
+
+        server_manager.fn_handler("/scan", Method::Get, move |req| {
+            info!("Received {:?} request for {}", req.method(), req.uri());
+
+            let json = serde_json::to_string(&config_manager_clone.scan_results.refresh(SCAN_TIMEOUT))?;
+            let mut resp = req.into_response(
+                200,
+                Some("OK"),
+                &[("Content-Type", "application/json")],
+            )?;
+            resp.write(json.as_bytes())?;
+            Ok(())
+        })?;
+
+        let config_manager_clone = config_manager.clone();
+
+        server_manager.fn_handler("/add_network", Method::Post, move |mut req| {
+            info!("Received {:?} request for {}", req.method(), req.uri());
+
+            let form = Self::read_form(&mut req)?;
+            let ssid = form.get("network_ssid").map(|s| s.as_str()).unwrap_or("").trim();
+            let password = form.get("network_password").map(|s| s.as_str()).unwrap_or("");
+            let priority = form.get("network_priority").map(|s| s.trim()).filter(|s| !s.is_empty()).unwrap_or("0").parse::<i32>()?;
+            let auth = form.get("network_auth").map(|s| s.as_str()).unwrap_or("");
+            let auth = AuthMode::from_str(auth).ok_or_else(|| anyhow!("Unknown auth mode {}", auth))?;
+            let identity = form.get("network_identity").map(|s| s.as_str()).unwrap_or("").trim();
+            let username = form.get("network_username").map(|s| s.as_str()).unwrap_or("").trim();
+            if auth == AuthMode::Wpa2Enterprise && identity.is_empty() {
+                anyhow::bail!("Enterprise networks need an identity");
+            }
+
+            let network = SavedNetwork::new(ssid, password, priority, auth)?.with_eap(identity, username)?;
+            config_manager_clone.networks.lock().unwrap().add(network)?;
+
+            Self::show_config_page(&config_manager_clone, req)
+        })?;
+
+        let config_manager_clone = config_manager.clone();
+
+        server_manager.fn_handler("/remove_network", Method::Post, move |mut req| {
+            info!("Received {:?} request for {}", req.method(), req.uri());
+
+            let form = Self::read_form(&mut req)?;
+            let index = form.get("index").map(|s| s.as_str()).unwrap_or("").parse::<usize>()?;
+
+            config_manager_clone.networks.lock().unwrap().remove(index)?;
+
+            Self::show_config_page(&config_manager_clone, req)
+        })?;
+
         let config_manager_clone = config_manager.clone();
         server_manager.fn_handler("/generate_204", Method::Get, move |req| {
 
@@ -782,39 +1631,576 @@ impl ConfigManager {
         info!("Erasing config");
         if let Some(core_feature_mutex) = self.features.get(CORE_FEATURE_NAME) {
             let core_feature = core_feature_mutex.lock().unwrap();
-            core_feature.nvs_namespace.erase_all()?;
+            core_feature.store.erase_all()?;
         }
+        self.networks.lock().unwrap().erase()?;
         Ok(())
     }
 
-    pub fn handle_config_form(&self, form: &IndexMap<String, String>) -> anyhow::Result<()> {
-        info!("Handling config form submission: {:?}", form);
+    #[cfg(target_os = "espidf")]
+    fn read_form(req: &mut esp_idf_svc::http::server::Request<&mut EspHttpConnection<'_>>) -> anyhow::Result<IndexMap<String, String>> {
+        let mut body = Vec::new();
+        let mut buf = [0u8; 256];
+
+        loop {
+            let read = req.read(&mut buf)?;
+            if read == 0 {
+                break;
+            }
+            body.extend_from_slice(&buf[..read]);
+        }
+
+        Ok(form_urlencoded::parse(&body)
+            .into_owned()
+            .collect::<IndexMap<String, String>>())
+    }
+
+    /// A copy of a submitted form which is safe to log, with the secret values replaced.
+    fn redacted(&self, form: &IndexMap<String, String>) -> IndexMap<String, String> {
+        let mut redacted = form.clone();
+        for (_feature_name, feature_config) in &self.features {
+            for (name, config_value) in &feature_config.lock().unwrap().config.map {
+                if config_value.is_secret() {
+                    if let Some(value) = redacted.get_mut(name) {
+                        *value = REDACTED.to_string();
+                    }
+                }
+            }
+        }
+        redacted
+    }
+
+    /// All the features' config as typed JSON, keyed by feature name. See `FeatureConfig::to_json`.
+    pub fn config_json(&self) -> serde_json::Value {
+        self.features.iter()
+            .map(|(name, feature_config)| (name.clone(), feature_config.lock().unwrap().to_json()))
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+
+    pub fn feature_json(&self, feature_name: &str) -> Option<serde_json::Value> {
+        self.features.get(feature_name).map(|feature_config| feature_config.lock().unwrap().to_json())
+    }
+
+    /// Describes every feature and config value, for scripts which set the config through the JSON API.
+    pub fn schema_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "features": self.features.values().map(|feature_config| feature_config.lock().unwrap().schema_json()).collect::<Vec<_>>(),
+        })
+    }
+
+    /// Applies a JSON update to one feature, see `FeatureConfig::json_to_form`. Returns the problems keyed by value
+    /// name, in which case nothing is saved.
+    pub fn update_feature_json(&self, feature_name: &str, update: &serde_json::Value, replace: bool) -> anyhow::Result<IndexMap<String, String>> {
+        let Some(feature_config) = self.features.get(feature_name) else {
+            anyhow::bail!("No feature {}", feature_name);
+        };
+        let form = match feature_config.lock().unwrap().json_to_form(update, replace) {
+            Ok(form) => form,
+            Err(errors) => return Ok(errors),
+        };
+        info!("Handling config update for feature {}: {:?}", feature_name, self.redacted(&form));
+
+        let mut errors = IndexMap::new();
+        feature_config.lock().unwrap().validate_form(&form, &mut errors);
+        if feature_name == CORE_FEATURE_NAME {
+            self.validate_core_form(&form, &mut errors);
+        }
+        if !errors.is_empty() {
+            log::warn!("Config update for feature {} rejected: {:?}", feature_name, errors);
+            return Ok(errors);
+        }
+
+        feature_config.lock().unwrap().handle_config_form(&form, self.feature_namespace.as_ref())?;
+        Ok(errors)
+    }
+
+    /// The saved secrets as (feature name, value name, secret), for the backup export.
+    pub fn secrets(&self) -> Vec<(String, String, SecretString)> {
+        let mut secrets = Vec::new();
+        for (feature_name, feature_config) in &self.features {
+            for (name, config_value) in &feature_config.lock().unwrap().config.map {
+                if let TypedValue::Secret(_len, Some(secret)) = &config_value.value {
+                    secrets.push((feature_name.clone(), name.clone(), secret.clone()));
+                }
+            }
+        }
+        secrets
+    }
+
+    /// Replaces the config of each feature in `updates` with its JSON, as `update_feature_json` does for a PUT, and
+    /// the saved networks with `networks` if given. Everything is checked before anything is saved, and if saving
+    /// fails part way the features already saved are put back, so a bad import leaves the config as it was. Returns
+    /// the problems keyed by `feature.value`, or `networks` for a list which can't be saved.
+    pub fn import_features(&self, updates: &IndexMap<String, serde_json::Value>, networks: Option<&[SavedNetwork]>) -> anyhow::Result<IndexMap<String, String>> {
+        let mut errors = IndexMap::new();
+        let mut forms = IndexMap::new();
+        for (feature_name, update) in updates {
+            let Some(feature_config) = self.features.get(feature_name) else {
+                errors.insert(feature_name.clone(), format!("No feature {}", feature_name));
+                continue;
+            };
+
+            let form = match feature_config.lock().unwrap().json_to_form(update, true) {
+                Ok(form) => form,
+                Err(feature_errors) => {
+                    errors.extend(feature_errors.into_iter().map(|(name, e)| (format!("{}.{}", feature_name, name), e)));
+                    continue;
+                },
+            };
+
+            let mut feature_errors = IndexMap::new();
+            feature_config.lock().unwrap().validate_form(&form, &mut feature_errors);
+            if feature_name == CORE_FEATURE_NAME {
+                self.validate_core_form(&form, &mut feature_errors);
+            }
+            errors.extend(feature_errors.into_iter().map(|(name, e)| (format!("{}.{}", feature_name, name), e)));
+            forms.insert(feature_name.clone(), form);
+        }
+        if let Some(Err(e)) = networks.map(NetworkList::check) {
+            errors.insert("networks".to_string(), e.to_string());
+        }
+        if !errors.is_empty() {
+            log::warn!("Config import rejected: {:?}", errors);
+            return Ok(errors);
+        }
+
+        // Copy everything before saving anything, so a failure part way through can put it all back.
+        let mut snapshots = Vec::new();
+        for feature_name in forms.keys() {
+            snapshots.push(self.features.get(feature_name).unwrap().lock().unwrap().snapshot(self.feature_namespace.as_ref())?);
+        }
+
+        for (i, (feature_name, form)) in forms.iter().enumerate() {
+            info!("Importing config for feature {}: {:?}", feature_name, self.redacted(form));
+            let mut feature_config = self.features.get(feature_name).unwrap().lock().unwrap();
+            if let Err(e) = feature_config.handle_config_form(form, self.feature_namespace.as_ref()) {
+                drop(feature_config);
+                log::error!("Config import failed saving {}, putting back the previous config: {}", feature_name, e);
+                // Put back this feature too, it may have been part saved.
+                self.restore_features(forms.keys().zip(&snapshots).take(i + 1));
+                return Err(e);
+            }
+        }
+
+        if let Some(networks) = networks {
+            info!("Importing {} saved networks", networks.len());
+            // The list is saved in one go, so it is either all saved or not at all.
+            if let Err(e) = self.networks.lock().unwrap().replace(networks.to_vec()) {
+                log::error!("Config import failed saving the networks, putting back the previous config: {}", e);
+                self.restore_features(forms.keys().zip(&snapshots));
+                return Err(e);
+            }
+        }
+        Ok(errors)
+    }
+
+    /// Puts back the features an import saved, last first.
+    fn restore_features<'a>(&self, snapshots: impl DoubleEndedIterator<Item = (&'a String, &'a Snapshot)>) {
+        for (feature_name, snapshot) in snapshots.rev() {
+            if let Err(restore_error) = self.features.get(feature_name).unwrap().lock().unwrap().restore(snapshot, self.feature_namespace.as_ref()) {
+                log::error!("Failed to put back the config for {}: {}", feature_name, restore_error);
+            }
+        }
+    }
+
+    /// Checks the config form and saves it if it is all valid. Returns an error message for each bad field, keyed by
+    /// field name, in which case nothing is saved.
+    pub fn handle_config_form(&self, form: &IndexMap<String, String>) -> anyhow::Result<IndexMap<String, String>> {
+        info!("Handling config form submission: {:?}", self.redacted(form));
+
+        let mut errors = IndexMap::new();
+        for (_feature_name, feature_config) in &self.features {
+            feature_config.lock().unwrap().validate_form(form, &mut errors);
+        }
+
+        self.validate_core_form(form, &mut errors);
+
+        if !errors.is_empty() {
+            log::warn!("Config form rejected: {:?}", errors);
+            return Ok(errors);
+        }
 
         // Self::handle_config_form_feature(&mut self.nvs, form, None, &mut self.system_config.core_config)?;
 
         for (_feature_name, feature_config) in &self.features {
-            feature_config.lock().unwrap().handle_config_form(form, &self.feature_namespace)?;
+            feature_config.lock().unwrap().handle_config_form(form, self.feature_namespace.as_ref())?;
         }
 
         // info!("Finished handling config form submission current config: {:?}", self.system_config);
-        Ok(())
+        Ok(errors)
+    }
+
+    /// The checks on the core config which involve more than one field, or more than the value's type.
+    fn validate_core_form(&self, form: &IndexMap<String, String>, errors: &mut IndexMap<String, String>) {
+        // The address fields only make sense together, so check them before saving any of them.
+        let field = |key: &str| form.get(key).map(|s| s.as_str()).unwrap_or("");
+        if let Err(e) = parse_static_ip(field(STATIC_IP), field(NETMASK), field(GATEWAY), field(DNS), field(SECONDARY_DNS)) {
+            let key = match &e {
+                IpConfigError::InvalidAddress { field: "Gateway", .. } | IpConfigError::Missing("Gateway") => GATEWAY,
+                IpConfigError::InvalidAddress { field: "DNS server", .. } => DNS,
+                IpConfigError::InvalidAddress { field: "Secondary DNS server", .. } => SECONDARY_DNS,
+                IpConfigError::InvalidNetmask(_) | IpConfigError::Missing(_) => NETMASK,
+                IpConfigError::GatewayNotInSubnet(_) | IpConfigError::GatewayIsAddress => GATEWAY,
+                IpConfigError::InvalidAddress { .. } | IpConfigError::NotAHostAddress(_) => STATIC_IP,
+            };
+            errors.entry(key.to_string()).or_insert(e.to_string());
+        }
+
+        let auth = field(WIFI_AUTH).trim();
+        if AuthMode::from_str(auth).is_none() {
+            let modes: Vec<&str> = AuthMode::iter().map(|mode| mode.to_str()).collect();
+            errors.entry(WIFI_AUTH.to_string()).or_insert(format!("Unknown auth mode \"{}\", use one of {}", auth, modes.join(", ")));
+        }
+        for key in [EAP_CA_CERT, EAP_CLIENT_CERT, EAP_CLIENT_KEY] {
+            let pem = field(key);
+            if !pem.trim().is_empty() && normalize_pem(pem).is_none() {
+                errors.entry(key.to_string()).or_insert("Not a PEM certificate or key".to_string());
+            }
+        }
+        // The key is a secret, so a blank field keeps the saved one unless it is being cleared.
+        let client_cert_set = !field(EAP_CLIENT_CERT).trim().is_empty();
+        let client_key_set = !field(EAP_CLIENT_KEY).trim().is_empty()
+            || (!FeatureConfig::clear_requested(form, EAP_CLIENT_KEY) && !self.get_valid_core_config(EAP_CLIENT_KEY).unwrap_or_default().is_empty());
+        if client_cert_set != client_key_set {
+            let missing = if client_cert_set { EAP_CLIENT_KEY } else { EAP_CLIENT_CERT };
+            errors.entry(missing.to_string()).or_insert("EAP-TLS needs both the client certificate and the client key".to_string());
+        }
     }
 }
 
 
 
 pub const CORE_FEATURE_NAME: &str = "core";
+/// The core config's schema version, see `FeatureDescriptor::version`.
+const CORE_SCHEMA_VERSION: u32 = 1;
 const FEATURE_NAMESPACE_NAME: &str = "feature";
-const RESERVED_FEATURE_NAMES: [&str; 6] = [
+/// The saved networks list is kept in its own namespace.
+const NETWORKS_NAMESPACE_NAME: &str = "networks";
+const RESERVED_FEATURE_NAMES: [&str; 7] = [
     CORE_FEATURE_NAME,
     FEATURE_NAMESPACE_NAME,
+    NETWORKS_NAMESPACE_NAME,
     "wifi",
     "phy",
     "bt_config",
     "nvs.net80211",
 ];
 
+/// Checks a feature name can be used as its NVS namespace.
+fn check_feature_name(name: &str) -> anyhow::Result<()> {
+    if RESERVED_FEATURE_NAMES.contains(&name) {
+        anyhow::bail!("Feature name '{}' is reserved and cannot be used", name);
+    }
+    storage::check_key(name)
+}
+
 pub const SSID: &str = "ssid";
 pub const WIFI_PASSWORD: &str = "wifi_password";
 pub const MDNS_HOSTNAME: &str = "mdns_hostname";
 pub const TIMEZONE: &str = "time_zone";
+/// Failed reconnects before going back to setup mode, 0 to keep trying for ever.
+pub const AP_FALLBACK_AFTER: &str = "ap_fallback";
+/// The setup access point SSID, Sparko-XXXX from the MAC address if not set.
+pub const AP_SSID: &str = "ap_ssid";
+/// The setup access point password, generated on first use if not set.
+pub const AP_PASSWORD: &str = "ap_password";
+pub const AP_CHANNEL: &str = "ap_channel";
+/// Run the setup access point as an open network.
+pub const AP_OPEN: &str = "ap_open";
+/// A static address for the station, DHCP is used if this is not set.
+pub const STATIC_IP: &str = "static_ip";
+/// The static address netmask, as 255.255.255.0 or /24.
+pub const NETMASK: &str = "netmask";
+pub const GATEWAY: &str = "gateway";
+/// The static address DNS server, the gateway if not set.
+pub const DNS: &str = "dns";
+pub const SECONDARY_DNS: &str = "dns_secondary";
+
+/// How to authenticate with the core network, one of the `AuthMode` names. Auto-detected from the scan if not set.
+pub const WIFI_AUTH: &str = "wifi_auth";
+/// The 802.1X outer identity for an enterprise core network.
+pub const EAP_IDENTITY: &str = "eap_identity";
+/// The 802.1X username, the identity if not set. The password is the core Wi-Fi password.
+pub const EAP_USERNAME: &str = "eap_username";
+/// PEM CA certificate to check the authentication server with. The server is not checked if this is not set.
+pub const EAP_CA_CERT: &str = "eap_ca_cert";
+/// PEM client certificate and key for EAP-TLS.
+pub const EAP_CLIENT_CERT: &str = "eap_client_cert";
+pub const EAP_CLIENT_KEY: &str = "eap_client_key";
+
+/// Added to a secret's field name for the box which clears it, since a blank secret field keeps the saved value.
+const CLEAR_SUFFIX: &str = "_clear";
+
+/// Long enough for a dotted netmask.
+const IPV4_LEN: usize = 15;
+const AUTH_MODE_LEN: usize = 16;
+/// NVS strings are limited to 4000 bytes, which is enough for a certificate or two.
+const PEM_LEN: usize = 3999;
+
+/// How long `/scan` waits for the main loop to scan before answering with the last networks seen.
+#[cfg(target_os = "espidf")]
+const SCAN_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{PASSWORD_LEN, SSID_LEN, storage::MemoryStore};
+
+    fn form(pairs: &[(&str, &str)]) -> IndexMap<String, String> {
+        pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    fn config() -> Config {
+        let mut config = Config::new();
+        config.insert("name".to_string(), ConfigValue::new(TypedValue::String(16, None), true)).unwrap();
+        config.insert("count".to_string(), ConfigValue::new(TypedValue::Int32(None), false).with_range(Some(0.0), Some(10.0))).unwrap();
+        config.insert("flag".to_string(), ConfigValue::new(TypedValue::Bool(false), false)).unwrap();
+        config.insert("interval".to_string(), ConfigValue::new(TypedValue::Duration(None), false).with_default(TypedValue::Duration(Some(Duration::from_secs(3600))))).unwrap();
+        config.insert("password".to_string(), ConfigValue::new(TypedValue::Secret(16, None), false)).unwrap();
+        config
+    }
+
+    fn feature(enabled: EnabledState) -> FeatureConfig {
+        FeatureConfig::new("test".to_string(), enabled, config(), Box::new(MemoryStore::new())).unwrap()
+    }
+
+    #[test]
+    fn round_trips_every_type() {
+        let store = MemoryStore::new();
+        let values = [
+            TypedValue::String(8, Some("abc".to_string())),
+            TypedValue::Int32(Some(-5)),
+            TypedValue::Int64(Some(1 << 40)),
+            TypedValue::Bool(true),
+            TypedValue::TimeZone(*TimeZone::iter().nth(1).unwrap()),
+            TypedValue::Float(Some(1.25)),
+            TypedValue::Enum(&["low", "high"], Some("high")),
+            TypedValue::Ipv4(Some("192.168.1.2".parse().unwrap())),
+            TypedValue::Ipv6(Some("fd00::1".parse().unwrap())),
+            TypedValue::Url(64, Some("https://example.com/x".to_string())),
+            TypedValue::Duration(Some(Duration::from_secs(5400))),
+            TypedValue::Secret(16, Some(SecretString::new("hunter22"))),
+        ];
+        for (i, value) in values.iter().enumerate() {
+            let key = format!("k{}", i);
+            value.write_to_store(&store, &key).unwrap();
+            assert_eq!(&value.to_none().read_from_store(&store, &key), value, "{}", key);
+        }
+    }
+
+    #[test]
+    fn round_trips_the_longest_strings() {
+        let store = MemoryStore::new();
+        let values = [
+            TypedValue::String(SSID_LEN, Some("s".repeat(SSID_LEN))),
+            TypedValue::String(PEM_LEN, Some("p".repeat(PEM_LEN))),
+            TypedValue::Url(32, Some(format!("https://{}", "u".repeat(24)))),
+            TypedValue::Secret(PASSWORD_LEN, Some(SecretString::new(&"x".repeat(PASSWORD_LEN)))),
+        ];
+        for (i, value) in values.iter().enumerate() {
+            let key = format!("k{}", i);
+            assert_eq!(value.parse(&value.to_string()).as_ref().ok(), Some(value), "{}", key);
+            value.write_to_store(&store, &key).unwrap();
+            assert_eq!(&value.to_none().read_from_store(&store, &key), value, "{}", key);
+        }
+    }
+
+    #[test]
+    fn parses_and_shows_each_kind() {
+        let cases = [
+            (TypedValue::Float(None), "1.25", "1.25"),
+            (TypedValue::Enum(&["Low", "High"], None), "high", "High"),
+            (TypedValue::Ipv4(None), " 192.168.1.2 ", "192.168.1.2"),
+            (TypedValue::Ipv6(None), "FD00:0::1", "fd00::1"),
+            (TypedValue::Url(64, None), " https://example.com/x ", "https://example.com/x"),
+            (TypedValue::Duration(None), "90m", "1h30m"),
+            (TypedValue::Duration(None), "3600", "1h"),
+        ];
+        for (kind, text, shown) in cases {
+            let value = kind.parse(text).unwrap();
+            assert_eq!(value.to_string(), shown, "{}", text);
+            assert_eq!(kind.parse(&value.to_string()).unwrap(), value, "{}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_text_for_each_kind() {
+        let cases = [
+            (TypedValue::Float(None), "NaN"),
+            (TypedValue::Enum(&["Low", "High"], None), "Medium"),
+            (TypedValue::Ipv4(None), "192.168.1.256"),
+            (TypedValue::Ipv4(None), "fd00::1"),
+            (TypedValue::Ipv6(None), "192.168.1.2"),
+            (TypedValue::Url(64, None), "example.com"),
+            (TypedValue::Url(16, None), "https://example.com/long"),
+            (TypedValue::Duration(None), "soon"),
+        ];
+        for (kind, text) in cases {
+            assert!(kind.parse(text).is_err(), "{:?} {}", kind, text);
+        }
+    }
+
+    #[test]
+    fn unset_values_read_as_none() {
+        let store = MemoryStore::new();
+        assert_eq!(TypedValue::Int32(Some(3)).read_from_store(&store, "missing"), TypedValue::Int32(None));
+        assert_eq!(TypedValue::Bool(true).read_from_store(&store, "missing"), TypedValue::Bool(false));
+    }
+
+    #[test]
+    fn saves_a_form() {
+        let mut feature = feature(EnabledState::Enabled);
+        let feature_namespace = MemoryStore::new();
+        let submitted = form(&[("feature_test", "on"), ("name", "kitchen"), ("count", "3"), ("flag", "on"), ("interval", "15m"), ("password", "secret")]);
+        let mut errors = IndexMap::new();
+        feature.validate_form(&submitted, &mut errors);
+        assert!(errors.is_empty(), "{:?}", errors);
+        feature.handle_config_form(&submitted, &feature_namespace).unwrap();
+
+        assert_eq!(feature.store.get_str("name", 16).unwrap().as_deref(), Some("kitchen"));
+        assert_eq!(feature.store.get_i32("count").unwrap(), Some(3));
+        assert_eq!(feature.store.get_u8("flag").unwrap(), Some(1));
+        assert_eq!(feature.store.get_i64("interval").unwrap(), Some(900));
+        assert_eq!(feature.store.get_str("password", 17).unwrap().as_deref(), Some("secret"));
+        assert_eq!(feature_namespace.get_u8("test").unwrap(), Some(1));
+        assert!(feature.enabled.is_enabled());
+
+        // An unticked checkbox posts nothing, which is false.
+        feature.handle_config_form(&form(&[("name", "kitchen")]), &feature_namespace).unwrap();
+        assert_eq!(feature.store.get_u8("flag").unwrap(), Some(0));
+        assert_eq!(feature_namespace.get_u8("test").unwrap(), Some(0));
+        assert!(!feature.enabled.is_enabled());
+    }
+
+    #[test]
+    fn loads_saved_values_and_defaults() {
+        let store = MemoryStore::new();
+        store.set_str("name", "hall").unwrap();
+        store.set_i32("count", 7).unwrap();
+        let feature = FeatureConfig::new("test".to_string(), EnabledState::Enabled, config(), Box::new(store)).unwrap();
+        assert_eq!(feature.config.map["name"].value, TypedValue::String(16, Some("hall".to_string())));
+        assert_eq!(feature.config.map["count"].value, TypedValue::Int32(Some(7)));
+        assert_eq!(feature.config.map["interval"].value, TypedValue::Duration(Some(Duration::from_secs(3600))));
+        assert!(feature.config.map["password"].value.is_none());
+    }
+
+    #[test]
+    fn clears_optional_values() {
+        let mut feature = feature(EnabledState::Required);
+        let feature_namespace = MemoryStore::new();
+        feature.handle_config_form(&form(&[("name", "a"), ("count", "3"), ("interval", "60")]), &feature_namespace).unwrap();
+        assert_eq!(feature.store.get_i32("count").unwrap(), Some(3));
+
+        feature.handle_config_form(&form(&[("name", "a"), ("count", ""), ("interval", "")]), &feature_namespace).unwrap();
+        assert!(feature.config.map["count"].value.is_none());
+        assert!(!feature.store.contains("count").unwrap());
+        // A cleared value with a default goes back to it.
+        assert_eq!(feature.config.map["interval"].value, TypedValue::Duration(Some(Duration::from_secs(3600))));
+        assert!(!feature.store.contains("interval").unwrap());
+        // Required features never write their enabled flag.
+        assert!(!feature_namespace.contains("test").unwrap());
+    }
+
+    #[test]
+    fn blank_secrets_are_kept_unless_cleared() {
+        let mut feature = feature(EnabledState::Required);
+        let feature_namespace = MemoryStore::new();
+        feature.handle_config_form(&form(&[("name", "a"), ("password", "secret")]), &feature_namespace).unwrap();
+        feature.handle_config_form(&form(&[("name", "a"), ("password", "")]), &feature_namespace).unwrap();
+        assert!(feature.store.contains("password").unwrap());
+        feature.handle_config_form(&form(&[("name", "a"), ("password", ""), ("password_clear", "on")]), &feature_namespace).unwrap();
+        assert!(!feature.store.contains("password").unwrap());
+        assert!(feature.config.map["password"].value.is_none());
+    }
+
+    #[test]
+    fn required_values_only_when_enabled() {
+        let feature = feature(EnabledState::Disabled);
+        let mut errors = IndexMap::new();
+        feature.validate_form(&form(&[]), &mut errors);
+        assert!(errors.is_empty(), "{:?}", errors);
+
+        feature.validate_form(&form(&[("feature_test", "on")]), &mut errors);
+        assert_eq!(errors.keys().collect::<Vec<_>>(), vec!["name"]);
+
+        let mut errors = IndexMap::new();
+        feature.validate_form(&form(&[("feature_test", "on"), ("name", "a"), ("count", "11"), ("interval", "soon")]), &mut errors);
+        assert_eq!(errors.keys().collect::<Vec<_>>(), vec!["count", "interval"]);
+        assert_eq!(errors["count"], "Must be at most 10");
+    }
+
+    #[test]
+    fn rejects_reserved_and_long_feature_names() {
+        for name in RESERVED_FEATURE_NAMES {
+            assert!(check_feature_name(name).is_err(), "{}", name);
+        }
+        assert!(check_feature_name("a_very_long_feature").is_err());
+        assert!(check_feature_name("").is_err());
+        assert!(check_feature_name("dyndns2").is_ok());
+    }
+
+    #[test]
+    fn json_updates() {
+        let mut feature = feature(EnabledState::Enabled);
+        let feature_namespace = MemoryStore::new();
+        feature.handle_config_form(&form(&[("feature_test", "on"), ("name", "a"), ("count", "3"), ("password", "secret")]), &feature_namespace).unwrap();
+
+        let patch = feature.json_to_form(&serde_json::json!({"values": {"flag": true}}), false).unwrap();
+        assert_eq!(patch["count"], "3");
+        assert_eq!(patch["flag"], "true");
+        assert_eq!(patch["feature_test"], "on");
+        assert!(!patch.contains_key("password"));
+
+        let put = feature.json_to_form(&serde_json::json!({"enabled": false, "values": {"name": "b", "password": null}}), true).unwrap();
+        assert_eq!(put["count"], "");
+        assert_eq!(put["password_clear"], "on");
+        assert!(!put.contains_key("feature_test"));
+
+        let errors = feature.json_to_form(&serde_json::json!({"values": {"count": "3", "nope": 1}, "extra": 1}), false).unwrap_err();
+        assert_eq!(errors.keys().collect::<Vec<_>>(), vec!["extra", "nope", "count"]);
+
+        let json = feature.to_json();
+        assert_eq!(json["values"]["count"], 3);
+        assert_eq!(json["values"]["interval"], 3600);
+        assert!(json["values"].get("password").is_none());
+        assert_eq!(json["secrets_set"], serde_json::json!(["password"]));
+    }
+
+    #[test]
+    fn restores_a_snapshot() {
+        let mut feature = feature(EnabledState::Enabled);
+        let feature_namespace = MemoryStore::new();
+        feature.handle_config_form(&form(&[("feature_test", "on"), ("name", "a"), ("count", "3")]), &feature_namespace).unwrap();
+        feature.store.remove("interval").unwrap();
+        let snapshot = feature.snapshot(&feature_namespace).unwrap();
+        feature.handle_config_form(&form(&[("name", "b"), ("password", "x"), ("interval", "2h")]), &feature_namespace).unwrap();
+        feature.restore(&snapshot, &feature_namespace).unwrap();
+        assert_eq!(feature.store.get_str("name", 16).unwrap().as_deref(), Some("a"));
+        assert_eq!(feature.store.get_i32("count").unwrap(), Some(3));
+        assert!(!feature.store.contains("password").unwrap());
+        // The default wasn't saved before, so it isn't now.
+        assert!(!feature.store.contains("interval").unwrap());
+        assert_eq!(feature.config.map["interval"].value, TypedValue::Duration(Some(Duration::from_secs(3600))));
+        assert_eq!(feature_namespace.get_u8("test").unwrap(), Some(1));
+        assert!(feature.enabled.is_enabled());
+    }
+
+    #[test]
+    fn restores_only_what_was_saved() {
+        let store = MemoryStore::new();
+        // Saved as the wrong type, so it can't be read.
+        store.set_str("count", "three").unwrap();
+        let mut feature = FeatureConfig::new("test".to_string(), EnabledState::Disabled, config(), Box::new(store)).unwrap();
+        let feature_namespace = MemoryStore::new();
+        let snapshot = feature.snapshot(&feature_namespace).unwrap();
+
+        feature.handle_config_form(&form(&[("feature_test", "on"), ("name", "a")]), &feature_namespace).unwrap();
+        feature.restore(&snapshot, &feature_namespace).unwrap();
+        assert!(!feature.store.contains("name").unwrap());
+        assert!(!feature.store.contains("flag").unwrap());
+        assert_eq!(feature.store.get_str("count", 16).unwrap().as_deref(), Some("three"));
+        assert!(!feature_namespace.contains("test").unwrap());
+        assert!(!feature.enabled.is_enabled());
+    }
+}
@@ -0,0 +1,509 @@
+use std::collections::VecDeque;
+#[cfg(target_os = "espidf")]
+use std::net::Ipv4Addr;
+#[cfg(target_os = "espidf")]
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::http::Method;
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::ping::{EspPing, Reply};
+#[cfg(target_os = "espidf")]
+use log::info;
+use serde::Serialize;
+
+#[cfg(target_os = "espidf")]
+use crate::http::HttpServerManager;
+#[cfg(target_os = "espidf")]
+use crate::values::escape_html;
+
+/// Link samples to keep, an hour at the default interval.
+pub const HISTORY_LEN: usize = 60;
+/// Disconnects to keep.
+const DISCONNECT_HISTORY_LEN: usize = 20;
+/// Pings sent to the gateway with each sample.
+#[cfg(target_os = "espidf")]
+const PING_COUNT: u32 = 3;
+
+/// The state of the station link at one point in time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LinkSample {
+    /// Seconds since boot.
+    pub uptime_secs: u64,
+    pub ssid: String,
+    pub bssid: String,
+    pub channel: u8,
+    pub rssi: i8,
+    /// None if the gateway couldn't be pinged at all, for example because we have no address.
+    pub ping: Option<PingResult>,
+}
+
+/// How pinging the gateway went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PingResult {
+    pub sent: u32,
+    pub received: u32,
+    /// The average round trip of the replies, None if there were none.
+    pub avg_ms: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Disconnect {
+    pub uptime_secs: u64,
+    /// The `wifi_err_reason_t` code from the driver.
+    pub reason: u16,
+    pub description: &'static str,
+}
+
+/// Totals over the samples in the history.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct LinkSummary {
+    pub samples: usize,
+    pub rssi_min: Option<i8>,
+    pub rssi_avg: Option<i8>,
+    pub rssi_max: Option<i8>,
+    pub pings_sent: u32,
+    pub pings_received: u32,
+    /// The average round trip over all replies.
+    pub ping_avg_ms: Option<u32>,
+}
+
+impl LinkSummary {
+    /// Lost pings as a percentage, None if none were sent.
+    pub fn ping_loss_percent(&self) -> Option<u32> {
+        if self.pings_sent == 0 {
+            return None;
+        }
+        Some((self.pings_sent - self.pings_received) * 100 / self.pings_sent)
+    }
+}
+
+/// A rolling history of the station link, for working out why a connection is flaky. The Wi-Fi event handler
+/// records disconnects, the main loop records link samples and reconnects.
+#[derive(Debug, Clone, Default)]
+pub struct WifiDiagnostics {
+    samples: VecDeque<LinkSample>,
+    disconnects: VecDeque<Disconnect>,
+    disconnect_count: u32,
+    reconnect_count: u32,
+}
+
+/// The JSON form of the diagnostics.
+#[derive(Serialize)]
+struct Report<'a> {
+    uptime_secs: u64,
+    disconnect_count: u32,
+    reconnect_count: u32,
+    summary: LinkSummary,
+    samples: &'a VecDeque<LinkSample>,
+    disconnects: &'a VecDeque<Disconnect>,
+}
+
+impl WifiDiagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn samples(&self) -> impl DoubleEndedIterator<Item = &LinkSample> {
+        self.samples.iter()
+    }
+
+    pub fn disconnects(&self) -> impl DoubleEndedIterator<Item = &Disconnect> {
+        self.disconnects.iter()
+    }
+
+    pub fn disconnect_count(&self) -> u32 {
+        self.disconnect_count
+    }
+
+    pub fn reconnect_count(&self) -> u32 {
+        self.reconnect_count
+    }
+
+    pub fn record_sample(&mut self, sample: LinkSample) {
+        if self.samples.len() == HISTORY_LEN {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    pub fn record_disconnect(&mut self, uptime_secs: u64, reason: u16) {
+        if self.disconnects.len() == DISCONNECT_HISTORY_LEN {
+            self.disconnects.pop_front();
+        }
+        self.disconnects.push_back(Disconnect {
+            uptime_secs,
+            reason,
+            description: reason_description(reason),
+        });
+        self.disconnect_count = self.disconnect_count.saturating_add(1);
+    }
+
+    /// Records that a lost connection came back.
+    pub fn record_reconnect(&mut self) {
+        self.reconnect_count = self.reconnect_count.saturating_add(1);
+    }
+
+    pub fn summary(&self) -> LinkSummary {
+        let rssis = || self.samples.iter().map(|sample| sample.rssi as i32);
+        let count = self.samples.len() as i32;
+        let pings = self.samples.iter().filter_map(|sample| sample.ping);
+
+        let (mut sent, mut received, mut total_ms) = (0u32, 0u32, 0u64);
+        for ping in pings {
+            sent += ping.sent;
+            received += ping.received;
+            if let Some(avg_ms) = ping.avg_ms {
+                total_ms += avg_ms as u64 * ping.received as u64;
+            }
+        }
+
+        LinkSummary {
+            samples: self.samples.len(),
+            rssi_min: rssis().min().map(|rssi| rssi as i8),
+            rssi_avg: if count == 0 { None } else { Some((rssis().sum::<i32>() / count) as i8) },
+            rssi_max: rssis().max().map(|rssi| rssi as i8),
+            pings_sent: sent,
+            pings_received: received,
+            ping_avg_ms: if received == 0 { None } else { Some((total_ms / received as u64) as u32) },
+        }
+    }
+
+    pub fn to_json(&self, uptime_secs: u64) -> anyhow::Result<String> {
+        Ok(serde_json::to_string(&Report {
+            uptime_secs,
+            disconnect_count: self.disconnect_count,
+            reconnect_count: self.reconnect_count,
+            summary: self.summary(),
+            samples: &self.samples,
+            disconnects: &self.disconnects,
+        })?)
+    }
+
+    #[cfg(target_os = "espidf")]
+    pub fn create_pages(diagnostics: &Arc<Mutex<WifiDiagnostics>>, server_manager: &mut HttpServerManager) -> anyhow::Result<()> {
+        let diagnostics_clone = diagnostics.clone();
+
+        server_manager.fn_handler("/status/wifi", Method::Get, move |req| {
+            info!("Received {:?} request for {}", req.method(), req.uri());
+
+            // Copy the history out rather than hold the lock while the page goes to a possibly slow client, the
+            // Wi-Fi event handler takes it to record disconnects.
+            let diagnostics = diagnostics_clone.lock().unwrap().clone();
+            let summary = diagnostics.summary();
+            let mut resp = req.into_ok_response()?;
+            resp.write(r#"
+                <!DOCTYPE html>
+                <html lang="en">
+                <head>
+                    <meta charset="utf-8" />
+                    <meta name="viewport" content="width=device-width, initial-scale=1" />
+                    <title>WiFi Status</title>
+                    <link rel="stylesheet" href="/main.css">
+                </head>
+                <body>
+                    <div class="page">
+                        <h1>WiFi Status</h1>"#.as_bytes())?;
+
+            let dbm = |rssi: Option<i8>| rssi.map(|rssi| format!("{} dBm", rssi)).unwrap_or_else(|| "-".to_string());
+            let ms = |ms: Option<u32>| ms.map(|ms| format!("{} ms", ms)).unwrap_or_else(|| "-".to_string());
+            let loss = summary.ping_loss_percent().map(|loss| format!("{}%", loss)).unwrap_or_else(|| "-".to_string());
+            resp.write(format!(r#"
+                        <table class="status">
+                            <tr><th>Up for</th><td>{uptime}</td></tr>
+                            <tr><th>Disconnects</th><td>{disconnects}</td></tr>
+                            <tr><th>Reconnects</th><td>{reconnects}</td></tr>
+                            <tr><th>Signal</th><td>{min} / {avg} / {max} (min / avg / max)</td></tr>
+                            <tr><th>Gateway ping</th><td>{ping_avg}, {loss} lost</td></tr>
+                        </table>
+                        <p><a href="/status/wifi.json">JSON</a> · <a href="/status/config">Config status</a></p>"#,
+                uptime = format_duration(Duration::from_secs(uptime_secs())),
+                disconnects = diagnostics.disconnect_count(),
+                reconnects = diagnostics.reconnect_count(),
+                min = dbm(summary.rssi_min),
+                avg = dbm(summary.rssi_avg),
+                max = dbm(summary.rssi_max),
+                ping_avg = ms(summary.ping_avg_ms),
+            ).as_bytes())?;
+
+            resp.write(r#"
+                        <h2>Samples</h2>
+                        <table class="status">
+                            <tr><th>Time</th><th>Network</th><th>Ch</th><th>Signal</th><th>Ping</th></tr>"#.as_bytes())?;
+            // Newest first.
+            for sample in diagnostics.samples().rev() {
+                let ping = match sample.ping {
+                    Some(ping) => format!("{} {}/{}", ms(ping.avg_ms), ping.received, ping.sent),
+                    None => "-".to_string(),
+                };
+                resp.write(format!(r#"
+                            <tr><td>{}</td><td>{}<br><small>{}</small></td><td>{}</td><td>{} dBm</td><td>{}</td></tr>"#,
+                    format_duration(Duration::from_secs(sample.uptime_secs)), escape_html(&sample.ssid), sample.bssid, sample.channel, sample.rssi, ping).as_bytes())?;
+            }
+
+            resp.write(r#"
+                        </table>
+                        <h2>Disconnects</h2>
+                        <table class="status">
+                            <tr><th>Time</th><th>Reason</th></tr>"#.as_bytes())?;
+            for disconnect in diagnostics.disconnects().rev() {
+                resp.write(format!(r#"
+                            <tr><td>{}</td><td>{} ({})</td></tr>"#,
+                    format_duration(Duration::from_secs(disconnect.uptime_secs)), disconnect.description, disconnect.reason).as_bytes())?;
+            }
+
+            resp.write(r#"
+                        </table>
+                    </div>
+                </body>
+                </html>"#.as_bytes())?;
+            Ok(())
+        })?;
+
+        let diagnostics_clone = diagnostics.clone();
+
+        server_manager.fn_handler("/status/wifi.json", Method::Get, move |req| {
+            info!("Received {:?} request for {}", req.method(), req.uri());
+
+            let json = diagnostics_clone.lock().unwrap().to_json(uptime_secs())?;
+            let mut resp = req.into_response(
+                200,
+                Some("OK"),
+                &[("Content-Type", "application/json")],
+            )?;
+            resp.write(json.as_bytes())?;
+            Ok(())
+        })?;
+
+        Ok(())
+    }
+}
+
+/// Seconds since boot.
+#[cfg(target_os = "espidf")]
+pub fn uptime_secs() -> u64 {
+    (unsafe { esp_idf_sys::esp_timer_get_time() } / 1_000_000) as u64
+}
+
+/// Formats a time since boot as 1d 02:03:04.
+pub fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let (days, secs) = (secs / 86_400, secs % 86_400);
+    let time = format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60);
+    if days > 0 {
+        format!("{}d {}", days, time)
+    }
+    else {
+        time
+    }
+}
+
+pub fn format_bssid(bssid: &[u8; 6]) -> String {
+    bssid.iter().map(|byte| format!("{:02x}", byte)).collect::<Vec<_>>().join(":")
+}
+
+/// Pings `ip` a few times and reports how many replies came back and how quickly.
+#[cfg(target_os = "espidf")]
+pub fn ping(ip: Ipv4Addr) -> anyhow::Result<PingResult> {
+    let configuration = esp_idf_svc::ping::Configuration {
+        count: PING_COUNT,
+        ..Default::default()
+    };
+
+    let mut total = Duration::ZERO;
+    let summary = EspPing::default().ping_details(ip, &configuration, |_summary, reply| {
+        if let Reply::Success(info) = reply {
+            total += info.elapsed_time;
+        }
+    })?;
+
+    Ok(PingResult {
+        sent: summary.transmitted,
+        received: summary.received,
+        avg_ms: if summary.received == 0 { None } else { Some((total / summary.received).as_millis() as u32) },
+    })
+}
+
+/// A short description of a `wifi_err_reason_t` disconnect reason. The codes are the `WIFI_REASON_*` values from
+/// esp_wifi_types.h, which follow the 802.11 reason codes up to 200.
+pub fn reason_description(reason: u16) -> &'static str {
+    match reason {
+        1 => "Unspecified",
+        2 => "Authentication expired",
+        3 => "Deauthenticated by the access point",
+        4 => "Disassociated for inactivity",
+        5 => "Access point has too many stations",
+        6 => "Not authenticated",
+        7 => "Not associated",
+        8 => "Left the network",
+        14 => "Message integrity check failure",
+        15 => "4-way handshake timeout",
+        16 => "Group key update timeout",
+        23 => "802.1X authentication failed",
+        200 => "Beacon timeout",
+        201 => "No access point found",
+        202 => "Authentication failed",
+        203 => "Association failed",
+        204 => "Handshake timeout",
+        205 => "Connection failed",
+        _ => "Other",
+    }
+}
+
+// The codes above are written out so the descriptions can be tested on the host, this checks them on the device.
+#[cfg(target_os = "espidf")]
+const _: () = {
+    use esp_idf_sys::*;
+    assert!(wifi_err_reason_t_WIFI_REASON_UNSPECIFIED == 1);
+    assert!(wifi_err_reason_t_WIFI_REASON_AUTH_EXPIRE == 2);
+    assert!(wifi_err_reason_t_WIFI_REASON_AUTH_LEAVE == 3);
+    assert!(wifi_err_reason_t_WIFI_REASON_ASSOC_EXPIRE == 4);
+    assert!(wifi_err_reason_t_WIFI_REASON_ASSOC_TOOMANY == 5);
+    assert!(wifi_err_reason_t_WIFI_REASON_NOT_AUTHED == 6);
+    assert!(wifi_err_reason_t_WIFI_REASON_NOT_ASSOCED == 7);
+    assert!(wifi_err_reason_t_WIFI_REASON_ASSOC_LEAVE == 8);
+    assert!(wifi_err_reason_t_WIFI_REASON_MIC_FAILURE == 14);
+    assert!(wifi_err_reason_t_WIFI_REASON_4WAY_HANDSHAKE_TIMEOUT == 15);
+    assert!(wifi_err_reason_t_WIFI_REASON_GROUP_KEY_UPDATE_TIMEOUT == 16);
+    assert!(wifi_err_reason_t_WIFI_REASON_802_1X_AUTH_FAILED == 23);
+    assert!(wifi_err_reason_t_WIFI_REASON_BEACON_TIMEOUT == 200);
+    assert!(wifi_err_reason_t_WIFI_REASON_NO_AP_FOUND == 201);
+    assert!(wifi_err_reason_t_WIFI_REASON_AUTH_FAIL == 202);
+    assert!(wifi_err_reason_t_WIFI_REASON_ASSOC_FAIL == 203);
+    assert!(wifi_err_reason_t_WIFI_REASON_HANDSHAKE_TIMEOUT == 204);
+    assert!(wifi_err_reason_t_WIFI_REASON_CONNECTION_FAIL == 205);
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(uptime_secs: u64, rssi: i8, ping: Option<PingResult>) -> LinkSample {
+        LinkSample {
+            uptime_secs,
+            ssid: "home".to_string(),
+            bssid: format_bssid(&[0x12, 0x34, 0x56, 0x78, 0x9a, 0xbc]),
+            channel: 6,
+            rssi,
+            ping,
+        }
+    }
+
+    fn ping(sent: u32, received: u32, avg_ms: Option<u32>) -> Option<PingResult> {
+        Some(PingResult { sent, received, avg_ms })
+    }
+
+    #[test]
+    fn keeps_the_latest_samples() {
+        let mut diagnostics = WifiDiagnostics::new();
+        for uptime_secs in 0..HISTORY_LEN as u64 + 5 {
+            diagnostics.record_sample(sample(uptime_secs, -50, None));
+        }
+        assert_eq!(diagnostics.samples().count(), HISTORY_LEN);
+        assert_eq!(diagnostics.samples().next().unwrap().uptime_secs, 5);
+        assert_eq!(diagnostics.samples().last().unwrap().uptime_secs, HISTORY_LEN as u64 + 4);
+    }
+
+    #[test]
+    fn keeps_the_latest_disconnects_and_counts_them_all() {
+        let mut diagnostics = WifiDiagnostics::new();
+        for uptime_secs in 0..DISCONNECT_HISTORY_LEN as u64 + 3 {
+            diagnostics.record_disconnect(uptime_secs, 201);
+        }
+        diagnostics.record_reconnect();
+        assert_eq!(diagnostics.disconnects().count(), DISCONNECT_HISTORY_LEN);
+        assert_eq!(diagnostics.disconnects().next().unwrap().uptime_secs, 3);
+        assert_eq!(diagnostics.disconnects().next().unwrap().description, "No access point found");
+        assert_eq!(diagnostics.disconnect_count(), DISCONNECT_HISTORY_LEN as u32 + 3);
+        assert_eq!(diagnostics.reconnect_count(), 1);
+    }
+
+    #[test]
+    fn summarises_signal_and_pings() {
+        let mut diagnostics = WifiDiagnostics::new();
+        diagnostics.record_sample(sample(0, -40, ping(3, 3, Some(10))));
+        diagnostics.record_sample(sample(60, -61, ping(3, 1, Some(40))));
+        diagnostics.record_sample(sample(120, -70, ping(3, 0, None)));
+        diagnostics.record_sample(sample(180, -50, None));
+
+        assert_eq!(diagnostics.summary(), LinkSummary {
+            samples: 4,
+            rssi_min: Some(-70),
+            // -221 / 4, rounded towards zero.
+            rssi_avg: Some(-55),
+            rssi_max: Some(-40),
+            pings_sent: 9,
+            pings_received: 4,
+            // Weighted by replies, (3 * 10 + 40) / 4.
+            ping_avg_ms: Some(17),
+        });
+        assert_eq!(diagnostics.summary().ping_loss_percent(), Some(55));
+    }
+
+    #[test]
+    fn summarises_nothing() {
+        let summary = WifiDiagnostics::new().summary();
+        assert_eq!(summary, LinkSummary {
+            samples: 0,
+            rssi_min: None,
+            rssi_avg: None,
+            rssi_max: None,
+            pings_sent: 0,
+            pings_received: 0,
+            ping_avg_ms: None,
+        });
+        assert_eq!(summary.ping_loss_percent(), None);
+    }
+
+    #[test]
+    fn works_out_ping_loss() {
+        let mut diagnostics = WifiDiagnostics::new();
+        diagnostics.record_sample(sample(0, -40, ping(3, 3, Some(10))));
+        assert_eq!(diagnostics.summary().ping_loss_percent(), Some(0));
+        diagnostics.record_sample(sample(60, -40, ping(3, 0, None)));
+        assert_eq!(diagnostics.summary().ping_loss_percent(), Some(50));
+        diagnostics.record_sample(sample(120, -40, ping(4, 0, None)));
+        assert_eq!(diagnostics.summary().ping_loss_percent(), Some(70));
+    }
+
+    #[test]
+    fn reports_as_json() {
+        let mut diagnostics = WifiDiagnostics::new();
+        diagnostics.record_sample(sample(60, -50, ping(3, 2, Some(12))));
+        diagnostics.record_disconnect(90, 15);
+        diagnostics.record_reconnect();
+
+        let json: serde_json::Value = serde_json::from_str(&diagnostics.to_json(120).unwrap()).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "uptime_secs": 120,
+            "disconnect_count": 1,
+            "reconnect_count": 1,
+            "summary": {
+                "samples": 1,
+                "rssi_min": -50,
+                "rssi_avg": -50,
+                "rssi_max": -50,
+                "pings_sent": 3,
+                "pings_received": 2,
+                "ping_avg_ms": 12,
+            },
+            "samples": [{
+                "uptime_secs": 60,
+                "ssid": "home",
+                "bssid": "12:34:56:78:9a:bc",
+                "channel": 6,
+                "rssi": -50,
+                "ping": { "sent": 3, "received": 2, "avg_ms": 12 },
+            }],
+            "disconnects": [{ "uptime_secs": 90, "reason": 15, "description": "4-way handshake timeout" }],
+        }));
+    }
+
+    #[test]
+    fn formats_durations() {
+        assert_eq!(format_duration(Duration::from_secs(0)), "00:00:00");
+        assert_eq!(format_duration(Duration::from_secs(3 * 3600 + 25 * 60 + 7)), "03:25:07");
+        assert_eq!(format_duration(Duration::from_secs(2 * 86_400 + 61)), "2d 00:01:01");
+    }
+}
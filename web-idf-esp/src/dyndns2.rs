@@ -1,4 +1,6 @@
-use crate::{Feature, config::{Config, ConfigManager, ConfigValue, FeatureConfig, FeatureDescriptor, TypedValue}};
+use std::time::Duration;
+
+use crate::{Feature, config::{Config, ConfigValue, FeatureDescriptor, TypedValue}, migration::Migration};
 
 pub const USER_NAME: &str = "user_name";
 pub const PASSWORD: &str = "password";
@@ -10,6 +12,10 @@ pub const UPDATE_URL: &str = "update_url";
 pub const UPDATE_REQUIRES_ADDRESS: &str = "upd_req_addr";
 pub const UPDATE_INTERVAL: &str = "upd_int";
 
+const URL_LEN: usize = 128;
+/// Version 1 made the update interval a Duration, it was an Int64 number of seconds.
+const SCHEMA_VERSION: u32 = 1;
+
 // pub struct DynDns2Config {
 //     user_name: String,
 //     password: String,
@@ -38,6 +44,7 @@ pub const UPDATE_INTERVAL: &str = "upd_int";
 //     }
 // }
 
+#[derive(Default)]
 pub struct DynDns2 {
 }
 
@@ -51,18 +58,43 @@ impl DynDns2 {
 impl Feature for DynDns2 {
     fn create_descriptor(&self) -> anyhow::Result<FeatureDescriptor> {
         let mut config = Config::new();
-        config.insert(USER_NAME.to_string(), ConfigValue { value: TypedValue::String(32, None), required: true })?;
-        config.insert(PASSWORD.to_string(), ConfigValue { value: TypedValue::String(32, None), required: true })?;
-        config.insert(HOSTNAME.to_string(), ConfigValue { value: TypedValue::String(32, None), required: true })?;
-        config.insert(BASE_SERVICE_URL.to_string(), ConfigValue { value: TypedValue::String(32, None), required: true })?;
-        config.insert(GET_IP_URL.to_string(), ConfigValue { value: TypedValue::String(32, None), required: true })?;
-        config.insert(GET_REQUIRES_STRIP.to_string(), ConfigValue { value: TypedValue::Bool(false), required: true })?;
-        config.insert(UPDATE_URL.to_string(), ConfigValue { value: TypedValue::String(32, None), required: true })?;
-        config.insert(UPDATE_REQUIRES_ADDRESS.to_string(), ConfigValue { value: TypedValue::Bool(false), required: false })?;
-        config.insert(UPDATE_INTERVAL.to_string(), ConfigValue { value: TypedValue::Int64(Some(3600)), required: true })?;
+        config.insert(USER_NAME.to_string(), ConfigValue::new(TypedValue::String(32, None), true)
+            .with_label("User name"))?;
+        config.insert(PASSWORD.to_string(), ConfigValue::new(TypedValue::Secret(32, None), true)
+            .with_label("Password"))?;
+        config.insert(HOSTNAME.to_string(), ConfigValue::new(TypedValue::String(32, None), true)
+            .with_label("Host name")
+            .with_help("The DNS name to keep up to date."))?;
+        config.insert(BASE_SERVICE_URL.to_string(), ConfigValue::new(TypedValue::Url(URL_LEN, None), true)
+            .with_label("Service URL"))?;
+        config.insert(GET_IP_URL.to_string(), ConfigValue::new(TypedValue::Url(URL_LEN, None), true)
+            .with_label("Address lookup URL")
+            .with_help("Returns this network's public address."))?;
+        config.insert(GET_REQUIRES_STRIP.to_string(), ConfigValue::new(TypedValue::Bool(false), true)
+            .with_label("Strip address lookup response")
+            .with_help("The lookup response has text around the address."))?;
+        config.insert(UPDATE_URL.to_string(), ConfigValue::new(TypedValue::Url(URL_LEN, None), true)
+            .with_label("Update URL"))?;
+        config.insert(UPDATE_REQUIRES_ADDRESS.to_string(), ConfigValue::new(TypedValue::Bool(false), false)
+            .with_label("Send address with update"))?;
+        config.insert(UPDATE_INTERVAL.to_string(), ConfigValue::new(TypedValue::Duration(None), true)
+            .with_label("Update interval")
+            .with_range(Some(60.0), None)
+            .with_default(TypedValue::Duration(Some(Duration::from_secs(3600)))))?;
         Ok(FeatureDescriptor {
             name: "DynDNS2".to_string(),
             config,
+            version: SCHEMA_VERSION,
         })
     }
+
+    fn migrate(&self, migration: &mut Migration) -> anyhow::Result<()> {
+        if migration.saved_version() < 1 {
+            migration.convert(UPDATE_INTERVAL, TypedValue::Int64(None), |value| match value {
+                TypedValue::Int64(Some(secs)) => u64::try_from(secs).ok().map(|secs| TypedValue::Duration(Some(Duration::from_secs(secs)))),
+                _ => None,
+            })?;
+        }
+        Ok(())
+    }
 }
\ No newline at end of file
@@ -0,0 +1,105 @@
+use std::ffi::CString;
+
+#[cfg(target_os = "espidf")]
+use log::info;
+
+/// WPA2-Enterprise credentials. PEAP needs the identity, username and password, EAP-TLS needs the client
+/// certificate and key as well. The server is only checked if a CA certificate is given.
+pub struct EapCredentials {
+    pub identity: String,
+    pub username: String,
+    pub password: String,
+    // The supplicant keeps pointers to the certificates rather than copying them, so they are kept here, and
+    // these credentials must outlive the connection.
+    ca_cert: Option<CString>,
+    client_cert_and_key: Option<(CString, CString)>,
+}
+
+impl EapCredentials {
+    pub fn new(identity: &str, username: &str, password: &str) -> Self {
+        Self {
+            identity: identity.to_string(),
+            username: username.to_string(),
+            password: password.to_string(),
+            ca_cert: None,
+            client_cert_and_key: None,
+        }
+    }
+
+    pub fn with_ca_cert(mut self, ca_cert: &str) -> anyhow::Result<Self> {
+        self.ca_cert = Some(pem_cstring(ca_cert)?);
+        Ok(self)
+    }
+
+    pub fn with_client_cert(mut self, cert: &str, key: &str) -> anyhow::Result<Self> {
+        self.client_cert_and_key = Some((pem_cstring(cert)?, pem_cstring(key)?));
+        Ok(self)
+    }
+
+    /// Hands the credentials to the supplicant and turns enterprise authentication on for the station.
+    #[cfg(target_os = "espidf")]
+    pub fn enable(&self) -> anyhow::Result<()> {
+        info!("Enabling WPA2-Enterprise for identity {}", self.identity);
+        unsafe {
+            esp_idf_sys::esp!(esp_idf_sys::esp_eap_client_set_identity(self.identity.as_ptr(), self.identity.len() as i32))?;
+            esp_idf_sys::esp!(esp_idf_sys::esp_eap_client_set_username(self.username.as_ptr(), self.username.len() as i32))?;
+            esp_idf_sys::esp!(esp_idf_sys::esp_eap_client_set_password(self.password.as_ptr(), self.password.len() as i32))?;
+
+            match &self.ca_cert {
+                // The lengths of PEM data include the terminating NUL.
+                Some(ca_cert) => esp_idf_sys::esp!(esp_idf_sys::esp_eap_client_set_ca_cert(ca_cert.as_ptr() as *const u8, ca_cert.as_bytes_with_nul().len() as i32))?,
+                None => esp_idf_sys::esp_eap_client_clear_ca_cert(),
+            }
+
+            match &self.client_cert_and_key {
+                Some((cert, key)) => esp_idf_sys::esp!(esp_idf_sys::esp_eap_client_set_certificate_and_key(
+                    cert.as_ptr() as *const u8, cert.as_bytes_with_nul().len() as i32,
+                    key.as_ptr() as *const u8, key.as_bytes_with_nul().len() as i32,
+                    std::ptr::null(), 0))?,
+                None => esp_idf_sys::esp_eap_client_clear_certificate_and_key(),
+            }
+
+            esp_idf_sys::esp!(esp_idf_sys::esp_wifi_sta_enterprise_enable())?;
+        }
+        Ok(())
+    }
+}
+
+/// Turns enterprise authentication off again, for joining a personal or open network.
+#[cfg(target_os = "espidf")]
+pub fn disable() -> anyhow::Result<()> {
+    unsafe {
+        esp_idf_sys::esp!(esp_idf_sys::esp_wifi_sta_enterprise_disable())?;
+    }
+    Ok(())
+}
+
+fn pem_cstring(text: &str) -> anyhow::Result<CString> {
+    let pem = normalize_pem(text).ok_or_else(|| anyhow::anyhow!("Not a PEM certificate or key"))?;
+    Ok(CString::new(pem)?)
+}
+
+/// Puts back the line breaks in PEM text which has lost them, as happens when it is pasted into a one line form
+/// field. Returns None if the text isn't a PEM block.
+pub fn normalize_pem(text: &str) -> Option<String> {
+    let text = text.trim();
+    let rest = text.strip_prefix("-----BEGIN ")?;
+    let (label, rest) = rest.split_once("-----")?;
+    let footer = format!("-----END {}-----", label);
+    let body = rest.strip_suffix(footer.as_str())?;
+
+    let body: String = body.chars().filter(|c| !c.is_whitespace()).collect();
+    if body.is_empty() || !body.chars().all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '/' || c == '=') {
+        return None;
+    }
+
+    let mut pem = format!("-----BEGIN {}-----\n", label);
+    for line in body.as_bytes().chunks(64) {
+        // The body is all ASCII so this can't split a character.
+        pem.push_str(std::str::from_utf8(line).unwrap());
+        pem.push('\n');
+    }
+    pem.push_str(&footer);
+    pem.push('\n');
+    Some(pem)
+}
@@ -1,6 +1,6 @@
 use std::sync::{Arc, Mutex};
 
-use esp_idf_svc::http::{Method, server::{EspHttpConnection, EspHttpServer}};
+use esp_idf_svc::http::{Method, server::{Configuration, EspHttpConnection, EspHttpServer}};
 use log::info;
 
 
@@ -10,7 +10,11 @@ pub struct HttpServerManager<'a>{
 
 impl HttpServerManager<'_> {
     pub fn new() -> anyhow::Result<Self> {
-        let server = EspHttpServer::new(&Default::default())?;
+        let server = EspHttpServer::new(&Configuration {
+            // The JSON API takes the feature name from the path, as in /api/config/core.
+            uri_match_wildcard: true,
+            ..Default::default()
+        })?;
         Ok(Self {
             server,
         })
@@ -46,6 +50,16 @@ label { display: block; margin: 12px 0 6px; font-weight: 600; }
 input, select { width: 100%; padding: 10px 10px; border: 1px solid #ccc; border-radius: 8px; box-sizing: border-box; }
 button { margin-top: 18px; width: 100%; padding: 12px; font-size: 1rem; border-radius: 10px; border: none; background: #007aff; color: #fff; }
 button:active { background: #005bb5; }
+.networks label { font-weight: normal; margin: 6px 0; }
+.networks input { width: auto; margin-right: 8px; }
+.signal { color: #007aff; letter-spacing: -2px; }
+.signal .off { color: #ccc; }
+table.status { width: 100%; border-collapse: collapse; margin-bottom: 18px; }
+.status th, .status td { text-align: left; padding: 6px 4px; border-bottom: 1px solid #ddd; vertical-align: top; }
+.status small { color: #888; }
+.help { display: block; color: #666; margin-top: 4px; }
+.field-error { color: #d00; margin-top: 4px; }
+input.invalid, select.invalid { border-color: #d00; }
                         "#.as_bytes())?;
             Ok(())
         })?;
@@ -0,0 +1,200 @@
+use std::fmt;
+use std::net::Ipv4Addr;
+
+/// A fixed IPv4 configuration for the station interface.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StaticIpConfig {
+    pub ip: Ipv4Addr,
+    /// The netmask as a prefix length, 24 for 255.255.255.0.
+    pub prefix_len: u8,
+    pub gateway: Ipv4Addr,
+    pub dns: Ipv4Addr,
+    pub secondary_dns: Option<Ipv4Addr>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IpConfigError {
+    /// A field which must hold an address doesn't.
+    InvalidAddress { field: &'static str, value: String },
+    InvalidNetmask(String),
+    /// A field needed for a static address is empty.
+    Missing(&'static str),
+    /// The address is the network or broadcast address of its subnet.
+    NotAHostAddress(Ipv4Addr),
+    GatewayNotInSubnet(Ipv4Addr),
+    GatewayIsAddress,
+}
+
+impl fmt::Display for IpConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IpConfigError::InvalidAddress { field, value } => write!(f, "{} \"{}\" is not an IPv4 address", field, value),
+            IpConfigError::InvalidNetmask(value) => write!(f, "Netmask \"{}\" is not valid, use a form like 255.255.255.0 or /24", value),
+            IpConfigError::Missing(field) => write!(f, "{} is needed for a static address", field),
+            IpConfigError::NotAHostAddress(ip) => write!(f, "{} is the network or broadcast address of its subnet", ip),
+            IpConfigError::GatewayNotInSubnet(gateway) => write!(f, "Gateway {} is not in the same subnet as the address", gateway),
+            IpConfigError::GatewayIsAddress => write!(f, "The gateway can't be the device's own address"),
+        }
+    }
+}
+
+impl std::error::Error for IpConfigError {}
+
+fn parse_address(field: &'static str, value: &str) -> Result<Ipv4Addr, IpConfigError> {
+    value.parse().map_err(|_| IpConfigError::InvalidAddress { field, value: value.to_string() })
+}
+
+/// Parses a netmask given as a dotted mask such as 255.255.255.0, or as a prefix length such as /24 or 24.
+pub fn parse_netmask(value: &str) -> Result<u8, IpConfigError> {
+    let invalid = || IpConfigError::InvalidNetmask(value.to_string());
+
+    if let Ok(mask) = value.parse::<Ipv4Addr>() {
+        let bits = u32::from(mask);
+        // The ones must be contiguous from the top.
+        if bits.leading_ones() + bits.trailing_zeros() != 32 {
+            return Err(invalid());
+        }
+        return Ok(bits.leading_ones() as u8);
+    }
+
+    let prefix_len = value.strip_prefix('/').unwrap_or(value).parse::<u8>().map_err(|_| invalid())?;
+    if prefix_len > 32 {
+        return Err(invalid());
+    }
+    Ok(prefix_len)
+}
+
+fn mask(prefix_len: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix_len as u32).unwrap_or(0)
+}
+
+/// Checks the static address fields from the config. Returns None if no address is set, which means DHCP.
+///
+/// The netmask and gateway are needed with an address. The DNS servers are optional, the gateway is used if
+/// none is given.
+pub fn parse_static_ip(ip: &str, netmask: &str, gateway: &str, dns: &str, secondary_dns: &str) -> Result<Option<StaticIpConfig>, IpConfigError> {
+    let (ip, netmask, gateway, dns, secondary_dns) = (ip.trim(), netmask.trim(), gateway.trim(), dns.trim(), secondary_dns.trim());
+    if ip.is_empty() {
+        return Ok(None);
+    }
+
+    let ip = parse_address("Address", ip)?;
+    if netmask.is_empty() {
+        return Err(IpConfigError::Missing("Netmask"));
+    }
+    let prefix_len = parse_netmask(netmask)?;
+    if gateway.is_empty() {
+        return Err(IpConfigError::Missing("Gateway"));
+    }
+    let gateway = parse_address("Gateway", gateway)?;
+
+    let mask = mask(prefix_len);
+    let host = |addr: Ipv4Addr| u32::from(addr) & !mask;
+    // /31 and /32 subnets have no network or broadcast address.
+    if prefix_len < 31 && (host(ip) == 0 || host(ip) == !mask) {
+        return Err(IpConfigError::NotAHostAddress(ip));
+    }
+    if u32::from(gateway) & mask != u32::from(ip) & mask {
+        return Err(IpConfigError::GatewayNotInSubnet(gateway));
+    }
+    if gateway == ip {
+        return Err(IpConfigError::GatewayIsAddress);
+    }
+
+    let dns = if dns.is_empty() { gateway } else { parse_address("DNS server", dns)? };
+    let secondary_dns = if secondary_dns.is_empty() { None } else { Some(parse_address("Secondary DNS server", secondary_dns)?) };
+
+    Ok(Some(StaticIpConfig {
+        ip,
+        prefix_len,
+        gateway,
+        dns,
+        secondary_dns,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(value: &str) -> Ipv4Addr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_netmasks() {
+        assert_eq!(parse_netmask("255.255.255.0"), Ok(24));
+        assert_eq!(parse_netmask("255.255.240.0"), Ok(20));
+        assert_eq!(parse_netmask("255.255.255.255"), Ok(32));
+        assert_eq!(parse_netmask("0.0.0.0"), Ok(0));
+        assert_eq!(parse_netmask("/24"), Ok(24));
+        assert_eq!(parse_netmask("16"), Ok(16));
+    }
+
+    #[test]
+    fn rejects_invalid_netmasks() {
+        for value in ["255.0.255.0", "255.255.255.1", "0.255.255.255", "/33", "33", "24.5", "/", "mask", "-1"] {
+            assert_eq!(parse_netmask(value), Err(IpConfigError::InvalidNetmask(value.to_string())), "{}", value);
+        }
+    }
+
+    #[test]
+    fn no_address_means_dhcp() {
+        assert_eq!(parse_static_ip("", "", "", "", ""), Ok(None));
+        // The other fields are ignored, so switching back to DHCP only needs the address cleared.
+        assert_eq!(parse_static_ip(" ", "255.255.255.0", "192.168.1.1", "bad", ""), Ok(None));
+    }
+
+    #[test]
+    fn parses_a_static_address() {
+        let config = parse_static_ip("192.168.1.50", "255.255.255.0", "192.168.1.1", "1.1.1.1", " 8.8.8.8 ").unwrap().unwrap();
+        assert_eq!(config, StaticIpConfig {
+            ip: addr("192.168.1.50"),
+            prefix_len: 24,
+            gateway: addr("192.168.1.1"),
+            dns: addr("1.1.1.1"),
+            secondary_dns: Some(addr("8.8.8.8")),
+        });
+    }
+
+    #[test]
+    fn dns_defaults_to_the_gateway() {
+        let config = parse_static_ip("10.0.0.2", "/8", "10.0.0.1", "", "").unwrap().unwrap();
+        assert_eq!(config.dns, addr("10.0.0.1"));
+        assert_eq!(config.secondary_dns, None);
+    }
+
+    #[test]
+    fn needs_a_netmask_and_gateway() {
+        assert_eq!(parse_static_ip("192.168.1.50", "", "192.168.1.1", "", ""), Err(IpConfigError::Missing("Netmask")));
+        assert_eq!(parse_static_ip("192.168.1.50", "/24", "", "", ""), Err(IpConfigError::Missing("Gateway")));
+    }
+
+    #[test]
+    fn rejects_bad_addresses() {
+        assert_eq!(parse_static_ip("192.168.1", "/24", "192.168.1.1", "", ""),
+            Err(IpConfigError::InvalidAddress { field: "Address", value: "192.168.1".to_string() }));
+        assert_eq!(parse_static_ip("192.168.1.50", "/24", "router", "", ""),
+            Err(IpConfigError::InvalidAddress { field: "Gateway", value: "router".to_string() }));
+        assert_eq!(parse_static_ip("192.168.1.50", "/24", "192.168.1.1", "::1", ""),
+            Err(IpConfigError::InvalidAddress { field: "DNS server", value: "::1".to_string() }));
+        assert_eq!(parse_static_ip("192.168.1.50", "/24", "192.168.1.1", "", "x"),
+            Err(IpConfigError::InvalidAddress { field: "Secondary DNS server", value: "x".to_string() }));
+    }
+
+    #[test]
+    fn rejects_network_and_broadcast_addresses() {
+        assert_eq!(parse_static_ip("192.168.1.0", "/24", "192.168.1.1", "", ""), Err(IpConfigError::NotAHostAddress(addr("192.168.1.0"))));
+        assert_eq!(parse_static_ip("192.168.1.255", "/24", "192.168.1.1", "", ""), Err(IpConfigError::NotAHostAddress(addr("192.168.1.255"))));
+        // A /31 link has no network or broadcast address.
+        assert!(parse_static_ip("192.168.1.0", "/31", "192.168.1.1", "", "").unwrap().is_some());
+    }
+
+    #[test]
+    fn rejects_a_gateway_outside_the_subnet() {
+        assert_eq!(parse_static_ip("192.168.1.50", "/24", "192.168.2.1", "", ""), Err(IpConfigError::GatewayNotInSubnet(addr("192.168.2.1"))));
+        assert_eq!(parse_static_ip("10.0.5.2", "255.255.252.0", "10.0.8.1", "", ""), Err(IpConfigError::GatewayNotInSubnet(addr("10.0.8.1"))));
+        assert!(parse_static_ip("10.0.5.2", "255.255.252.0", "10.0.4.1", "", "").unwrap().is_some());
+        assert_eq!(parse_static_ip("192.168.1.50", "/24", "192.168.1.50", "", ""), Err(IpConfigError::GatewayIsAddress));
+    }
+}
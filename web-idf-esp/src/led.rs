@@ -4,6 +4,33 @@ use log::info;
 use esp_idf_hal::{gpio::PinDriver, ledc::LedcDriver};
 use esp_idf_hal::units::*;
 
+/// What the LED shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedState {
+    Starting,
+    Connected,
+    /// The WiFi connection was lost and we are trying to get it back.
+    Reconnecting,
+    Failed,
+    /// Running the setup access point.
+    AccessPoint,
+    /// Connected, with the setup access point running alongside.
+    SetupAccessPoint,
+}
+
+impl LedState {
+    pub fn color(&self) -> (u8, u8, u8) {
+        match self {
+            LedState::Starting => (64, 64, 0),
+            LedState::Connected => (0, 64, 0),
+            LedState::Reconnecting => (64, 16, 0),
+            LedState::Failed => (64, 0, 0),
+            LedState::AccessPoint => (0, 0, 64),
+            LedState::SetupAccessPoint => (0, 64, 64),
+        }
+    }
+}
+
 pub struct LedManager<'a> {
     led_timer_driver: esp_idf_hal::ledc::LedcTimerDriver<'a, esp_idf_hal::ledc::LowSpeed>,
     led_channel_red: Arc<Mutex<esp_idf_hal::ledc::LedcDriver<'a>>>,
@@ -43,4 +70,10 @@ impl<'a> LedManager<'a> {
         self.led_channel_blue.lock().unwrap().set_duty((255 - b) as u32)?;
         Ok(())
     }
+
+    pub fn show(&self, state: LedState) -> anyhow::Result<()> {
+        info!("Led state {:?}", state);
+        let (r, g, b) = state.color();
+        self.set_color(r, g, b)
+    }
 }
\ No newline at end of file
@@ -1,20 +1,38 @@
-use crate::config::{Config, FeatureConfig, FeatureDescriptor};
+use crate::config::FeatureDescriptor;
+use crate::migration::Migration;
 
 pub const SSID_LEN: usize = 32;
 pub const PASSWORD_LEN: usize = 64;
 pub const HOSTNAME_LEN: usize = 32;
 pub const FQDN_LEN: usize = 64;
 
+#[cfg(target_os = "espidf")]
 pub mod sparko_cyd;
 
-mod config;
+pub mod config;
+#[cfg(target_os = "espidf")]
 mod wifi;
+pub mod networks;
+pub mod eap;
+pub mod supervisor;
+pub mod diagnostics;
+pub mod ipconfig;
+#[cfg(target_os = "espidf")]
 mod http;
-mod portal;
+#[cfg(target_os = "espidf")]
+mod api;
+pub mod backup;
+pub mod migration;
+pub mod portal;
+#[cfg(target_os = "espidf")]
 mod led;
-mod tz;
+pub mod tz;
+pub mod values;
+#[cfg(target_os = "espidf")]
 mod mdns;
 pub mod dyndns2;
+pub mod qr;
+pub mod storage;
 
 // trait Task {
 //     fn run(&self, sparko_cyd: &sparko_cyd::SparkoCyd) -> anyhow::Result<u64>;
@@ -23,4 +41,10 @@ pub mod dyndns2;
 pub trait Feature {
     // fn start(&self, sparko_cyd: &sparko_cyd::SparkoCyd) -> anyhow::Result<()>;
     fn create_descriptor(&self) -> anyhow::Result<FeatureDescriptor>;
+
+    /// Updates values saved with an older `FeatureDescriptor::version`, before they are loaded. Runs at startup
+    /// only when the saved version is older. An error leaves the saved version alone so it runs again next boot.
+    fn migrate(&self, _migration: &mut Migration) -> anyhow::Result<()> {
+        Ok(())
+    }
 }
\ No newline at end of file
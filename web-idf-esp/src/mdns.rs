@@ -1,18 +1,18 @@
 use simple_dns::{Name, CLASS, ResourceRecord, rdata::RData, rdata::A, Packet};
 use std::net::{Ipv4Addr, UdpSocket};
+use std::sync::{Arc, Mutex};
 use std::thread;
 use std::time::Duration;
 
-pub fn start_mdns(hostname: &str, ip_address: &Ipv4Addr) -> anyhow::Result<()> {
-    let hostname_local = format!("{}.local", hostname);
-    let domain_name = Name::new_unchecked(&hostname_local);
+fn build_announcement(hostname_local: &str, ip_address: Ipv4Addr) -> anyhow::Result<Vec<u8>> {
+    let domain_name = Name::new_unchecked(hostname_local);
 
     // Create A record
     let a_record = ResourceRecord::new(
         domain_name.clone(),
         CLASS::IN,
         120,  // TTL
-        RData::A(A { address: (*ip_address).into() }),
+        RData::A(A { address: ip_address.into() }),
     );
 
     // Create DNS response packet
@@ -20,7 +20,16 @@ pub fn start_mdns(hostname: &str, ip_address: &Ipv4Addr) -> anyhow::Result<()> {
     packet.answers.push(a_record);
 
     // Serialize packet
-    let packet_data = packet.build_bytes_vec()?;
+    Ok(packet.build_bytes_vec()?)
+}
+
+/// Announces `hostname`.local, the address is re-read for each announcement so it can change when we roam.
+pub fn start_mdns(hostname: &str, ip_address: Arc<Mutex<Ipv4Addr>>) -> anyhow::Result<()> {
+    let hostname_local = format!("{}.local", hostname);
+    let initial_address = *ip_address.lock().unwrap();
+
+    // Check the packet can be built before starting the thread
+    build_announcement(&hostname_local, initial_address)?;
 
     // Start background thread to send mDNS announcements
     thread::spawn(move || {
@@ -47,6 +56,15 @@ pub fn start_mdns(hostname: &str, ip_address: &Ipv4Addr) -> anyhow::Result<()> {
         let multicast_addr = "224.0.0.251:5353";
 
         loop {
+            let address = *ip_address.lock().unwrap();
+            let packet_data = match build_announcement(&hostname_local, address) {
+                Ok(packet_data) => packet_data,
+                Err(e) => {
+                    log::error!("Failed to build mDNS packet: {}", e);
+                    return;
+                }
+            };
+
             match socket.send_to(&packet_data, multicast_addr) {
                 Ok(_) => log::info!("Sent mDNS announcement for {}", hostname_local),
                 Err(e) => log::error!("Failed to send mDNS packet: {}", e),
@@ -55,6 +73,6 @@ pub fn start_mdns(hostname: &str, ip_address: &Ipv4Addr) -> anyhow::Result<()> {
         }
     });
 
-    log::info!("mDNS advertiser started for: {}.local ({})", hostname, ip_address);
+    log::info!("mDNS advertiser started for: {}.local ({})", hostname, initial_address);
     Ok(())
 }
\ No newline at end of file
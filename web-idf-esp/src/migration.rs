@@ -0,0 +1,350 @@
+#[cfg(target_os = "espidf")]
+use std::sync::Arc;
+
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::http::Method;
+use log::info;
+
+use crate::Feature;
+use crate::config::TypedValue;
+#[cfg(target_os = "espidf")]
+use crate::config::ConfigManager;
+#[cfg(target_os = "espidf")]
+use crate::http::HttpServerManager;
+use crate::storage::ConfigStore;
+#[cfg(target_os = "espidf")]
+use crate::values::escape_html;
+
+/// The key in each feature's namespace holding the schema version its values were saved with. Config names can't
+/// start with _, so it can't clash with a value.
+pub const SCHEMA_VERSION_KEY: &str = "_schema_ver";
+
+/// What happened to one saved value during a migration.
+#[derive(Debug, Clone)]
+pub struct MigrationStep {
+    pub name: String,
+    pub description: String,
+    pub ok: bool,
+}
+
+/// What happened to one feature's saved config when it was loaded, shown on /status/config.
+#[derive(Debug, Clone)]
+pub struct MigrationReport {
+    pub feature: String,
+    /// The version the values were saved with, None if nothing was saved yet.
+    pub from_version: Option<u32>,
+    pub to_version: u32,
+    pub steps: Vec<MigrationStep>,
+    /// Why the migration stopped, in which case the saved version is left as it was and it runs again at the next
+    /// boot.
+    pub error: Option<String>,
+}
+
+impl MigrationReport {
+    pub fn summary(&self) -> String {
+        match (&self.error, self.from_version) {
+            (Some(e), _) => format!("Failed: {}", e),
+            (None, None) => format!("New, version {}", self.to_version),
+            (None, Some(from)) if from == self.to_version => format!("Up to date, version {}", self.to_version),
+            (None, Some(from)) => format!("Migrated from version {} to {}", from, self.to_version),
+        }
+    }
+}
+
+/// Passed to `Feature::migrate` to change the saved values of a feature whose schema has changed. Each change is
+/// logged and recorded for the status page. Values are read with the type they were saved as, so a change of type
+/// has to be converted here rather than left to read back as None.
+pub struct Migration<'a> {
+    store: &'a dyn ConfigStore,
+    from_version: u32,
+    steps: Vec<MigrationStep>,
+}
+
+impl<'a> Migration<'a> {
+    /// The version the values were saved with. Config saved before versions were kept is version 0.
+    pub fn saved_version(&self) -> u32 {
+        self.from_version
+    }
+
+    /// Moves a value to a new name. `saved_as` is the type it was saved with, which it keeps.
+    pub fn rename(&mut self, from: &str, to: &str, saved_as: TypedValue) -> anyhow::Result<()> {
+        let Some(value) = self.read(from, &saved_as)? else {
+            return Ok(());
+        };
+        value.write_to_store(self.store, to)?;
+        self.store.remove(from)?;
+        self.record(from, format!("Renamed to {}", to), true);
+        Ok(())
+    }
+
+    /// Reads a value saved as the type of `saved_as` and saves what `convert` makes of it in its place. If `convert`
+    /// returns None the value is dropped, so it reads back as unset or as its default.
+    pub fn convert(&mut self, name: &str, saved_as: TypedValue, convert: impl FnOnce(TypedValue) -> Option<TypedValue>) -> anyhow::Result<()> {
+        let Some(value) = self.read(name, &saved_as)? else {
+            return Ok(());
+        };
+        let from_kind = value.kind();
+        match convert(value) {
+            Some(converted) if !converted.is_none() => {
+                converted.write_to_store(self.store, name)?;
+                self.record(name, format!("Converted from {} to {}", from_kind, converted.kind()), true);
+            },
+            _ => {
+                self.store.remove(name)?;
+                self.record(name, format!("Dropped, the {} value could not be converted", from_kind), false);
+            },
+        }
+        Ok(())
+    }
+
+    /// Removes a value which is no longer used.
+    pub fn drop_value(&mut self, name: &str) -> anyhow::Result<()> {
+        if self.store.remove(name)? {
+            self.record(name, "Dropped".to_string(), true);
+        }
+        Ok(())
+    }
+
+    /// Reads a saved value, None if it isn't saved. A value which doesn't read as `saved_as` is recorded as a
+    /// failure and left alone.
+    fn read(&mut self, name: &str, saved_as: &TypedValue) -> anyhow::Result<Option<TypedValue>> {
+        if !self.store.contains(name)? {
+            return Ok(None);
+        }
+        let value = saved_as.read_from_store(self.store, name);
+        if value.is_none() {
+            self.record(name, format!("Left alone, it does not read as {}", saved_as.kind()), false);
+            return Ok(None);
+        }
+        Ok(Some(value))
+    }
+
+    fn record(&mut self, name: &str, description: String, ok: bool) {
+        if ok {
+            info!("Migration of {}: {}", name, description);
+        }
+        else {
+            log::warn!("Migration of {}: {}", name, description);
+        }
+        self.steps.push(MigrationStep {
+            name: name.to_string(),
+            description,
+            ok,
+        });
+    }
+}
+
+/// Brings a feature's saved values up to `version` before they are loaded, using `feature`'s migration hook. The
+/// core config has no `Feature`, so it only has its version kept. Never fails, so one feature's bad migration
+/// doesn't stop the device starting. Problems go in the report instead.
+pub fn migrate(name: &str, feature: Option<&dyn Feature>, version: u32, store: &dyn ConfigStore) -> MigrationReport {
+    let mut report = MigrationReport {
+        feature: name.to_string(),
+        from_version: None,
+        to_version: version,
+        steps: Vec::new(),
+        error: None,
+    };
+
+    let saved_version = match store.get_u32(SCHEMA_VERSION_KEY) {
+        Ok(Some(saved_version)) => Some(saved_version),
+        // Values saved before versions were kept count as version 0.
+        Ok(None) if store.keys().map(|keys| !keys.is_empty()).unwrap_or(false) => Some(0),
+        Ok(None) => None,
+        Err(e) => {
+            report.error = Some(format!("Can't read the saved version: {}", e));
+            return report;
+        },
+    };
+    report.from_version = saved_version;
+
+    match saved_version {
+        Some(saved_version) if saved_version == version => return report,
+        Some(saved_version) if saved_version > version => {
+            log::warn!("Config for {} was saved by newer firmware, version {} rather than {}", name, saved_version, version);
+            report.error = Some(format!("Saved by newer firmware with version {}, some values may not load", saved_version));
+            return report;
+        },
+        Some(saved_version) => {
+            info!("Migrating config for {} from version {} to {}", name, saved_version, version);
+            let mut migration = Migration {
+                store,
+                from_version: saved_version,
+                steps: Vec::new(),
+            };
+            let result = feature.map(|feature| feature.migrate(&mut migration)).unwrap_or(Ok(()));
+            report.steps = migration.steps;
+            if let Err(e) = result {
+                log::error!("Migrating config for {} failed, it will be tried again at the next boot: {}", name, e);
+                report.error = Some(e.to_string());
+                return report;
+            }
+        },
+        None => {},
+    }
+
+    if let Err(e) = store.set_u32(SCHEMA_VERSION_KEY, version) {
+        log::error!("Failed to save the config version for {}: {}", name, e);
+        report.error = Some(format!("Can't save the version: {}", e));
+    }
+    report
+}
+
+/// Registers /status/config, which shows each feature's config version and what any migration at startup did.
+#[cfg(target_os = "espidf")]
+pub fn create_pages(config_manager: &Arc<ConfigManager>, server_manager: &mut HttpServerManager) -> anyhow::Result<()> {
+    let config_manager_clone = config_manager.clone();
+
+    server_manager.fn_handler("/status/config", Method::Get, move |req| {
+        info!("Received {:?} request for {}", req.method(), req.uri());
+
+        let mut resp = req.into_ok_response()?;
+        resp.write(r#"
+            <!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta charset="utf-8" />
+                <meta name="viewport" content="width=device-width, initial-scale=1" />
+                <title>Config Status</title>
+                <link rel="stylesheet" href="/main.css">
+            </head>
+            <body>
+                <div class="page">
+                    <h1>Config Status</h1>
+                    <table class="status">
+                        <tr><th>Feature</th><th>Version</th></tr>"#.as_bytes())?;
+
+        for report in &config_manager_clone.migrations {
+            let mut steps = String::new();
+            for step in &report.steps {
+                let class = if step.ok { "" } else { r#" class="field-error""# };
+                steps.push_str(&format!(r#"<br><small{}>{}: {}</small>"#, class, escape_html(&step.name), escape_html(&step.description)));
+            }
+            let class = if report.error.is_some() { r#" class="field-error""# } else { "" };
+            resp.write(format!(r#"
+                        <tr><td>{}</td><td><span{}>{}</span>{}</td></tr>"#,
+                escape_html(&report.feature), class, escape_html(&report.summary()), steps).as_bytes())?;
+        }
+
+        resp.write(r#"
+                    </table>
+                    <p><a href="/status/wifi">WiFi status</a></p>
+                </div>
+            </body>
+            </html>"#.as_bytes())?;
+        Ok(())
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, ConfigValue, EnabledState, FeatureConfig, FeatureDescriptor};
+    use crate::dyndns2::{self, DynDns2};
+    use crate::storage::MemoryStore;
+
+    /// Loads `store` the way `ConfigManager` does after migrating it, giving the values as JSON.
+    fn load(feature: &dyn Feature, store: MemoryStore) -> serde_json::Value {
+        let descriptor = feature.create_descriptor().unwrap();
+        FeatureConfig::new(descriptor.name, EnabledState::Enabled, descriptor.config, Box::new(store)).unwrap().to_json()["values"].clone()
+    }
+
+    #[test]
+    fn migrates_the_dyndns2_update_interval() {
+        let dyndns2 = DynDns2::default();
+        let descriptor = dyndns2.create_descriptor().unwrap();
+        let store = MemoryStore::new();
+        store.set_str(dyndns2::USER_NAME, "sparko").unwrap();
+        store.set_i64(dyndns2::UPDATE_INTERVAL, 900).unwrap();
+
+        let report = migrate(&descriptor.name, Some(&dyndns2), descriptor.version, &store);
+        assert_eq!(report.from_version, Some(0));
+        assert!(report.error.is_none());
+        assert_eq!(store.get_u32(SCHEMA_VERSION_KEY).unwrap(), Some(descriptor.version));
+        let oks: Vec<_> = report.steps.iter().map(|s| (s.name.as_str(), s.ok)).collect();
+        assert_eq!(oks, vec![(dyndns2::UPDATE_INTERVAL, true)]);
+        assert_eq!(report.summary(), format!("Migrated from version 0 to {}", descriptor.version));
+
+        let again = migrate(&descriptor.name, Some(&dyndns2), descriptor.version, &store);
+        assert!(again.steps.is_empty());
+        assert_eq!(again.summary(), format!("Up to date, version {}", descriptor.version));
+
+        let values = load(&dyndns2, store);
+        assert_eq!(values[dyndns2::UPDATE_INTERVAL], 900);
+        assert_eq!(values[dyndns2::USER_NAME], "sparko");
+    }
+
+    #[test]
+    fn drops_a_dyndns2_update_interval_which_wont_convert() {
+        let dyndns2 = DynDns2::default();
+        let descriptor = dyndns2.create_descriptor().unwrap();
+        let store = MemoryStore::new();
+        store.set_i64(dyndns2::UPDATE_INTERVAL, -5).unwrap();
+
+        let report = migrate(&descriptor.name, Some(&dyndns2), descriptor.version, &store);
+        assert!(report.error.is_none());
+        assert!(!report.steps[0].ok);
+        assert!(!store.contains(dyndns2::UPDATE_INTERVAL).unwrap());
+        assert_eq!(load(&dyndns2, store)[dyndns2::UPDATE_INTERVAL], 3600);
+    }
+
+    /// Renames, drops and fails to convert a value, with a descriptor of where they end up.
+    struct Reshuffled;
+
+    impl Feature for Reshuffled {
+        fn create_descriptor(&self) -> anyhow::Result<FeatureDescriptor> {
+            let mut config = Config::new();
+            config.insert("new".to_string(), ConfigValue::new(TypedValue::String(16, None), false))?;
+            config.insert("bad".to_string(), ConfigValue::new(TypedValue::Int32(None), false))?;
+            Ok(FeatureDescriptor {
+                name: "reshuffled".to_string(),
+                config,
+                version: 1,
+            })
+        }
+
+        fn migrate(&self, migration: &mut Migration) -> anyhow::Result<()> {
+            if migration.saved_version() < 1 {
+                migration.rename("old", "new", TypedValue::String(16, None))?;
+                migration.drop_value("gone")?;
+                migration.convert("bad", TypedValue::Int32(None), Some)?;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn renames_and_drops_values() {
+        let descriptor = Reshuffled.create_descriptor().unwrap();
+        let store = MemoryStore::new();
+        store.set_str("old", "x").unwrap();
+        store.set_u8("gone", 1).unwrap();
+        store.set_str("bad", "text").unwrap();
+
+        let report = migrate(&descriptor.name, Some(&Reshuffled), descriptor.version, &store);
+        assert!(report.error.is_none());
+        assert!(!store.contains("old").unwrap());
+        assert!(!store.contains("gone").unwrap());
+        assert!(store.contains("bad").unwrap());
+        let oks: Vec<_> = report.steps.iter().map(|s| (s.name.as_str(), s.ok)).collect();
+        assert_eq!(oks, vec![("old", true), ("gone", true), ("bad", false)]);
+
+        let values = load(&Reshuffled, store);
+        assert_eq!(values["new"], "x");
+        assert!(values["bad"].is_null());
+    }
+
+    #[test]
+    fn new_and_newer() {
+        let dyndns2 = DynDns2::default();
+        let store = MemoryStore::new();
+        let report = migrate("DynDNS2", Some(&dyndns2), 2, &store);
+        assert_eq!(report.summary(), "New, version 2");
+        assert_eq!(store.get_u32(SCHEMA_VERSION_KEY).unwrap(), Some(2));
+        let report = migrate("DynDNS2", Some(&dyndns2), 1, &store);
+        assert!(report.error.is_some());
+        assert_eq!(store.get_u32(SCHEMA_VERSION_KEY).unwrap(), Some(2));
+    }
+}
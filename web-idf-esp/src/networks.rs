@@ -0,0 +1,717 @@
+use std::cmp::Reverse;
+use std::sync::{Condvar, Mutex};
+use std::time::Duration;
+
+use embedded_svc::wifi::{AccessPointInfo, AuthMethod};
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::http::server::EspHttpConnection;
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{PASSWORD_LEN, SSID_LEN};
+use crate::storage::ConfigStore;
+#[cfg(target_os = "espidf")]
+use crate::values::escape_html;
+
+const NETWORKS_KEY: &str = "list";
+
+/// The most networks which can be saved, this keeps the stored list well under the NVS string limit.
+pub const MAX_NETWORKS: usize = 8;
+
+/// The longest 802.1X identity or username.
+pub const EAP_NAME_LEN: usize = 64;
+
+/// The largest string NVS stores, counting the terminating nul. The whole list is saved as one string.
+const MAX_LIST_LEN: usize = 4000;
+
+/// How to authenticate with a network.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum AuthMode {
+    /// Use whatever the network advertises in the scan.
+    #[default]
+    Auto,
+    Open,
+    Wpa2Personal,
+    Wpa3Personal,
+    Wpa2Wpa3Personal,
+    /// 802.1X, PEAP or EAP-TLS.
+    Wpa2Enterprise,
+}
+
+static AUTH_MODES: [AuthMode; 6] = [
+    AuthMode::Auto,
+    AuthMode::Open,
+    AuthMode::Wpa2Personal,
+    AuthMode::Wpa3Personal,
+    AuthMode::Wpa2Wpa3Personal,
+    AuthMode::Wpa2Enterprise,
+];
+
+impl AuthMode {
+    pub fn iter() -> impl Iterator<Item = &'static AuthMode> {
+        AUTH_MODES.iter()
+    }
+
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            AuthMode::Auto => "auto",
+            AuthMode::Open => "open",
+            AuthMode::Wpa2Personal => "wpa2",
+            AuthMode::Wpa3Personal => "wpa3",
+            AuthMode::Wpa2Wpa3Personal => "wpa2_wpa3",
+            AuthMode::Wpa2Enterprise => "wpa2_enterprise",
+        }
+    }
+
+    /// Parses the names from `to_str`, an empty string is `Auto`.
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        if s.is_empty() {
+            return Some(AuthMode::Auto);
+        }
+        AUTH_MODES.iter().find(|mode| mode.to_str() == s).copied()
+    }
+
+    /// The auth method to ask the driver for. `Auto` takes the one seen in the scan, or guesses from whether
+    /// there is a password if the network wasn't seen.
+    pub fn resolve(&self, scanned: Option<AuthMethod>, password: &str) -> AuthMethod {
+        match self {
+            AuthMode::Auto => match scanned {
+                Some(auth_method) => auth_method,
+                None if password.is_empty() => AuthMethod::None,
+                None => AuthMethod::WPA2Personal,
+            },
+            AuthMode::Open => AuthMethod::None,
+            AuthMode::Wpa2Personal => AuthMethod::WPA2Personal,
+            AuthMode::Wpa3Personal => AuthMethod::WPA3Personal,
+            AuthMode::Wpa2Wpa3Personal => AuthMethod::WPA2WPA3Personal,
+            AuthMode::Wpa2Enterprise => AuthMethod::WPA2Enterprise,
+        }
+    }
+}
+
+/// True for the auth methods which need 802.1X credentials rather than a pre-shared key. embedded-svc has no
+/// WPA3-Enterprise methods, so this is only WPA2-Enterprise.
+pub fn is_enterprise(auth_method: AuthMethod) -> bool {
+    matches!(auth_method, AuthMethod::WPA2Enterprise)
+}
+
+/// A network we can join. Higher priority networks are preferred, between networks of the same priority the
+/// strongest signal wins.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SavedNetwork {
+    pub ssid: String,
+    /// The pre-shared key, or the 802.1X password for enterprise networks.
+    pub password: String,
+    pub priority: i32,
+    #[serde(default)]
+    pub auth: AuthMode,
+    /// The 802.1X outer identity, for enterprise networks.
+    #[serde(default)]
+    pub identity: String,
+    /// The 802.1X username, for enterprise networks.
+    #[serde(default)]
+    pub username: String,
+}
+
+impl SavedNetwork {
+    pub fn new(ssid: &str, password: &str, priority: i32, auth: AuthMode) -> anyhow::Result<Self> {
+        if ssid.is_empty() || ssid.len() > SSID_LEN {
+            anyhow::bail!("SSID must be 1 to {} bytes long", SSID_LEN);
+        }
+        if password.len() > PASSWORD_LEN {
+            anyhow::bail!("Password too long: max length is {}", PASSWORD_LEN);
+        }
+        match auth {
+            AuthMode::Open if !password.is_empty() => anyhow::bail!("Open networks don't have a password"),
+            AuthMode::Wpa2Enterprise => {},
+            _ if !password.is_empty() && password.len() < 8 => {
+                anyhow::bail!("Password must be at least 8 characters, or empty for an open network");
+            },
+            _ => {},
+        }
+        Ok(Self {
+            ssid: ssid.to_string(),
+            password: password.to_string(),
+            priority,
+            auth,
+            identity: String::new(),
+            username: String::new(),
+        })
+    }
+
+    /// Sets the 802.1X identity and username, the username defaults to the identity.
+    pub fn with_eap(mut self, identity: &str, username: &str) -> anyhow::Result<Self> {
+        if identity.len() > EAP_NAME_LEN || username.len() > EAP_NAME_LEN {
+            anyhow::bail!("Identity and username must be at most {} bytes long", EAP_NAME_LEN);
+        }
+        self.identity = identity.to_string();
+        self.username = if username.is_empty() { identity.to_string() } else { username.to_string() };
+        Ok(self)
+    }
+}
+
+/// A saved network to try, with the strongest access point the last scan found for it.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub network: SavedNetwork,
+    /// Signal strength in dBm, None if the network wasn't seen, which could mean it is hidden.
+    pub rssi: Option<i8>,
+    pub bssid: Option<[u8; 6]>,
+    pub channel: Option<u8>,
+    /// The auth method the network advertises.
+    pub auth_method: Option<AuthMethod>,
+}
+
+/// Orders the saved networks by how good a choice they are given a scan.
+///
+/// Networks seen in the scan come first, by priority and then signal strength. Networks which weren't seen are
+/// left at the end, by priority, as they may be hidden. Where an SSID is saved more than once the first wins.
+pub fn rank(saved: &[SavedNetwork], scan: &[AccessPointInfo]) -> Vec<Candidate> {
+    let mut candidates: Vec<Candidate> = Vec::new();
+
+    for network in saved {
+        if candidates.iter().any(|candidate| candidate.network.ssid == network.ssid) {
+            continue;
+        }
+
+        let strongest = scan.iter()
+            .filter(|ap| ap.ssid.as_str() == network.ssid)
+            .max_by_key(|ap| ap.signal_strength);
+
+        candidates.push(Candidate {
+            network: network.clone(),
+            rssi: strongest.map(|ap| ap.signal_strength),
+            bssid: strongest.map(|ap| ap.bssid),
+            channel: strongest.map(|ap| ap.channel),
+            auth_method: strongest.and_then(|ap| ap.auth_method),
+        });
+    }
+
+    // The sort is stable, so unseen networks of the same priority stay in list order.
+    candidates.sort_by_key(|candidate| (candidate.rssi.is_none(), Reverse(candidate.network.priority), Reverse(candidate.rssi)));
+    candidates
+}
+
+/// How much stronger, in dB, another network of the same priority must be before we move to it.
+pub const ROAM_MARGIN_DB: i32 = 10;
+
+/// True if `best` is enough of an improvement on the `current` network to be worth dropping the connection for.
+pub fn should_roam(current: &Candidate, best: &Candidate) -> bool {
+    if best.network.ssid == current.network.ssid {
+        return false;
+    }
+    let Some(best_rssi) = best.rssi else {
+        return false;
+    };
+    let Some(current_rssi) = current.rssi else {
+        // We can no longer see the current network at all.
+        return true;
+    };
+
+    best.network.priority > current.network.priority
+        || (best.network.priority == current.network.priority && best_rssi as i32 >= current_rssi as i32 + ROAM_MARGIN_DB)
+}
+
+/// A network seen in a scan, as listed on the config page and by `/scan`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ScannedNetwork {
+    pub ssid: String,
+    /// Signal strength in dBm.
+    pub rssi: i8,
+    pub channel: u8,
+    /// None if the driver didn't say, `/scan` gives it as "Unknown".
+    #[serde(serialize_with = "serialize_auth")]
+    pub auth: Option<AuthMethod>,
+}
+
+/// The name of an auth method for people, as shown on the config page and by `/scan`.
+pub fn auth_name(auth: Option<AuthMethod>) -> &'static str {
+    match auth {
+        None => "Unknown",
+        Some(AuthMethod::None) => "Open",
+        Some(AuthMethod::WEP) => "WEP",
+        Some(AuthMethod::WPA) => "WPA",
+        Some(AuthMethod::WPA2Personal) => "WPA2 Personal",
+        Some(AuthMethod::WPAWPA2Personal) => "WPA & WPA2 Personal",
+        Some(AuthMethod::WPA2Enterprise) => "WPA2 Enterprise",
+        Some(AuthMethod::WPA3Personal) => "WPA3 Personal",
+        Some(AuthMethod::WPA2WPA3Personal) => "WPA2 & WPA3 Personal",
+        Some(AuthMethod::WAPIPersonal) => "WAPI Personal",
+    }
+}
+
+fn serialize_auth<S: serde::Serializer>(auth: &Option<AuthMethod>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(auth_name(*auth))
+}
+
+impl ScannedNetwork {
+    /// Signal strength as 0 to 4 bars.
+    pub fn bars(&self) -> u8 {
+        match self.rssi {
+            -55.. => 4,
+            -67..=-56 => 3,
+            -75..=-68 => 2,
+            -85..=-76 => 1,
+            _ => 0,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.auth == Some(AuthMethod::None)
+    }
+}
+
+/// The networks in a scan, strongest first, with one entry per SSID. Hidden networks have no SSID so are left out.
+pub fn visible_networks(scan: &[AccessPointInfo]) -> Vec<ScannedNetwork> {
+    let mut networks: Vec<ScannedNetwork> = Vec::new();
+
+    for ap in scan {
+        if ap.ssid.is_empty() {
+            continue;
+        }
+        if let Some(existing) = networks.iter_mut().find(|network| network.ssid == ap.ssid.as_str()) {
+            if ap.signal_strength > existing.rssi {
+                existing.rssi = ap.signal_strength;
+                existing.channel = ap.channel;
+            }
+            continue;
+        }
+        networks.push(ScannedNetwork {
+            ssid: ap.ssid.to_string(),
+            rssi: ap.signal_strength,
+            channel: ap.channel,
+            auth: ap.auth_method,
+        });
+    }
+
+    networks.sort_by_key(|network| Reverse(network.rssi));
+    networks
+}
+
+/// The networks seen in the last scan, shared between the WiFi manager, which owns the radio and scans, and the
+/// pages which list them. A page can ask for a fresh scan with `refresh`, the main loop waits for those requests in
+/// `wait_for_request` and answers them with `update`.
+#[derive(Default)]
+pub struct ScanResults {
+    state: Mutex<ScanState>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct ScanState {
+    networks: Vec<ScannedNetwork>,
+    requested: bool,
+}
+
+impl ScanResults {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The networks from the last scan.
+    pub fn networks(&self) -> Vec<ScannedNetwork> {
+        self.state.lock().unwrap().networks.clone()
+    }
+
+    /// Asks for a fresh scan and waits up to `timeout` for it. Returns the last networks seen, which are the old
+    /// ones if the scan didn't finish in time.
+    pub fn refresh(&self, timeout: Duration) -> Vec<ScannedNetwork> {
+        let mut state = self.state.lock().unwrap();
+        state.requested = true;
+        self.changed.notify_all();
+        let (state, _) = self.changed.wait_timeout_while(state, timeout, |state| state.requested).unwrap();
+        state.networks.clone()
+    }
+
+    /// Waits up to `timeout` for a `refresh`. Returns true if a scan is wanted.
+    pub fn wait_for_request(&self, timeout: Duration) -> bool {
+        let state = self.state.lock().unwrap();
+        let (state, _) = self.changed.wait_timeout_while(state, timeout, |state| !state.requested).unwrap();
+        state.requested
+    }
+
+    /// Records the networks from a scan, which answers any `refresh` waiting for one.
+    pub fn update(&self, networks: Vec<ScannedNetwork>) {
+        let mut state = self.state.lock().unwrap();
+        state.networks = networks;
+        state.requested = false;
+        self.changed.notify_all();
+    }
+
+    /// Gives up on a scan, the waiting `refresh` calls get the old networks.
+    pub fn scan_failed(&self) {
+        self.state.lock().unwrap().requested = false;
+        self.changed.notify_all();
+    }
+}
+
+/// Writes the scanned networks as a list to pick from, which fills in the text input `input_id`. The last entry
+/// is for a hidden network, which clears the input so the name can be typed in. SSIDs come from whatever is
+/// broadcasting nearby, so they are escaped and the handlers only read them back from the escaped `value`.
+#[cfg(target_os = "espidf")]
+pub fn create_scan_list(resp: &mut esp_idf_svc::http::server::Response<&mut EspHttpConnection<'_>>, networks: &[ScannedNetwork], input_id: &str) -> anyhow::Result<()> {
+    resp.write(r#"
+                        <h2>Nearby networks</h2>
+                        <div class="networks">"#.as_bytes())?;
+
+    if networks.is_empty() {
+        resp.write(r#"
+                            <p>No networks found.</p>"#.as_bytes())?;
+    }
+
+    let input_id = escape_html(input_id);
+    for network in networks {
+        let ssid = escape_html(&network.ssid);
+        let channel = network.channel;
+        let auth = auth_name(network.auth);
+        let bars = network.bars() as usize;
+        let on = "&#9646;".repeat(bars);
+        let off = "&#9646;".repeat(4 - bars);
+        resp.write(format!(r#"
+                            <label><input type="radio" name="scan_pick" value="{ssid}" onclick="document.getElementById('{input_id}').value=this.value">
+                                <span class="signal">{on}<span class="off">{off}</span></span> {ssid} <small>ch {channel}, {auth}</small></label>"#).as_bytes())?;
+    }
+
+    resp.write(format!(r#"
+                            <label><input type="radio" name="scan_pick" value="" onclick="var i=document.getElementById('{input_id}');i.value='';i.focus()">
+                                Hidden network, enter its name below</label>
+                        </div>"#).as_bytes())?;
+
+    Ok(())
+}
+
+/// Writes a datalist of the scanned networks, for text inputs with `list="nearby_networks"`.
+#[cfg(target_os = "espidf")]
+pub fn create_scan_datalist(resp: &mut esp_idf_svc::http::server::Response<&mut EspHttpConnection<'_>>, networks: &[ScannedNetwork]) -> anyhow::Result<()> {
+    resp.write(r#"
+                        <datalist id="nearby_networks">"#.as_bytes())?;
+    for network in networks {
+        resp.write(format!(r#"<option value="{}">"#, escape_html(&network.ssid)).as_bytes())?;
+    }
+    resp.write(r#"</datalist>"#.as_bytes())?;
+    Ok(())
+}
+
+/// The list of saved networks, kept in its own NVS namespace as JSON.
+pub struct NetworkList {
+    networks: Vec<SavedNetwork>,
+    store: Box<dyn ConfigStore>,
+}
+
+impl NetworkList {
+    pub fn new(store: Box<dyn ConfigStore>) -> anyhow::Result<Self> {
+        let networks = match store.get_str(NETWORKS_KEY, MAX_LIST_LEN) {
+            Ok(Some(json)) => serde_json::from_str(&json).unwrap_or_else(|e| {
+                log::error!("Failed to parse saved networks, ignoring them: {}", e);
+                Vec::new()
+            }),
+            Ok(None) => Vec::new(),
+            Err(e) => {
+                log::error!("Failed to read saved networks: {}", e);
+                Vec::new()
+            },
+        };
+        info!("Loaded {} saved networks", networks.len());
+
+        Ok(Self {
+            networks,
+            store,
+        })
+    }
+
+    pub fn networks(&self) -> &[SavedNetwork] {
+        &self.networks
+    }
+
+    /// Saves a network, replacing any saved network with the same SSID. Nothing changes if the list would be too
+    /// big to store.
+    pub fn add(&mut self, network: SavedNetwork) -> anyhow::Result<()> {
+        let mut networks = self.networks.clone();
+        if let Some(existing) = networks.iter_mut().find(|existing| existing.ssid == network.ssid) {
+            info!("Updating saved network {}", network.ssid);
+            *existing = network;
+        }
+        else if networks.len() >= MAX_NETWORKS {
+            anyhow::bail!("Too many saved networks: max is {}", MAX_NETWORKS);
+        }
+        else {
+            info!("Adding saved network {}", network.ssid);
+            networks.push(network);
+        }
+        self.save(networks)
+    }
+
+    pub fn remove(&mut self, index: usize) -> anyhow::Result<()> {
+        if index >= self.networks.len() {
+            anyhow::bail!("No saved network {}", index);
+        }
+        let mut networks = self.networks.clone();
+        let network = networks.remove(index);
+        info!("Removed saved network {}", network.ssid);
+        self.save(networks)
+    }
+
+    /// Replaces the whole list, for a config import. Nothing changes if the list is too big to store.
+    pub fn replace(&mut self, networks: Vec<SavedNetwork>) -> anyhow::Result<()> {
+        Self::check(&networks)?;
+        self.save(networks)
+    }
+
+    /// Checks `networks` isn't too big to be the list.
+    pub fn check(networks: &[SavedNetwork]) -> anyhow::Result<()> {
+        if networks.len() > MAX_NETWORKS {
+            anyhow::bail!("Too many saved networks: max is {}", MAX_NETWORKS);
+        }
+        Self::to_json(networks).map(|_| ())
+    }
+
+    pub fn erase(&mut self) -> anyhow::Result<()> {
+        self.networks.clear();
+        self.store.erase_all()?;
+        Ok(())
+    }
+
+    /// Stores `networks` and makes them the list. A list which wouldn't read back into `MAX_LIST_LEN` is
+    /// refused, rather than saved and lost at the next boot.
+    fn save(&mut self, networks: Vec<SavedNetwork>) -> anyhow::Result<()> {
+        let json = Self::to_json(&networks)?;
+        self.store.set_str(NETWORKS_KEY, &json)?;
+        self.networks = networks;
+        Ok(())
+    }
+
+    fn to_json(networks: &[SavedNetwork]) -> anyhow::Result<String> {
+        let json = serde_json::to_string(networks)?;
+        if json.len() + 1 > MAX_LIST_LEN {
+            anyhow::bail!("The saved networks would take {} bytes, more than the {} which can be stored", json.len() + 1, MAX_LIST_LEN);
+        }
+        Ok(json)
+    }
+
+    #[cfg(target_os = "espidf")]
+    pub fn create_config_page(&self, resp: &mut esp_idf_svc::http::server::Response<&mut EspHttpConnection<'_>>) -> anyhow::Result<()> {
+        resp.write(r#"
+                        <h2>Saved networks</h2>
+                        <p>The network above is always tried. Higher priority networks are preferred, otherwise the strongest signal wins.</p>"#.as_bytes())?;
+
+        for (index, network) in self.networks.iter().enumerate() {
+            let ssid = escape_html(&network.ssid);
+            let priority = network.priority;
+            let security = match network.auth {
+                AuthMode::Auto if network.password.is_empty() => "open",
+                AuthMode::Auto => "secured",
+                auth => auth.to_str(),
+            };
+            resp.write(format!(r#"
+                        <form method="POST" action="/remove_network">
+                            <label>{ssid} (priority {priority}, {security})</label>
+                            <input name="index" type="hidden" value="{index}">
+                            <button type="submit">Remove</button>
+                        </form>"#).as_bytes())?;
+        }
+
+        if self.networks.len() < MAX_NETWORKS {
+            let auth_options: String = AuthMode::iter()
+                .map(|mode| format!(r#"<option value="{}">{}</option>"#, mode.to_str(), mode.to_str()))
+                .collect();
+            resp.write(format!(r#"
+                        <form method="POST" action="/add_network">
+                            <label for="network_ssid">ssid</label>
+                            <input id="network_ssid" name="network_ssid" type="text" maxlength="{SSID_LEN}" autocomplete="off" list="nearby_networks">
+                            <label for="network_password">password</label>
+                            <input id="network_password" name="network_password" type="password" maxlength="{PASSWORD_LEN}" autocomplete="new-password">
+                            <label for="network_priority">priority</label>
+                            <input id="network_priority" name="network_priority" type="number" value="0">
+                            <label for="network_auth">auth</label>
+                            <select id="network_auth" name="network_auth">{auth_options}</select>
+                            <label for="network_identity">identity (enterprise only)</label>
+                            <input id="network_identity" name="network_identity" type="text" maxlength="{EAP_NAME_LEN}" autocomplete="off">
+                            <label for="network_username">username (enterprise only, defaults to the identity)</label>
+                            <input id="network_username" name="network_username" type="text" maxlength="{EAP_NAME_LEN}" autocomplete="off">
+                            <button type="submit">Add network</button>
+                        </form>"#).as_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(ssid: &str, priority: i32) -> SavedNetwork {
+        SavedNetwork::new(ssid, "password", priority, AuthMode::Auto).unwrap()
+    }
+
+    fn ap(ssid: &str, rssi: i8, channel: u8) -> AccessPointInfo {
+        AccessPointInfo {
+            ssid: ssid.try_into().unwrap(),
+            bssid: [0, 0, 0, 0, 0, channel],
+            channel,
+            signal_strength: rssi,
+            auth_method: Some(AuthMethod::WPA2Personal),
+            ..Default::default()
+        }
+    }
+
+    fn ssids(candidates: &[Candidate]) -> Vec<&str> {
+        candidates.iter().map(|candidate| candidate.network.ssid.as_str()).collect()
+    }
+
+    fn candidate(ssid: &str, priority: i32, rssi: Option<i8>) -> Candidate {
+        Candidate {
+            network: network(ssid, priority),
+            rssi,
+            bssid: None,
+            channel: None,
+            auth_method: None,
+        }
+    }
+
+    #[test]
+    fn ranks_by_priority_then_signal() {
+        let saved = [network("weak", 0), network("strong", 0), network("preferred", 1)];
+        let scan = [ap("weak", -80, 1), ap("strong", -50, 6), ap("preferred", -85, 11)];
+        assert_eq!(ssids(&rank(&saved, &scan)), ["preferred", "strong", "weak"]);
+    }
+
+    #[test]
+    fn takes_the_strongest_access_point_of_a_network() {
+        let scan = [ap("home", -70, 1), ap("home", -40, 6), ap("home", -60, 11)];
+        let candidates = rank(&[network("home", 0)], &scan);
+        assert_eq!(candidates[0].rssi, Some(-40));
+        assert_eq!(candidates[0].channel, Some(6));
+        assert_eq!(candidates[0].bssid, Some([0, 0, 0, 0, 0, 6]));
+        assert_eq!(candidates[0].auth_method, Some(AuthMethod::WPA2Personal));
+    }
+
+    #[test]
+    fn unseen_networks_go_last_in_list_order() {
+        let saved = [network("hidden_a", 5), network("seen", 0), network("hidden_b", 5), network("hidden_c", 9)];
+        let candidates = rank(&saved, &[ap("seen", -90, 1)]);
+        assert_eq!(ssids(&candidates), ["seen", "hidden_c", "hidden_a", "hidden_b"]);
+        assert_eq!(candidates[1].rssi, None);
+        assert_eq!(candidates[1].channel, None);
+    }
+
+    #[test]
+    fn first_of_a_duplicate_ssid_wins() {
+        let saved = [network("home", 1), network("home", 7)];
+        let candidates = rank(&saved, &[ap("home", -50, 1)]);
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].network.priority, 1);
+    }
+
+    #[test]
+    fn roams_for_a_much_stronger_network() {
+        let current = candidate("current", 0, Some(-70));
+        assert!(should_roam(&current, &candidate("better", 0, Some((-70 + ROAM_MARGIN_DB) as i8))));
+        assert!(!should_roam(&current, &candidate("better", 0, Some((-70 + ROAM_MARGIN_DB - 1) as i8))));
+        // A lower priority network is never worth moving to, however strong.
+        assert!(!should_roam(&current, &candidate("lower", -1, Some(-20))));
+    }
+
+    #[test]
+    fn roams_for_a_higher_priority_network() {
+        let current = candidate("current", 0, Some(-40));
+        assert!(should_roam(&current, &candidate("preferred", 1, Some(-85))));
+        // Unless it wasn't seen.
+        assert!(!should_roam(&current, &candidate("preferred", 1, None)));
+    }
+
+    #[test]
+    fn roams_when_the_current_network_is_gone() {
+        let current = candidate("current", 5, None);
+        assert!(should_roam(&current, &candidate("other", 0, Some(-90))));
+        assert!(!should_roam(&current, &candidate("other", 0, None)));
+        // Nothing to do when the best network is the one we are on.
+        assert!(!should_roam(&current, &candidate("current", 5, Some(-30))));
+    }
+
+    #[test]
+    fn checks_saved_network_limits() {
+        assert!(SavedNetwork::new(&"s".repeat(SSID_LEN), &"p".repeat(PASSWORD_LEN), 0, AuthMode::Auto).is_ok());
+        assert!(SavedNetwork::new("", "password", 0, AuthMode::Auto).is_err());
+        assert!(SavedNetwork::new(&"s".repeat(SSID_LEN + 1), "password", 0, AuthMode::Auto).is_err());
+        assert!(SavedNetwork::new("home", &"p".repeat(PASSWORD_LEN + 1), 0, AuthMode::Auto).is_err());
+        assert!(SavedNetwork::new("home", "short", 0, AuthMode::Wpa2Personal).is_err());
+        assert!(SavedNetwork::new("home", "", 0, AuthMode::Auto).is_ok());
+        assert!(SavedNetwork::new("cafe", "password", 0, AuthMode::Open).is_err());
+        // 802.1X passwords can be any length.
+        assert!(SavedNetwork::new("work", "pw", 0, AuthMode::Wpa2Enterprise).is_ok());
+    }
+
+    #[test]
+    fn checks_eap_limits() {
+        let work = SavedNetwork::new("work", "pw", 0, AuthMode::Wpa2Enterprise).unwrap();
+        let eap = work.clone().with_eap("anonymous@example.com", "").unwrap();
+        assert_eq!(eap.username, "anonymous@example.com");
+        let eap = work.clone().with_eap("anonymous@example.com", "alice").unwrap();
+        assert_eq!(eap.username, "alice");
+
+        assert!(work.clone().with_eap(&"i".repeat(EAP_NAME_LEN), &"u".repeat(EAP_NAME_LEN)).is_ok());
+        assert!(work.clone().with_eap(&"i".repeat(EAP_NAME_LEN + 1), "").is_err());
+        assert!(work.with_eap("anonymous", &"u".repeat(EAP_NAME_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn lists_visible_networks_strongest_first() {
+        let mut open = ap("cafe", -80, 11);
+        open.auth_method = Some(AuthMethod::None);
+        let mut unknown = ap("mystery", -60, 3);
+        unknown.auth_method = None;
+        let scan = [ap("home", -70, 1), open, ap("", -30, 6), ap("home", -50, 6), unknown];
+
+        let networks = visible_networks(&scan);
+        let listed: Vec<(&str, i8, u8)> = networks.iter().map(|network| (network.ssid.as_str(), network.rssi, network.channel)).collect();
+        assert_eq!(listed, [("home", -50, 6), ("mystery", -60, 3), ("cafe", -80, 11)]);
+        assert!(networks[2].is_open());
+        assert!(!networks[0].is_open());
+        assert!(!networks[1].is_open());
+    }
+
+    #[test]
+    fn serializes_the_auth_method_by_name() {
+        let mut unknown = ap("mystery", -60, 3);
+        unknown.auth_method = None;
+        let json = serde_json::to_value(visible_networks(&[ap("home", -50, 6), unknown])).unwrap();
+        assert_eq!(json, serde_json::json!([
+            { "ssid": "home", "rssi": -50, "channel": 6, "auth": "WPA2 Personal" },
+            { "ssid": "mystery", "rssi": -60, "channel": 3, "auth": "Unknown" },
+        ]));
+    }
+
+    #[test]
+    fn refresh_waits_for_a_scan() {
+        let scan_results = std::sync::Arc::new(ScanResults::new());
+        let scanner = {
+            let scan_results = scan_results.clone();
+            std::thread::spawn(move || {
+                assert!(scan_results.wait_for_request(Duration::from_secs(10)));
+                scan_results.update(visible_networks(&[ap("home", -50, 6)]));
+            })
+        };
+
+        let networks = scan_results.refresh(Duration::from_secs(10));
+        scanner.join().unwrap();
+        assert_eq!(networks.len(), 1);
+        assert_eq!(scan_results.networks(), networks);
+        assert!(!scan_results.wait_for_request(Duration::ZERO));
+    }
+
+    #[test]
+    fn refresh_gives_the_old_networks_without_a_scan() {
+        let scan_results = ScanResults::new();
+        assert!(!scan_results.wait_for_request(Duration::from_millis(1)));
+        scan_results.update(visible_networks(&[ap("home", -50, 6)]));
+
+        assert_eq!(scan_results.refresh(Duration::from_millis(1)).len(), 1);
+        // The request is still there for the main loop to pick up.
+        assert!(scan_results.wait_for_request(Duration::ZERO));
+        scan_results.scan_failed();
+        assert!(!scan_results.wait_for_request(Duration::ZERO));
+        assert_eq!(scan_results.networks().len(), 1);
+    }
+}
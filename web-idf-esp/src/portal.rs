@@ -0,0 +1,100 @@
+use std::net::{Ipv4Addr, SocketAddrV4, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use log::info;
+
+const DNS_PORT: u16 = 53;
+
+/// How long the server waits for a query before checking whether it should stop.
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The captive portal DNS server, which answers every query with the access point's own address so phones open the
+/// config page when they join. It is bound to the access point address, so it only answers clients of the access
+/// point and not the network the station is on. It stops when dropped.
+pub struct CaptiveDns {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl CaptiveDns {
+    pub fn start(server_addr: Ipv4Addr) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind(SocketAddrV4::new(server_addr, DNS_PORT))
+            .map_err(|e| anyhow::anyhow!("Can't bind the captive portal DNS server to {}: {}", server_addr, e))?;
+        socket.set_read_timeout(Some(POLL_INTERVAL))?;
+        info!("Captive portal DNS server running on {}", server_addr);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let stop_clone = stop.clone();
+        let thread = thread::Builder::new()
+            .name("captive_dns".to_string())
+            .spawn(move || Self::serve(socket, server_addr, &stop_clone))?;
+
+        Ok(Self {
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    fn serve(socket: UdpSocket, server_addr: Ipv4Addr, stop: &AtomicBool) {
+        let mut buf = [0u8; 512];
+        while !stop.load(Ordering::SeqCst) {
+            let (size, src) = match socket.recv_from(&mut buf) {
+                Ok(received) => received,
+                Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => continue,
+                Err(e) => {
+                    log::error!("Captive portal DNS server failed to receive: {}", e);
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                },
+            };
+
+            let Some(response) = build_dns_response(&buf[..size], &server_addr.octets()) else {
+                continue;
+            };
+            if let Err(e) = socket.send_to(&response, src) {
+                log::warn!("Captive portal DNS server failed to answer {}: {}", src, e);
+            }
+        }
+        info!("Captive portal DNS server stopped");
+    }
+}
+
+impl Drop for CaptiveDns {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Answers a DNS query with a single A record for `server_addr`. Returns None for anything which isn't a query.
+fn build_dns_response(query: &[u8], server_addr: &[u8; 4]) -> Option<Vec<u8>> {
+    // A header is 12 bytes, and the QR bit is set in responses.
+    if query.len() < 12 || query[2] & 0x80 != 0 {
+        return None;
+    }
+    let mut resp = query.to_vec();
+
+    resp[2] |= 0x80; // set QR bit (response)
+    resp[3] |= 0x80; // set RA bit (recursion available)
+
+    // Set ANCOUNT to 1 (answer count)
+    resp[6] = 0x00;
+    resp[7] = 0x01;
+
+    resp.extend_from_slice(&[
+        0xc0, 0x0c, // pointer to domain
+        0x00, 0x01, // type A
+        0x00, 0x01, // class IN
+        0x00, 0x00, 0x00, 0x3c, // TTL (60 seconds)
+        0x00, 0x04, // data length (4 bytes for IPv4)
+
+        server_addr[0], server_addr[1], server_addr[2], server_addr[3] // IP address
+    ]);
+
+    Some(resp)
+}
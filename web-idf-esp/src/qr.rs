@@ -0,0 +1,895 @@
+use embedded_graphics::{prelude::*, primitives::Rectangle};
+
+/// Width of the light border around a QR code, in modules, required by the spec.
+pub const QUIET_ZONE: u32 = 4;
+
+const MIN_VERSION: u8 = 1;
+const MAX_VERSION: u8 = 40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EccLevel {
+    Low,
+    Medium,
+    Quartile,
+    High,
+}
+
+impl EccLevel {
+    fn ordinal(self) -> usize {
+        match self {
+            EccLevel::Low => 0,
+            EccLevel::Medium => 1,
+            EccLevel::Quartile => 2,
+            EccLevel::High => 3,
+        }
+    }
+
+    fn format_bits(self) -> u32 {
+        match self {
+            EccLevel::Low => 1,
+            EccLevel::Medium => 0,
+            EccLevel::Quartile => 3,
+            EccLevel::High => 2,
+        }
+    }
+}
+
+/// A QR code symbol encoded in byte mode.
+///
+/// This is a small self contained encoder so that we don't need to pull in an allocator heavy crate just to show a
+/// join code on the display. The module grid is stored row major with `true` meaning a dark module.
+#[derive(Debug, Clone)]
+pub struct QrCode {
+    version: u8,
+    size: u32,
+    ecc: EccLevel,
+    mask: u8,
+    modules: Vec<bool>,
+    is_function: Vec<bool>,
+}
+
+impl QrCode {
+    /// Encodes the given text using the smallest version which fits at the given error correction level.
+    pub fn encode_text(text: &str, ecc: EccLevel) -> anyhow::Result<Self> {
+        Self::encode_bytes(text.as_bytes(), ecc)
+    }
+
+    /// Encodes the given bytes using the smallest version which fits at the given error correction level.
+    pub fn encode_bytes(data: &[u8], ecc: EccLevel) -> anyhow::Result<Self> {
+        let mut version = MIN_VERSION;
+        loop {
+            let capacity_bits = num_data_codewords(version, ecc) * 8;
+            if data_bit_len(version, data.len()) <= capacity_bits {
+                break;
+            }
+            if version >= MAX_VERSION {
+                anyhow::bail!("Data too long for a QR code: {} bytes", data.len());
+            }
+            version += 1;
+        }
+
+        let codewords = Self::create_data_codewords(version, ecc, data);
+        Ok(Self::from_codewords(version, ecc, &codewords, None))
+    }
+
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn ecc(&self) -> EccLevel {
+        self.ecc
+    }
+
+    pub fn mask(&self) -> u8 {
+        self.mask
+    }
+
+    /// The width (and height) of the symbol in modules, not including the quiet zone.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    /// Returns true if the module at the given position is dark. Positions outside the symbol are light.
+    pub fn get(&self, x: i32, y: i32) -> bool {
+        let size = self.size as i32;
+        if x < 0 || y < 0 || x >= size || y >= size {
+            return false;
+        }
+        self.modules[(y * size + x) as usize]
+    }
+
+    /// Returns the largest module size in pixels which fits the symbol and its quiet zone into the given area.
+    pub fn module_size_for(&self, available: Size) -> u32 {
+        let modules = self.size + 2 * QUIET_ZONE;
+        (available.width.min(available.height) / modules).max(1)
+    }
+
+    /// Draws the symbol, including the quiet zone, with its top left corner at the given point.
+    pub fn draw<D, C>(
+        &self,
+        target: &mut D,
+        top_left: Point,
+        module_size: u32,
+        dark_color: C,
+        light_color: C,
+    ) -> Result<(), D::Error>
+    where
+        C: PixelColor,
+        D: DrawTarget<Color = C>,
+    {
+        let total = (self.size + 2 * QUIET_ZONE) * module_size;
+        target.fill_solid(&Rectangle::new(top_left, Size::new(total, total)), light_color)?;
+
+        let origin = top_left + Point::new((QUIET_ZONE * module_size) as i32, (QUIET_ZONE * module_size) as i32);
+        for y in 0..self.size as i32 {
+            // Draw each horizontal run of dark modules as a single rectangle, this is much
+            // faster than a rectangle per module on an SPI display.
+            let mut x = 0;
+            while x < self.size as i32 {
+                if !self.get(x, y) {
+                    x += 1;
+                    continue;
+                }
+                let start = x;
+                while x < self.size as i32 && self.get(x, y) {
+                    x += 1;
+                }
+                let run = Rectangle::new(
+                    origin + Point::new(start * module_size as i32, y * module_size as i32),
+                    Size::new((x - start) as u32 * module_size, module_size),
+                );
+                target.fill_solid(&run, dark_color)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders the symbol, including the quiet zone, as an SVG image for a web page, `module_size` pixels to a
+    /// module.
+    pub fn to_svg(&self, module_size: u32) -> String {
+        let modules = self.size + 2 * QUIET_ZONE;
+        let mut path = String::new();
+        for y in 0..self.size as i32 {
+            let mut x = 0;
+            while x < self.size as i32 {
+                if !self.get(x, y) {
+                    x += 1;
+                    continue;
+                }
+                let start = x;
+                while x < self.size as i32 && self.get(x, y) {
+                    x += 1;
+                }
+                path.push_str(&format!("M{},{}h{}v1h-{}z", start + QUIET_ZONE as i32, y + QUIET_ZONE as i32, x - start, x - start));
+            }
+        }
+        format!(r##"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{0}" viewBox="0 0 {1} {1}" shape-rendering="crispEdges"><rect width="{1}" height="{1}" fill="#fff"/><path d="{2}" fill="#000"/></svg>"##,
+            modules * module_size, modules, path)
+    }
+
+    /// Draws the symbol as large as possible, centered in the given area.
+    pub fn draw_centered<D, C>(
+        &self,
+        target: &mut D,
+        area: &Rectangle,
+        dark_color: C,
+        light_color: C,
+    ) -> Result<(), D::Error>
+    where
+        C: PixelColor,
+        D: DrawTarget<Color = C>,
+    {
+        let module_size = self.module_size_for(area.size);
+        let total = ((self.size + 2 * QUIET_ZONE) * module_size) as i32;
+        let top_left = area.center() - Point::new(total / 2, total / 2);
+        self.draw(target, top_left, module_size, dark_color, light_color)
+    }
+
+    fn create_data_codewords(version: u8, ecc: EccLevel, data: &[u8]) -> Vec<u8> {
+        let capacity_bits = num_data_codewords(version, ecc) * 8;
+        let mut bits = BitBuffer::new();
+
+        // Byte mode indicator and character count.
+        bits.append(0b0100, 4);
+        bits.append(data.len() as u32, char_count_bits(version));
+        for b in data {
+            bits.append(*b as u32, 8);
+        }
+
+        // Terminator, then pad to a byte boundary.
+        let terminator = (capacity_bits - bits.len()).min(4);
+        bits.append(0, terminator as u8);
+        let padding = (8 - bits.len() % 8) % 8;
+        bits.append(0, padding as u8);
+
+        // Alternate pad bytes until the capacity is reached.
+        let mut pad = 0xEC;
+        while bits.len() < capacity_bits {
+            bits.append(pad, 8);
+            pad ^= 0xEC ^ 0x11;
+        }
+
+        bits.into_bytes()
+    }
+
+    fn from_codewords(version: u8, ecc: EccLevel, data: &[u8], mask: Option<u8>) -> Self {
+        let size = version as u32 * 4 + 17;
+        let mut qr = Self {
+            version,
+            size,
+            ecc,
+            mask: 0,
+            modules: vec![false; (size * size) as usize],
+            is_function: vec![false; (size * size) as usize],
+        };
+
+        qr.draw_function_patterns();
+        let all_codewords = add_ecc_and_interleave(version, ecc, data);
+        qr.draw_codewords(&all_codewords);
+
+        let mask = mask.unwrap_or_else(|| {
+            let mut best = 0;
+            let mut min_penalty = u32::MAX;
+            for candidate in 0..8 {
+                qr.apply_mask(candidate);
+                qr.draw_format_bits(candidate);
+                let penalty = qr.penalty_score();
+                if penalty < min_penalty {
+                    best = candidate;
+                    min_penalty = penalty;
+                }
+                // Masks are XOR so applying again undoes it.
+                qr.apply_mask(candidate);
+            }
+            best
+        });
+
+        qr.mask = mask;
+        qr.apply_mask(mask);
+        qr.draw_format_bits(mask);
+        qr.is_function = Vec::new();
+        qr
+    }
+
+    fn set_function(&mut self, x: i32, y: i32, dark: bool) {
+        let i = (y * self.size as i32 + x) as usize;
+        self.modules[i] = dark;
+        self.is_function[i] = true;
+    }
+
+    fn draw_function_patterns(&mut self) {
+        let size = self.size as i32;
+
+        // Timing patterns.
+        for i in 0..size {
+            self.set_function(6, i, i % 2 == 0);
+            self.set_function(i, 6, i % 2 == 0);
+        }
+
+        // Finder patterns, these overwrite part of the timing patterns.
+        self.draw_finder_pattern(3, 3);
+        self.draw_finder_pattern(size - 4, 3);
+        self.draw_finder_pattern(3, size - 4);
+
+        // Alignment patterns, skipping the three which would overlap the finders.
+        let positions = alignment_pattern_positions(self.version);
+        let count = positions.len();
+        for i in 0..count {
+            for j in 0..count {
+                let overlaps_finder = (i == 0 && (j == 0 || j == count - 1)) || (i == count - 1 && j == 0);
+                if !overlaps_finder {
+                    self.draw_alignment_pattern(positions[i], positions[j]);
+                }
+            }
+        }
+
+        // Reserve the format areas now, the real bits are drawn after masking.
+        self.draw_format_bits(0);
+        self.draw_version();
+    }
+
+    fn draw_finder_pattern(&mut self, x: i32, y: i32) {
+        let size = self.size as i32;
+        for dy in -4..=4 {
+            for dx in -4..=4 {
+                let xx = x + dx;
+                let yy = y + dy;
+                if (0..size).contains(&xx) && (0..size).contains(&yy) {
+                    let dist = dx.abs().max(dy.abs());
+                    self.set_function(xx, yy, dist != 2 && dist != 4);
+                }
+            }
+        }
+    }
+
+    fn draw_alignment_pattern(&mut self, x: i32, y: i32) {
+        for dy in -2..=2 {
+            for dx in -2..=2 {
+                self.set_function(x + dx, y + dy, dx.abs().max(dy.abs()) != 1);
+            }
+        }
+    }
+
+    fn draw_format_bits(&mut self, mask: u8) {
+        let data = self.ecc.format_bits() << 3 | mask as u32;
+        let mut rem = data;
+        for _ in 0..10 {
+            rem = (rem << 1) ^ ((rem >> 9) * 0x537);
+        }
+        let bits = (data << 10 | rem) ^ 0x5412;
+        let bit = |i: u32| (bits >> i) & 1 != 0;
+        let size = self.size as i32;
+
+        // First copy, around the top left finder.
+        for i in 0..=5 {
+            self.set_function(8, i, bit(i as u32));
+        }
+        self.set_function(8, 7, bit(6));
+        self.set_function(8, 8, bit(7));
+        self.set_function(7, 8, bit(8));
+        for i in 9..15 {
+            self.set_function(14 - i, 8, bit(i as u32));
+        }
+
+        // Second copy, split between the other two finders.
+        for i in 0..8 {
+            self.set_function(size - 1 - i, 8, bit(i as u32));
+        }
+        for i in 8..15 {
+            self.set_function(8, size - 15 + i, bit(i as u32));
+        }
+        // The dark module.
+        self.set_function(8, size - 8, true);
+    }
+
+    fn draw_version(&mut self) {
+        if self.version < 7 {
+            return;
+        }
+        let mut rem = self.version as u32;
+        for _ in 0..12 {
+            rem = (rem << 1) ^ ((rem >> 11) * 0x1F25);
+        }
+        let bits = (self.version as u32) << 12 | rem;
+        let size = self.size as i32;
+
+        for i in 0..18 {
+            let dark = (bits >> i) & 1 != 0;
+            let a = size - 11 + i % 3;
+            let b = i / 3;
+            self.set_function(a, b, dark);
+            self.set_function(b, a, dark);
+        }
+    }
+
+    fn draw_codewords(&mut self, data: &[u8]) {
+        let size = self.size as i32;
+        let mut i = 0usize;
+
+        // Zig zag up and down the symbol in two column strips, from the right, skipping the vertical timing column.
+        let mut right = size - 1;
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+            for vert in 0..size {
+                for j in 0..2 {
+                    let x = right - j;
+                    let upward = (right + 1) & 2 == 0;
+                    let y = if upward { size - 1 - vert } else { vert };
+                    let index = (y * size + x) as usize;
+                    if !self.is_function[index] && i < data.len() * 8 {
+                        self.modules[index] = (data[i >> 3] >> (7 - (i & 7))) & 1 != 0;
+                        i += 1;
+                    }
+                    // Any remainder bits are left light.
+                }
+            }
+            right -= 2;
+        }
+    }
+
+    fn apply_mask(&mut self, mask: u8) {
+        let size = self.size as i32;
+        for y in 0..size {
+            for x in 0..size {
+                let invert = match mask {
+                    0 => (x + y) % 2 == 0,
+                    1 => y % 2 == 0,
+                    2 => x % 3 == 0,
+                    3 => (x + y) % 3 == 0,
+                    4 => (x / 3 + y / 2) % 2 == 0,
+                    5 => x * y % 2 + x * y % 3 == 0,
+                    6 => (x * y % 2 + x * y % 3) % 2 == 0,
+                    _ => ((x + y) % 2 + x * y % 3) % 2 == 0,
+                };
+                let index = (y * size + x) as usize;
+                if invert && !self.is_function[index] {
+                    self.modules[index] = !self.modules[index];
+                }
+            }
+        }
+    }
+
+    /// The lengths of the alternating light and dark runs along a row, or a column if `transpose`, starting and
+    /// ending with light. Everything outside the symbol is light, so the first and last runs include a symbol's
+    /// width of it.
+    fn runs(&self, a: i32, transpose: bool) -> Vec<u32> {
+        let size = self.size as i32;
+        let mut runs = vec![self.size];
+        let mut run_color = false;
+        for b in 0..size {
+            let dark = if transpose { self.get(a, b) } else { self.get(b, a) };
+            if dark != run_color {
+                runs.push(0);
+                run_color = dark;
+            }
+            *runs.last_mut().unwrap() += 1;
+        }
+        if run_color {
+            runs.push(0);
+        }
+        *runs.last_mut().unwrap() += self.size;
+        runs
+    }
+
+    fn penalty_score(&self) -> u32 {
+        const N1: u32 = 3;
+        const N2: u32 = 3;
+        const N3: u32 = 40;
+        const N4: u32 = 10;
+
+        let size = self.size as i32;
+        let mut result = 0;
+
+        // Runs of 5 or more modules of the same colour, in rows then columns.
+        for transpose in [false, true] {
+            for a in 0..size {
+                let mut run_color = false;
+                let mut run_len = 0;
+                for b in 0..size {
+                    let dark = if transpose { self.get(a, b) } else { self.get(b, a) };
+                    if b > 0 && dark == run_color {
+                        run_len += 1;
+                        if run_len == 5 {
+                            result += N1;
+                        } else if run_len > 5 {
+                            result += 1;
+                        }
+                    } else {
+                        run_color = dark;
+                        run_len = 1;
+                    }
+                }
+            }
+        }
+
+        // 2x2 blocks of the same colour.
+        for y in 0..size - 1 {
+            for x in 0..size - 1 {
+                let dark = self.get(x, y);
+                if dark == self.get(x + 1, y) && dark == self.get(x, y + 1) && dark == self.get(x + 1, y + 1) {
+                    result += N2;
+                }
+            }
+        }
+
+        // Finder like patterns, dark and light runs in the ratio 1:1:3:1:1 with four light modules on one side and
+        // at least one on the other. Each side with four counts, as in the reference encoders.
+        for transpose in [false, true] {
+            for a in 0..size {
+                let runs = self.runs(a, transpose);
+                for pattern in runs.windows(7).step_by(2) {
+                    let n = pattern[1];
+                    let core = pattern[2] == n && pattern[3] == n * 3 && pattern[4] == n && pattern[5] == n;
+                    if core && pattern[0] >= n * 4 && pattern[6] >= n {
+                        result += N3;
+                    }
+                    if core && pattern[6] >= n * 4 && pattern[0] >= n {
+                        result += N3;
+                    }
+                }
+            }
+        }
+
+        // Balance of dark and light modules.
+        let dark = self.modules.iter().filter(|m| **m).count() as u32;
+        let total = self.size * self.size;
+        // Smallest k such that (45-5k)% <= dark/total <= (55+5k)%.
+        let k = (dark * 20).abs_diff(total * 10).div_ceil(total) - 1;
+        result += k * N4;
+
+        result
+    }
+}
+
+/// Builds a Wi-Fi join payload in the format understood by the Android and iOS camera apps.
+///
+/// An empty or missing password produces an open network join code.
+pub fn wifi_join_payload(ssid: &str, password: Option<&str>) -> String {
+    let mut payload = String::from("WIFI:S:");
+    escape_wifi_field(&mut payload, ssid);
+    match password {
+        Some(password) if !password.is_empty() => {
+            payload.push_str(";T:WPA;P:");
+            escape_wifi_field(&mut payload, password);
+        },
+        _ => payload.push_str(";T:nopass"),
+    }
+    payload.push_str(";;");
+    payload
+}
+
+/// Builds the URL of the device home page from its mDNS hostname.
+pub fn device_url(hostname: &str) -> String {
+    format!("http://{}.local/", hostname)
+}
+
+fn escape_wifi_field(out: &mut String, value: &str) {
+    for c in value.chars() {
+        if matches!(c, '\\' | ';' | ',' | ':' | '"') {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+}
+
+struct BitBuffer {
+    bits: Vec<bool>,
+}
+
+impl BitBuffer {
+    fn new() -> Self {
+        Self { bits: Vec::new() }
+    }
+
+    fn len(&self) -> usize {
+        self.bits.len()
+    }
+
+    fn append(&mut self, value: u32, len: u8) {
+        for i in (0..len).rev() {
+            self.bits.push((value >> i) & 1 != 0);
+        }
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        self.bits
+            .chunks(8)
+            .map(|chunk| chunk.iter().fold(0u8, |acc, bit| acc << 1 | *bit as u8))
+            .collect()
+    }
+}
+
+fn char_count_bits(version: u8) -> u8 {
+    if version <= 9 { 8 } else { 16 }
+}
+
+fn data_bit_len(version: u8, len: usize) -> usize {
+    4 + char_count_bits(version) as usize + len * 8
+}
+
+fn alignment_pattern_positions(version: u8) -> Vec<i32> {
+    if version == 1 {
+        return Vec::new();
+    }
+    let count = version as i32 / 7 + 2;
+    let size = version as i32 * 4 + 17;
+    let step = (version as i32 * 8 + count * 3 + 5) / (count * 4 - 4) * 2;
+    let mut result: Vec<i32> = (0..count - 1).map(|i| size - 7 - i * step).collect();
+    result.push(6);
+    result.reverse();
+    result
+}
+
+fn num_raw_data_modules(version: u8) -> usize {
+    let v = version as usize;
+    let mut result = (16 * v + 128) * v + 64;
+    if v >= 2 {
+        let count = v / 7 + 2;
+        result -= (25 * count - 10) * count - 55;
+        if v >= 7 {
+            result -= 36;
+        }
+    }
+    result
+}
+
+fn num_data_codewords(version: u8, ecc: EccLevel) -> usize {
+    let v = version as usize;
+    let e = ecc.ordinal();
+    num_raw_data_modules(version) / 8 - ECC_CODEWORDS_PER_BLOCK[e][v] as usize * NUM_ERROR_CORRECTION_BLOCKS[e][v] as usize
+}
+
+fn add_ecc_and_interleave(version: u8, ecc: EccLevel, data: &[u8]) -> Vec<u8> {
+    let v = version as usize;
+    let e = ecc.ordinal();
+    let num_blocks = NUM_ERROR_CORRECTION_BLOCKS[e][v] as usize;
+    let block_ecc_len = ECC_CODEWORDS_PER_BLOCK[e][v] as usize;
+    let raw_codewords = num_raw_data_modules(version) / 8;
+    let num_short_blocks = num_blocks - raw_codewords % num_blocks;
+    let short_block_len = raw_codewords / num_blocks;
+
+    // Split the data into blocks and append the error correction to each one.
+    let divisor = reed_solomon_divisor(block_ecc_len);
+    let mut blocks: Vec<Vec<u8>> = Vec::with_capacity(num_blocks);
+    let mut k = 0;
+    for i in 0..num_blocks {
+        let data_len = short_block_len - block_ecc_len + if i < num_short_blocks { 0 } else { 1 };
+        let mut block = data[k..k + data_len].to_vec();
+        k += data_len;
+        let ecc_bytes = reed_solomon_remainder(&block, &divisor);
+        if i < num_short_blocks {
+            // Pad short blocks so every block has the same layout when interleaving.
+            block.push(0);
+        }
+        block.extend_from_slice(&ecc_bytes);
+        blocks.push(block);
+    }
+
+    // Interleave the bytes from every block, skipping the padding in the short blocks.
+    let mut result = Vec::with_capacity(raw_codewords);
+    for i in 0..blocks[0].len() {
+        for (j, block) in blocks.iter().enumerate() {
+            if i != short_block_len - block_ecc_len || j >= num_short_blocks {
+                result.push(block[i]);
+            }
+        }
+    }
+    result
+}
+
+fn reed_solomon_divisor(degree: usize) -> Vec<u8> {
+    let mut result = vec![0u8; degree];
+    result[degree - 1] = 1;
+
+    let mut root = 1u8;
+    for _ in 0..degree {
+        for j in 0..degree {
+            result[j] = reed_solomon_multiply(result[j], root);
+            if j + 1 < degree {
+                result[j] ^= result[j + 1];
+            }
+        }
+        root = reed_solomon_multiply(root, 0x02);
+    }
+    result
+}
+
+fn reed_solomon_remainder(data: &[u8], divisor: &[u8]) -> Vec<u8> {
+    let mut result = vec![0u8; divisor.len()];
+    for b in data {
+        let factor = b ^ result.remove(0);
+        result.push(0);
+        for (x, y) in result.iter_mut().zip(divisor.iter()) {
+            *x ^= reed_solomon_multiply(*y, factor);
+        }
+    }
+    result
+}
+
+fn reed_solomon_multiply(x: u8, y: u8) -> u8 {
+    // Russian peasant multiplication in GF(2^8) modulo x^8 + x^4 + x^3 + x^2 + 1.
+    let mut z: u32 = 0;
+    for i in (0..8).rev() {
+        z = (z << 1) ^ ((z >> 7) * 0x11D);
+        z ^= ((y as u32 >> i) & 1) * x as u32;
+    }
+    z as u8
+}
+
+const ECC_CODEWORDS_PER_BLOCK: [[u8; 41]; 4] = [
+    [0, 7, 10, 15, 20, 26, 18, 20, 24, 30, 18, 20, 24, 26, 30, 22, 24, 28, 30, 28, 28, 28, 28, 30, 30, 26, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30],
+    [0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26, 26, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28],
+    [0, 13, 22, 18, 26, 18, 24, 18, 22, 20, 24, 28, 26, 24, 20, 30, 24, 28, 28, 26, 30, 28, 30, 30, 30, 30, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30],
+    [0, 17, 28, 22, 16, 22, 28, 26, 26, 24, 28, 24, 28, 22, 24, 24, 30, 28, 28, 26, 28, 30, 24, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30],
+];
+
+const NUM_ERROR_CORRECTION_BLOCKS: [[u8; 41]; 4] = [
+    [0, 1, 1, 1, 1, 1, 2, 2, 2, 2, 4, 4, 4, 4, 4, 6, 6, 6, 6, 7, 8, 8, 9, 9, 10, 12, 12, 12, 13, 14, 15, 16, 17, 18, 19, 19, 20, 21, 22, 24, 25],
+    [0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16, 17, 17, 18, 20, 21, 23, 25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49],
+    [0, 1, 1, 2, 2, 4, 4, 6, 6, 8, 8, 8, 10, 12, 16, 12, 17, 16, 18, 21, 20, 23, 23, 25, 27, 29, 34, 34, 35, 38, 40, 43, 45, 48, 51, 53, 56, 59, 62, 65, 68],
+    [0, 1, 1, 2, 4, 4, 4, 5, 6, 8, 8, 11, 11, 16, 16, 18, 16, 19, 21, 25, 25, 25, 34, 30, 32, 35, 37, 40, 42, 45, 48, 51, 54, 57, 60, 63, 66, 70, 74, 77, 81],
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(qr: &QrCode) -> Vec<String> {
+        (0..qr.size() as i32)
+            .map(|y| (0..qr.size() as i32).map(|x| if qr.get(x, y) { '#' } else { '.' }).collect())
+            .collect()
+    }
+
+    fn encode_with_mask(text: &str, ecc: EccLevel, mask: u8) -> QrCode {
+        let version = QrCode::encode_text(text, ecc).unwrap().version();
+        let codewords = QrCode::create_data_codewords(version, ecc, text.as_bytes());
+        QrCode::from_codewords(version, ecc, &codewords, Some(mask))
+    }
+
+    /// The first copy of the format information, most significant bit first.
+    fn format_bits(qr: &QrCode) -> u32 {
+        let mut positions = Vec::new();
+        for i in (9..15).rev() {
+            positions.push((14 - i, 8));
+        }
+        positions.extend([(7, 8), (8, 8), (8, 7)]);
+        for i in (0..=5).rev() {
+            positions.push((8, i));
+        }
+        positions.iter().fold(0, |bits, (x, y)| bits << 1 | qr.get(*x, *y) as u32)
+    }
+
+    // Reference symbols from Nayuki's qrcodegen, byte mode with boost off.
+
+    #[test]
+    fn matches_reference_join_code() {
+        let qr = QrCode::encode_text("WIFI:S:Sparko;T:nopass;;", EccLevel::Medium).unwrap();
+        assert_eq!(qr.version(), 2);
+        assert_eq!(qr.size(), 25);
+        assert_eq!(qr.mask(), 3);
+        assert_eq!(rows(&qr), [
+            "#######.##.###..#.#######",
+            "#.....#.####.#....#.....#",
+            "#.###.#....#.###..#.###.#",
+            "#.###.#.#######...#.###.#",
+            "#.###.#..##....##.#.###.#",
+            "#.....#..#...#..#.#.....#",
+            "#######.#.#.#.#.#.#######",
+            "........##.##.##.........",
+            "#.##.###.#....##..#..#.##",
+            "..#.##.###.........#....#",
+            ".##.#.####.##.#..#..#.#..",
+            "###.#..#.#.####..#.#.###.",
+            "#..######..#.#########...",
+            "..#.##...#.#....#.###.##.",
+            ".#.##.##....#.......####.",
+            "#.#.##..#.#.#.#......#.#.",
+            "...####.####.#.########..",
+            "........###.#####...#..#.",
+            "#######.#..###..#.#.#####",
+            "#.....#.#.#.#...#...##.#.",
+            "#.###.#...##....#######.#",
+            "#.###.#.###.###.####.#.##",
+            "#.###.#.################.",
+            "#.....#..#.#.##...###.#..",
+            "#######.####.#...#..#.###",
+        ]);
+    }
+
+    #[test]
+    fn matches_reference_with_a_fixed_mask() {
+        let qr = encode_with_mask("hello", EccLevel::Low, 3);
+        assert_eq!(qr.version(), 1);
+        assert_eq!(rows(&qr), [
+            "#######.#.###.#######",
+            "#.....#...##..#.....#",
+            "#.###.#.##.#..#.###.#",
+            "#.###.#.##..#.#.###.#",
+            "#.###.#.#..#..#.###.#",
+            "#.....#..####.#.....#",
+            "#######.#.#.#.#######",
+            "...........##........",
+            "####..#.######..###.#",
+            "#.###...##.####..##.#",
+            "####..#.#.##.......##",
+            "..#.##...###..####.#.",
+            "..##..###...#..#....#",
+            "........#..#..#...#..",
+            "#######..####..#.....",
+            "#.....#..##....#####.",
+            "#.###.#..#..#########",
+            "#.###.#.##.#..#.####.",
+            "#.###.#.#...#.##..#..",
+            "#.....#.#....#.##...#",
+            "#######.#.#..#.#..#..",
+        ]);
+    }
+
+    #[test]
+    fn picks_the_smallest_version_which_fits() {
+        // Byte mode capacities from the spec tables.
+        let cases = [
+            (17, EccLevel::Low, 1),
+            (18, EccLevel::Low, 2),
+            (14, EccLevel::Medium, 1),
+            (15, EccLevel::Medium, 2),
+            (11, EccLevel::Quartile, 1),
+            (7, EccLevel::High, 1),
+            (8, EccLevel::High, 2),
+            (154, EccLevel::Low, 7),
+            (155, EccLevel::Low, 8),
+            (2953, EccLevel::Low, 40),
+        ];
+        for (len, ecc, version) in cases {
+            let qr = QrCode::encode_bytes(&vec![b'a'; len], ecc).unwrap();
+            assert_eq!(qr.version(), version, "{} bytes at {:?}", len, ecc);
+            assert_eq!(qr.size(), version as u32 * 4 + 17);
+            assert_eq!(qr.ecc(), ecc);
+        }
+        assert!(QrCode::encode_bytes(&[b'a'; 2954], EccLevel::Low).is_err());
+    }
+
+    #[test]
+    fn writes_the_format_bits_for_each_mask() {
+        // The format information table from the spec, for levels L and M.
+        let low = [0x77C4, 0x72F3, 0x7DAA, 0x789D, 0x662F, 0x6318, 0x6C41, 0x6976];
+        let medium = [0x5412, 0x5125, 0x5E7C, 0x5B4B, 0x45F9, 0x40CE, 0x4F97, 0x4AA0];
+        for mask in 0..8 {
+            let qr = encode_with_mask("hello", EccLevel::Low, mask);
+            assert_eq!(format_bits(&qr), low[mask as usize], "L mask {}", mask);
+            let qr = encode_with_mask("hello", EccLevel::Medium, mask);
+            assert_eq!(qr.mask(), mask);
+            assert_eq!(format_bits(&qr), medium[mask as usize], "M mask {}", mask);
+        }
+    }
+
+    #[test]
+    fn masks_only_flip_data_modules() {
+        let plain = encode_with_mask("hello", EccLevel::Low, 0);
+        let size = plain.size() as i32;
+        for mask in 1..8 {
+            let masked = encode_with_mask("hello", EccLevel::Low, mask);
+            let mut reference = QrCode::from_codewords(1, EccLevel::Low, &[0; 19], Some(0));
+            reference.is_function = vec![false; (size * size) as usize];
+            reference.draw_function_patterns();
+            for y in 0..size {
+                for x in 0..size {
+                    let index = (y * size + x) as usize;
+                    if reference.is_function[index] {
+                        continue;
+                    }
+                    let flip = |mask| {
+                        let mut grid = QrCode { is_function: vec![false; (size * size) as usize], modules: vec![false; (size * size) as usize], ..plain.clone() };
+                        grid.apply_mask(mask);
+                        grid.modules[index]
+                    };
+                    assert_eq!(masked.get(x, y), plain.get(x, y) ^ flip(0) ^ flip(mask), "mask {} at {},{}", mask, x, y);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn writes_version_information() {
+        let qr = QrCode::encode_bytes(&[b'a'; 154], EccLevel::Low).unwrap();
+        assert_eq!(qr.version(), 7);
+        let size = qr.size() as i32;
+        let mut bottom_left = 0;
+        let mut top_right = 0;
+        for i in (0..18).rev() {
+            bottom_left = bottom_left << 1 | qr.get(i / 3, size - 11 + i % 3) as u32;
+            top_right = top_right << 1 | qr.get(size - 11 + i % 3, i / 3) as u32;
+        }
+        // The version 7 information from the spec.
+        assert_eq!(bottom_left, 0x07C94);
+        assert_eq!(top_right, 0x07C94);
+    }
+
+    #[test]
+    fn computes_error_correction() {
+        // "HELLO WORLD" at 1-M, the worked example in the spec.
+        let data = [32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17];
+        let ecc = reed_solomon_remainder(&data, &reed_solomon_divisor(10));
+        assert_eq!(ecc, [196, 35, 39, 119, 235, 215, 231, 226, 93, 23]);
+    }
+
+    #[test]
+    fn escapes_join_payloads() {
+        assert_eq!(wifi_join_payload("Home", Some("hunter22")), "WIFI:S:Home;T:WPA;P:hunter22;;");
+        assert_eq!(wifi_join_payload("Cafe", None), "WIFI:S:Cafe;T:nopass;;");
+        assert_eq!(wifi_join_payload("Cafe", Some("")), "WIFI:S:Cafe;T:nopass;;");
+        assert_eq!(wifi_join_payload(r#"a\b;c,d:e"f"#, Some(r#"p;w:"\,"#)),
+            r#"WIFI:S:a\\b\;c\,d\:e\"f;T:WPA;P:p\;w\:\"\\\,;;"#);
+    }
+
+    #[test]
+    fn draws_svg_runs() {
+        let qr = QrCode::encode_text("hello", EccLevel::Low).unwrap();
+        let svg = qr.to_svg(4);
+        assert!(svg.starts_with(r#"<svg xmlns="http://www.w3.org/2000/svg" width="116" height="116" viewBox="0 0 29 29""#));
+        // The top row of the top left finder is one run of seven.
+        assert!(svg.contains(r#"d="M4,4h7v1h-7z"#));
+    }
+}
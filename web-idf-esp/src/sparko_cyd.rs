@@ -1,4 +1,4 @@
-use std::{backtrace::Backtrace, net::{IpAddr, UdpSocket}, sync::{Arc, Mutex}, thread};
+use std::{backtrace::Backtrace, net::IpAddr, sync::{Arc, Mutex}, time::{Duration, Instant}};
 
 use esp_idf_hal::{gpio::PinDriver, ledc::LedcDriver};
 use esp_idf_svc::{eventloop::EspSystemEventLoop, hal::peripherals::Peripherals, http::{Method, client::EspHttpConnection, server::EspHttpServer}, nvs::{EspDefaultNvsPartition, EspNvs}, timer::EspTaskTimerService};
@@ -8,12 +8,30 @@ use std::str::FromStr;
 use esp_idf_svc::sntp::*;
 use chrono::{Local, Utc};
 
-use crate::{Feature, config::ConfigManager, http::HttpServerManager, led::LedManager, wifi::WiFiManager};
+use crate::{Feature, api, config::ConfigManager, migration, diagnostics::WifiDiagnostics, http::HttpServerManager, led::{LedManager, LedState}, networks::ScanResults, qr::{EccLevel, QrCode}, supervisor::{Action, Backoff, ConnectionSupervisor}, wifi::{self, WiFiManager}};
 
 
 use esp_idf_sys::*;
 use std::ffi::CStr;
 
+/// How often to rescan in case a better known network has come into range.
+const ROAM_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// The wait before each attempt to reconnect a lost WiFi connection.
+const RECONNECT_BACKOFF: Backoff = Backoff::new(Duration::from_secs(2), Duration::from_secs(5 * 60));
+
+/// Failed reconnects before going back to setup mode when the core config doesn't say.
+const DEFAULT_AP_FALLBACK_AFTER: u32 = 10;
+
+/// The wait before each attempt to join a known network from the setup access point, after the reconnects gave up.
+const AP_RETRY_BACKOFF: Backoff = Backoff::new(Duration::from_secs(60), Duration::from_secs(15 * 60));
+
+/// How often to record the link quality for the WiFi status page.
+const DIAGNOSTICS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long the setup access point stays up alongside the client connection.
+const SETUP_AP_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
 fn list_nvs_keys() {
     info!("Listing NVS keys:");
     unsafe {
@@ -117,6 +135,11 @@ pub struct SparkoCyd {
     pub server_manager: HttpServerManager<'static>,
     features: Vec::<Box<dyn Feature>>,
     pub ap_mode: Arc<Mutex<bool>>,
+    /// Set to bring the setup access point up alongside the client connection, for example from a button.
+    /// It is cleared again after `SETUP_AP_TIMEOUT`.
+    pub setup_ap: Arc<Mutex<bool>>,
+    /// The link quality history shown on /status/wifi.
+    pub diagnostics: Arc<Mutex<WifiDiagnostics>>,
 }
 
 
@@ -147,6 +170,9 @@ impl SparkoCyd {
 
         let failure_reason: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
         let ap_mode = Arc::new(Mutex::new(false));
+        let setup_ap = Arc::new(Mutex::new(false));
+        let scan_results = Arc::new(ScanResults::new());
+        let diagnostics = Arc::new(Mutex::new(WifiDiagnostics::new()));
 
         let peripherals = Peripherals::take()?;
 
@@ -156,7 +182,7 @@ impl SparkoCyd {
             peripherals.ledc.channel1, peripherals.pins.gpio16,
             peripherals.ledc.channel2, peripherals.pins.gpio17)?;
         
-        led_manager.set_color(64, 64, 0)?;
+        led_manager.show(LedState::Starting)?;
 
 
         let sys_loop = EspSystemEventLoop::take()?;
@@ -167,7 +193,7 @@ impl SparkoCyd {
         list_nvs_keys();
 
         let wifi_manager = //wifi::wifi(peripherals.modem, sys_loop,Some(nvs_partition.clone()),timer_service)?;
-            WiFiManager::new(peripherals.modem, sys_loop, nvs_partition.clone(), failure_reason.clone())?;
+            WiFiManager::new(peripherals.modem, sys_loop, nvs_partition.clone(), failure_reason.clone(), scan_results.clone(), diagnostics.clone())?;
 
         // let led_red_pin = PinDriver::output(peripherals.pins.gpio4)?;
         // let led_green_pin = PinDriver::output(peripherals.pins.gpio16)?;
@@ -182,12 +208,16 @@ impl SparkoCyd {
         // let led_channel_blue = Arc::new(Mutex::new(LedcDriver::new(peripherals.ledc.channel2, &led_timer_driver, peripherals.pins.gpio17)?));
         // let led = Arc::new(Mutex::new(led_pin));
 
-        let config_manager = ConfigManager::new(nvs_partition, &features, failure_reason, ap_mode.clone())?;
+        let config_manager = ConfigManager::new(nvs_partition, &features, failure_reason, ap_mode.clone(), setup_ap.clone(), scan_results)?;
         let mut server_manager = HttpServerManager::new()?;
 
         server_manager.init_common_pages()?;
         
         ConfigManager::create_pages(&config_manager, &mut server_manager)?;
+        api::create_pages(&config_manager, &mut server_manager)?;
+        migration::create_pages(&config_manager, &mut server_manager)?;
+        WifiDiagnostics::create_pages(&diagnostics, &mut server_manager)?;
+        wifi::create_pages(&config_manager, &mut server_manager)?;
 
         Ok(Self {
             wifi_manager,
@@ -196,10 +226,32 @@ impl SparkoCyd {
             server_manager,
             features,
             ap_mode,
+            setup_ap,
+            diagnostics,
         })
     }
     
 
+    /// Returns the payload to show as a QR code on a display.
+    ///
+    /// While the setup access point is running this is a Wi-Fi join code for it, otherwise it is a link to the device
+    /// home page.
+    pub fn qr_payload(&self) -> anyhow::Result<String> {
+        if let Some((ssid, password)) = self.wifi_manager.ap_credentials() {
+            Ok(crate::qr::wifi_join_payload(&ssid, password.as_deref()))
+        }
+        else {
+            let hostname = self.config_manager.get_valid_core_config(crate::config::MDNS_HOSTNAME)?;
+            Ok(crate::qr::device_url(&hostname))
+        }
+    }
+
+    /// The `qr_payload` as a QR code for a display. In setup mode this is how someone at the device gets the
+    /// generated access point password, the /setup_ap page shows it too.
+    pub fn qr_code(&self) -> anyhow::Result<QrCode> {
+        QrCode::encode_text(&self.qr_payload()?, EccLevel::Medium)
+    }
+
     pub fn start_client(&mut self) -> anyhow::Result<()> {
 
         // start wifi
@@ -231,12 +283,72 @@ impl SparkoCyd {
 
         let hostname = self.config_manager.get_valid_core_config(crate::config::MDNS_HOSTNAME)?;
 
-        crate::mdns::start_mdns(&hostname, &ip_address)?;
+        let mdns_address = Arc::new(Mutex::new(ip_address));
+        crate::mdns::start_mdns(&hostname, mdns_address.clone())?;
+        info!("Device URL: {}", crate::qr::device_url(&hostname));
 
-        self.led_manager.set_color(0, 64, 0)?;
+        self.led_manager.show(LedState::Connected)?;
+        let mut supervisor = ConnectionSupervisor::new(RECONNECT_BACKOFF, self.ap_fallback_after());
+        let mut last_roam = Instant::now();
+        let mut last_sample: Option<Instant> = None;
+        let mut setup_ap_started = None;
         loop {
                 log::info!("Top of loop");
 
+                if let Err(error) = self.update_setup_ap(&mut setup_ap_started) {
+                    log::error!("Failed to update setup access point: {}", error);
+                    *self.setup_ap.lock().unwrap() = self.wifi_manager.is_setup_ap_running();
+                }
+
+                if !self.wifi_manager.is_connected() {
+                    self.led_manager.show(LedState::Reconnecting)?;
+
+                    match supervisor.next_action() {
+                        Action::Reconnect { after } => {
+                            info!("WiFi connection lost, reconnecting in {:?} (attempt {})", after, supervisor.failures() + 1);
+                            std::thread::sleep(after);
+
+                            match self.wifi_manager.connect_best(&self.config_manager) {
+                                Ok(ip_address) => {
+                                    info!("WiFi reconnected, address is {}", ip_address);
+                                    *mdns_address.lock().unwrap() = ip_address;
+                                    supervisor.connected();
+                                    self.diagnostics.lock().unwrap().record_reconnect();
+                                    last_roam = Instant::now();
+                                    self.led_manager.show(if self.wifi_manager.is_setup_ap_running() { LedState::SetupAccessPoint } else { LedState::Connected })?;
+                                },
+                                Err(error) => {
+                                    log::error!("Failed to reconnect WiFi: {}", error);
+                                    supervisor.failed();
+                                },
+                            }
+                        },
+                        Action::FallBackToAp => {
+                            anyhow::bail!("Failed to reconnect WiFi after {} attempts", supervisor.failures());
+                        },
+                    }
+                    continue;
+                }
+
+                if last_roam.elapsed() >= ROAM_INTERVAL {
+                    last_roam = Instant::now();
+                    match self.wifi_manager.roam(&self.config_manager) {
+                        Ok(Some(ip_address)) => {
+                            info!("Now on a different network, address is {}", ip_address);
+                            *mdns_address.lock().unwrap() = ip_address;
+                        },
+                        Ok(None) => {},
+                        Err(error) => log::error!("Failed to re-evaluate WiFi networks: {}", error),
+                    }
+                }
+
+                if last_sample.is_none_or(|last_sample| last_sample.elapsed() >= DIAGNOSTICS_INTERVAL) {
+                    last_sample = Some(Instant::now());
+                    if let Err(error) = self.wifi_manager.sample_link() {
+                        log::error!("Failed to sample WiFi link: {}", error);
+                    }
+                }
+
                 let datetime = Utc::now();
                 info!("Time synced: {}", datetime.format("%Y-%m-%d %H:%M:%S"));
 
@@ -247,10 +359,16 @@ impl SparkoCyd {
                 
                 // TODO: force a reset if we run low on heap
 
-                std::thread::sleep(std::time::Duration::from_secs(10));
+                self.wifi_manager.idle(std::time::Duration::from_secs(10));
             }
+    }
 
-        Ok(())
+    /// The number of failed reconnects before going back to setup mode, from the core config.
+    fn ap_fallback_after(&self) -> u32 {
+        self.config_manager.get_valid_core_config(crate::config::AP_FALLBACK_AFTER)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_AP_FALLBACK_AFTER)
     }
     
 
@@ -266,7 +384,7 @@ impl SparkoCyd {
 
             if let Err(error) = self.start_client() {
                 log::error!("Error starting client: {}", error);
-                self.led_manager.set_color(64, 0, 0)?;
+                self.led_manager.show(LedState::Failed)?;
             }
             else {
                 log::info!("Client mode started successfully");
@@ -300,7 +418,7 @@ impl SparkoCyd {
             
         }
         else {
-            self.led_manager.set_color(0, 0, 64)?;
+            self.led_manager.show(LedState::AccessPoint)?;
             info!("Invalid config, starting AP mode");
         }
 
@@ -316,18 +434,31 @@ impl SparkoCyd {
 
         // self.server_manager.init_ap_pages()?;
 
-        let server_addr = self.wifi_manager.start_access_point()?;
+        let server_addr = self.wifi_manager.start_access_point(&self.config_manager)?;
+        if let Some((ssid, _)) = self.wifi_manager.ap_credentials() {
+            info!("Setup access point {} running at http://{}/config", ssid, server_addr);
+        }
+        self.led_manager.show(LedState::AccessPoint)?;
+
+        // With a valid config we are only here because none of the networks could be joined, which may not last,
+        // for example after a power cut takes the router down too. So keep trying them.
+        let mut retry = self.config_manager.is_core_config_valid().then(|| (ConnectionSupervisor::new(AP_RETRY_BACKOFF, 0), Instant::now()));
 
-        thread::spawn(move || Self::captive_dns_server(server_addr));
-        
         loop {
             log::info!("Top of AP loop");
 
-            
+            if let Some((supervisor, last_attempt)) = &mut retry {
+                if let Action::Reconnect { after } = supervisor.next_action() {
+                    if last_attempt.elapsed() >= after {
+                        *last_attempt = Instant::now();
+                        self.retry_networks(supervisor)?;
+                    }
+                }
+            }
 
             // let mut led = led.lock()?;
             // led.toggle()?;
-            std::thread::sleep(std::time::Duration::from_secs(10));
+            self.wifi_manager.idle(std::time::Duration::from_secs(10));
         }
 
         fn system_halt<S: AsRef<str>>(s: S) {
@@ -356,47 +487,57 @@ impl SparkoCyd {
     //     }
     // }
 
-    fn captive_dns_server(server_addr: std::net::Ipv4Addr)  {
-        info!("DNS server start");
-        let socket = UdpSocket::bind("0.0.0.0:53").unwrap();
-        let addr_bytes = server_addr.octets();
-        loop {
-            let mut buf = [0u8; 512];
-
-            // info!("DNS server recv_from...");
-            let (size, src) = socket.recv_from(&mut buf).unwrap();
-
-            // info!("DNS server recv_from...{:?}", &buf[..size]);
-
-            let response = Self::build_dns_response(&buf[..size], &addr_bytes);
+    /// Tries the known networks again from setup mode. Joining one restarts the device, which then starts up as a
+    /// client as normal. The station and access point share the radio, so this is skipped while anyone is joined to
+    /// the access point, rather than take it off channel under them.
+    fn retry_networks(&mut self, supervisor: &mut ConnectionSupervisor) -> anyhow::Result<()> {
+        if self.wifi_manager.ap_station_count() > 0 {
+            info!("Setup access point in use, not trying the known networks");
+            return Ok(());
+        }
 
-            socket.send_to(&response, src).unwrap();
+        info!("Trying the known networks again (attempt {})", supervisor.failures() + 1);
+        self.led_manager.show(LedState::Reconnecting)?;
+        match self.wifi_manager.connect_best(&self.config_manager) {
+            Ok(ip_address) => {
+                info!("WiFi connected, address is {}, restarting to leave setup mode", ip_address);
+                unsafe { esp_restart(); }
+            },
+            Err(error) => {
+                log::error!("Still unable to join a network: {}", error);
+                supervisor.failed();
+            },
         }
+        self.led_manager.show(LedState::AccessPoint)?;
+        Ok(())
     }
 
-    fn build_dns_response(query: &[u8], server_addr: &[u8; 4]) -> Vec<u8> {
-        // info!("Received DNS query: {:?}", query);
-        let mut resp = query.to_vec();
-
-        resp[2] |= 0x80; // set QR bit (response)
-        resp[3] |= 0x80; // set RD bit (recursion desired, optional)
-
-        // Set ANCOUNT to 1 (answer count)
-        resp[6] = 0x00;
-        resp[7] = 0x01;
+    /// Starts or stops the setup access point to match `setup_ap`, turning it off once it has run for
+    /// `SETUP_AP_TIMEOUT`.
+    fn update_setup_ap(&mut self, started: &mut Option<Instant>) -> anyhow::Result<()> {
+        let mut wanted = *self.setup_ap.lock().unwrap();
 
-        resp.extend_from_slice(&[
-            0xc0, 0x0c, // pointer to domain
-            0x00, 0x01, // type A
-            0x00, 0x01, // class IN
-            0x00, 0x00, 0x00, 0x3c, // TTL (60 seconds)
-            0x00, 0x04, // data length (4 bytes for IPv4)
-
-            server_addr[0], server_addr[1], server_addr[2], server_addr[3] // IP address
-        ]);
+        if let Some(since) = started {
+            if since.elapsed() >= SETUP_AP_TIMEOUT {
+                info!("Setup access point timed out");
+                *self.setup_ap.lock().unwrap() = false;
+                wanted = false;
+            }
+        }
 
-        // info!("Sending DNS response: {:?}", resp);
-        
-        resp
+        if wanted && !self.wifi_manager.is_setup_ap_running() {
+            let server_addr = self.wifi_manager.start_setup_ap(&self.config_manager)?;
+            *started = Some(Instant::now());
+            if let Some((ssid, _)) = self.wifi_manager.ap_credentials() {
+                info!("Setup access point {} running at http://{}/config", ssid, server_addr);
+            }
+            self.led_manager.show(LedState::SetupAccessPoint)?;
+        }
+        else if !wanted && self.wifi_manager.is_setup_ap_running() {
+            self.wifi_manager.stop_setup_ap()?;
+            *started = None;
+            self.led_manager.show(LedState::Connected)?;
+        }
+        Ok(())
     }
-}
\ No newline at end of file
+}
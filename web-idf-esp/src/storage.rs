@@ -0,0 +1,263 @@
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+#[cfg(target_os = "espidf")]
+use esp_idf_svc::nvs::{EspNvs, EspNvsPartition, NvsDefault};
+
+/// The longest key or namespace name NVS allows.
+pub const MAX_KEY_LEN: usize = 15;
+
+/// One namespace of typed values, the way NVS keeps them. The config code goes through this rather than `EspNvs`
+/// so it can run against `MemoryStore` off the device.
+///
+/// Reading a key which isn't set gives `Ok(None)`. Reading it as the wrong type, or a string into a buffer smaller
+/// than `max_len`, is an error, as it is in NVS.
+pub trait ConfigStore: Send + Sync {
+    fn contains(&self, key: &str) -> anyhow::Result<bool>;
+    /// Reads a string of up to `max_len` bytes, counting the terminating nul NVS stores.
+    fn get_str(&self, key: &str, max_len: usize) -> anyhow::Result<Option<String>>;
+    fn set_str(&self, key: &str, value: &str) -> anyhow::Result<()>;
+    fn get_i32(&self, key: &str) -> anyhow::Result<Option<i32>>;
+    fn set_i32(&self, key: &str, value: i32) -> anyhow::Result<()>;
+    fn get_i64(&self, key: &str) -> anyhow::Result<Option<i64>>;
+    fn set_i64(&self, key: &str, value: i64) -> anyhow::Result<()>;
+    fn get_u8(&self, key: &str) -> anyhow::Result<Option<u8>>;
+    fn set_u8(&self, key: &str, value: u8) -> anyhow::Result<()>;
+    fn get_u32(&self, key: &str) -> anyhow::Result<Option<u32>>;
+    fn set_u32(&self, key: &str, value: u32) -> anyhow::Result<()>;
+    /// Removes a key, returning whether it was set.
+    fn remove(&self, key: &str) -> anyhow::Result<bool>;
+    fn erase_all(&self) -> anyhow::Result<()>;
+    /// The keys which are set and the type of each, for logging.
+    fn keys(&self) -> anyhow::Result<Vec<(String, String)>>;
+}
+
+/// A namespace in the NVS partition.
+#[cfg(target_os = "espidf")]
+pub struct NvsStore {
+    nvs: EspNvs<NvsDefault>,
+}
+
+#[cfg(target_os = "espidf")]
+impl NvsStore {
+    pub fn new(nvs_partition: EspNvsPartition<NvsDefault>, namespace: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            nvs: EspNvs::new(nvs_partition, namespace, true)?,
+        })
+    }
+}
+
+#[cfg(target_os = "espidf")]
+impl ConfigStore for NvsStore {
+    fn contains(&self, key: &str) -> anyhow::Result<bool> {
+        Ok(self.nvs.contains(key)?)
+    }
+
+    fn get_str(&self, key: &str, max_len: usize) -> anyhow::Result<Option<String>> {
+        let mut buf = vec![0u8; max_len];
+        Ok(self.nvs.get_str(key, buf.as_mut_slice())?.map(|str| str.to_string()))
+    }
+
+    fn set_str(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        Ok(self.nvs.set_str(key, value)?)
+    }
+
+    fn get_i32(&self, key: &str) -> anyhow::Result<Option<i32>> {
+        Ok(self.nvs.get_i32(key)?)
+    }
+
+    fn set_i32(&self, key: &str, value: i32) -> anyhow::Result<()> {
+        Ok(self.nvs.set_i32(key, value)?)
+    }
+
+    fn get_i64(&self, key: &str) -> anyhow::Result<Option<i64>> {
+        Ok(self.nvs.get_i64(key)?)
+    }
+
+    fn set_i64(&self, key: &str, value: i64) -> anyhow::Result<()> {
+        Ok(self.nvs.set_i64(key, value)?)
+    }
+
+    fn get_u8(&self, key: &str) -> anyhow::Result<Option<u8>> {
+        Ok(self.nvs.get_u8(key)?)
+    }
+
+    fn set_u8(&self, key: &str, value: u8) -> anyhow::Result<()> {
+        Ok(self.nvs.set_u8(key, value)?)
+    }
+
+    fn get_u32(&self, key: &str) -> anyhow::Result<Option<u32>> {
+        Ok(self.nvs.get_u32(key)?)
+    }
+
+    fn set_u32(&self, key: &str, value: u32) -> anyhow::Result<()> {
+        Ok(self.nvs.set_u32(key, value)?)
+    }
+
+    fn remove(&self, key: &str) -> anyhow::Result<bool> {
+        Ok(self.nvs.remove(key)?)
+    }
+
+    fn erase_all(&self) -> anyhow::Result<()> {
+        Ok(self.nvs.erase_all()?)
+    }
+
+    fn keys(&self) -> anyhow::Result<Vec<(String, String)>> {
+        let mut keys = self.nvs.keys(None)?;
+        let mut found = Vec::new();
+        while let Some((key, data_type)) = keys.next_key() {
+            found.push((key.to_string(), format!("{:?}", data_type)));
+        }
+        Ok(found)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum StoredValue {
+    Str(String),
+    I32(i32),
+    I64(i64),
+    U8(u8),
+    U32(u32),
+}
+
+impl StoredValue {
+    fn type_name(&self) -> &'static str {
+        match self {
+            StoredValue::Str(_) => "Str",
+            StoredValue::I32(_) => "I32",
+            StoredValue::I64(_) => "I64",
+            StoredValue::U8(_) => "U8",
+            StoredValue::U32(_) => "U32",
+        }
+    }
+}
+
+/// Keeps values in memory, with the same limits on key length, string size and types as NVS, so code tested
+/// against it behaves the same on the device.
+#[derive(Default)]
+pub struct MemoryStore {
+    values: Mutex<BTreeMap<String, StoredValue>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn get(&self, key: &str) -> anyhow::Result<Option<StoredValue>> {
+        check_key(key)?;
+        Ok(self.values.lock().unwrap().get(key).cloned())
+    }
+
+    fn set(&self, key: &str, value: StoredValue) -> anyhow::Result<()> {
+        check_key(key)?;
+        self.values.lock().unwrap().insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+/// Checks a key or namespace name is one NVS would accept.
+pub fn check_key(key: &str) -> anyhow::Result<()> {
+    if key.is_empty() || key.len() > MAX_KEY_LEN {
+        anyhow::bail!("NVS key \"{}\" must be 1 to {} bytes", key, MAX_KEY_LEN);
+    }
+    Ok(())
+}
+
+/// Reads a typed value, failing on a type mismatch the way NVS does.
+macro_rules! get_typed {
+    ($store:expr, $key:expr, $variant:ident) => {
+        match $store.get($key)? {
+            None => Ok(None),
+            Some(StoredValue::$variant(value)) => Ok(Some(value)),
+            Some(other) => Err(anyhow::anyhow!("NVS key {} holds {}, not {}", $key, other.type_name(), stringify!($variant))),
+        }
+    };
+}
+
+impl ConfigStore for MemoryStore {
+    fn contains(&self, key: &str) -> anyhow::Result<bool> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn get_str(&self, key: &str, max_len: usize) -> anyhow::Result<Option<String>> {
+        let value: Option<String> = get_typed!(self, key, Str)?;
+        match value {
+            Some(value) if value.len() + 1 > max_len => {
+                anyhow::bail!("NVS key {} holds {} bytes, more than the {} byte buffer", key, value.len() + 1, max_len)
+            },
+            value => Ok(value),
+        }
+    }
+
+    fn set_str(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        self.set(key, StoredValue::Str(value.to_string()))
+    }
+
+    fn get_i32(&self, key: &str) -> anyhow::Result<Option<i32>> {
+        get_typed!(self, key, I32)
+    }
+
+    fn set_i32(&self, key: &str, value: i32) -> anyhow::Result<()> {
+        self.set(key, StoredValue::I32(value))
+    }
+
+    fn get_i64(&self, key: &str) -> anyhow::Result<Option<i64>> {
+        get_typed!(self, key, I64)
+    }
+
+    fn set_i64(&self, key: &str, value: i64) -> anyhow::Result<()> {
+        self.set(key, StoredValue::I64(value))
+    }
+
+    fn get_u8(&self, key: &str) -> anyhow::Result<Option<u8>> {
+        get_typed!(self, key, U8)
+    }
+
+    fn set_u8(&self, key: &str, value: u8) -> anyhow::Result<()> {
+        self.set(key, StoredValue::U8(value))
+    }
+
+    fn get_u32(&self, key: &str) -> anyhow::Result<Option<u32>> {
+        get_typed!(self, key, U32)
+    }
+
+    fn set_u32(&self, key: &str, value: u32) -> anyhow::Result<()> {
+        self.set(key, StoredValue::U32(value))
+    }
+
+    fn remove(&self, key: &str) -> anyhow::Result<bool> {
+        check_key(key)?;
+        Ok(self.values.lock().unwrap().remove(key).is_some())
+    }
+
+    fn erase_all(&self) -> anyhow::Result<()> {
+        self.values.lock().unwrap().clear();
+        Ok(())
+    }
+
+    fn keys(&self) -> anyhow::Result<Vec<(String, String)>> {
+        Ok(self.values.lock().unwrap().iter()
+            .map(|(key, value)| (key.clone(), value.type_name().to_string()))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn behaves_like_nvs() {
+        let store = MemoryStore::new();
+        store.set_str("s", "hello").unwrap();
+        assert!(store.get_i32("s").is_err());
+        assert!(store.get_str("s", 5).is_err());
+        assert_eq!(store.get_str("s", 6).unwrap().as_deref(), Some("hello"));
+        assert!(store.set_u8("sixteen_chars_xx", 1).is_err());
+        assert!(store.remove("s").unwrap());
+        assert!(!store.remove("s").unwrap());
+        assert!(!store.contains("s").unwrap());
+    }
+}
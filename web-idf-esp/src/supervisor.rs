@@ -0,0 +1,152 @@
+use std::time::Duration;
+
+/// Exponential backoff between reconnect attempts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    /// The wait before the first attempt.
+    pub initial: Duration,
+    /// The longest wait between attempts.
+    pub max: Duration,
+}
+
+impl Backoff {
+    pub const fn new(initial: Duration, max: Duration) -> Self {
+        Self { initial, max }
+    }
+
+    /// The wait before attempt `attempt`, counting from 0. The wait doubles each time up to `max`.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        self.initial
+            .checked_mul(1 << attempt.min(31))
+            .unwrap_or(self.max)
+            .min(self.max)
+    }
+}
+
+/// What the supervisor wants done about a lost connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Wait, then try to reconnect.
+    Reconnect { after: Duration },
+    /// Give up and go back to the setup access point.
+    FallBackToAp,
+}
+
+/// Decides how to recover from losing the Wi-Fi connection. It does no I/O itself, the caller reports how each
+/// attempt went and does what `next_action` says.
+#[derive(Debug, Clone)]
+pub struct ConnectionSupervisor {
+    backoff: Backoff,
+    /// Failed attempts before falling back to the access point, 0 to keep trying for ever.
+    ap_fallback_after: u32,
+    failures: u32,
+}
+
+impl ConnectionSupervisor {
+    pub fn new(backoff: Backoff, ap_fallback_after: u32) -> Self {
+        Self {
+            backoff,
+            ap_fallback_after,
+            failures: 0,
+        }
+    }
+
+    /// Failed attempts since the connection was last up.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    /// Records that we are connected. Returns true if we had been trying to reconnect.
+    pub fn connected(&mut self) -> bool {
+        let was_reconnecting = self.failures > 0;
+        self.failures = 0;
+        was_reconnecting
+    }
+
+    /// Records a failed reconnect attempt.
+    pub fn failed(&mut self) {
+        self.failures = self.failures.saturating_add(1);
+    }
+
+    pub fn next_action(&self) -> Action {
+        if self.ap_fallback_after > 0 && self.failures >= self.ap_fallback_after {
+            Action::FallBackToAp
+        }
+        else {
+            Action::Reconnect { after: self.backoff.delay(self.failures) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BACKOFF: Backoff = Backoff::new(Duration::from_secs(2), Duration::from_secs(5 * 60));
+
+    fn secs(secs: u64) -> Action {
+        Action::Reconnect { after: Duration::from_secs(secs) }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let delays: Vec<u64> = (0..10).map(|attempt| BACKOFF.delay(attempt).as_secs()).collect();
+        assert_eq!(delays, [2, 4, 8, 16, 32, 64, 128, 256, 300, 300]);
+    }
+
+    #[test]
+    fn backoff_never_overflows() {
+        assert_eq!(BACKOFF.delay(31), BACKOFF.max);
+        assert_eq!(BACKOFF.delay(u32::MAX), BACKOFF.max);
+        let long = Backoff::new(Duration::from_secs(u64::MAX / 2), Duration::MAX);
+        assert_eq!(long.delay(4), Duration::MAX);
+    }
+
+    #[test]
+    fn waits_longer_after_each_failure() {
+        let mut supervisor = ConnectionSupervisor::new(BACKOFF, 0);
+        assert_eq!(supervisor.next_action(), secs(2));
+        supervisor.failed();
+        assert_eq!(supervisor.next_action(), secs(4));
+        supervisor.failed();
+        assert_eq!(supervisor.next_action(), secs(8));
+        assert_eq!(supervisor.failures(), 2);
+    }
+
+    #[test]
+    fn starts_again_after_connecting() {
+        let mut supervisor = ConnectionSupervisor::new(BACKOFF, 0);
+        assert!(!supervisor.connected());
+        for _ in 0..5 {
+            supervisor.failed();
+        }
+        assert!(supervisor.connected());
+        assert_eq!(supervisor.failures(), 0);
+        assert_eq!(supervisor.next_action(), secs(2));
+        assert!(!supervisor.connected());
+    }
+
+    #[test]
+    fn falls_back_to_the_access_point() {
+        let mut supervisor = ConnectionSupervisor::new(BACKOFF, 3);
+        supervisor.failed();
+        supervisor.failed();
+        assert_eq!(supervisor.next_action(), secs(8));
+        supervisor.failed();
+        assert_eq!(supervisor.next_action(), Action::FallBackToAp);
+        supervisor.failed();
+        assert_eq!(supervisor.next_action(), Action::FallBackToAp);
+
+        supervisor.connected();
+        assert_eq!(supervisor.next_action(), secs(2));
+    }
+
+    #[test]
+    fn keeps_trying_for_ever_without_a_fallback() {
+        let mut supervisor = ConnectionSupervisor::new(BACKOFF, 0);
+        for _ in 0..1000 {
+            supervisor.failed();
+        }
+        assert_eq!(supervisor.next_action(), secs(300));
+    }
+}
@@ -66,7 +66,7 @@ pub enum TimeZone {
 }
 
 
-pub const ALL: &'static [TimeZone] = &[
+pub const ALL: &[TimeZone] = &[
     // Europe
     TimeZone::EuropeLondon,
     TimeZone::EuropeBerlin,
@@ -139,6 +139,7 @@ pub const ALL: &'static [TimeZone] = &[
 // ];
 
 impl /*ConfigSerializable for*/ TimeZone {
+    #[cfg(target_os = "espidf")]
     pub fn set_as_system_timezone(&self) {
         let tz = std::ffi::CString::new(self.to_posix_tz()).unwrap();
         unsafe {
@@ -209,7 +210,7 @@ impl /*ConfigSerializable for*/ TimeZone {
         }
     }
 
-    pub fn to_str(&self) -> &'static str {
+    pub fn to_str(self) -> &'static str {
         match self {
             TimeZone::EuropeLondon => TZ_EUROPE_LONDON,
             TimeZone::EuropeBerlin => TZ_EUROPE_BERLIN,
@@ -243,6 +244,7 @@ impl /*ConfigSerializable for*/ TimeZone {
         }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Option<Self> {
         match s {
             TZ_EUROPE_LONDON => Some(Self::EuropeLondon),
@@ -0,0 +1,292 @@
+use std::time::Duration;
+
+/// Units accepted in durations, largest first so `format_duration` can use the same table.
+const DURATION_UNITS: [(char, u64); 4] = [
+    ('d', 24 * 60 * 60),
+    ('h', 60 * 60),
+    ('m', 60),
+    ('s', 1),
+];
+
+/// Shown in logs in place of secret values.
+pub const REDACTED: &str = "********";
+
+/// A string such as a password which must not end up in the logs. Debug shows `REDACTED`, the value itself is
+/// only available through `expose`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(secret: &str) -> Self {
+        Self(secret.to_string())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", REDACTED)
+    }
+}
+
+/// Extra checks on a config value, beyond what its type allows.
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    /// Bounds for numbers, durations are in seconds.
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// The shortest text allowed, the longest comes from the type.
+    pub min_len: Option<usize>,
+    /// A regular expression the whole text must match, and what to say when it doesn't.
+    pub pattern: Option<(&'static str, &'static str)>,
+}
+
+impl Rules {
+    /// Checks a number against `min` and `max`. `show` formats the bounds for the message, so durations can be
+    /// shown as durations.
+    pub fn check_number(&self, value: f64, show: impl Fn(f64) -> String) -> Result<(), String> {
+        if let Some(min) = self.min {
+            if value < min {
+                return Err(format!("Must be at least {}", show(min)));
+            }
+        }
+        if let Some(max) = self.max {
+            if value > max {
+                return Err(format!("Must be at most {}", show(max)));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn check_text(&self, value: &str) -> Result<(), String> {
+        if let Some(min_len) = self.min_len {
+            if value.chars().count() < min_len {
+                return Err(format!("Must be at least {} characters", min_len));
+            }
+        }
+        if let Some((pattern, message)) = self.pattern {
+            match full_match_regex(pattern) {
+                Ok(regex) if regex.is_match(value) => {},
+                Ok(_) => return Err(message.to_string()),
+                Err(e) => return Err(format!("Can't check against {}: {}", pattern, e)),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compiles `pattern` so it must match the whole of the text rather than just part of it.
+pub fn full_match_regex(pattern: &str) -> Result<regex_lite::Regex, regex_lite::Error> {
+    regex_lite::Regex::new(&format!("^(?:{})$", pattern))
+}
+
+/// Escapes text for use in HTML, inside elements or quoted attributes.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Parses a float, refusing NaN and infinities which can't be entered in a form anyway.
+pub fn parse_float(value: &str) -> anyhow::Result<f32> {
+    let float = value.trim().parse::<f32>().map_err(|_| anyhow::anyhow!("\"{}\" is not a number", value))?;
+    if !float.is_finite() {
+        anyhow::bail!("\"{}\" is not a finite number", value);
+    }
+    Ok(float)
+}
+
+/// Finds `value` in `choices`, ignoring case. Returns the entry from `choices` so it can be kept without copying.
+pub fn parse_choice(choices: &'static [&'static str], value: &str) -> anyhow::Result<&'static str> {
+    let value = value.trim();
+    choices.iter()
+        .find(|choice| choice.eq_ignore_ascii_case(value))
+        .copied()
+        .ok_or_else(|| anyhow::anyhow!("\"{}\" is not one of {}", value, choices.join(", ")))
+}
+
+/// Checks that `value` is an absolute http or https URL with a host.
+pub fn parse_url(value: &str) -> anyhow::Result<url::Url> {
+    let url = url::Url::parse(value.trim()).map_err(|e| anyhow::anyhow!("\"{}\" is not a valid URL: {}", value, e))?;
+    if url.scheme() != "http" && url.scheme() != "https" {
+        anyhow::bail!("\"{}\" must start with http:// or https://", value);
+    }
+    if url.host_str().is_none_or(|host| host.is_empty()) {
+        anyhow::bail!("\"{}\" has no host", value);
+    }
+    Ok(url)
+}
+
+/// Parses a duration given as a number of seconds, such as 90, or as numbers with units, such as 1h30m or 2d.
+/// The units are d, h, m and s, and spaces between the parts are allowed.
+pub fn parse_duration(value: &str) -> anyhow::Result<Duration> {
+    let invalid = || anyhow::anyhow!("\"{}\" is not a duration, use a form like 90, 90s, 15m or 1h30m", value);
+    let trimmed = value.trim();
+    if trimmed.is_empty() {
+        return Err(invalid());
+    }
+    if let Ok(secs) = trimmed.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+
+    let mut secs: u64 = 0;
+    let mut number = String::new();
+    let mut last_unit = None;
+    for c in trimmed.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+        }
+        else if c.is_whitespace() {
+            if !number.is_empty() {
+                // A number must be followed by its unit.
+                return Err(invalid());
+            }
+        }
+        else {
+            let Some(index) = DURATION_UNITS.iter().position(|(unit, _)| *unit == c.to_ascii_lowercase()) else {
+                return Err(invalid());
+            };
+            // Each unit once, largest first.
+            if number.is_empty() || last_unit.is_some_and(|last| index <= last) {
+                return Err(invalid());
+            }
+            let count = number.parse::<u64>().map_err(|_| invalid())?;
+            secs = count.checked_mul(DURATION_UNITS[index].1)
+                .and_then(|part| secs.checked_add(part))
+                .ok_or_else(invalid)?;
+            number.clear();
+            last_unit = Some(index);
+        }
+    }
+    if !number.is_empty() {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// Formats a duration the way `parse_duration` reads it, such as 1h30m. Fractions of a second are dropped.
+pub fn format_duration(duration: Duration) -> String {
+    let mut secs = duration.as_secs();
+    if secs == 0 {
+        return "0s".to_string();
+    }
+
+    let mut text = String::new();
+    for (unit, unit_secs) in DURATION_UNITS {
+        if secs >= unit_secs {
+            text.push_str(&format!("{}{}", secs / unit_secs, unit));
+            secs %= unit_secs;
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_durations() {
+        let cases = [
+            ("90", 90),
+            ("0", 0),
+            ("90s", 90),
+            ("15m", 15 * 60),
+            ("1h30m", 90 * 60),
+            ("1H 30M", 90 * 60),
+            (" 2d ", 2 * 24 * 60 * 60),
+            ("1d2h3m4s", 93784),
+        ];
+        for (text, secs) in cases {
+            assert_eq!(parse_duration(text).unwrap(), Duration::from_secs(secs), "{}", text);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_durations() {
+        for text in ["", " ", "m", "1x", "1.5h", "-5", "30m1h", "1h1h", "1 h", "5m3", "99999999999999999999d"] {
+            assert!(parse_duration(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn formats_durations_the_way_they_are_parsed() {
+        assert_eq!(format_duration(Duration::ZERO), "0s");
+        assert_eq!(format_duration(Duration::from_millis(1500)), "1s");
+        assert_eq!(format_duration(Duration::from_secs(3600)), "1h");
+        assert_eq!(format_duration(Duration::from_secs(93784)), "1d2h3m4s");
+        for secs in [1, 59, 60, 61, 3599, 86400, 90061, 1 << 40] {
+            let duration = Duration::from_secs(secs);
+            assert_eq!(parse_duration(&format_duration(duration)).unwrap(), duration, "{}", secs);
+        }
+    }
+
+    #[test]
+    fn parses_urls() {
+        assert_eq!(parse_url(" https://svc.example.com/nic/update ").unwrap().host_str(), Some("svc.example.com"));
+        assert_eq!(parse_url("http://192.168.1.1:8080/").unwrap().port(), Some(8080));
+        for text in ["", "example.com", "ftp://example.com/", "file:///etc/passwd", "http://", "mailto:a@b.c"] {
+            assert!(parse_url(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn parses_floats() {
+        assert_eq!(parse_float(" 1.5 ").unwrap(), 1.5);
+        assert_eq!(parse_float("-2e3").unwrap(), -2000.0);
+        for text in ["", "one", "NaN", "inf", "-infinity", "1e39"] {
+            assert!(parse_float(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn parses_choices_ignoring_case() {
+        const CHOICES: &[&str] = &["Off", "Low", "High"];
+        assert_eq!(parse_choice(CHOICES, "low").unwrap(), "Low");
+        assert_eq!(parse_choice(CHOICES, " HIGH ").unwrap(), "High");
+        assert!(parse_choice(CHOICES, "medium").is_err());
+        assert!(parse_choice(CHOICES, "").is_err());
+    }
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(escape_html(r#"<a href="x">Tom & Jerry's</a>"#), "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;");
+        assert_eq!(escape_html("plain café"), "plain café");
+    }
+
+    #[test]
+    fn checks_rules() {
+        let rules = Rules {
+            min: Some(1.0),
+            max: Some(10.0),
+            min_len: Some(3),
+            pattern: Some(("[a-z]+", "Lower case letters only")),
+        };
+        assert!(rules.check_number(5.0, |n| n.to_string()).is_ok());
+        assert_eq!(rules.check_number(0.5, |n| n.to_string()), Err("Must be at least 1".to_string()));
+        assert_eq!(rules.check_number(11.0, |n| n.to_string()), Err("Must be at most 10".to_string()));
+        assert!(rules.check_text("abc").is_ok());
+        assert_eq!(rules.check_text("ab"), Err("Must be at least 3 characters".to_string()));
+        // The pattern has to match all of the text.
+        assert_eq!(rules.check_text("abc1"), Err("Lower case letters only".to_string()));
+    }
+
+    #[test]
+    fn secrets_are_not_debug_printed() {
+        let secret = SecretString::new("hunter22");
+        assert_eq!(format!("{:?}", secret), REDACTED);
+        assert_eq!(secret.expose(), "hunter22");
+    }
+}
@@ -1,7 +1,9 @@
 // use embedded_svc::wifi::{Configuration, AuthMethod};
 use embedded_svc::wifi::ClientConfiguration;
 use esp_idf_hal::modem::WifiModemPeripheral;
+use esp_idf_svc::http::Method;
 use esp_idf_svc::wifi::AccessPointConfiguration;
+use esp_idf_svc::wifi::AccessPointInfo;
 use esp_idf_svc::wifi::AuthMethod;
 use esp_idf_svc::wifi::Configuration;
 use esp_idf_svc::wifi::EspWifi;
@@ -18,17 +20,52 @@ use esp_idf_svc::eventloop::EspSystemEventLoop;
 use esp_idf_svc::ping::EspPing;
 use esp_idf_svc::nvs::EspNvsPartition;
 use esp_idf_svc::nvs::NvsDefault;
+use esp_idf_svc::ipv4;
+use esp_idf_svc::netif::{EspNetif, NetifConfiguration};
 
 use crate::PASSWORD_LEN;
 use crate::SSID_LEN;
 use crate::config::CORE_FEATURE_NAME;
 use crate::config::ConfigManager;
+use crate::diagnostics::{self, LinkSample, WifiDiagnostics};
+use crate::eap::{self, EapCredentials};
+use crate::ipconfig::StaticIpConfig;
+use crate::http::HttpServerManager;
+use crate::networks::{self, Candidate, ScanResults};
+use crate::portal::CaptiveDns;
+use crate::qr::{self, EccLevel, QrCode};
+use crate::values::escape_html;
+
+/// The setup access point SSID is this followed by the end of the MAC address, unless configured.
+pub const AP_SSID_PREFIX: &str = "Sparko-";
+const DEFAULT_AP_CHANNEL: u8 = 1;
+const AP_MAX_CONNECTIONS: u16 = 4;
+/// Length of generated access point passwords.
+const AP_PASSWORD_LEN: usize = 10;
+/// Characters for generated passwords, leaving out ones which are easily mistaken for each other.
+const AP_PASSWORD_CHARS: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// How long to wait for an address after joining a network before giving up on it.
+const CONNECT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
 
 pub struct WiFiManager<'a> {
     wifi: EspWifi<'a>,
     sys_loop: EspSystemEventLoop,
     wifi_sub: Arc<Mutex<Option<esp_idf_svc::eventloop::EspSubscription<'a, esp_idf_svc::eventloop::System>>>>,
     failure_reason: Arc<Mutex<Option<String>>>,
+    /// The network we are connected to.
+    current: Option<Candidate>,
+    /// The networks seen in the last scan, for the config page.
+    scan_results: Arc<ScanResults>,
+    /// True while the setup access point runs alongside the station.
+    setup_ap: bool,
+    /// The configuration of the access point while one is running.
+    ap_config: Option<AccessPointConfiguration>,
+    /// The 802.1X credentials while joined to an enterprise network, the supplicant points into them.
+    eap: Option<EapCredentials>,
+    /// The captive portal DNS server while an access point is running.
+    captive_dns: Option<CaptiveDns>,
+    diagnostics: Arc<Mutex<WifiDiagnostics>>,
 }
 
 impl WiFiManager<'_> {
@@ -37,6 +74,8 @@ impl WiFiManager<'_> {
         sys_loop: EspSystemEventLoop,
         nvs: EspNvsPartition<NvsDefault>,
         failure_reason: Arc<Mutex<Option<String>>>,
+        scan_results: Arc<ScanResults>,
+        diagnostics: Arc<Mutex<WifiDiagnostics>>,
     ) -> anyhow::Result<Self> {
         let esp_wifi = EspWifi::new(modem, sys_loop.clone(), Some(nvs))?;
 
@@ -45,32 +84,120 @@ impl WiFiManager<'_> {
             sys_loop,
             wifi_sub: Arc::new(Mutex::new(None)),
             failure_reason,
+            current: None,
+            scan_results,
+            setup_ap: false,
+            ap_config: None,
+            eap: None,
+            captive_dns: None,
+            diagnostics,
         })
     }
 
-    pub fn start_access_point(&mut self) -> anyhow::Result<std::net::Ipv4Addr> {
-        
+    /// Starts the access point for setup mode. The station runs alongside it, unconnected, so we can still scan
+    /// for networks to offer on the config page and try the known networks again.
+    pub fn start_access_point(&mut self, config_manager: &Arc<ConfigManager>) -> anyhow::Result<std::net::Ipv4Addr> {
+        let server_addr = self.start_setup_ap(config_manager)?;
+        self.scan();
+        Ok(server_addr)
+    }
 
-        let ap_config = AccessPointConfiguration {
-            ssid: heapless::String::<SSID_LEN>::try_from("ESP32-Setup").unwrap(),
-            password: heapless::String::<PASSWORD_LEN>::try_from("password").unwrap(),
-            channel: 1,
-            auth_method: AuthMethod::WPA2Personal,
-            max_connections: 4,
-            ..Default::default()
-        };
+    /// Brings up the setup access point alongside the station connection, so the device can be reconfigured on
+    /// site while it stays on the network. The station may briefly drop while the mode changes. Starts the radio
+    /// if it isn't running yet.
+    pub fn start_setup_ap(&mut self, config_manager: &Arc<ConfigManager>) -> anyhow::Result<std::net::Ipv4Addr> {
+        let ap_config = self.ap_configuration(config_manager)?;
+        info!("Starting setup access point {} alongside the station", ap_config.ssid);
+        self.setup_ap = true;
+        let client_config = self.client_configuration()?;
+        self.wifi.set_configuration(&Configuration::Mixed(client_config, ap_config.clone()))?;
+        self.ap_config = Some(ap_config);
+        if !self.wifi.is_started()? {
+            self.wifi.start()?;
+        }
+        let server_addr = self.configure_ap_dhcp()?;
+        self.start_captive_dns(server_addr);
+        Ok(server_addr)
+    }
 
-        info!("Starting WiFi Access Point with config: {:?}", ap_config);
+    /// Takes the setup access point started by `start_setup_ap` down again.
+    pub fn stop_setup_ap(&mut self) -> anyhow::Result<()> {
+        info!("Stopping setup access point");
+        self.captive_dns = None;
+        self.setup_ap = false;
+        self.ap_config = None;
+        let client_config = self.client_configuration()?;
+        self.wifi.set_configuration(&Configuration::Client(client_config))?;
+        Ok(())
+    }
 
-        self.wifi.set_configuration(&Configuration::AccessPoint(ap_config))?;
+    /// Starts the captive portal DNS server on the access point address, replacing any running one. Without it the
+    /// config page can still be reached by address, so a failure is only logged.
+    fn start_captive_dns(&mut self, server_addr: Ipv4Addr) {
+        // Stop the old one first, it holds the port.
+        self.captive_dns = None;
+        match CaptiveDns::start(server_addr) {
+            Ok(captive_dns) => self.captive_dns = Some(captive_dns),
+            Err(e) => log::error!("{}", e),
+        }
+    }
 
-        // self.wifi.start().await?;
+    /// The SSID and password of the running access point, the password is None for an open network.
+    pub fn ap_credentials(&self) -> Option<(String, Option<String>)> {
+        self.ap_config.as_ref().map(|ap_config| {
+            let password = if ap_config.auth_method == AuthMethod::None { None } else { Some(ap_config.password.to_string()) };
+            (ap_config.ssid.to_string(), password)
+        })
+    }
 
-        let ip_info = self.wifi.ap_netif().get_ip_info()?;
-        info!("WiFi Access Point IP Info: {:?}", ip_info);
+    /// The number of devices joined to our access point.
+    pub fn ap_station_count(&self) -> usize {
+        let mut stations: esp_idf_sys::wifi_sta_list_t = unsafe { std::mem::zeroed() };
+        if unsafe { esp_idf_sys::esp_wifi_ap_get_sta_list(&mut stations) } != esp_idf_sys::ESP_OK {
+            return 0;
+        }
+        stations.num as usize
+    }
 
-        // Start the AP first, then update the DHCP server DNS option.
-        self.wifi.start()?;
+    pub fn is_setup_ap_running(&self) -> bool {
+        self.setup_ap
+    }
+
+    fn client_configuration(&self) -> anyhow::Result<ClientConfiguration> {
+        Ok(match self.wifi.get_configuration()? {
+            Configuration::Client(client_config) | Configuration::Mixed(client_config, _) => client_config,
+            _ => ClientConfiguration::default(),
+        })
+    }
+
+    /// The access point settings from the core config, see `setup_ap_credentials`.
+    fn ap_configuration(&self, config_manager: &Arc<ConfigManager>) -> anyhow::Result<AccessPointConfiguration> {
+        let (ssid, password) = setup_ap_credentials(config_manager)?;
+        let open = password.is_none();
+        let password = password.unwrap_or_default();
+
+        let channel = match config_manager.get_valid_core_config(crate::config::AP_CHANNEL)?.parse::<u8>() {
+            Ok(channel @ 1..=13) => channel,
+            Ok(channel) => {
+                log::warn!("Ignoring invalid setup access point channel {}", channel);
+                DEFAULT_AP_CHANNEL
+            },
+            Err(_) => DEFAULT_AP_CHANNEL,
+        };
+
+        Ok(AccessPointConfiguration {
+            ssid: heapless::String::<SSID_LEN>::try_from(ssid.as_str()).map_err(|_| anyhow::anyhow!("Setup access point SSID too long"))?,
+            password: heapless::String::<PASSWORD_LEN>::try_from(password.as_str()).map_err(|_| anyhow::anyhow!("Setup access point password too long"))?,
+            channel,
+            auth_method: if open { AuthMethod::None } else { AuthMethod::WPA2Personal },
+            max_connections: AP_MAX_CONNECTIONS,
+            ..Default::default()
+        })
+    }
+
+    /// Points the access point's DHCP clients at us for DNS and the captive portal. The AP must be running.
+    fn configure_ap_dhcp(&self) -> anyhow::Result<std::net::Ipv4Addr> {
+        let ip_info = self.wifi.ap_netif().get_ip_info()?;
 
         // Desired DNS server for clients.
         let dns_server = ip_info.ip; //Ipv4Addr::new(1, 1, 1, 1);
@@ -147,22 +274,13 @@ impl WiFiManager<'_> {
         Ok(ip_info.ip)
     }
 
+    /// Starts the station and joins the best of the known networks, see `connect_best`.
     pub fn start_client(&mut self, config_manager: &Arc<ConfigManager>) -> anyhow::Result<std::net::Ipv4Addr> {
-        let wifi_configuration: embedded_svc::wifi::Configuration = embedded_svc::wifi::Configuration::Client(ClientConfiguration {
-            ssid: heapless::String::<32>::try_from(config_manager.get_valid_core_config(crate::config::SSID)?.as_str()).unwrap(),
-            bssid: None,
-            auth_method: embedded_svc::wifi::AuthMethod::WPA2Personal,
-            password: heapless::String::<64>::try_from(config_manager.get_valid_core_config(crate::config::WIFI_PASSWORD)?.as_str()).unwrap(),
-            channel: None,
-            scan_method: ScanMethod::CompleteScan(ScanSortMethod::Security),
-            pmf_cfg: esp_idf_svc::wifi::PmfConfiguration::Capable{ required: false },
-        });
-
-
-        self.wifi.set_configuration(&wifi_configuration)?;
-
+        let hostname = config_manager.get_valid_core_config(crate::config::MDNS_HOSTNAME)?;
+        self.configure_sta_netif(&hostname, config_manager.static_ip_config()?)?;
 
         let failure_reason_clone = self.failure_reason.clone();
+        let diagnostics_clone = self.diagnostics.clone();
 
         let wifi_sub: esp_idf_svc::eventloop::EspSubscription<'_, esp_idf_svc::eventloop::System> = self.sys_loop.subscribe::<WifiEvent, _>(move |event: WifiEvent| {
             match event {
@@ -173,6 +291,7 @@ impl WiFiManager<'_> {
                 WifiEvent::StaConnected(sta_connected_ref) => info!("WiFi Station Connected {:?}", sta_connected_ref),
                 WifiEvent::StaDisconnected(sta_disconnected_ref) => {
                     info!("WiFi Station Disconnected {:?}", sta_disconnected_ref);
+                    diagnostics_clone.lock().unwrap().record_disconnect(diagnostics::uptime_secs(), sta_disconnected_ref.reason());
                     match sta_disconnected_ref.reason() as u32{
                         esp_idf_sys::wifi_err_reason_t_WIFI_REASON_AUTH_FAIL => {
                             info!("WiFi Station Authentication Failed");
@@ -229,9 +348,207 @@ impl WiFiManager<'_> {
 
         info!("WiFi event subscription set up, starting WiFi...");
 
+        self.wifi.set_configuration(&Configuration::Client(ClientConfiguration::default()))?;
         self.wifi.start()?;
         info!("Wifi started");
 
+        self.connect_best(config_manager)
+    }
+
+    /// Replaces the station interface with one using `static_ip`, or DHCP if that is None, and names it `hostname`
+    /// so routers show which device it is. The station must not be started.
+    fn configure_sta_netif(&mut self, hostname: &str, static_ip: Option<StaticIpConfig>) -> anyhow::Result<()> {
+        let ip_configuration = match static_ip {
+            Some(static_ip) => {
+                info!("Using static address {}/{}", static_ip.ip, static_ip.prefix_len);
+                ipv4::ClientConfiguration::Fixed(ipv4::ClientSettings {
+                    ip: static_ip.ip,
+                    subnet: ipv4::Subnet {
+                        gateway: static_ip.gateway,
+                        mask: ipv4::Mask(static_ip.prefix_len),
+                    },
+                    dns: Some(static_ip.dns),
+                    secondary_dns: static_ip.secondary_dns,
+                })
+            },
+            None => ipv4::ClientConfiguration::DHCP(ipv4::DHCPClientSettings {
+                hostname: heapless::String::try_from(hostname).ok(),
+            }),
+        };
+
+        let mut netif = EspNetif::new_with_conf(&NetifConfiguration {
+            ip_configuration: Some(ipv4::Configuration::Client(ip_configuration)),
+            ..NetifConfiguration::wifi_default_client()
+        })?;
+        netif.set_hostname(hostname)?;
+
+        self.wifi.swap_netif_sta(netif)?;
+        Ok(())
+    }
+
+    /// True if we are connected to a network and have an address.
+    pub fn is_connected(&self) -> bool {
+        self.wifi.is_up().unwrap_or(false)
+    }
+
+    /// Records the state of the link and how well the gateway answers pings in the diagnostics history.
+    /// Does nothing if we aren't connected.
+    pub fn sample_link(&self) -> anyhow::Result<()> {
+        let mut ap_info: esp_idf_sys::wifi_ap_record_t = unsafe { std::mem::zeroed() };
+        if unsafe { esp_idf_sys::esp_wifi_sta_get_ap_info(&mut ap_info) } != esp_idf_sys::ESP_OK {
+            return Ok(());
+        }
+
+        let ssid_len = ap_info.ssid.iter().position(|&b| b == 0).unwrap_or(ap_info.ssid.len());
+        let gateway = self.wifi.sta_netif().get_ip_info()?.subnet.gateway;
+        let ping = match diagnostics::ping(gateway) {
+            Ok(ping) => Some(ping),
+            Err(error) => {
+                log::error!("Failed to ping gateway {}: {}", gateway, error);
+                None
+            },
+        };
+
+        let sample = LinkSample {
+            uptime_secs: diagnostics::uptime_secs(),
+            ssid: String::from_utf8_lossy(&ap_info.ssid[..ssid_len]).into_owned(),
+            bssid: diagnostics::format_bssid(&ap_info.bssid),
+            channel: ap_info.primary,
+            rssi: ap_info.rssi,
+            ping,
+        };
+        info!("WiFi link: {:?}", sample);
+        self.diagnostics.lock().unwrap().record_sample(sample);
+        Ok(())
+    }
+
+    /// Scans for the known networks and tries them best first until one gives us an address.
+    pub fn connect_best(&mut self, config_manager: &Arc<ConfigManager>) -> anyhow::Result<std::net::Ipv4Addr> {
+        let candidates = networks::rank(&config_manager.known_networks(), &self.scan());
+        if candidates.is_empty() {
+            anyhow::bail!("No networks configured");
+        }
+
+        let mut last_error = None;
+        for candidate in candidates {
+            match self.connect(config_manager, &candidate) {
+                Ok(ip) => {
+                    self.current = Some(candidate);
+                    return Ok(ip);
+                },
+                Err(error) => {
+                    log::error!("Failed to join {}: {}", candidate.network.ssid, error);
+                    last_error = Some(error);
+                },
+            }
+        }
+
+        // Leave the last failure reason for the config page.
+        self.current = None;
+        Err(last_error.unwrap())
+    }
+
+    /// Rescans and moves to a better network if there is one, see `networks::should_roam`.
+    /// Returns the new address if we moved. If the new network fails we go back to the best one that works.
+    pub fn roam(&mut self, config_manager: &Arc<ConfigManager>) -> anyhow::Result<Option<std::net::Ipv4Addr>> {
+        let Some(current) = self.current.clone() else {
+            return Ok(Some(self.connect_best(config_manager)?));
+        };
+
+        let candidates = networks::rank(&config_manager.known_networks(), &self.scan());
+        let Some(best) = candidates.first() else {
+            return Ok(None);
+        };
+        let current = candidates.iter()
+            .find(|candidate| candidate.network.ssid == current.network.ssid)
+            .cloned()
+            .unwrap_or_else(|| networks::Candidate { rssi: None, bssid: None, channel: None, auth_method: None, ..current });
+
+        if !networks::should_roam(&current, best) {
+            info!("Staying on {} ({:?} dBm)", current.network.ssid, current.rssi);
+            return Ok(None);
+        }
+
+        info!("Roaming from {} ({:?} dBm) to {} ({:?} dBm)", current.network.ssid, current.rssi, best.network.ssid, best.rssi);
+        match self.connect(config_manager, best) {
+            Ok(ip) => {
+                self.current = Some(best.clone());
+                Ok(Some(ip))
+            },
+            Err(error) => {
+                log::error!("Failed to roam to {}: {}", best.network.ssid, error);
+                Ok(Some(self.connect_best(config_manager)?))
+            },
+        }
+    }
+
+    /// Scans for networks, which also answers any page waiting in `ScanResults::refresh`.
+    fn scan(&mut self) -> Vec<AccessPointInfo> {
+        match self.wifi.scan() {
+            Ok(access_points) => {
+                info!("WiFi scan found {} access points", access_points.len());
+                self.scan_results.update(networks::visible_networks(&access_points));
+                access_points
+            },
+            Err(error) => {
+                // We can still try the known networks blind.
+                log::error!("WiFi scan failed: {}", error);
+                self.scan_results.scan_failed();
+                Vec::new()
+            },
+        }
+    }
+
+    /// Waits for up to `timeout`, scanning if a page asks for a fresh list of networks in the meantime. The main
+    /// loops wait here so `/scan` gets an answer quickly.
+    pub fn idle(&mut self, timeout: std::time::Duration) {
+        if self.scan_results.wait_for_request(timeout) {
+            self.scan();
+        }
+    }
+
+    /// Joins one network and waits for an address.
+    fn connect(&mut self, config_manager: &Arc<ConfigManager>, candidate: &Candidate) -> anyhow::Result<std::net::Ipv4Addr> {
+        let auth_method = candidate.network.auth.resolve(candidate.auth_method, &candidate.network.password);
+        info!("Joining {} ({:?} dBm, {:?})", candidate.network.ssid, candidate.rssi, auth_method);
+
+        if self.wifi.is_connected()? {
+            self.wifi.disconnect()?;
+        }
+        self.failure_reason.lock().unwrap().take();
+
+        // The password goes to the supplicant for enterprise networks, not in the station config.
+        let password = if networks::is_enterprise(auth_method) {
+            let credentials = config_manager.eap_credentials(&candidate.network)?;
+            credentials.enable()?;
+            self.eap = Some(credentials);
+            ""
+        }
+        else {
+            if self.eap.take().is_some() {
+                eap::disable()?;
+            }
+            candidate.network.password.as_str()
+        };
+
+        let client_config = ClientConfiguration {
+            ssid: heapless::String::<32>::try_from(candidate.network.ssid.as_str()).unwrap(),
+            bssid: candidate.bssid,
+            auth_method,
+            password: heapless::String::<64>::try_from(password).unwrap(),
+            channel: candidate.channel,
+            scan_method: ScanMethod::CompleteScan(ScanSortMethod::Security),
+            pmf_cfg: esp_idf_svc::wifi::PmfConfiguration::Capable{ required: false },
+        };
+
+        // Keep the setup access point up if it is running.
+        let wifi_configuration = match (&self.ap_config, self.setup_ap) {
+            (Some(ap_config), true) => Configuration::Mixed(client_config, ap_config.clone()),
+            _ => Configuration::Client(client_config),
+        };
+
+        self.wifi.set_configuration(&wifi_configuration)?;
+
         let mut retry_cnt = 4;
         while self.failure_reason.lock().unwrap().is_none() && retry_cnt > 0 {
             match self.wifi.connect() {
@@ -255,6 +572,7 @@ impl WiFiManager<'_> {
 
         // Wait for IP (this replaces wait_netif_up)
         let ip_info;
+        let deadline = std::time::Instant::now() + CONNECT_TIMEOUT;
         // while self.failure_reason.lock().unwrap().is_none() {
         loop {
             if let Some(reason) = self.failure_reason.lock().unwrap().clone() {
@@ -262,7 +580,14 @@ impl WiFiManager<'_> {
                 return Err(anyhow::anyhow!(reason));
             }
 
-            if let Ok(info) = self.wifi.sta_netif().get_ip_info() {
+            if std::time::Instant::now() > deadline {
+                let reason = format!("Timed out joining {}", candidate.network.ssid);
+                self.failure_reason.lock().unwrap().replace(reason.clone());
+                return Err(anyhow::anyhow!(reason));
+            }
+
+            // A static address is there from the start, so wait for the link as well.
+            if let (Ok(true), Ok(info)) = (self.wifi.is_up(), self.wifi.sta_netif().get_ip_info()) {
                 if info.ip != Ipv4Addr::UNSPECIFIED {
                     ip_info = info;
                     break;
@@ -282,3 +607,91 @@ impl WiFiManager<'_> {
         Ok(ip_info.ip)
     }
 }
+
+/// The setup access point SSID and password from the core config, the password is None for an open network. The
+/// SSID defaults to one made from the end of the MAC address. A password is generated and saved the first time one
+/// is needed, so each device has its own.
+pub fn setup_ap_credentials(config_manager: &ConfigManager) -> anyhow::Result<(String, Option<String>)> {
+    let mut ssid = config_manager.get_valid_core_config(crate::config::AP_SSID)?;
+    if ssid.is_empty() {
+        let mut mac = [0u8; 6];
+        esp_idf_sys::esp!(unsafe { esp_idf_sys::esp_read_mac(mac.as_mut_ptr(), esp_idf_sys::esp_mac_type_t_ESP_MAC_WIFI_SOFTAP) })?;
+        ssid = format!("{}{:02X}{:02X}", AP_SSID_PREFIX, mac[4], mac[5]);
+    }
+
+    if config_manager.get_valid_core_config(crate::config::AP_OPEN)? == "true" {
+        return Ok((ssid, None));
+    }
+
+    let mut password = config_manager.get_valid_core_config(crate::config::AP_PASSWORD)?;
+    if password.len() < 8 {
+        if !password.is_empty() {
+            log::warn!("Setup access point password is too short, generating a new one");
+        }
+        password = generate_password();
+        config_manager.set_core_config(crate::config::AP_PASSWORD, &password)?;
+    }
+    Ok((ssid, Some(password)))
+}
+
+fn generate_password() -> String {
+    (0..AP_PASSWORD_LEN)
+        .map(|_| {
+            let random = unsafe { esp_idf_sys::esp_random() } as usize;
+            AP_PASSWORD_CHARS[random % AP_PASSWORD_CHARS.len()] as char
+        })
+        .collect()
+}
+
+/// Registers /setup_ap, the one place the setup access point password can be read back. The password is a secret,
+/// so the config page and the API never show it, but it is needed to join the access point. The page shows the
+/// network name, the password and a join code to scan with a phone. Anyone who can open it can also change the
+/// password on the config page, so it gives nothing away they couldn't already get. Devices with a display can show
+/// the same join code with `SparkoCyd::qr_code`.
+pub fn create_pages(config_manager: &Arc<ConfigManager>, server_manager: &mut HttpServerManager) -> anyhow::Result<()> {
+    let config_manager_clone = config_manager.clone();
+
+    server_manager.fn_handler("/setup_ap", Method::Get, move |req| {
+        info!("Received {:?} request for {}", req.method(), req.uri());
+
+        let (ssid, password) = setup_ap_credentials(&config_manager_clone)?;
+        let join_code = QrCode::encode_text(&qr::wifi_join_payload(&ssid, password.as_deref()), EccLevel::Medium)?;
+
+        let mut resp = req.into_response(
+            200,
+            None,
+            &[
+                ("Content-Type", "text/html"),
+                ("Cache-Control", "no-store"),
+            ],
+        )?;
+        resp.write(format!(r#"
+            <!DOCTYPE html>
+            <html lang="en">
+            <head>
+                <meta charset="utf-8" />
+                <meta name="viewport" content="width=device-width, initial-scale=1" />
+                <title>Setup Access Point</title>
+                <link rel="stylesheet" href="/main.css">
+            </head>
+            <body>
+                <div class="page">
+                    <h1>Setup Access Point</h1>
+                    <table class="status">
+                        <tr><th>Network</th><td>{}</td></tr>
+                        <tr><th>Password</th><td>{}</td></tr>
+                    </table>
+                    <p>Scan to join:</p>
+                    {}
+                    <p><a href="/config">Back to setup</a></p>
+                </div>
+            </body>
+            </html>"#,
+            escape_html(&ssid),
+            password.as_deref().map(escape_html).unwrap_or_else(|| "None, the network is open".to_string()),
+            join_code.to_svg(4)).as_bytes())?;
+        Ok(())
+    })?;
+
+    Ok(())
+}
@@ -0,0 +1,219 @@
+//! Serial console commands for provisioning.
+//!
+//! Bytes typed on the serial port are collected into lines with `LineBuffer` and turned into a
+//! `Command` with `parse`. Arguments containing spaces can be wrapped in double quotes:
+//!
+//! ```text
+//! wifi set "Office Guest" hunter22
+//! ```
+
+use heapless::String;
+
+pub const HELP: &str = "\
+Commands:
+  wifi show                   show the stored network
+  wifi set <ssid> [password]  store a network and restart, quote names with spaces
+  wifi clear                  forget the stored network and restart
+  restart                     restart
+  help                        show this help";
+
+/// A console command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command<'a> {
+    Help,
+    Show,
+    /// An empty password is an open network.
+    Set { ssid: &'a str, password: &'a str },
+    Clear,
+    Restart,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    Unknown,
+    MissingArgument,
+    TooManyArguments,
+    /// A quoted argument wasn't closed.
+    Unterminated,
+}
+
+/// Splits a line into arguments, treating anything in double quotes as one argument.
+struct Args<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Iterator for Args<'a> {
+    type Item = Result<&'a str, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.rest = self.rest.trim_start();
+        if self.rest.is_empty() {
+            return None;
+        }
+
+        if let Some(quoted) = self.rest.strip_prefix('"') {
+            let Some(end) = quoted.find('"') else {
+                self.rest = "";
+                return Some(Err(ParseError::Unterminated));
+            };
+            self.rest = &quoted[end + 1..];
+            return Some(Ok(&quoted[..end]));
+        }
+
+        let end = self.rest.find(char::is_whitespace).unwrap_or(self.rest.len());
+        let (arg, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Some(Ok(arg))
+    }
+}
+
+/// Parses one line of input. Returns None for a blank line.
+pub fn parse(line: &str) -> Option<Result<Command<'_>, ParseError>> {
+    let mut args = Args { rest: line };
+    let mut next = || args.next().transpose();
+
+    let command = match next() {
+        Ok(Some(command)) => command,
+        Ok(None) => return None,
+        Err(e) => return Some(Err(e)),
+    };
+
+    let result = (|| {
+        let command = match command {
+            "help" | "?" => Command::Help,
+            "restart" => Command::Restart,
+            "wifi" => match next()?.ok_or(ParseError::MissingArgument)? {
+                "show" => Command::Show,
+                "clear" => Command::Clear,
+                "set" => Command::Set {
+                    ssid: next()?.ok_or(ParseError::MissingArgument)?,
+                    password: next()?.unwrap_or(""),
+                },
+                _ => return Err(ParseError::Unknown),
+            },
+            _ => return Err(ParseError::Unknown),
+        };
+        match next()? {
+            Some(_) => Err(ParseError::TooManyArguments),
+            None => Ok(command),
+        }
+    })();
+
+    Some(result)
+}
+
+/// Collects bytes from a serial port into lines, handling backspace.
+#[derive(Debug, Default)]
+pub struct LineBuffer<const N: usize> {
+    line: String<N>,
+    /// Set when the line has grown too long, so it is thrown away at the end.
+    overflow: bool,
+}
+
+impl<const N: usize> LineBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            line: String::new(),
+            overflow: false,
+        }
+    }
+
+    /// Adds a byte, returning the line when Enter is pressed. Lines which were too long or not
+    /// valid text are dropped.
+    pub fn push(&mut self, byte: u8) -> Option<String<N>> {
+        match byte {
+            b'\r' | b'\n' => {
+                let line = core::mem::take(&mut self.line);
+                let overflow = core::mem::take(&mut self.overflow);
+                (!overflow && !line.is_empty()).then_some(line)
+            }
+            // Backspace and delete.
+            0x08 | 0x7F => {
+                self.line.pop();
+                None
+            }
+            // Only printable ASCII, so multi-byte characters can't be split.
+            0x20..=0x7E => {
+                if self.line.push(byte as char).is_err() {
+                    self.overflow = true;
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines<const N: usize>(buffer: &mut LineBuffer<N>, input: &[u8]) -> heapless::Vec<String<N>, 4> {
+        input.iter().filter_map(|&byte| buffer.push(byte)).collect()
+    }
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(parse("help"), Some(Ok(Command::Help)));
+        assert_eq!(parse("?"), Some(Ok(Command::Help)));
+        assert_eq!(parse("  restart  "), Some(Ok(Command::Restart)));
+        assert_eq!(parse("wifi show"), Some(Ok(Command::Show)));
+        assert_eq!(parse("wifi clear"), Some(Ok(Command::Clear)));
+        assert_eq!(parse("wifi set Home hunter22"), Some(Ok(Command::Set { ssid: "Home", password: "hunter22" })));
+        assert_eq!(parse("wifi\tset Home"), Some(Ok(Command::Set { ssid: "Home", password: "" })));
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("   "), None);
+    }
+
+    #[test]
+    fn quotes_arguments_with_spaces() {
+        assert_eq!(
+            parse(r#"wifi set "Office Guest" hunter22"#),
+            Some(Ok(Command::Set { ssid: "Office Guest", password: "hunter22" }))
+        );
+        assert_eq!(
+            parse(r#"wifi set Home "correct horse battery""#),
+            Some(Ok(Command::Set { ssid: "Home", password: "correct horse battery" }))
+        );
+        assert_eq!(parse(r#"wifi set " Spaced " """#), Some(Ok(Command::Set { ssid: " Spaced ", password: "" })));
+        assert_eq!(parse(r#""wifi" "show""#), Some(Ok(Command::Show)));
+        assert_eq!(parse(r#"wifi set "Office Guest hunter22"#), Some(Err(ParseError::Unterminated)));
+        assert_eq!(parse(r#""help"#), Some(Err(ParseError::Unterminated)));
+    }
+
+    #[test]
+    fn rejects_unknown_commands() {
+        assert_eq!(parse("reboot"), Some(Err(ParseError::Unknown)));
+        assert_eq!(parse("HELP"), Some(Err(ParseError::Unknown)));
+        assert_eq!(parse("wifi forget"), Some(Err(ParseError::Unknown)));
+        assert_eq!(parse("wifi"), Some(Err(ParseError::MissingArgument)));
+        assert_eq!(parse("wifi set"), Some(Err(ParseError::MissingArgument)));
+        assert_eq!(parse("wifi set Home hunter22 extra"), Some(Err(ParseError::TooManyArguments)));
+        assert_eq!(parse("help me"), Some(Err(ParseError::TooManyArguments)));
+        assert_eq!(parse("wifi show \"x"), Some(Err(ParseError::Unterminated)));
+    }
+
+    #[test]
+    fn collects_lines() {
+        let mut buffer = LineBuffer::<16>::new();
+        assert_eq!(lines(&mut buffer, b"wifi show\r\n"), ["wifi show"]);
+        // Blank lines, such as the second half of CR LF, aren't returned.
+        assert!(lines(&mut buffer, b"\r\n\n").is_empty());
+        assert_eq!(lines(&mut buffer, b"helq\x08p\rrestarx\x7ft\n"), ["help", "restart"]);
+        // Backspace on an empty line does nothing.
+        assert_eq!(lines(&mut buffer, b"\x08\x08?\n"), ["?"]);
+        // Control characters and anything outside ASCII are ignored.
+        assert_eq!(lines(&mut buffer, "h\x1belp\té\n".as_bytes()), ["help"]);
+    }
+
+    #[test]
+    fn drops_overlong_lines() {
+        let mut buffer = LineBuffer::<8>::new();
+        assert_eq!(lines(&mut buffer, b"12345678\n"), ["12345678"]);
+        assert!(lines(&mut buffer, b"123456789\n").is_empty());
+        // Deleting characters doesn't rescue a line which has already overflowed.
+        assert!(lines(&mut buffer, b"123456789\x08\x08\n").is_empty());
+        // The next line is unaffected.
+        assert_eq!(lines(&mut buffer, b"help\n"), ["help"]);
+    }
+}
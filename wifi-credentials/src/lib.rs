@@ -0,0 +1,301 @@
+//! Wi-Fi credentials stored in flash.
+//!
+//! The credentials are kept as a single fixed size record at the start of their own flash
+//! partition, so a board can be moved to another network without a new build. The record is laid
+//! out as:
+//!
+//! | Offset | Size | Contents                                     |
+//! |--------|------|----------------------------------------------|
+//! | 0      | 4    | Magic, `WCRD`                                |
+//! | 4      | 1    | Format version                               |
+//! | 5      | 1    | SSID length                                  |
+//! | 6      | 1    | Password length                              |
+//! | 7      | 1    | Reserved, 0                                  |
+//! | 8      | 32   | SSID, padded with zeros                      |
+//! | 40     | 64   | Password, padded with zeros                  |
+//! | 104    | 4    | CRC-32 of the bytes before it, little endian |
+//!
+//! Erased flash reads back as all `0xFF`, which is reported as `RecordError::Empty` so it can be
+//! told apart from a damaged record. Nothing here touches the hardware: `CredentialStore` works
+//! with any `embedded_storage::Storage`, such as a partition of an esp-storage flash.
+//!
+//! The serial console commands which set the credentials are parsed in `console`.
+
+#![no_std]
+
+pub mod console;
+
+use embedded_storage::Storage;
+use heapless::String;
+
+pub const SSID_LEN: usize = 32;
+pub const PASSWORD_LEN: usize = 64;
+pub const RECORD_LEN: usize = 108;
+
+const MAGIC: [u8; 4] = *b"WCRD";
+/// The current format version, bumped whenever the layout changes.
+pub const VERSION: u8 = 1;
+
+const SSID_OFFSET: usize = 8;
+const PASSWORD_OFFSET: usize = SSID_OFFSET + SSID_LEN;
+const CRC_OFFSET: usize = PASSWORD_OFFSET + PASSWORD_LEN;
+
+/// Why a record couldn't be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordError {
+    /// The flash is erased, nothing has been stored.
+    Empty,
+    TooShort,
+    BadMagic,
+    UnsupportedVersion(u8),
+    BadCrc,
+    BadLength,
+    BadUtf8,
+}
+
+/// An SSID and password.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Credentials {
+    pub ssid: String<SSID_LEN>,
+    pub password: String<PASSWORD_LEN>,
+}
+
+impl Credentials {
+    /// Returns None if the SSID is empty or either is too long. An empty password is an open
+    /// network.
+    pub fn new(ssid: &str, password: &str) -> Option<Self> {
+        if ssid.is_empty() {
+            return None;
+        }
+        Some(Self {
+            ssid: ssid.try_into().ok()?,
+            password: password.try_into().ok()?,
+        })
+    }
+
+    /// Encodes the credentials as a record.
+    pub fn encode(&self) -> [u8; RECORD_LEN] {
+        let mut record = [0; RECORD_LEN];
+        record[..4].copy_from_slice(&MAGIC);
+        record[4] = VERSION;
+        record[5] = self.ssid.len() as u8;
+        record[6] = self.password.len() as u8;
+        record[SSID_OFFSET..SSID_OFFSET + self.ssid.len()].copy_from_slice(self.ssid.as_bytes());
+        record[PASSWORD_OFFSET..PASSWORD_OFFSET + self.password.len()].copy_from_slice(self.password.as_bytes());
+
+        let crc = crc32(&record[..CRC_OFFSET]);
+        record[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+        record
+    }
+
+    /// Decodes a record, checking the magic, version and CRC.
+    pub fn decode(record: &[u8]) -> Result<Self, RecordError> {
+        let record = record.get(..RECORD_LEN).ok_or(RecordError::TooShort)?;
+        if record.iter().all(|&b| b == 0xFF) {
+            return Err(RecordError::Empty);
+        }
+        if record[..4] != MAGIC {
+            return Err(RecordError::BadMagic);
+        }
+        if record[4] != VERSION {
+            return Err(RecordError::UnsupportedVersion(record[4]));
+        }
+        let crc = u32::from_le_bytes([record[CRC_OFFSET], record[CRC_OFFSET + 1], record[CRC_OFFSET + 2], record[CRC_OFFSET + 3]]);
+        if crc != crc32(&record[..CRC_OFFSET]) {
+            return Err(RecordError::BadCrc);
+        }
+
+        let (ssid_len, password_len) = (record[5] as usize, record[6] as usize);
+        if ssid_len == 0 || ssid_len > SSID_LEN || password_len > PASSWORD_LEN {
+            return Err(RecordError::BadLength);
+        }
+        let ssid = core::str::from_utf8(&record[SSID_OFFSET..SSID_OFFSET + ssid_len]).map_err(|_| RecordError::BadUtf8)?;
+        let password = core::str::from_utf8(&record[PASSWORD_OFFSET..PASSWORD_OFFSET + password_len])
+            .map_err(|_| RecordError::BadUtf8)?;
+
+        Self::new(ssid, password).ok_or(RecordError::BadLength)
+    }
+}
+
+/// CRC-32 as used by Ethernet and zip (reflected, polynomial 0xEDB88320).
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Why the store couldn't load or save credentials.
+#[derive(Debug)]
+pub enum StoreError<E> {
+    Storage(E),
+    Record(RecordError),
+}
+
+/// Credentials kept at `offset` in some flash storage.
+pub struct CredentialStore<S> {
+    storage: S,
+    offset: u32,
+}
+
+impl<S: Storage> CredentialStore<S> {
+    pub fn new(storage: S, offset: u32) -> Self {
+        Self { storage, offset }
+    }
+
+    /// Reads the stored credentials.
+    pub fn load(&mut self) -> Result<Credentials, StoreError<S::Error>> {
+        let mut record = [0; RECORD_LEN];
+        self.storage.read(self.offset, &mut record).map_err(StoreError::Storage)?;
+        Credentials::decode(&record).map_err(StoreError::Record)
+    }
+
+    /// Reads the stored credentials, or None if nothing valid is stored.
+    pub fn get(&mut self) -> Option<Credentials> {
+        self.load().ok()
+    }
+
+    pub fn save(&mut self, credentials: &Credentials) -> Result<(), StoreError<S::Error>> {
+        self.storage.write(self.offset, &credentials.encode()).map_err(StoreError::Storage)
+    }
+
+    /// Forgets the stored credentials by writing an erased record.
+    pub fn clear(&mut self) -> Result<(), StoreError<S::Error>> {
+        self.storage.write(self.offset, &[0xFF; RECORD_LEN]).map_err(StoreError::Storage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_storage::ReadStorage;
+
+    /// A flash sector in RAM, starting out erased.
+    struct Ram([u8; 256]);
+
+    impl ReadStorage for Ram {
+        type Error = ();
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), ()> {
+            let start = offset as usize;
+            bytes.copy_from_slice(self.0.get(start..start + bytes.len()).ok_or(())?);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    impl Storage for Ram {
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), ()> {
+            let start = offset as usize;
+            self.0.get_mut(start..start + bytes.len()).ok_or(())?.copy_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn crc_matches_the_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn round_trips() {
+        let longest_ssid = "s".repeat(SSID_LEN);
+        let longest_password = "p".repeat(PASSWORD_LEN);
+        for (ssid, password) in [
+            ("Home", "hunter22"),
+            ("Office Guest", ""),
+            ("Café ☕", "pässwörd"),
+            (longest_ssid.as_str(), longest_password.as_str()),
+        ] {
+            let credentials = Credentials::new(ssid, password).unwrap();
+            let record = credentials.encode();
+            assert_eq!(&record[..5], b"WCRD\x01");
+            assert_eq!(Credentials::decode(&record), Ok(credentials));
+        }
+    }
+
+    #[test]
+    fn rejects_credentials_that_dont_fit() {
+        assert_eq!(Credentials::new("", "password"), None);
+        assert_eq!(Credentials::new(&"s".repeat(SSID_LEN + 1), ""), None);
+        assert_eq!(Credentials::new("Home", &"p".repeat(PASSWORD_LEN + 1)), None);
+    }
+
+    #[test]
+    fn any_corrupted_byte_is_caught() {
+        let record = Credentials::new("Home", "hunter22").unwrap().encode();
+        for i in 0..RECORD_LEN {
+            for bit in 0..8 {
+                let mut corrupted = record;
+                corrupted[i] ^= 1 << bit;
+                let expected = match i {
+                    0..4 => RecordError::BadMagic,
+                    4 => RecordError::UnsupportedVersion(corrupted[4]),
+                    _ => RecordError::BadCrc,
+                };
+                assert_eq!(Credentials::decode(&corrupted), Err(expected), "byte {i} bit {bit}");
+            }
+        }
+    }
+
+    #[test]
+    fn checks_fields_covered_by_the_crc() {
+        let fix_crc = |record: &mut [u8; RECORD_LEN]| {
+            let crc = crc32(&record[..CRC_OFFSET]);
+            record[CRC_OFFSET..].copy_from_slice(&crc.to_le_bytes());
+        };
+        let record = Credentials::new("Home", "hunter22").unwrap().encode();
+
+        let mut no_ssid = record;
+        no_ssid[5] = 0;
+        fix_crc(&mut no_ssid);
+        assert_eq!(Credentials::decode(&no_ssid), Err(RecordError::BadLength));
+
+        let mut long_password = record;
+        long_password[6] = PASSWORD_LEN as u8 + 1;
+        fix_crc(&mut long_password);
+        assert_eq!(Credentials::decode(&long_password), Err(RecordError::BadLength));
+
+        let mut not_utf8 = record;
+        not_utf8[SSID_OFFSET] = 0xFF;
+        fix_crc(&mut not_utf8);
+        assert_eq!(Credentials::decode(&not_utf8), Err(RecordError::BadUtf8));
+    }
+
+    #[test]
+    fn tells_erased_flash_from_damage() {
+        assert_eq!(Credentials::decode(&[0xFF; RECORD_LEN]), Err(RecordError::Empty));
+        assert_eq!(Credentials::decode(&[0; RECORD_LEN]), Err(RecordError::BadMagic));
+        assert_eq!(Credentials::decode(&[0xFF; RECORD_LEN - 1]), Err(RecordError::TooShort));
+    }
+
+    #[test]
+    fn stores_and_clears() {
+        let mut store = CredentialStore::new(Ram([0xFF; 256]), 16);
+        assert!(matches!(store.load(), Err(StoreError::Record(RecordError::Empty))));
+
+        let credentials = Credentials::new("Home", "hunter22").unwrap();
+        store.save(&credentials).unwrap();
+        assert_eq!(store.get(), Some(credentials));
+        assert!(store.storage.0[..16].iter().all(|&b| b == 0xFF));
+
+        store.clear().unwrap();
+        assert_eq!(store.get(), None);
+    }
+
+    #[test]
+    fn reports_storage_errors() {
+        let mut store = CredentialStore::new(Ram([0xFF; 256]), 200);
+        assert!(matches!(store.load(), Err(StoreError::Storage(()))));
+        assert!(matches!(store.save(&Credentials::default()), Err(StoreError::Storage(()))));
+    }
+}
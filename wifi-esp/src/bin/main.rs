@@ -1,33 +1,38 @@
 //! Embassy SNTP example
 //!
 //!
-//! Set SSID and PASSWORD env variable before running this example.
+//! The network to join is stored in the `wifi` flash partition (see partitions.csv), and can be
+//! changed from the serial console with `wifi set <ssid> [password]` (type `help` for the other
+//! commands). Until a network has been stored the SSID and PASSWORD env variables from the build
+//! are used, if they were set.
 //!
-//! This gets an ip address via DHCP then performs an SNTP request to update the RTC time with the
-//! response. The RTC time is then compared with the received data parsed with jiff.
-//! You can change the timezone to your local timezone.
+//! This gets an ip address via DHCP then uses the `timesync` crate to keep the RTC in step with a
+//! list of NTP servers, moving on to the next server if one stops answering. The RTC time is
+//! printed with jiff after each sync, in the POSIX TZ string given by the TIMEZONE env variable
+//! at build time, or UK time if it isn't set.
 
 #![no_std]
 #![no_main]
 
-use core::net::{IpAddr, SocketAddr};
-
+use timesync::{self, NTP_PORT, TimeSync};
 use embassy_executor::Spawner;
 use embassy_net::{
     Runner,
     StackResources,
-    dns::DnsQueryType,
     udp::{PacketMetadata, UdpSocket},
 };
 use embassy_time::{Duration, Timer};
 use esp_alloc as _;
 use esp_backtrace as _;
+use esp_bootloader_esp_idf::partitions::{self, FlashRegion, RawPartitionType};
 use esp_hal::{
+    Async,
     clock::CpuClock,
     interrupt::software::SoftwareInterruptControl,
     rng::Rng,
     rtc_cntl::Rtc,
     timer::timg::TimerGroup,
+    uart::{self, Uart, UartRx},
 };
 use esp_println::println;
 use esp_radio::{
@@ -42,8 +47,12 @@ use esp_radio::{
         WifiStaState,
     },
 };
+use esp_storage::FlashStorage;
 use defmt::{error, info};
-use sntpc::{NtpContext, NtpTimestampGenerator, get_time};
+use wifi_esp::{
+    console::{self, Command, LineBuffer},
+    credentials::{CredentialStore, Credentials},
+};
 
 esp_bootloader_esp_idf::esp_app_desc!();
 
@@ -57,34 +66,21 @@ macro_rules! mk_static {
     }};
 }
 
-const SSID: &str = env!("SSID");
-const PASSWORD: &str = env!("PASSWORD");
-const TIMEZONE: jiff::tz::TimeZone = jiff::tz::get!("UTC");
-const NTP_SERVER: &str = "pool.ntp.org";
+/// Used when no network has been stored in flash.
+const SSID: Option<&str> = option_env!("SSID");
+const PASSWORD: Option<&str> = option_env!("PASSWORD");
+const TIMEZONE: &str = match option_env!("TIMEZONE") {
+    Some(tz) => tz,
+    None => "GMT0BST,M3.5.0/1,M10.5.0/2",
+};
+/// The partition the credentials are stored in, a custom data subtype so nothing else uses it.
+const CREDENTIALS_PARTITION: &str = "wifi";
+const CREDENTIALS_SUBTYPE: u8 = 0x40;
+const NTP_SERVERS: &[&str] = &["pool.ntp.org", "time.google.com", "time.cloudflare.com"];
 
 /// Microseconds in a second
 const USEC_IN_SEC: u64 = 1_000_000;
 
-#[derive(Clone, Copy)]
-struct Timestamp<'a> {
-    rtc: &'a Rtc<'a>,
-    current_time_us: u64,
-}
-
-impl NtpTimestampGenerator for Timestamp<'_> {
-    fn init(&mut self) {
-        self.current_time_us = self.rtc.current_time_us();
-    }
-
-    fn timestamp_sec(&self) -> u64 {
-        self.current_time_us / 1_000_000
-    }
-
-    fn timestamp_subsec_micros(&self) -> u32 {
-        (self.current_time_us % 1_000_000) as u32
-    }
-}
-
 #[esp_rtos::main]
 async fn main(spawner: Spawner) -> ! {
     // esp_println::logger::init_logger_from_env();
@@ -100,6 +96,46 @@ async fn main(spawner: Spawner) -> ! {
         //, sw_int.software_interrupt0
         );
 
+    // Look the partition up by label, find_partition can't handle custom subtypes.
+    let flash = mk_static!(FlashStorage<'static>, FlashStorage::new(peripherals.FLASH));
+    let pt_mem = mk_static!([u8; partitions::PARTITION_TABLE_MAX_LEN], [0; partitions::PARTITION_TABLE_MAX_LEN]);
+    let partition = partitions::read_partition_table(flash, pt_mem)
+        .unwrap()
+        .iter()
+        .find(|p| {
+            p.label_as_str() == CREDENTIALS_PARTITION
+                && p.raw_type() == RawPartitionType::Data as u8
+                && p.raw_subtype() == CREDENTIALS_SUBTYPE
+        })
+        .expect("no wifi partition to store credentials in, flash with partitions.csv");
+    let mut store = CredentialStore::new(partition.as_embedded_storage(flash), 0);
+
+    let credentials = match store.load() {
+        Ok(credentials) => {
+            println!("Using stored network {}", credentials.ssid.as_str());
+            Some(credentials)
+        }
+        Err(e) => {
+            println!("No stored network ({e:?}), using the build settings");
+            SSID.and_then(|ssid| Credentials::new(ssid, PASSWORD.unwrap_or("")))
+        }
+    };
+
+    let uart0 = Uart::new(peripherals.UART0, uart::Config::default())
+        .unwrap()
+        .with_rx(peripherals.GPIO3)
+        .into_async();
+    let (rx, _tx) = uart0.split();
+    spawner.spawn(console_task(rx, store)).ok();
+
+    let Some(credentials) = credentials else {
+        println!("No network configured, use `wifi set <ssid> [password]` on the console");
+        loop {
+            Timer::after(Duration::from_secs(60)).await;
+        }
+    };
+    let credentials = &*mk_static!(Credentials, credentials);
+
     let esp_radio_ctrl = &*mk_static!(Controller<'static>, esp_radio::init().unwrap());
 
     let (controller, interfaces) =
@@ -120,7 +156,7 @@ async fn main(spawner: Spawner) -> ! {
         seed,
     );
 
-    spawner.spawn(connection(controller)).ok();
+    spawner.spawn(connection(controller, credentials)).ok();
     spawner.spawn(net_task(runner)).ok();
 
     let mut rx_meta = [PacketMetadata::EMPTY; 16];
@@ -145,12 +181,6 @@ async fn main(spawner: Spawner) -> ! {
         Timer::after(Duration::from_millis(500)).await;
     }
 
-    let ntp_addrs = stack.dns_query(NTP_SERVER, DnsQueryType::A).await.unwrap();
-
-    if ntp_addrs.is_empty() {
-        panic!("Failed to resolve DNS. Empty result");
-    }
-
     let mut socket = UdpSocket::new(
         stack,
         &mut rx_meta,
@@ -159,61 +189,52 @@ async fn main(spawner: Spawner) -> ! {
         &mut tx_buffer,
     );
 
-    socket.bind(123).unwrap();
+    socket.bind(NTP_PORT).unwrap();
+
+    let timezone = jiff::tz::TimeZone::posix(TIMEZONE).unwrap_or_else(|e| {
+        println!("Can't use TIMEZONE {TIMEZONE} ({e}), showing UTC");
+        jiff::tz::TimeZone::UTC
+    });
 
     // Display initial Rtc time before synchronization
     let now = jiff::Timestamp::from_microsecond(rtc.current_time_us() as i64).unwrap();
     println!("Rtc: {now}");
 
+    // Sync every 10 seconds to show the offset staying small.
+    let mut sync = TimeSync::new(NTP_SERVERS).with_interval(10 * USEC_IN_SEC);
+
     loop {
-        let addr: IpAddr = ntp_addrs[0].into();
-        let result = get_time(
-            SocketAddr::from((addr, 123)),
-            &socket,
-            NtpContext::new(Timestamp {
-                rtc: &rtc,
-                current_time_us: 0,
-            }),
-        )
-        .await;
-
-        match result {
-            Ok(time) => {
-                // Set time immediately after receiving to reduce time offset.
-                rtc.set_current_time_us(
-                    (time.sec() as u64 * USEC_IN_SEC)
-                        + ((time.sec_fraction() as u64 * USEC_IN_SEC) >> 32),
-                );
-
-                // Compare RTC to parsed time
-                println!(
-                    "Response: {:?}\nTime: {}\nRtc : {}",
-                    time,
-                    // Create a Jiff Timestamp from seconds and nanoseconds
-                    jiff::Timestamp::from_second(time.sec() as i64)
-                        .unwrap()
-                        .checked_add(
-                            jiff::Span::new()
-                                .nanoseconds((time.seconds_fraction as i64 * 1_000_000_000) >> 32),
-                        )
-                        .unwrap()
-                        .to_zoned(TIMEZONE),
-                    jiff::Timestamp::from_microsecond(rtc.current_time_us() as i64)
-                        .unwrap()
-                        .to_zoned(TIMEZONE)
-                );
-            }
-            Err(e) => {
-                println!("Error getting time: {e:?}");
+        if sync.is_due(rtc.current_time_us())
+            && let Some(server) = sync.server()
+        {
+            match timesync::request(stack, &socket, server, &rtc).await {
+                Ok(sample) => {
+                    sync.apply(&rtc, sample);
+
+                    println!(
+                        "Server: {}\nOffset: {}us, roundtrip: {}us\nRtc : {}",
+                        server,
+                        sample.offset_us,
+                        sample.roundtrip_us,
+                        jiff::Timestamp::from_microsecond(rtc.current_time_us() as i64)
+                            .unwrap()
+                            .to_zoned(timezone.clone())
+                    );
+                }
+                Err(e) => {
+                    println!("Error getting time from {server}: {e:?}");
+                    sync.failed(rtc.current_time_us());
+                }
             }
         }
 
-        Timer::after(Duration::from_secs(10)).await;
+        sync.trim(&rtc);
+        Timer::after(Duration::from_micros(sync.until_due(rtc.current_time_us()))).await;
     }
 }
 
 #[embassy_executor::task]
-async fn connection(mut controller: WifiController<'static>) {
+async fn connection(mut controller: WifiController<'static>, credentials: &'static Credentials) {
     println!("start connection task");
     println!("Device capabilities: {:?}", controller.capabilities());
     loop {
@@ -225,8 +246,8 @@ async fn connection(mut controller: WifiController<'static>) {
         if !matches!(controller.is_started(), Ok(true)) {
             let client_config = ModeConfig::Client(
                 ClientConfig::default()
-                    .with_ssid(SSID.into())
-                    .with_password(PASSWORD.into()),
+                    .with_ssid(credentials.ssid.as_str().into())
+                    .with_password(credentials.password.as_str().into()),
             );
             controller.set_config(&client_config).unwrap();
             println!("Starting wifi");
@@ -258,4 +279,55 @@ async fn connection(mut controller: WifiController<'static>) {
 #[embassy_executor::task]
 async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
     runner.run().await
-}
\ No newline at end of file
+}
+
+/// Reads commands from the serial console, for storing the network to join.
+#[embassy_executor::task]
+async fn console_task(mut rx: UartRx<'static, Async>, mut store: CredentialStore<FlashRegion<'static, FlashStorage<'static>>>) {
+    let mut line = LineBuffer::<128>::new();
+    let mut buf = [0u8; 32];
+
+    loop {
+        let Ok(len) = rx.read_async(&mut buf).await else {
+            continue;
+        };
+
+        for &byte in &buf[..len] {
+            let Some(input) = line.push(byte) else {
+                continue;
+            };
+
+            match console::parse(&input) {
+                None => {}
+                Some(Ok(Command::Help)) => println!("{}", console::HELP),
+                Some(Ok(Command::Show)) => match store.load() {
+                    Ok(credentials) => println!(
+                        "Stored network: {} ({})",
+                        credentials.ssid.as_str(),
+                        if credentials.password.is_empty() { "open" } else { "with password" }
+                    ),
+                    Err(e) => println!("No stored network: {e:?}"),
+                },
+                Some(Ok(Command::Set { ssid, password })) => match Credentials::new(ssid, password) {
+                    Some(credentials) => match store.save(&credentials) {
+                        Ok(()) => {
+                            println!("Stored {}, restarting", ssid);
+                            esp_hal::system::software_reset();
+                        }
+                        Err(e) => println!("Failed to store network: {e:?}"),
+                    },
+                    None => println!("The SSID must be 1 to 32 bytes and the password at most 64"),
+                },
+                Some(Ok(Command::Clear)) => match store.clear() {
+                    Ok(()) => {
+                        println!("Stored network cleared, restarting");
+                        esp_hal::system::software_reset();
+                    }
+                    Err(e) => println!("Failed to clear network: {e:?}"),
+                },
+                Some(Ok(Command::Restart)) => esp_hal::system::software_reset(),
+                Some(Err(e)) => println!("{e:?}, type `help` for the commands"),
+            }
+        }
+    }
+}
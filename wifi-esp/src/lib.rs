@@ -1 +1,6 @@
 #![no_std]
+
+/// The credential record, which lives in `wifi-credentials` so it can be tested on the host.
+pub use wifi_credentials as credentials;
+/// The serial console commands, also in `wifi-credentials` to be tested on the host.
+pub use wifi_credentials::console;
@@ -9,7 +9,7 @@
 
 
 use core::fmt::Write;
-use chrono::NaiveTime;
+use chrono::{NaiveDate, NaiveTime};
 use chrono::Timelike;
 use defmt::info;
 use embedded_graphics::primitives::Circle;
@@ -21,6 +21,9 @@ use embedded_graphics::text::Text;
 use embedded_graphics::mono_font::MonoTextStyle;
 use embedded_graphics::mono_font::ascii::FONT_10X20;
 // use embedded_graphics::prelude::*;
+use clock_core::astro::{Location, sunrise_sunset};
+use clock_core::face::{ClockFace, Dial, Numerals, Theme};
+use clock_core::tz::PosixTz;
 use esp_backtrace as _;
 use esp_hal::clock::CpuClock;
 use esp_hal::delay::Delay;
@@ -33,7 +36,7 @@ use esp_hal::spi::master::Config;
 use mipidsi::{Builder, models::ILI9341Rgb565, options::{Orientation, Rotation}};
 use embedded_graphics::{pixelcolor::Rgb565, prelude::*};
 use esp_hal::spi::master::Spi;
-use ws_esp32_c6_147_clock::*;
+use clock_core::*;
 
 // This creates a default app-descriptor required by the esp-idf bootloader.
 // For more information see: <https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-reference/system/app_image_format.html#application-description>
@@ -216,12 +219,22 @@ If you need full SPI (e.g. with MISO), you can also assign MISO to a spare GPIO,
 
     display.clear(bg_color).unwrap();
 
-    let clock_face = create_face(&display);
-    draw_face(&mut display, &clock_face, clock_color).unwrap();
+    // There is no date source yet, so the RTC counts from local midnight on a fixed day in London.
+    let start_date = NaiveDate::from_ymd_opt(2025, 6, 21).unwrap();
+    let location = Location::new(51.5074, -0.1278);
+    let tz = PosixTz::parse("GMT0BST,M3.5.0/1,M10.5.0").unwrap_or(PosixTz::UTC);
+    let start = tz.to_utc(start_date.and_time(NaiveTime::MIN));
 
-    let mut prev_hour = 0;
-    let mut prev_minute = 0;
-    let mut prev_second = 0;    
+    let mut face = ClockFace::fit(&display, 10, Theme::CLASSIC)
+        .with_dial(Dial::TwentyFourHour)
+        .with_minute_ticks(true)
+        .with_numerals(Numerals::Arabic);
+    face.set_daylight(sunrise_sunset(start_date, &location).local_hours(&tz));
+    face.draw(&mut display).unwrap();
+    let mut sun_date = start_date;
+
+    let clock_face = face.circle;
+    let mut hands = face.hands();
 
     loop {
         // let now = Timestamp::from_microsecond(rtc.current_time_us() as i64)?;
@@ -231,10 +244,20 @@ If you need full SPI (e.g. with MISO), you can also assign MISO to a spare GPIO,
         info!("elapsed_ms: {}", elapsed_ms);
         let secs = elapsed_ms / 1000;
         let bedtime = 1000 * (secs + 1) - elapsed_ms;
-        let nanos = now % 1_000_000;
         // let duration = chrono::Duration::milliseconds(now);
         // let time = NaiveTime::from_hms_milli_opt(0, 0, secs, ms).unwrap();
-        let time = NaiveTime::from_num_seconds_from_midnight_opt(secs, nanos as u32).unwrap();
+        let local = tz.to_local(start + secs as i64);
+        let time = local.time();
+
+        // Move the night band on at midnight.
+        let date = local.date();
+        if date != sun_date {
+            sun_date = date;
+            if face.set_daylight(sunrise_sunset(date, &location).local_hours(&tz)) {
+                face.draw(&mut display).unwrap();
+                hands.reset();
+            }
+        }
 
         let mut time_str: String<64> = String::new();
         write!(time_str, "Time: {:02}:{:02}:{:02}", time.hour(), time.minute(), time.second()).unwrap();
@@ -265,29 +288,8 @@ If you need full SPI (e.g. with MISO), you can also assign MISO to a spare GPIO,
         let minute = time.minute();
         let second = time.second();
 
-        if hour != prev_hour {
-             draw_hand(&mut display, &clock_face, bg_color, hour_to_angle(prev_hour), -60).unwrap();
-        }
-        if minute != prev_minute {
-             draw_hand(&mut display, &clock_face, bg_color, sexagesimal_to_angle(prev_minute), -30).unwrap();
-        }
-
-        if second != prev_second {
-            let seconds_radians = sexagesimal_to_angle(prev_second);
-            draw_hand(&mut display, &clock_face, bg_color, seconds_radians, 0).unwrap();
-            draw_second_decoration(&mut display, &clock_face, bg_color, bg_color, seconds_radians, -20).unwrap();
-        }
-
-        prev_hour = hour;
-        prev_minute = minute;
-        prev_second = second;
-
-        draw_hand(&mut display, &clock_face, clock_color, hour_to_angle(hour), -60).unwrap();
-        draw_hand(&mut display, &clock_face, clock_color, sexagesimal_to_angle(minute), -30).unwrap();
-
-        let seconds_radians = sexagesimal_to_angle(second);
-        draw_hand(&mut display, &clock_face, clock_color, seconds_radians, 0).unwrap();
-        draw_second_decoration(&mut display, &clock_face, clock_color, bg_color, seconds_radians, -20).unwrap();
+        let angles = [face.hour_angle(hour, minute), sexagesimal_to_angle(minute), sexagesimal_to_angle(second)];
+        hands.update(&mut display, &clock_face, bg_color, angles, &face).unwrap();
 
         // Draw a small circle over the hands in the center of the clock face.
         // This has to happen after the hands are drawn so they're covered up.